@@ -0,0 +1,430 @@
+//! Unpacks uploaded archives, either zip files or gzipped tarballs, and packs submissions written
+//! in the browser editor into zip files like the ones students upload
+//!
+//! Nothing in an upload is trusted. Every entry has to stay inside the destination, so absolute
+//! paths and `..` are turned away, and links and device files are skipped. Sizes are counted as
+//! entries are written instead of being taken from the archive's headers. Unpacking stops as soon
+//! as the upload has more than `archive_max_entries` entries, a file larger than
+//! `archive_max_file_mb`, or more than `workdir_quota_mb` in all.
+
+use std::{
+    fs::{File, Permissions, create_dir_all, set_permissions},
+    io::{Cursor, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::config;
+
+const INVALID: &str =
+    "The upload could not be unpacked. Make sure it is a valid zip or tar.gz file.";
+
+const MB: u64 = 1024 * 1024;
+
+pub enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    /// Tells the format from the archive's first bytes. `None` => neither.
+    pub fn of(archive: &[u8]) -> Option<Format> {
+        if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+            Some(Format::Zip)
+        } else if archive.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// The file extension an uploaded archive is saved with
+pub fn extension(archive: &[u8]) -> &'static str {
+    match Format::of(archive) {
+        Some(Format::TarGz) => "tar.gz",
+        _ => "zip",
+    }
+}
+
+pub struct Limits {
+    pub max_entries: usize,
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+}
+
+impl Limits {
+    pub fn from_config() -> Limits {
+        let config = config::get();
+        Limits {
+            max_entries: config.archive_max_entries,
+            max_file_bytes: config.archive_max_file_mb * MB,
+            max_total_bytes: config.workdir_quota_mb * MB,
+        }
+    }
+}
+
+pub enum ExtractError {
+    /// The upload is invalid or too large. The reason is meant for whoever uploaded it.
+    Rejected(String),
+    /// Writing it out failed, e.g. because the disk is full
+    Io(std::io::Error),
+}
+
+/// Unpacks `archive` into `destination`, which must already exist
+pub fn extract(archive: &[u8], destination: &Path, limits: &Limits) -> Result<(), ExtractError> {
+    let mut extractor = Extractor {
+        destination,
+        limits,
+        entries: 0,
+        total_bytes: 0,
+    };
+
+    match Format::of(archive) {
+        Some(Format::Zip) => extractor.zip(archive),
+        Some(Format::TarGz) => extractor.tar_gz(archive),
+        None => Err(rejected(INVALID)),
+    }
+}
+
+struct Extractor<'a> {
+    destination: &'a Path,
+    limits: &'a Limits,
+    entries: usize,
+    total_bytes: u64,
+}
+
+impl Extractor<'_> {
+    fn zip(&mut self, archive: &[u8]) -> Result<(), ExtractError> {
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive)).map_err(|_| rejected(INVALID))?;
+
+        for index in 0..zip.len() {
+            self.count()?;
+            let mut file = zip.by_index(index).map_err(|e| {
+                ExtractError::Rejected(format!("The upload could not be unpacked: {e}"))
+            })?;
+            if file.is_symlink() {
+                continue;
+            }
+
+            let path = PathBuf::from(file.name());
+            let target = self.target(&path)?;
+            if file.is_dir() {
+                create_dir_all(&target).map_err(ExtractError::Io)?;
+                continue;
+            }
+
+            let mode = file.unix_mode();
+            self.write(&path, &target, &mut file, mode)?;
+        }
+
+        Ok(())
+    }
+
+    fn tar_gz(&mut self, archive: &[u8]) -> Result<(), ExtractError> {
+        let mut tar = tar::Archive::new(GzDecoder::new(archive));
+
+        for entry in tar.entries().map_err(|_| rejected(INVALID))? {
+            self.count()?;
+            let mut entry = entry.map_err(|_| rejected(INVALID))?;
+            let path = entry.path().map_err(|_| rejected(INVALID))?.into_owned();
+
+            let kind = entry.header().entry_type();
+            if kind.is_dir() {
+                let target = self.target(&path)?;
+                create_dir_all(&target).map_err(ExtractError::Io)?;
+                continue;
+            }
+            if !kind.is_file() {
+                continue;
+            }
+
+            let target = self.target(&path)?;
+            let mode = entry.header().mode().ok();
+            self.write(&path, &target, &mut entry, mode)?;
+        }
+
+        Ok(())
+    }
+
+    fn count(&mut self) -> Result<(), ExtractError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(ExtractError::Rejected(format!(
+                "The upload has more than the {} files allowed",
+                self.limits.max_entries
+            )));
+        }
+        Ok(())
+    }
+
+    /// Where an entry goes, as long as that's inside the destination
+    fn target(&self, path: &Path) -> Result<PathBuf, ExtractError> {
+        let inside = path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(ExtractError::Rejected(format!(
+                "{} points outside the upload",
+                path.display()
+            )));
+        }
+
+        Ok(self.destination.join(path))
+    }
+
+    /// Writes out a file, counting its size as it goes. It's made executable if it was in the
+    /// archive.
+    fn write(
+        &mut self,
+        path: &Path,
+        target: &Path,
+        contents: &mut impl Read,
+        mode: Option<u32>,
+    ) -> Result<(), ExtractError> {
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent).map_err(ExtractError::Io)?;
+        }
+        let mut file = File::create(target).map_err(ExtractError::Io)?;
+
+        let mut buf = vec![0; 64 * 1024];
+        let mut file_bytes = 0;
+        loop {
+            let n = match contents.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(_) => {
+                    return Err(ExtractError::Rejected(format!(
+                        "{} is corrupted",
+                        path.display()
+                    )));
+                }
+            };
+
+            file_bytes += n as u64;
+            self.total_bytes += n as u64;
+            if file_bytes > self.limits.max_file_bytes {
+                return Err(ExtractError::Rejected(format!(
+                    "{} is larger than the {} MB allowed for one file",
+                    path.display(),
+                    self.limits.max_file_bytes / MB
+                )));
+            }
+            if self.total_bytes > self.limits.max_total_bytes {
+                return Err(ExtractError::Rejected(format!(
+                    "The upload unpacks to more than the {} MB allowed",
+                    self.limits.max_total_bytes / MB
+                )));
+            }
+
+            file.write_all(&buf[..n]).map_err(ExtractError::Io)?;
+        }
+
+        if mode.is_some_and(|mode| mode & 0o111 != 0) {
+            set_permissions(target, Permissions::from_mode(0o755)).map_err(ExtractError::Io)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Packs `files`, each a path inside the archive and its contents, into a zip
+pub fn pack(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for (path, contents) in files {
+        zip.start_file(path.as_str(), options)
+            .map_err(|e| format!("{e}"))?;
+        zip.write_all(contents).map_err(|e| format!("{e}"))?;
+    }
+
+    zip.finish()
+        .map(Cursor::into_inner)
+        .map_err(|e| format!("{e}"))
+}
+
+fn rejected(reason: &str) -> ExtractError {
+    ExtractError::Rejected(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, remove_dir_all, symlink_metadata};
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+
+    enum Entry {
+        File(&'static str, &'static [u8]),
+        Symlink(&'static str, &'static str),
+    }
+
+    const LIMITS: Limits = Limits {
+        max_entries: 3,
+        max_file_bytes: 8,
+        max_total_bytes: 12,
+    };
+
+    fn zip_of(entries: &[Entry]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+
+        for entry in entries {
+            match entry {
+                Entry::File(name, contents) => {
+                    zip.start_file(*name, options).unwrap();
+                    zip.write_all(contents).unwrap();
+                }
+                Entry::Symlink(name, target) => zip.add_symlink(*name, *target, options).unwrap(),
+            }
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// Names are written into the headers as they are, since `tar` won't build an archive with
+    /// `..` or absolute paths
+    fn tar_gz_of(entries: &[Entry]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            let (name, contents): (&str, &[u8]) = match entry {
+                Entry::File(name, contents) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    (name, contents)
+                }
+                Entry::Symlink(name, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.as_gnu_mut().unwrap().linkname[..target.len()]
+                        .copy_from_slice(target.as_bytes());
+                    (name, &[])
+                }
+            };
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, contents).unwrap();
+        }
+
+        tar.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Unpacks the archive into a fresh directory, inside another so escapes land somewhere
+    /// they can be looked for. Returns the outer directory, the result and the destination.
+    fn extract_in_scratch(archive: &[u8]) -> (PathBuf, Result<(), ExtractError>, PathBuf) {
+        let suffix: String = rand::random::<[u8; 8]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let scratch = std::env::temp_dir().join(format!("securegrade-archive-{suffix}"));
+        let destination = scratch.join("destination");
+        create_dir_all(&destination).unwrap();
+
+        let result = extract(archive, &destination, &LIMITS);
+        (scratch, result, destination)
+    }
+
+    /// Unpacks each archive, checks the result, then cleans up
+    fn check(archives: [Vec<u8>; 2], expect: impl Fn(&Path, Result<(), ExtractError>)) {
+        for archive in archives {
+            let (scratch, result, destination) = extract_in_scratch(&archive);
+            expect(&destination, result);
+            remove_dir_all(scratch).unwrap();
+        }
+    }
+
+    fn both(entries: &[Entry]) -> [Vec<u8>; 2] {
+        [zip_of(entries), tar_gz_of(entries)]
+    }
+
+    fn is_rejected(result: &Result<(), ExtractError>, reason: &str) -> bool {
+        matches!(result, Err(ExtractError::Rejected(r)) if r.contains(reason))
+    }
+
+    #[test]
+    fn unpacks_files() {
+        let archives = both(&[
+            Entry::File("main.py", b"print()"),
+            Entry::File("src/lib.py", b"x = 1"),
+        ]);
+
+        check(archives, |destination, result| {
+            assert!(result.is_ok());
+            assert_eq!(read(destination.join("main.py")).unwrap(), b"print()");
+            assert_eq!(read(destination.join("src/lib.py")).unwrap(), b"x = 1");
+        });
+    }
+
+    #[test]
+    fn rejects_parent_dir_entries() {
+        let archives = both(&[Entry::File("../escaped.txt", b"out")]);
+
+        check(archives, |destination, result| {
+            assert!(is_rejected(&result, "points outside the upload"));
+            assert!(!destination.parent().unwrap().join("escaped.txt").exists());
+        });
+    }
+
+    #[test]
+    fn rejects_absolute_entries() {
+        let archives = both(&[Entry::File("/securegrade-absolute.txt", b"out")]);
+
+        check(archives, |_, result| {
+            assert!(is_rejected(&result, "points outside the upload"));
+            assert!(!Path::new("/securegrade-absolute.txt").exists());
+        });
+    }
+
+    #[test]
+    fn skips_symlinks() {
+        let archives = both(&[
+            Entry::Symlink("passwd", "/etc/passwd"),
+            Entry::File("main.py", b"print()"),
+        ]);
+
+        check(archives, |destination, result| {
+            assert!(result.is_ok());
+            assert!(symlink_metadata(destination.join("passwd")).is_err());
+            assert!(destination.join("main.py").exists());
+        });
+    }
+
+    #[test]
+    fn rejects_too_many_entries() {
+        let archives = both(&[
+            Entry::File("a", b""),
+            Entry::File("b", b""),
+            Entry::File("c", b""),
+            Entry::File("d", b""),
+        ]);
+
+        check(archives, |_, result| {
+            assert!(is_rejected(&result, "more than the 3 files allowed"));
+        });
+    }
+
+    #[test]
+    fn rejects_oversized_files() {
+        let archives = both(&[Entry::File("big", b"123456789")]);
+
+        check(archives, |_, result| {
+            assert!(is_rejected(&result, "big is larger than"));
+        });
+    }
+
+    #[test]
+    fn rejects_oversized_uploads() {
+        let archives = both(&[Entry::File("a", b"1234567"), Entry::File("b", b"1234567")]);
+
+        check(archives, |_, result| {
+            assert!(is_rejected(&result, "unpacks to more than"));
+        });
+    }
+}
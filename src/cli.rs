@@ -0,0 +1,92 @@
+//! Command-line subcommands for recovering access when the web login path is unavailable
+//!
+//! These talk to the database directly, using the same hashing code as the signup endpoint.
+//! Passwords are read from stdin so they don't end up in shell history.
+//!
+//! ## Usage:
+//!
+//! ```text
+//! grader admin create-user <user_name> <email> <first_name> <last_name> [--admin]
+//! grader admin reset-password <user_name>
+//! ```
+
+use std::io::BufRead;
+
+use crate::database;
+
+const USAGE: &str = "Usage:
+    grader admin create-user <user_name> <email> <first_name> <last_name> [--admin]
+    grader admin reset-password <user_name>";
+
+/// Runs the `admin` subcommand. Returns the process exit code.
+pub async fn run_admin(args: &[String]) -> i32 {
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    if !matches!(args.first(), Some(&"create-user") | Some(&"reset-password")) {
+        eprintln!("{USAGE}");
+        return 2;
+    }
+
+    if let Err(e) = database::init_database().await {
+        eprintln!("Could not connect to the database: {e}");
+        return 1;
+    }
+
+    let result = match &args[..] {
+        ["create-user", user_name, email, first_name, last_name, flags @ ..] => {
+            let is_admin = flags.contains(&"--admin");
+            let Some(pass) = read_password() else {
+                eprintln!("No password provided.");
+                return 1;
+            };
+
+            database::user::create_user(
+                first_name.to_string(),
+                last_name.to_string(),
+                user_name.to_string(),
+                email.to_string(),
+                pass,
+                is_admin,
+            )
+            .await
+            .map(|id| format!("Created user {user_name} (id {id}, admin: {is_admin})"))
+        }
+        ["reset-password", user_name] => {
+            let Some(pass) = read_password() else {
+                eprintln!("No password provided.");
+                return 1;
+            };
+
+            match database::user::reset_password(user_name.to_string(), pass).await {
+                Ok(true) => Ok(format!("Password reset for {user_name}")),
+                Ok(false) => Err(format!("No user named {user_name}")),
+                Err(e) => Err(e),
+            }
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(message) => {
+            println!("{message}");
+            0
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+/// Reads a single line from stdin as the password
+fn read_password() -> Option<String> {
+    eprint!("Password: ");
+    let mut pass = String::new();
+    std::io::stdin().lock().read_line(&mut pass).ok()?;
+
+    let pass = pass.trim_end_matches(['\r', '\n']).to_string();
+    (!pass.is_empty()).then_some(pass)
+}
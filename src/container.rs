@@ -1,22 +1,63 @@
 //! Contains the necessary functions for building, running, and evaluating containerized submissions
 
 use std::{
-    fs::{copy, create_dir_all, read_dir, remove_dir_all},
+    fs::{create_dir_all, read_dir},
     path::PathBuf,
-    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
 };
 
-use tokio::sync::Semaphore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tracing::{error, info, warn};
 
 use crate::{
-    database::{self, assignment::Test},
-    model::submission_response::SubmissionResponse,
+    config,
+    database::{
+        self,
+        assignment::{TaskDetails, Test},
+        grading_job::GradingJob,
+    },
+    email,
+    model::{
+        comparison,
+        grading_event::GradingEvent,
+        language_info::{DefaultLimits, LanguageInfo},
+        submission_response::{HIDDEN, SubmissionResponse, TestMeta},
+        test_method::TestMethod,
+    },
 };
 
-use image::ImageBuilder;
+use image::{BuildError, Dialog, Image, ImageBuilder, ResourceLimits, RunOutcome};
+use manifest::Manifest;
+pub use janitor::{janitor, janitor_stats};
+pub use manifest::Limits as LanguageLimits;
+pub use pool::{
+    estimated_wait, queue, queue_full, queue_stats, set_grading_threads, shutdown_queue,
+    start_queue,
+};
+pub use progress::subscribe;
+pub use remote::{
+    RunnerJob, RunnerReport, RunnerRequest, claim, grade_job, heartbeat, report, runner_monitor,
+};
+pub use runtime::{Isolation, RuntimeKind};
+use runtime::runtime;
+use warm::WarmContainer;
+pub use warm::warm_pools;
+pub use workdir::Workdir;
 
+mod base;
+mod http;
 mod image;
+mod janitor;
+mod junit;
+mod manifest;
+mod pool;
+mod progress;
+mod remote;
+mod runtime;
+mod warm;
+mod workdir;
 
 // Supported Languages
 // pub enum Language {
@@ -34,6 +75,8 @@ pub struct ContainerEntry {
     task_id: i32,
     was_late: bool,
     lang: String,
+    /// Run only the sample tests, without recording a grade
+    sample: bool,
 }
 
 impl ContainerEntry {
@@ -50,71 +93,358 @@ impl ContainerEntry {
             task_id,
             was_late,
             lang: lang.into(),
+            sample: false,
+        }
+    }
+
+    /// A sample run, checked against only the task's sample tests
+    pub fn sample(
+        zip_file: axum::body::Bytes,
+        user_id: i32,
+        task_id: i32,
+        lang: impl Into<String>,
+    ) -> Self {
+        Self {
+            sample: true,
+            ..Self::new(zip_file, user_id, task_id, false, lang)
         }
     }
 }
 
-pub async fn container_queue(
-    mut rx: tokio::sync::mpsc::Receiver<ContainerEntry>,
-    n_threads: Option<usize>,
-) -> ! {
-    static SEMAPHORE: Semaphore = Semaphore::const_new(20);
+/// Whether the container runtime answered its most recent health check
+static RUNTIME_AVAILABLE: AtomicBool = AtomicBool::new(true);
 
-    if let Some(n) = n_threads {
-        let cur_n = SEMAPHORE.available_permits();
-        let diff = n as i32 - cur_n as i32;
+/// How often the container runtime's health is checked
+const RUNTIME_CHECK_INTERVAL_SECS: u64 = 30;
 
-        match diff {
-            ..0 => _ = SEMAPHORE.forget_permits(-diff as usize),
-            1.. => SEMAPHORE.add_permits(diff as usize),
-            0 => (),
-        };
-    }
+/// The most of a grading error's reason shown to students
+const MAX_STUDENT_ERROR_CHARS: usize = 200;
+
+pub fn runtime_available() -> bool {
+    RUNTIME_AVAILABLE.load(Ordering::SeqCst)
+}
 
-    warn!("MAX THREADS: {}", SEMAPHORE.available_permits());
+/// Asks the container daemon whether it is up
+pub async fn check_runtime() -> bool {
+    let runtime = runtime();
+    runtime
+        .command()
+        .args(["info", "--format", runtime.version_template()])
+        .output()
+        .await
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Periodically checks the container runtime. Admins are alerted when it goes down, and submissions
+/// delayed while it was down are queued again once it comes back.
+pub async fn runtime_monitor() -> ! {
+    let mut previous = None;
 
     loop {
-        if let Ok(perm) = SEMAPHORE.acquire().await
-            && let Some(container) = rx.recv().await
+        let available = check_runtime().await;
+        // Grading jobs the runtime failed mark it down too, so they have to be caught coming back
+        let was_available = RUNTIME_AVAILABLE.swap(available, Ordering::SeqCst);
+
+        if !available && previous != Some(false) {
+            error!("Container runtime is unavailable; grading is delayed");
+            alert_admins(
+                "The container runtime is unavailable. Submissions will be graded once it is back.",
+            )
+            .await;
+        }
+        if available && (previous.is_none() || !was_available) {
+            requeue_delayed().await;
+        }
+
+        previous = Some(available);
+        tokio::time::sleep(tokio::time::Duration::from_secs(RUNTIME_CHECK_INTERVAL_SECS)).await;
+    }
+}
+
+async fn alert_admins(message: &str) {
+    let admins = match database::user::admin_ids().await {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Could not look up admins to alert: {e}");
+            return;
+        }
+    };
+
+    for admin_id in admins {
+        if let Err(e) = database::notification::add_notification(admin_id, message, None).await {
+            error!("Could not alert admin {admin_id}: {e}");
+        }
+    }
+}
+
+async fn requeue_delayed() {
+    let entries = match database::assignment::take_delayed_submissions().await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Could not retrieve delayed submissions: {e}");
+            return;
+        }
+    };
+
+    if entries.is_empty() {
+        return;
+    }
+
+    info!("Queueing {} delayed submissions", entries.len());
+    requeue(entries).await;
+}
+
+/// Queues stored submissions to be graded again. They're queued even if the queue is full.
+pub async fn requeue(entries: Vec<ContainerEntry>) {
+    for entry in entries {
+        let (user_id, task_id) = (entry.user_id, entry.task_id);
+        if let Err(e) = queue(entry).await {
+            error!("Could not queue submission {user_id}-{task_id}: {e}");
+        }
+    }
+}
+
+/// Graded submissions wait for the container runtime to come back. Sample runs are dropped, so the
+/// student can simply start another.
+async fn postpone(user_id: i32, task_id: i32, sample: bool) {
+    if !sample {
+        delay_grading(user_id, task_id).await;
+    } else if let Err(e) = database::sample_run::discard_sample_run(user_id, task_id).await {
+        error!("Could not discard sample run {user_id}-{task_id}: {e}");
+    }
+}
+
+/// Leaves a submission to be graded later, once the container runtime is back
+async fn delay_grading(user_id: i32, task_id: i32) {
+    RUNTIME_AVAILABLE.store(false, Ordering::SeqCst);
+    warn!("Container runtime unavailable; delaying grading of {user_id}-{task_id}");
+
+    if let Err(e) = database::assignment::mark_grading_delayed(user_id, task_id).await {
+        error!("Could not mark submission {user_id}-{task_id} as delayed: {e}");
+    }
+    progress::publish(user_id, task_id, GradingEvent::Delayed);
+}
+
+/// What became of a submission taken from the queue
+enum Outcome {
+    Graded,
+    /// It couldn't be graded, and was postponed or dropped
+    Failed,
+    /// It failed for reasons outside the submission, and is queued to be tried again
+    Retrying,
+}
+
+/// Grades one submission from the queue and stores its results
+async fn grade(
+    GradingJob {
+        id,
+        entry: container,
+        attempts,
+    }: GradingJob,
+) -> Outcome {
+    let user_id = container.user_id;
+    let task_id = container.task_id;
+    let lang = container.lang.clone();
+    let sample = container.sample;
+
+    if !runtime_available() {
+        postpone(user_id, task_id, sample).await;
+        return Outcome::Failed;
+    }
+
+    let result = run_container(container, id).await;
+
+    // Jobs that failed may have failed because the runtime did
+    if result.is_err() && !check_runtime().await {
+        postpone(user_id, task_id, sample).await;
+        return Outcome::Failed;
+    }
+
+    // Student code failing only shows in the results, so an error is never the submission's fault
+    if let Err(e) = &result
+        && pool::retry(id, attempts).await
+    {
+        warn!("Could not grade {user_id}-{task_id}, trying again later: {e}");
+        if !sample {
+            progress::publish(user_id, task_id, GradingEvent::Retrying);
+        }
+        return Outcome::Retrying;
+    }
+
+    match store(id, user_id, task_id, &lang, sample, result).await {
+        true => Outcome::Graded,
+        false => Outcome::Failed,
+    }
+}
+
+/// Stores the outcome of grading job `job_id`, whether it was graded here or by a remote runner.
+/// `false` => it couldn't be graded.
+async fn store(
+    job_id: i32,
+    user_id: i32,
+    task_id: i32,
+    lang: &str,
+    sample: bool,
+    result: Result<SubmissionResponse, String>,
+) -> bool {
+    let mut results = match result {
+        Ok(results) => results,
+        Err(e) => {
+            error!("Unable to grade {user_id}-{task_id}: {e}");
+
+            if sample {
+                postpone(user_id, task_id, sample).await;
+                return false;
+            }
+
+            let error = student_error(&e);
+            if let Err(e) =
+                database::assignment::container_add_task_error(user_id, task_id, &error).await
+            {
+                error!("Could not record the grading error of {user_id}-{task_id}: {e}");
+            }
+            progress::publish(user_id, task_id, GradingEvent::Failed { error });
+
+            return false;
+        }
+    };
+
+    if let Some(log) = results.take_build_log()
+        && let Err(e) = database::grading_job::set_build_log(job_id, &log).await
+    {
+        error!("Could not store the build log of {user_id}-{task_id}: {e}");
+    }
+
+    if let Some(times) = results.take_times()
+        && let Err(e) = database::grading_job::set_times(job_id, times).await
+    {
+        error!("Could not store the grading times of {user_id}-{task_id}: {e}");
+    }
+
+    let config = config::get();
+    let full_outputs = results.truncate_io(config.result_max_lines, config.result_max_bytes);
+    let json_results = serde_json::to_vec(&results).unwrap();
+
+    // Sample runs only keep the shortened output
+    if sample {
+        if let Err(e) =
+            database::sample_run::store_sample_results(user_id, task_id, &json_results).await
         {
-            tokio::spawn(async move {
-                let user_id = container.user_id;
-                let task_id = container.task_id;
-                let Ok(results) = run_container(container).await else {
-                    drop(perm);
-                    tracing::error!("Unable to run container");
-
-                    // Log error in psql
-
-                    return;
-                };
-                drop(perm);
-
-                let json_results = serde_json::to_vec(&results).unwrap();
-
-                database::assignment::container_add_task_grade(
-                    user_id,
-                    task_id,
-                    &json_results,
-                    results.score(),
-                )
-                .await
-                .unwrap();
-            });
-        } else {
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+            error!("Could not store sample run {user_id}-{task_id}: {e}");
+            return false;
         }
+        return true;
+    }
+
+    if let Err(e) = database::assignment::container_add_task_grade(
+        user_id,
+        task_id,
+        &json_results,
+        results.score(),
+        toolchain_version(lang).as_deref(),
+    )
+    .await
+    {
+        error!("Could not store the grade of {user_id}-{task_id}: {e}");
+        return false;
     }
+
+    if let Err(e) = database::assignment::store_full_outputs(user_id, task_id, &full_outputs).await
+    {
+        error!("Could not store full outputs of {user_id}-{task_id}: {e}");
+    }
+
+    progress::publish(
+        user_id,
+        task_id,
+        GradingEvent::Graded {
+            score: results.score(),
+        },
+    );
+    // Sent in the background, so a slow mail server doesn't hold up grading
+    tokio::spawn(email::send_grade_notification(
+        user_id,
+        task_id,
+        results.score(),
+    ));
+    true
+}
+
+/// The words of a grading error that are most likely paths, image ids or addresses of the grading
+/// machines
+fn internal_detail(word: &str) -> bool {
+    word.contains('/')
+        || word.contains("sha256:")
+        || (word.len() >= 12 && word.chars().all(|c| c.is_ascii_hexdigit()))
+        || word.parse::<std::net::SocketAddr>().is_ok()
+}
+
+/// The message students see when their submission couldn't be graded, without the details of the
+/// grading machines
+fn student_error(e: &str) -> String {
+    let reason: Vec<&str> = e
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|word| if internal_detail(word) { "[…]" } else { word })
+        .collect();
+    let mut reason = reason.join(" ");
+    if let Some((end, _)) = reason.char_indices().nth(MAX_STUDENT_ERROR_CHARS) {
+        reason.truncate(end);
+        reason.push('…');
+    }
+
+    format!(
+        "Your submission could not be graded because of a problem with the grader ({reason}). You can submit it again."
+    )
 }
 
 async fn run_container(
+    container: ContainerEntry,
+    job_id: i32,
+) -> Result<SubmissionResponse, String> {
+    let task = match database::assignment::container_get_task_details(
+        container.task_id,
+        container.user_id,
+        container.sample,
+        &container.lang,
+    )
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(e),
+    };
+
+    run_with_details(container, &task, Some(job_id)).await
+}
+
+/// Builds the submission and runs it against the task's tests. The progress of `job_id` in the
+/// grading queue is recorded along the way, if there is one.
+async fn run_with_details(
+    container: ContainerEntry,
+    task: &TaskDetails,
+    job_id: Option<i32>,
+) -> Result<SubmissionResponse, String> {
+    let started = Instant::now();
+    let mut results = build_and_run(container, task, job_id, started).await?;
+    results.finish_timing(started.elapsed().as_secs_f64());
+    Ok(results)
+}
+
+/// [`run_with_details`], with the time the build took measured from `started`
+async fn build_and_run(
     ContainerEntry {
         zip_file,
         user_id,
         task_id,
         was_late,
         lang,
+        sample,
     }: ContainerEntry,
+    task: &TaskDetails,
+    job_id: Option<i32>,
+    started: Instant,
 ) -> Result<SubmissionResponse, String> {
     let Some(container) = get_container_for_language(&lang) else {
         error!("No container found for language: {}", lang);
@@ -122,100 +452,720 @@ async fn run_container(
         return Err("Language not supported".into());
     };
 
-    let workdir = format!("/tmp/securegrade/{}-{}", user_id, task_id);
+    // Sample runs skip the build when a warm container can run them
+    let warm = match sample && warm::fits(task) {
+        true => warm::take(&lang, &limits(task, None)).await,
+        false => None,
+    };
 
-    // Delete and recreate working directory
-    let _ = remove_dir_all(&workdir);
-    create_dir_all(&workdir).unwrap();
+    let image = match warm {
+        Some(warm) => warm.prepare(&zip_file).await.map(|()| Target::Warm(warm)),
+        None => {
+            let mode = if sample { "-sample" } else { "" };
+            let name = format!("{user_id}-{task_id}{mode}");
+            build_submission(&name, &container, &zip_file)
+                .await
+                .map(Target::Image)
+        }
+    };
 
-    copy(
-        container.join("Dockerfile"),
-        format!("{}/Dockerfile", workdir),
-    )
-    .unwrap();
-
-    std::fs::write(format!("{workdir}/submission.zip"), zip_file).unwrap();
-    Command::new("unzip")
-        .args([
-            &format!("{workdir}/submission.zip"),
-            "-d",
-            &format!("{workdir}/submission"),
-        ])
-        .spawn()
-        .unwrap()
-        .wait()
-        .unwrap();
-
-    let task = match database::assignment::container_get_task_details(task_id).await {
-        Ok(r) => r,
-        Err(e) => return Err(e),
+    // let mut test_results = ResponseObject::default();
+    let mut test_results =
+        SubmissionResponse::default().with_build_secs(started.elapsed().as_secs_f64());
+
+    let image = match image {
+        Ok(image) => image,
+        Err(BuildError::Compile {
+            compiler_output,
+            log,
+        }) => {
+            // Nothing can run, so every test fails with the compiler's message
+            for test in &task.tests {
+                test_results.compile_error(test_meta(test), text(&test.input), text(&test.output));
+            }
+            return Ok(test_results
+                .with_compiler_output(compiler_output)
+                .with_build_log(log));
+        }
+        Err(BuildError::Runtime(e)) => return Err(e),
     };
 
-    let image = ImageBuilder::new(&workdir).build().unwrap();
-    info!("Removing working directory {workdir}");
-    remove_dir_all(&workdir).unwrap();
+    if let Some(job_id) = job_id
+        && let Err(e) = database::grading_job::mark_running(job_id).await
+    {
+        warn!("Could not record the progress of grading job {job_id}: {e}");
+    }
 
-    // let mut test_results = ResponseObject::default();
-    let mut test_results = SubmissionResponse::default();
-
-    for Test {
-        test_name,
-        input,
-        output,
-        public,
-        timeout,
-    } in &task
+    // Students can follow the tests of graded submissions as they run
+    let mut reporter = (job_id.is_some() && !sample).then(|| {
+        progress::publish(user_id, task_id, GradingEvent::Running);
+        progress::Reporter::new(user_id, task_id)
+    });
+
+    // Warm containers only take stdio tasks
+    if task.test_method == TestMethod::Junit
+        && let Some(built) = image.image()
     {
-        let container_output = match image.exec(&input, *timeout).await {
-            Ok(Some(s)) => s,
-            Ok(None) => {
-                if *public {
-                    test_results.pub_time_out(test_name.clone(), input, output);
-                } else {
-                    test_results.time_out(test_name.clone());
+        let test_results = grade_report(built, task, was_late, test_results).await;
+        if let Some(reporter) = &mut reporter {
+            reporter.report(&test_results);
+        }
+        return Ok(lint(&image, task, test_results).await);
+    }
+
+    // Web services are started once and keep running across the task's tests
+    let server = match task.test_method {
+        TestMethod::Stdio | TestMethod::Junit => None,
+        TestMethod::Http(port) => {
+            // One server answers every test, so it gets the most generous limit among them
+            let memory_limit_mb = task
+                .tests
+                .iter()
+                .map(|t| t.memory_limit_mb)
+                .max_by_key(|limit| limit.unwrap_or(i32::MAX));
+
+            let limits = limits(task, memory_limit_mb.flatten());
+
+            match image.serve(port, &limits).await {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    for test in &task.tests {
+                        test_results.err(
+                            test_meta(test),
+                            text(&test.input),
+                            text(&test.output),
+                            &e,
+                        );
+                    }
+                    return Ok(test_results);
                 }
+            }
+        }
+    };
+
+    let mut failed = false;
+    // Positions of the tests whose program ran to completion
+    let mut ran = vec![];
+
+    for (index, test) in task.tests.iter().enumerate() {
+        if let Some(reporter) = &mut reporter {
+            reporter.report(&test_results);
+        }
+
+        let meta = test_meta(test);
+
+        if failed && task.stop_on_failure {
+            test_results.skip(meta, text(&test.input), text(&test.output));
+            continue;
+        }
+
+        let Test {
+            input,
+            output,
+            timeout,
+            comparison,
+            tolerance,
+            partial_credit,
+            ..
+        } = test;
+
+        // Text views of the test's data, for HTTP requests and the student-facing results
+        let (input_text, output_text) = (text(input), text(output));
+
+        if test.interactive && server.is_none() {
+            match image.interact(test, &test_limits(task, test)).await {
+                Ok(Dialog::Completed(transcript)) => {
+                    test_results.pass(meta, was_late, input_text.trim(), "", transcript.trim());
+                }
+                Ok(Dialog::Failed(transcript, expected)) => {
+                    test_results.fail(meta, input_text.trim(), expected, transcript.trim());
+                    failed = true;
+                }
+                Ok(Dialog::TimedOut) => {
+                    test_results.time_out(meta, input_text, "");
+                    failed = true;
+                }
+                Ok(Dialog::OutOfMemory) => {
+                    test_results.out_of_memory(meta, input_text, "");
+                    failed = true;
+                }
+                Ok(Dialog::OutputLimitExceeded) => {
+                    test_results.output_limit_exceeded(meta, input_text, "");
+                    failed = true;
+                }
+                Err(e) => {
+                    test_results.err(meta, input_text, "", e);
+                    failed = true;
+                }
+            }
+            continue;
+        }
+
+        let result = match &server {
+            None => image.exec(test, &test_limits(task, test)).await,
+            Some(server) => match server.request(&input_text, &output_text, *timeout).await {
+                Ok(Some(response)) => Ok(RunOutcome::Output(response.into_bytes())),
+                Ok(None) => Ok(RunOutcome::TimedOut),
+                Err(e) => match server.out_of_memory().await {
+                    true => Ok(RunOutcome::OutOfMemory),
+                    false => Err(e),
+                },
+            },
+        };
+
+        let container_output = match result {
+            Ok(RunOutcome::Output(s)) => s,
+            Ok(RunOutcome::TimedOut) => {
+                test_results.time_out(meta, input_text, output_text);
+                failed = true;
+                continue;
+            }
+            Ok(RunOutcome::OutOfMemory) => {
+                test_results.out_of_memory(meta, input_text, output_text);
+                failed = true;
+                continue;
+            }
+            Ok(RunOutcome::OutputLimitExceeded) => {
+                test_results.output_limit_exceeded(meta, input_text, output_text);
+                failed = true;
                 continue;
             }
             Err(e) => {
-                if *public {
-                    test_results.pub_err(test_name.clone(), input, output, e);
-                } else {
-                    test_results.err(test_name.clone());
-                }
+                test_results.err(meta, input_text, output_text, e);
+                failed = true;
                 continue;
             }
         };
+        let found_text = text(&container_output);
+        ran.push(index);
+
+        let passed = match &server {
+            None => comparison.matches_bytes(output, &container_output, tolerance),
+            Some(_) => http::response_matches(&output_text, &found_text, comparison, tolerance),
+        };
 
-        if container_output.trim() == output.trim() {
-            if *public {
-                test_results.pub_pass(
-                    test_name.clone(),
-                    was_late,
-                    input.trim(),
-                    output.trim(),
-                    container_output.trim(),
-                );
-            } else {
-                test_results.pass(test_name.clone(), was_late);
-            }
-        } else if *public {
-            test_results.pub_fail(
-                test_name.clone(),
-                input.trim(),
-                output.trim(),
-                container_output.trim(),
+        if passed {
+            test_results.pass(
+                meta,
+                was_late,
+                input_text.trim(),
+                output_text.trim(),
+                found_text.trim(),
+            );
+        } else if *partial_credit
+            && let credit = comparison::line_similarity(&output_text, &found_text)
+            && credit > 0.0
+        {
+            test_results.partial(
+                meta,
+                credit,
+                input_text.trim(),
+                output_text.trim(),
+                found_text.trim(),
             );
+            failed = true;
         } else {
-            test_results.fail(test_name.clone());
+            test_results.fail(meta, input_text.trim(), output_text.trim(), found_text.trim());
+            failed = true;
+        }
+    }
+
+    if let Some(reporter) = &mut reporter {
+        reporter.report(&test_results);
+    }
+
+    // Extra pass under the memory checker, for languages whose container has one
+    if task.memory_check
+        && server.is_none()
+        && let Some(built) = image.image()
+        && built.supports_memcheck().await
+    {
+        for index in ran {
+            let test = &task.tests[index];
+            match built.memcheck(test, &test_limits(task, test)).await {
+                Ok(Some(report)) => test_results.memory_errors(index, report),
+                Ok(None) => (),
+                Err(e) => warn!("Could not run the memory checker for task {task_id}: {e}"),
+            }
+        }
+
+        if let Some(penalty) = task.memory_error_penalty
+            && test_results.has_memory_errors()
+        {
+            test_results.deduct_memory_penalty(penalty);
         }
     }
 
     // Store test_results in database
-    Ok(test_results)
+    Ok(lint(&image, task, test_results).await)
+}
+
+/// Adds the style check to the results, for tasks that weigh it and languages with a linter
+async fn lint(
+    image: &Target,
+    task: &TaskDetails,
+    test_results: SubmissionResponse,
+) -> SubmissionResponse {
+    let (Some(weight), Some(image)) = (task.lint_weight, image.image()) else {
+        return test_results;
+    };
+
+    match image.lint(&limits(task, None)).await {
+        Ok(Some(findings)) => test_results.with_lint(weight, findings),
+        Ok(None) => test_results,
+        Err(e) => {
+            warn!("Could not lint submission: {e}");
+            test_results
+        }
+    }
+}
+
+/// Grades a `junit` task. The test command runs once with every test's fixtures mounted, and each
+/// test is graded by the test case of the same name in the report it writes.
+async fn grade_report(
+    image: &Image,
+    task: &TaskDetails,
+    was_late: bool,
+    mut test_results: SubmissionResponse,
+) -> SubmissionResponse {
+    let fixtures: Vec<_> = task
+        .tests
+        .iter()
+        .flat_map(|t| t.fixtures.iter().cloned())
+        .collect();
+
+    // A single run covers every test, so it gets the most generous limits among them
+    let timeout = task
+        .tests
+        .iter()
+        .map(|t| t.timeout)
+        .max_by_key(|timeout| timeout.unwrap_or(std::time::Duration::MAX));
+    let memory_limit_mb = task
+        .tests
+        .iter()
+        .map(|t| t.memory_limit_mb)
+        .max_by_key(|limit| limit.unwrap_or(i32::MAX));
+
+    let limits = limits(task, memory_limit_mb.flatten());
+
+    let command = task.test_command.as_deref().unwrap_or("");
+    let report = match image
+        .report(command, &fixtures, timeout.flatten(), &limits)
+        .await
+    {
+        Ok(RunOutcome::Output(report)) => junit::parse(&text(&report)),
+        Ok(RunOutcome::TimedOut) => {
+            for test in &task.tests {
+                test_results.time_out(test_meta(test), "", "");
+            }
+            return test_results;
+        }
+        Ok(RunOutcome::OutOfMemory) => {
+            for test in &task.tests {
+                test_results.out_of_memory(test_meta(test), "", "");
+            }
+            return test_results;
+        }
+        Ok(RunOutcome::OutputLimitExceeded) => {
+            for test in &task.tests {
+                test_results.output_limit_exceeded(test_meta(test), "", "");
+            }
+            return test_results;
+        }
+        Err(e) => Err(e),
+    };
+
+    let cases = match report {
+        Ok(cases) => cases,
+        Err(e) => {
+            for test in &task.tests {
+                test_results.err(test_meta(test), "", "", &e);
+            }
+            return test_results;
+        }
+    };
+
+    let mut failed = false;
+
+    for test in &task.tests {
+        let meta = test_meta(test);
+
+        if failed && task.stop_on_failure {
+            test_results.skip(meta, "", "");
+            continue;
+        }
+
+        let name = test.test_name.as_deref().unwrap_or("");
+        let Some(case) = cases.iter().find(|c| c.is_named(name)) else {
+            test_results.err(meta, "", "", format!("No unit test named \"{name}\" ran."));
+            failed = true;
+            continue;
+        };
+
+        match &case.outcome {
+            junit::CaseOutcome::Passed => test_results.pass(meta, was_late, "", "", ""),
+            junit::CaseOutcome::Failed(message) => {
+                test_results.fail(meta, "", "", message);
+                failed = true;
+            }
+            junit::CaseOutcome::Errored(message) => {
+                test_results.err(meta, "", "", message);
+                failed = true;
+            }
+            junit::CaseOutcome::Skipped => {
+                test_results.skip(meta, "", "");
+                failed = true;
+            }
+        }
+    }
+
+    test_results
+}
+
+/// What a submission's tests run in: its own image, or a warm container it was copied into
+enum Target {
+    Image(Image),
+    Warm(WarmContainer),
+}
+
+impl Target {
+    /// The built image. `None` => the submission runs in a warm container.
+    fn image(&self) -> Option<&Image> {
+        match self {
+            Target::Image(image) => Some(image),
+            Target::Warm(_) => None,
+        }
+    }
+
+    async fn exec(&self, test: &Test, limits: &ResourceLimits) -> Result<RunOutcome, String> {
+        match self {
+            Target::Image(image) => image.exec(test, limits).await,
+            Target::Warm(warm) => warm.exec(test, limits).await,
+        }
+    }
+
+    async fn interact(&self, test: &Test, limits: &ResourceLimits) -> Result<Dialog, String> {
+        match self {
+            Target::Image(image) => image.interact(test, limits).await,
+            Target::Warm(warm) => warm.interact(test, limits).await,
+        }
+    }
+
+    async fn serve(&self, port: u16, limits: &ResourceLimits) -> Result<http::Server, String> {
+        match self {
+            Target::Image(image) => image.serve(port, limits).await,
+            Target::Warm(_) => Err("Web services can't run in a warm container".into()),
+        }
+    }
+}
+
+/// What each run of a stdio test may use
+fn test_limits(task: &TaskDetails, test: &Test) -> ResourceLimits {
+    limits(task, test.memory_limit_mb)
+}
+
+/// What a run of the task's submission may use. Memory the test doesn't limit gets the task's
+/// default.
+fn limits(task: &TaskDetails, memory_limit_mb: Option<i32>) -> ResourceLimits {
+    let config = config::get();
+    ResourceLimits {
+        memory_limit_mb: memory_limit_mb.or(task.default_memory_limit_mb),
+        cpus: task.cpus,
+        cpu_shares: config.cpu_shares,
+        pids_limit: task.pids_limit,
+        disk_limit_mb: task.disk_limit_mb,
+        network_access: task.network_access,
+        isolation: task.isolation,
+    }
+}
+
+/// Unpacks a submission next to its language's Dockerfile in a new working directory named after
+/// `name` and builds it. The working directory is removed once the image is built.
+async fn build_submission(
+    name: &str,
+    container: &std::path::Path,
+    zip_file: &[u8],
+) -> Result<Image, BuildError> {
+    let lang = container.file_name().unwrap_or_default().to_string_lossy();
+    let manifest = manifest::get(&lang);
+    let dockerfile = std::fs::read(container.join("Dockerfile"))
+        .map_err(|e| BuildError::Runtime(format!("{e}")))?;
+
+    // Identical code built by the same Dockerfile gives the same image, e.g. when resubmitting or
+    // regrading
+    let digest = Sha256::new()
+        .chain_update(&dockerfile)
+        .chain_update(zip_file)
+        .finalize();
+    let tag: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+
+    let workdir = Workdir::new(name);
+    let builder = ImageBuilder::new(workdir.path())
+        .sandbox(manifest.sandbox)
+        .tag(format!("securegrade-submission:{tag}"));
+    if let Some(image) = builder.cached().await {
+        return Ok(image);
+    }
+
+    workdir.create().await?;
+    workdir.write("Dockerfile", &dockerfile)?;
+    workdir.unpack(zip_file, "submission").await?;
+
+    builder
+        .base_image(base::ensure(container).await)
+        .build()
+        .await
+}
+
+/// What running the reference solution against one test produced
+#[derive(Serialize)]
+pub struct ReferenceOutput {
+    test_id: i32,
+    test_name: Option<String>,
+    /// `GENERATED`, `TIMED OUT`, `OUT OF MEMORY`, `OUTPUT LIMIT EXCEEDED` or `ERR`
+    status: &'static str,
+    /// What went wrong, for `ERR`
+    error: Option<String>,
+    #[serde(skip)]
+    output: Option<Vec<u8>>,
+}
+
+impl ReferenceOutput {
+    /// `(test id, output)`, if an output was generated
+    pub fn generated(&self) -> Option<(i32, Vec<u8>)> {
+        self.output.clone().map(|output| (self.test_id, output))
+    }
+}
+
+/// Runs a task's reference solution against each of its tests to produce their expected outputs.
+/// Only stdio tasks are supported.
+///
+/// Err(e) => The solution couldn't be built (with message)
+pub async fn run_reference_solution(
+    task_id: i32,
+    solution: &database::assignment::ReferenceSolution,
+) -> Result<Vec<ReferenceOutput>, String> {
+    let Some(container) = get_container_for_language(&solution.lang) else {
+        return Err(format!("Language not supported: {}", solution.lang));
+    };
+
+    let name = format!("reference-{task_id}");
+    let image = match build_submission(&name, &container, &solution.zip_file).await {
+        Ok(image) => image,
+        Err(BuildError::Compile {
+            compiler_output, ..
+        }) => {
+            return Err(format!(
+                "The reference solution did not compile:\n{compiler_output}"
+            ));
+        }
+        Err(BuildError::Runtime(e)) => return Err(e),
+    };
+
+    let task = &solution.details;
+    let mut outputs = vec![];
+
+    // Interactive tests have no expected output to generate
+    for (test, &test_id) in task
+        .tests
+        .iter()
+        .zip(&solution.test_ids)
+        .filter(|(t, _)| !t.interactive)
+    {
+        let (status, error, output) = match image.exec(test, &test_limits(task, test)).await {
+            Ok(RunOutcome::Output(output)) => ("GENERATED", None, Some(output)),
+            Ok(RunOutcome::TimedOut) => ("TIMED OUT", None, None),
+            Ok(RunOutcome::OutOfMemory) => ("OUT OF MEMORY", None, None),
+            Ok(RunOutcome::OutputLimitExceeded) => ("OUTPUT LIMIT EXCEEDED", None, None),
+            Err(e) => ("ERR", Some(e), None),
+        };
+
+        outputs.push(ReferenceOutput {
+            test_id,
+            test_name: test.test_name.clone(),
+            status,
+            error,
+            output,
+        });
+    }
+
+    Ok(outputs)
+}
+
+/// Grades a submission against every test of the task right away, outside the grading queue, and
+/// returns its results without recording them anywhere. Lets instructors try their own solutions.
+pub async fn test_run(
+    zip_file: axum::body::Bytes,
+    user_id: i32,
+    task_id: i32,
+    lang: &str,
+) -> Result<SubmissionResponse, String> {
+    let task =
+        database::assignment::container_get_task_details(task_id, user_id, false, lang).await?;
+    let entry = ContainerEntry::new(zip_file, user_id, task_id, false, lang);
+
+    run_with_details(entry, &task, None).await
+}
+
+/// Test data as text, with any bytes that aren't UTF-8 replaced
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+fn test_meta(test: &Test) -> TestMeta {
+    TestMeta {
+        test_name: test.test_name.clone(),
+        public: test.public,
+        points: test.points,
+        hint: test.hint.clone(),
+        env: test
+            .env
+            .iter()
+            .map(|var| {
+                let value = if var.secret { HIDDEN } else { &var.value };
+                (var.name.clone(), value.to_string())
+            })
+            .collect(),
+        secrets: test
+            .env
+            .iter()
+            .filter(|var| var.secret)
+            .map(|var| var.value.clone())
+            .collect(),
+    }
+}
+
+/// Builds the base image of every language whose Dockerfile has a base stage
+pub async fn build_base_images() {
+    base::build_all().await;
+}
+
+/// Languages new submissions may use
+pub fn supported_languages() -> std::io::Result<Vec<String>> {
+    Ok(all_languages()?
+        .into_iter()
+        .filter(|lang| !manifest::get(lang).disabled)
+        .collect())
+}
+
+/// Lists the languages with a container definition under `dockerfiles`, disabled ones included
+pub fn all_languages() -> std::io::Result<Vec<String>> {
+    Ok(read_dir("dockerfiles")?
+        .filter_map(|f| f.ok())
+        .filter_map(|f| f.file_name().into_string().ok())
+        .collect())
+}
+
+/// Every supported language, described by its manifest
+pub fn languages() -> std::io::Result<Vec<LanguageInfo>> {
+    let mut languages: Vec<LanguageInfo> = supported_languages()?
+        .into_iter()
+        .map(language_info)
+        .collect();
+
+    languages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(languages)
+}
+
+/// Describes `lang` by its manifest
+pub fn language_info(lang: String) -> LanguageInfo {
+    let config = config::get();
+    let manifest = manifest::get(&lang);
+    let limits = manifest.limits;
+    LanguageInfo {
+        name: manifest.name.unwrap_or_else(|| lang.clone()),
+        version: manifest.version,
+        compile: manifest.compile,
+        run: manifest.run,
+        source_file: manifest.source_file,
+        default_limits: DefaultLimits {
+            cpus: limits.cpus.unwrap_or(config.default_cpus),
+            pids_limit: limits.pids_limit.unwrap_or(config.default_pids_limit),
+            memory_limit_mb: limits.memory_limit_mb.or(config.default_memory_limit_mb),
+            disk_limit_mb: limits.disk_limit_mb.unwrap_or(config.default_disk_limit_mb),
+        },
+        lang,
+    }
+}
+
+/// Whether new submissions may use `lang`
+pub fn language_enabled(lang: &str) -> bool {
+    get_container_for_language(lang).is_some() && !manifest::get(lang).disabled
+}
+
+/// Whether `lang` can name a language's directory
+pub fn valid_language_name(lang: &str) -> bool {
+    !lang.is_empty()
+        && lang.len() <= 32
+        && lang
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '+'))
+}
+
+/// Adds a language, or replaces the container definition of an existing one, then builds its base
+/// image in the background. `lang` has to be a [valid name](valid_language_name).
+pub fn install_language(lang: &str, dockerfile: &str, manifest: &str) -> Result<(), String> {
+    Manifest::parse(manifest).map_err(|e| format!("Invalid {}: {e}", manifest::MANIFEST_FILE))?;
+
+    let container = std::path::Path::new("dockerfiles").join(lang);
+    create_dir_all(&container).map_err(|e| format!("{e}"))?;
+    std::fs::write(container.join("Dockerfile"), dockerfile).map_err(|e| format!("{e}"))?;
+    std::fs::write(container.join(manifest::MANIFEST_FILE), manifest)
+        .map_err(|e| format!("{e}"))?;
+    load_manifests()?;
+
+    tokio::spawn(async move {
+        base::ensure(&container).await;
+    });
+
+    Ok(())
+}
+
+/// Stops or resumes new submissions in `lang`. `Ok(false)` => there is no such language.
+pub fn set_language_disabled(lang: &str, disabled: bool) -> Result<bool, String> {
+    let Some(container) = get_container_for_language(lang) else {
+        return Ok(false);
+    };
+
+    manifest::set_disabled(&container, disabled)?;
+    load_manifests()?;
+    Ok(true)
+}
+
+/// The defaults `lang`'s manifest sets for tasks without limits of their own
+pub fn language_limits(lang: &str) -> LanguageLimits {
+    manifest::get(lang).limits
+}
+
+/// Where a file written in the editor goes in a submission in `lang`. `None` => the language can't
+/// be submitted from the editor.
+pub fn source_file(lang: &str) -> Option<String> {
+    manifest::get(lang).source_file
+}
+
+/// Re-reads every language's manifest
+pub fn load_manifests() -> Result<(), String> {
+    manifest::load_all(std::path::Path::new("dockerfiles"))
+}
+
+/// Fingerprints the toolchain of a language, so grades produced by different versions of it can
+/// be told apart. That's the id of the language's base image, or its Dockerfile when it has no
+/// base stage. `None` => the base image hasn't been built yet.
+pub fn toolchain_version(lang: impl AsRef<str>) -> Option<String> {
+    let lang = lang.as_ref();
+    let dockerfile =
+        std::fs::read_to_string(get_container_for_language(lang)?.join("Dockerfile")).ok()?;
+    let digest = if base::has_base_stage(&dockerfile) {
+        Sha256::digest(base::image_id(lang)?)
+    } else {
+        Sha256::digest(dockerfile)
+    };
+
+    Some(digest[..6].iter().map(|b| format!("{b:02x}")).collect())
 }
 
 fn get_container_for_language(lang: impl AsRef<str>) -> Option<PathBuf> {
-    let containers = read_dir("dockerfiles").unwrap();
+    let containers = read_dir("dockerfiles").ok()?;
     for container_dir in containers.filter_map(|f| f.ok()) {
         if container_dir.file_name() == lang.as_ref() {
             return Some(container_dir.path());
@@ -0,0 +1,184 @@
+//! Base images: each language's toolchain, built once and shared by every submission
+//!
+//! A language's Dockerfile opts in by naming the stage that doesn't depend on the submission
+//! `base`:
+//!
+//! ```dockerfile
+//! FROM python:3.13-alpine AS base
+//! RUN pip install --no-cache-dir pytest flake8
+//! WORKDIR /app
+//!
+//! FROM base
+//! COPY ./submission /app
+//! ```
+//!
+//! That stage is built and tagged at start-up, and again whenever the Dockerfile changes.
+//! Submissions are then built on top of the tagged image, so only their own layers are built.
+//! Dockerfiles without a `base` stage are built in full every time.
+//!
+//! The id of each language's base image is recorded as it's built or found, and is what
+//! [`toolchain_version`](super::toolchain_version) fingerprints: a rebuilt image whose layers
+//! changed gets a new id even when its Dockerfile didn't.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{LazyLock, RwLock},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use tracing::{error, info};
+
+use super::{all_languages, get_container_for_language, image::image_exists, runtime::runtime};
+
+/// Name of the stage a Dockerfile's base image is built from
+pub const BASE_STAGE: &str = "base";
+
+/// Held while a base image is built, so concurrent submissions don't build the same one twice
+static BUILDING: Mutex<()> = Mutex::const_new(());
+
+/// Id of the base image [`ensure`] last built or found, by language
+static IMAGE_IDS: LazyLock<RwLock<HashMap<String, String>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Builds the base image of every language that has one, disabled ones included for regrades
+pub async fn build_all() {
+    let languages = match all_languages() {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Could not list languages: {e}");
+            return;
+        }
+    };
+
+    for lang in languages {
+        if let Some(container) = get_container_for_language(&lang) {
+            ensure(&container).await;
+        }
+    }
+}
+
+/// Returns the tag of the base image of the language in `container`, building it first if this
+/// version of the Dockerfile hasn't been built yet. `None` => the Dockerfile has no base stage, or
+/// it failed to build and submissions have to build it themselves.
+pub async fn ensure(container: &Path) -> Option<String> {
+    let lang = container.file_name()?.to_str()?;
+    let dockerfile = std::fs::read_to_string(container.join("Dockerfile")).ok()?;
+    if !has_base_stage(&dockerfile) {
+        return None;
+    }
+
+    let digest = Sha256::digest(&dockerfile);
+    let version: String = digest[..6].iter().map(|b| format!("{b:02x}")).collect();
+    let tag = format!("securegrade-base/{lang}:{version}");
+    let _building = BUILDING.lock().await;
+
+    if image_exists(&tag).await {
+        record_image_id(lang, &tag).await;
+        return Some(tag);
+    }
+
+    info!("Building base image {tag}");
+    let runtime = runtime();
+    let build = runtime
+        .command()
+        .args(runtime.build_args())
+        .args(["--target", BASE_STAGE, "-t", &tag])
+        .arg(container)
+        .output()
+        .await;
+
+    match build {
+        Ok(output) if output.status.success() => {
+            record_image_id(lang, &tag).await;
+            Some(tag)
+        }
+        Ok(output) => {
+            error!(
+                "Could not build base image {tag}: {}",
+                runtime.build_log(&output).trim()
+            );
+            None
+        }
+        Err(e) => {
+            error!("Could not run {}: {e}", runtime.program());
+            None
+        }
+    }
+}
+
+/// Looks up the id of the image tagged `tag` and records it as `lang`'s base image
+async fn record_image_id(lang: &str, tag: &str) {
+    let inspect = runtime()
+        .command()
+        .args(["image", "inspect", "--format", "{{.Id}}", tag])
+        .output()
+        .await;
+
+    match inspect {
+        Ok(output) if output.status.success() => {
+            let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            IMAGE_IDS.write().unwrap().insert(lang.to_string(), id);
+        }
+        Ok(output) => error!(
+            "Could not inspect base image {tag}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => error!("Could not run {}: {e}", runtime().program()),
+    }
+}
+
+/// Id of `lang`'s base image, once [`ensure`] has built or found it
+pub fn image_id(lang: &str) -> Option<String> {
+    IMAGE_IDS.read().unwrap().get(lang).cloned()
+}
+
+/// Whether the Dockerfile has a stage named [`BASE_STAGE`]
+pub fn has_base_stage(dockerfile: &str) -> bool {
+    dockerfile
+        .lines()
+        .any(|line| starts_base_stage(&line.split_whitespace().collect::<Vec<&str>>()))
+}
+
+/// Whether `instruction`, as a build reports it, belongs to the Dockerfile's [`BASE_STAGE`]. Lines
+/// continued with `\` are joined, and whitespace is compared loosely.
+pub fn in_base_stage(dockerfile: &str, instruction: &str) -> bool {
+    let wanted: Vec<&str> = instruction.split_whitespace().collect();
+    let mut in_base = false;
+    let mut current = String::new();
+
+    for line in dockerfile.lines() {
+        if let Some(continued) = line.trim_end().strip_suffix('\\') {
+            current.push_str(continued);
+            current.push(' ');
+            continue;
+        }
+        current.push_str(line);
+
+        let words: Vec<&str> = current.split_whitespace().collect();
+        if words
+            .first()
+            .is_some_and(|w| w.eq_ignore_ascii_case("FROM"))
+        {
+            in_base = starts_base_stage(&words);
+        } else if words == wanted {
+            return in_base;
+        }
+        current.clear();
+    }
+
+    false
+}
+
+/// Whether the words of a line are `FROM <image> AS base`
+fn starts_base_stage(words: &[&str]) -> bool {
+    matches!(
+        words,
+        [from, _, as_, name]
+            if from.eq_ignore_ascii_case("FROM")
+                && as_.eq_ignore_ascii_case("AS")
+                && *name == BASE_STAGE
+    )
+}
@@ -0,0 +1,354 @@
+//! Runs tasks whose submissions are web services
+//!
+//! A test's input is the request and its output is the expected response, both written like a
+//! stripped-down HTTP message:
+//!
+//! ```text
+//! POST /todos
+//! Content-Type: application/json
+//!
+//! {"title": "write tests"}
+//! ```
+//!
+//! ```text
+//! 201
+//! Content-Type: application/json
+//!
+//! {"id": 1, "title": "write tests"}
+//! ```
+//!
+//! The request starts with the method and path; the response with the status code. Headers follow
+//! until a blank line, and the body is everything after it. Only the headers listed in the expected
+//! response are checked, and the body is compared using the test's comparison mode. An expected
+//! response without a blank line doesn't check the body at all.
+
+use std::process::Stdio;
+
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::{
+    image::ResourceLimits,
+    runtime::{RUN_LABEL, run_command, runtime},
+};
+use crate::model::comparison::{ComparisonMode, Tolerance};
+
+/// How long a server has to start accepting requests
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A submission running as a server in the background. The container is removed when dropped.
+///
+/// Unless the task allows the network, the server is put on a network of its own created with
+/// `--internal`: its port can still be published to the host, but it can't reach anything else.
+/// The network is removed along with the container.
+pub struct Server {
+    container_id: String,
+    /// The server's own internal network, if it has one
+    network: Option<String>,
+    /// `host:port` the container's port is published on
+    address: String,
+    client: reqwest::Client,
+}
+
+struct Message<'a> {
+    /// `METHOD /path` for requests, the status code for responses
+    start: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+    /// `None` if there was no blank line after the headers
+    body: Option<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(text: &'a str) -> Message<'a> {
+        let text = text.trim_start();
+        let (head, body) = match text
+            .split_once("\n\n")
+            .or_else(|| text.split_once("\r\n\r\n"))
+        {
+            Some((head, body)) => (head, Some(body)),
+            None => (text, None),
+        };
+
+        let mut lines = head.lines();
+        let start = lines.next().unwrap_or("").trim();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+
+        Message {
+            start,
+            headers,
+            body,
+        }
+    }
+
+    /// The status code, ignoring any reason phrase after it
+    fn status(&self) -> &str {
+        self.start.split_whitespace().next().unwrap_or("")
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+}
+
+impl Server {
+    /// Starts the image in the background and waits for it to answer on `port`
+    pub async fn start(
+        image_id: &str,
+        port: u16,
+        limits: &ResourceLimits,
+        sandbox_args: &[String],
+    ) -> Result<Server, String> {
+        // Redirects are compared as they are. Following them would let the server have the grading
+        // host fetch any address it likes and show the student the response.
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| format!("{e}"))?;
+
+        let network = match limits.network_access {
+            true => None,
+            false => Some(create_network().await?),
+        };
+
+        // `--network=none` would leave nothing to publish the port on
+        let mut run_args = ResourceLimits {
+            network_access: true,
+            ..*limits
+        }
+        .args();
+        if let Some(network) = &network {
+            run_args.push(format!("--network={network}"));
+        }
+
+        let run = run_command(limits.isolation)
+            .args(["-d", "-p", &format!("127.0.0.1::{port}")])
+            .args(run_args)
+            .args(sandbox_args)
+            .arg(image_id)
+            .output()
+            .await;
+
+        // Created before anything else can fail, so the container and network are always
+        // cleaned up
+        let mut server = Server {
+            container_id: String::new(),
+            network,
+            address: String::new(),
+            client,
+        };
+
+        let run = run.map_err(|e| format!("{e}"))?;
+        if !run.status.success() {
+            return Err(String::from_utf8_lossy(&run.stderr).trim().to_string());
+        }
+        server.container_id = String::from_utf8_lossy(&run.stdout).trim().to_string();
+
+        let mapping = runtime()
+            .command()
+            .args(["port", &server.container_id, &format!("{port}/tcp")])
+            .output()
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        server.address = match String::from_utf8_lossy(&mapping.stdout).lines().next() {
+            Some(address) if mapping.status.success() => address.trim().to_string(),
+            _ => return Err(format!("Port {port} is not published by the container")),
+        };
+
+        let started = Instant::now();
+        loop {
+            if server
+                .client
+                .get(format!("http://{}/", server.address))
+                .timeout(Duration::from_secs(1))
+                .send()
+                .await
+                .is_ok()
+            {
+                info!(
+                    "Server {} listening on {}",
+                    server.container_id, server.address
+                );
+                return Ok(server);
+            }
+
+            if started.elapsed() > STARTUP_TIMEOUT {
+                return Err(format!(
+                    "The server did not start listening on port {port} within {} seconds",
+                    STARTUP_TIMEOUT.as_secs()
+                ));
+            }
+
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Sends the request described by `input` and renders the response in the same format as
+    /// `expected`, keeping only the headers `expected` mentions
+    ///
+    /// Ok(Some(response)) => Got a response \
+    /// Ok(None) => Timed Out \
+    /// Err(e) => Error (with message)
+    pub async fn request(
+        &self,
+        input: &str,
+        expected: &str,
+        duration: Option<Duration>,
+    ) -> Result<Option<String>, String> {
+        let request = Message::parse(input);
+        let (method, path) = request
+            .start
+            .split_once(' ')
+            .unwrap_or((request.start, "/"));
+
+        let method = reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|_| format!("Invalid request method: {method}"))?;
+        let path = path.trim();
+        let path = path.strip_prefix('/').unwrap_or(path);
+
+        let mut builder = self
+            .client
+            .request(method, format!("http://{}/{path}", self.address))
+            .body(request.body.unwrap_or("").to_string());
+
+        for (name, value) in &request.headers {
+            builder = builder.header(*name, *value);
+        }
+
+        if let Some(duration) = duration {
+            builder = builder.timeout(duration);
+        }
+
+        let response = match builder.send().await {
+            Ok(r) => r,
+            Err(e) if e.is_timeout() => {
+                warn!("Request to server {} timed out", self.container_id);
+                return Ok(None);
+            }
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let status = response.status().as_u16();
+        let expected = Message::parse(expected);
+        let headers = expected
+            .headers
+            .iter()
+            .filter_map(|(name, _)| {
+                let value = response.headers().get(*name)?.to_str().ok()?;
+                Some(format!("{name}: {value}\n"))
+            })
+            .collect::<String>();
+
+        // The timeout also covers reading the body
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) if e.is_timeout() => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        Ok(Some(format!("{status}\n{headers}\n{body}")))
+    }
+}
+
+impl Server {
+    /// Whether the server was killed for going over its memory limit
+    pub async fn out_of_memory(&self) -> bool {
+        runtime()
+            .command()
+            .args(["inspect", "-f", "{{.State.OOMKilled}}", &self.container_id])
+            .output()
+            .await
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).trim() == "true")
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        info!("Stopping server {}", self.container_id);
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            warn!("Server {} left for the janitor", self.container_id);
+            return;
+        };
+        let container_id = std::mem::take(&mut self.container_id);
+        let network = self.network.take();
+
+        // Drop can't wait, so the container is removed in the background. The network can only go
+        // once nothing is attached to it.
+        let removal = async move {
+            if !container_id.is_empty() {
+                let _ = runtime()
+                    .command()
+                    .args(["rm", "-f", "-v", &container_id])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .await;
+            }
+            if let Some(network) = network {
+                let _ = runtime()
+                    .command()
+                    .args(["network", "rm", &network])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .await;
+            }
+        };
+        handle.spawn(removal);
+    }
+}
+
+/// Creates a network that containers on it can't leave, returning its name. It carries the run
+/// label, so the janitor removes it if its server is never cleaned up.
+async fn create_network() -> Result<String, String> {
+    let name = format!(
+        "securegrade-{}",
+        rand::random::<[u8; 8]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+
+    let create = runtime()
+        .command()
+        .args(["network", "create", "--internal", "--label", RUN_LABEL])
+        .arg(&name)
+        .output()
+        .await
+        .map_err(|e| format!("{e}"))?;
+
+    if !create.status.success() {
+        return Err(format!(
+            "Could not create the server's network: {}",
+            String::from_utf8_lossy(&create.stderr).trim()
+        ));
+    }
+
+    Ok(name)
+}
+
+/// Whether a response rendered by [`Server::request`] matches the expected one
+pub fn response_matches(
+    expected: &str,
+    found: &str,
+    comparison: &ComparisonMode,
+    tolerance: &Tolerance,
+) -> bool {
+    let expected = Message::parse(expected);
+    let found = Message::parse(found);
+
+    expected.status() == found.status()
+        && expected
+            .headers
+            .iter()
+            .all(|(name, value)| found.header(name) == Some(*value))
+        && expected
+            .body
+            .is_none_or(|body| comparison.matches(body, found.body.unwrap_or(""), tolerance))
+}
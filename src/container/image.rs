@@ -1,124 +1,1020 @@
 use std::{
-    io::Write,
-    process::{Command, Stdio},
+    fs::{create_dir_all, remove_dir_all},
+    path::PathBuf,
+    process::{Output, Stdio},
 };
 
-use tokio::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Child,
+    time::{Duration, Instant},
+};
 use tracing::{error, info, warn};
 
+use super::{
+    base::{BASE_STAGE, in_base_stage},
+    http::Server,
+    junit,
+    manifest::Sandbox,
+    runtime::{FailedStep, Isolation, run_command, runtime},
+};
+use crate::{
+    config,
+    database::assignment::{Test, TestFixture},
+    model::interactive::{self, Step},
+};
+
 pub struct ImageBuilder {
     directory: String,
+    sandbox: Sandbox,
+    /// Tag of the language's prebuilt base image
+    base_image: Option<String>,
+    /// Tag the built image is given, and looked up by to skip the build
+    tag: Option<String>,
 }
 
+/// Label of every image built for a submission. Images are kept after grading so resubmissions of
+/// the same code reuse them, and the janitor prunes them once they're old.
+pub const SUBMISSION_LABEL: &str = "securegrade.submission";
+
+/// Where each run's scratch directory is made
+pub const RUNS_ROOT: &str = "/tmp/securegrade/runs";
+
 #[derive(Clone)]
 pub struct Image {
     image_id: String,
+    sandbox: Sandbox,
+}
+
+/// What happened when the program was run against a test
+pub enum RunOutcome {
+    /// The program exited and printed this, as raw bytes
+    Output(Vec<u8>),
+    TimedOut,
+    /// The program was killed for going over its memory limit
+    OutOfMemory,
+    /// The program was stopped for printing more than `output_limit_bytes`
+    OutputLimitExceeded,
+}
+
+/// Why a run was cut short before its program exited
+pub enum Stopped {
+    TimedOut,
+    OutputLimitExceeded,
+}
+
+impl From<Stopped> for RunOutcome {
+    fn from(stopped: Stopped) -> Self {
+        match stopped {
+            Stopped::TimedOut => RunOutcome::TimedOut,
+            Stopped::OutputLimitExceeded => RunOutcome::OutputLimitExceeded,
+        }
+    }
+}
+
+/// How an interactive test went
+pub enum Dialog {
+    /// Every expected reply arrived. Holds the transcript.
+    Completed(String),
+    /// A reply never arrived. Holds the transcript so far and what was expected.
+    Failed(String, String),
+    TimedOut,
+    OutOfMemory,
+    OutputLimitExceeded,
+}
+
+/// Exit status of a container whose process was killed with SIGKILL, which is how the kernel
+/// stops a process that runs out of memory
+pub const KILLED_EXIT_CODE: i32 = 137;
+
+/// Label a language's Dockerfile sets when its image has valgrind installed
+const MEMCHECK_LABEL: &str = "securegrade.memcheck";
+
+/// Exit status valgrind is told to use when it finds errors
+const MEMCHECK_EXIT_CODE: i32 = 86;
+
+/// Programs run many times slower under valgrind, so their timeouts are stretched by this much
+const MEMCHECK_SLOWDOWN: u32 = 10;
+
+/// Label a language's Dockerfile sets to the shell command that lints a submission
+const LINT_LABEL: &str = "securegrade.lint";
+
+/// How long the linter may take
+const LINT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What a single grading run may use
+pub struct ResourceLimits {
+    /// In megabytes. `None` => no limit.
+    pub memory_limit_mb: Option<i32>,
+    pub cpus: f32,
+    /// Weight against other containers when the host's CPUs are busy
+    pub cpu_shares: u32,
+    pub pids_limit: i32,
+    /// Size of the writable `/tmp`, and of the working directory of a read-only image, in
+    /// megabytes
+    pub disk_limit_mb: i32,
+    /// `false` => the container gets no network at all
+    pub network_access: bool,
+    pub isolation: Isolation,
+}
+
+impl ResourceLimits {
+    /// `docker run` arguments that enforce the limits. Memory gets no extra swap on top.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--cpus".into(),
+            self.cpus.to_string(),
+            "--cpu-shares".into(),
+            self.cpu_shares.to_string(),
+            "--pids-limit".into(),
+            self.pids_limit.to_string(),
+            "--tmpfs".into(),
+            format!("/tmp:size={}m", self.disk_limit_mb),
+        ];
+
+        if !self.network_access {
+            args.push("--network=none".into());
+        }
+
+        if let Some(mb) = self.memory_limit_mb {
+            args.extend([
+                "--memory".into(),
+                format!("{mb}m"),
+                "--memory-swap".into(),
+                format!("{mb}m"),
+            ]);
+        }
+
+        args
+    }
 }
 
 impl ImageBuilder {
     pub fn new(directory: impl Into<String>) -> ImageBuilder {
         Self {
             directory: directory.into(),
+            sandbox: Sandbox::default(),
+            base_image: None,
+            tag: None,
+        }
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> ImageBuilder {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// The image already built with this builder's tag, if there is one
+    pub async fn cached(&self) -> Option<Image> {
+        let tag = self.tag.as_ref()?;
+        if !image_exists(tag).await {
+            return None;
         }
+        info!("Reusing image {tag}");
+
+        Some(Image {
+            image_id: tag.clone(),
+            sandbox: self.sandbox.clone(),
+        })
     }
 
-    /// Build the docker container object
-    pub fn build(self) -> Result<Image, String> {
-        let container = Command::new("docker")
-            .args(["buildx", "build", "-q", &self.directory])
+    /// Builds the submission on top of the language's prebuilt base image instead of building
+    /// the Dockerfile's base stage again
+    pub fn base_image(mut self, tag: Option<String>) -> ImageBuilder {
+        self.base_image = tag;
+        self
+    }
+
+    /// How containers of the image are locked down, from the language's manifest
+    pub fn sandbox(mut self, sandbox: Sandbox) -> ImageBuilder {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Build the docker container object. This is the compile phase: the language's Dockerfile
+    /// compiles the submission while the image is built.
+    pub async fn build(self) -> Result<Image, BuildError> {
+        let iidfile = format!("{}/image_id", self.directory);
+        let runtime = runtime();
+        let container = match runtime
+            .command()
+            .args(runtime.build_args())
+            .args(["--iidfile", &iidfile])
+            .args(["--label", SUBMISSION_LABEL])
+            .args(
+                self.tag
+                    .iter()
+                    .flat_map(|tag| ["-t".to_string(), tag.clone()]),
+            )
+            .args(self.base_image.iter().flat_map(|tag| {
+                [
+                    "--build-context".to_string(),
+                    format!("{BASE_STAGE}=docker-image://{tag}"),
+                ]
+            }))
+            .arg(&self.directory)
             .output()
-            .unwrap();
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Could not run {}: {e}", runtime.program());
+                return Err(BuildError::Runtime(format!("{e}")));
+            }
+        };
 
-        if !container.stderr.is_empty() {
-            let err_str = String::from_utf8(container.stderr)
-                .unwrap()
-                .trim()
-                .to_string();
-            error!("Error creating container: {}", err_str);
-            return Err(err_str);
+        if !container.status.success() {
+            let log = runtime.build_log(&container);
+            let dockerfile = std::fs::read_to_string(format!("{}/Dockerfile", self.directory))
+                .unwrap_or_default();
+
+            return match runtime.failed_step(&container) {
+                Some(step) if submission_failed(&dockerfile, &step) => {
+                    info!("Submission in {} failed to compile", self.directory);
+                    Err(BuildError::compile(log))
+                }
+                Some(step) => {
+                    error!(
+                        "Build in {} failed at {}: {}",
+                        self.directory, step.instruction, step.error
+                    );
+                    Err(BuildError::Runtime(step.error))
+                }
+                None => {
+                    error!("Build in {} failed: {}", self.directory, log.trim());
+                    Err(BuildError::Runtime("The build failed".into()))
+                }
+            };
         }
 
-        let image_id = String::from_utf8(container.stdout)
-            .unwrap()
-            .trim()
-            .to_owned();
+        let image_id = match std::fs::read_to_string(&iidfile) {
+            Ok(id) => id.trim().to_owned(),
+            Err(e) => {
+                error!("Could not read the built image id: {e}");
+                return Err(BuildError::Runtime(format!("{e}")));
+            }
+        };
         info!("Image {image_id} created");
 
-        Ok(Image { image_id })
+        Ok(Image {
+            image_id,
+            sandbox: self.sandbox,
+        })
     }
 }
 
+/// Whether the image or tag is known to the container runtime
+pub async fn image_exists(image: &str) -> bool {
+    runtime()
+        .command()
+        .args(["image", "inspect", image])
+        .output()
+        .await
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Why an image couldn't be built
+pub enum BuildError {
+    /// The submission didn't compile
+    Compile {
+        /// The compiler's part of the build's output, for the student
+        compiler_output: String,
+        /// Everything the build printed, for instructors
+        log: String,
+    },
+    /// Docker itself failed, or something it depends on, like the registry or the disk
+    Runtime(String),
+}
+
+impl BuildError {
+    /// A submission that failed to compile, from everything its build printed
+    pub fn compile(log: String) -> BuildError {
+        BuildError::Compile {
+            compiler_output: truncate(compiler_output(&log)),
+            log: tail(log),
+        }
+    }
+
+    /// A submission turned away before it was built, for the student's reason given
+    pub fn rejected(reason: String) -> BuildError {
+        BuildError::Compile {
+            compiler_output: reason.clone(),
+            log: reason,
+        }
+    }
+}
+
+/// Whether the build failed in one of the submission's own commands: a `RUN` step outside the base
+/// stage whose command exited unsuccessfully. Anything else, like pulling the base image or running
+/// out of disk while copying the submission, is the grader's problem. The build's output is never
+/// looked at, since the submission controls what it prints.
+fn submission_failed(dockerfile: &str, step: &FailedStep) -> bool {
+    step.exit_code.is_some()
+        && step.instruction.starts_with("RUN ")
+        && !in_base_stage(dockerfile, &step.instruction)
+}
+
+/// The most compiler output kept in a result, in bytes
+const MAX_COMPILER_OUTPUT: usize = 16 * 1024;
+
+/// The most of a build log kept for instructors, in bytes
+const MAX_BUILD_LOG: usize = 256 * 1024;
+
+/// `docker run` arguments that mount the test's fixtures into `working_dir` and set its
+/// environment variables
+fn run_args(scratch: &ScratchDir, working_dir: &str, test: &Test) -> Vec<String> {
+    let mut args = scratch.mounts(working_dir);
+    for var in &test.env {
+        args.extend(["--env".to_string(), format!("{}={}", var.name, var.value)]);
+    }
+
+    args
+}
+
+/// Sends and expects each step of an interactive script in turn. `timeout` limits the whole
+/// conversation, on top of each step's own limit.
+pub async fn converse(
+    child: &mut Child,
+    steps: Vec<Step>,
+    timeout: Option<Duration>,
+    memory_limited: bool,
+) -> Dialog {
+    let started = Instant::now();
+    let output_limit = config::get().output_limit_bytes;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0; 4096];
+
+    let mut transcript = String::new();
+    let mut unread = String::new();
+    let mut step_timeout = interactive::DEFAULT_STEP_TIMEOUT;
+
+    for step in steps {
+        match step {
+            Step::Timeout(t) => step_timeout = t,
+            Step::Send(line) => {
+                transcript.push_str(&format!("> {line}\n"));
+                let sent = match stdin.write_all(format!("{line}\n").as_bytes()).await {
+                    Ok(()) => stdin.flush().await,
+                    Err(e) => Err(e),
+                };
+                if sent.is_err() {
+                    let reason = "The program stopped reading input".to_string();
+                    return exited(child, transcript + &unread, reason, memory_limited).await;
+                }
+            }
+            Step::Expect(pattern) => {
+                let expected = format!("Expected output matching /{pattern}/");
+                let step_deadline = Instant::now() + step_timeout;
+                let deadline = timeout.map_or(step_deadline, |t| step_deadline.min(started + t));
+
+                loop {
+                    if let Some(m) = pattern.find(&unread) {
+                        transcript.push_str(&unread[..m.end()]);
+                        unread.drain(..m.end());
+                        break;
+                    }
+                    if transcript.len() + unread.len() > output_limit {
+                        return Dialog::OutputLimitExceeded;
+                    }
+
+                    match tokio::time::timeout_at(deadline, stdout.read(&mut buf)).await {
+                        Ok(Ok(n @ 1..)) => unread.push_str(&String::from_utf8_lossy(&buf[..n])),
+                        Ok(_) => {
+                            let reason = format!("The program exited. {expected}");
+                            return exited(child, transcript + &unread, reason, memory_limited)
+                                .await;
+                        }
+                        Err(_) if deadline < step_deadline => return Dialog::TimedOut,
+                        Err(_) => return Dialog::Failed(transcript + &unread, expected),
+                    }
+                }
+            }
+        }
+    }
+
+    // Anything printed after the last expected reply is kept too
+    Dialog::Completed(transcript + &unread)
+}
+
+/// Feeds `input` to a started program and waits for it to exit, stopping it if it runs past
+/// `timeout` or prints more than `output_limit_bytes` to stdout or stderr
+pub async fn feed(
+    child: &mut Child,
+    input: &[u8],
+    timeout: Option<Duration>,
+) -> Result<Output, Stopped> {
+    // Written alongside reading the output, so a program that prints before reading all of its
+    // input can't fill the pipe and stall
+    let mut child_stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move {
+        let _ = child_stdin.write_all(&input).await;
+    });
+
+    let output = capped_output(child, config::get().output_limit_bytes);
+    let output = match timeout {
+        Some(duration) => tokio::time::timeout(duration, output)
+            .await
+            .unwrap_or(Err(Stopped::TimedOut)),
+        None => output.await,
+    };
+
+    if output.is_err() {
+        writer.abort();
+    }
+    output
+}
+
+/// Waits for the program to exit, keeping what it printed. Reading stops as soon as stdout or
+/// stderr goes over `limit` bytes, so a program can't fill the server's memory with output.
+async fn capped_output(child: &mut Child, limit: usize) -> Result<Output, Stopped> {
+    let stdout = read_capped(child.stdout.take(), limit);
+    let stderr = read_capped(child.stderr.take(), limit);
+    let (stdout, stderr) = tokio::try_join!(stdout, stderr)?;
+
+    Ok(Output {
+        status: child.wait().await.unwrap(),
+        stdout,
+        stderr,
+    })
+}
+
+async fn read_capped(
+    stream: Option<impl AsyncRead + Unpin>,
+    limit: usize,
+) -> Result<Vec<u8>, Stopped> {
+    let mut output = vec![];
+    if let Some(stream) = stream {
+        let _ = stream.take(limit as u64 + 1).read_to_end(&mut output).await;
+    }
+
+    if output.len() > limit {
+        return Err(Stopped::OutputLimitExceeded);
+    }
+    Ok(output)
+}
+
+/// The program stopped partway through a script
+async fn exited(
+    child: &mut Child,
+    transcript: String,
+    reason: String,
+    memory_limited: bool,
+) -> Dialog {
+    let killed = child
+        .wait()
+        .await
+        .is_ok_and(|status| status.code() == Some(KILLED_EXIT_CODE));
+
+    if memory_limited && killed {
+        Dialog::OutOfMemory
+    } else {
+        Dialog::Failed(transcript, reason)
+    }
+}
+
+/// Pulls the output of the failed build step out of docker's plain progress log. Lines look like
+/// `#7 0.532 error[E0425]: ...`; the failed step is the one that logged `#7 ERROR: ...`. Other
+/// runtimes' logs are kept whole.
+fn compiler_output(log: &str) -> String {
+    let failed_step = log.lines().find_map(|line| {
+        let (step, rest) = line.split_once(' ')?;
+        rest.starts_with("ERROR:").then_some(step)
+    });
+
+    let Some(step) = failed_step else {
+        return log.trim().to_string();
+    };
+
+    log.lines()
+        .filter_map(|line| line.strip_prefix(step)?.strip_prefix(' '))
+        .filter_map(|line| {
+            // Drop the elapsed time in front of each line of output
+            let (time, text) = line.split_once(' ').unwrap_or((line, ""));
+            time.parse::<f32>().is_ok().then_some(text)
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_COMPILER_OUTPUT {
+        let mut end = MAX_COMPILER_OUTPUT;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n... (truncated)");
+    }
+
+    output
+}
+
+/// The end of a build log, where the errors are, if it's longer than [`MAX_BUILD_LOG`]
+fn tail(mut log: String) -> String {
+    if log.len() > MAX_BUILD_LOG {
+        let mut start = log.len() - MAX_BUILD_LOG;
+        while !log.is_char_boundary(start) {
+            start += 1;
+        }
+        log.replace_range(..start, "(truncated) ...\n");
+    }
+
+    log
+}
+
 impl Image {
-    /// Runs the docker container with the provided input
+    /// Runs the image in the background as a server listening on `port`
+    pub async fn serve(&self, port: u16, limits: &ResourceLimits) -> Result<Server, String> {
+        let sandbox_args = self.sandbox_args(limits).await;
+        Server::start(&self.image_id, port, limits, &sandbox_args).await
+    }
+
+    /// Runs the docker container with the test's input, with its fixtures mounted in the working
+    /// directory. For artifact tests, the output is the artifact file rather than stdout.
+    ///
+    /// Err(e) => Error (with message)
+    pub async fn exec(&self, test: &Test, limits: &ResourceLimits) -> Result<RunOutcome, String> {
+        let scratch = ScratchDir::create(&test.fixtures)?;
+        let working_dir = if scratch.fixtures.is_empty() && test.artifact.is_none() {
+            String::new()
+        } else {
+            self.working_dir().await?
+        };
+
+        let process_output = match self
+            .run(
+                &test.input,
+                limits,
+                &scratch,
+                &run_args(&scratch, &working_dir, test),
+                &[],
+                test.timeout,
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(stopped) => return Ok(stopped.into()),
+        };
+
+        if limits.memory_limit_mb.is_some()
+            && process_output.status.code() == Some(KILLED_EXIT_CODE)
+        {
+            warn!("Container {} ran out of memory", self.image_id);
+            return Ok(RunOutcome::OutOfMemory);
+        }
+
+        if !process_output.stderr.is_empty() {
+            let err_str = String::from_utf8_lossy(&process_output.stderr)
+                .trim()
+                .to_string();
+            warn!("Error running container {}: {}", self.image_id, err_str);
+
+            return Err(err_str);
+        }
+
+        match &test.artifact {
+            Some(artifact) => scratch
+                .copy_artifact(&working_dir, artifact)
+                .await
+                .map(RunOutcome::Output),
+            None => Ok(RunOutcome::Output(process_output.stdout)),
+        }
+    }
+
+    /// Runs the test again with the program under valgrind
     ///
-    /// Ok(Some(output)) => Produced output \
-    /// Ok(None) => Timed Out \
+    /// Ok(Some(report)) => Memory errors were found \
+    /// Ok(None) => No errors, or the run didn't finish \
     /// Err(e) => Error (with message)
-    pub async fn exec(
+    pub async fn memcheck(
         &self,
-        stdin: impl AsRef<[u8]>,
-        duration: Option<Duration>,
+        test: &Test,
+        limits: &ResourceLimits,
     ) -> Result<Option<String>, String> {
-        let mut child = Command::new("docker")
-            .args(["run", "-i", &self.image_id])
+        let scratch = ScratchDir::create(&test.fixtures)?;
+        let working_dir = if scratch.fixtures.is_empty() {
+            String::new()
+        } else {
+            self.working_dir().await?
+        };
+
+        let mut command: Vec<String> = [
+            "valgrind",
+            "-q",
+            "--trace-children=yes",
+            "--leak-check=full",
+            "--errors-for-leak-kinds=definite",
+        ]
+        .map(String::from)
+        .into();
+        command.push(format!("--error-exitcode={MEMCHECK_EXIT_CODE}"));
+        command.extend(self.command().await?);
+
+        let timeout = test.timeout.map(|t| t * MEMCHECK_SLOWDOWN);
+        let Ok(process_output) = self
+            .run(
+                &test.input,
+                limits,
+                &scratch,
+                &run_args(&scratch, &working_dir, test),
+                &command,
+                timeout,
+            )
+            .await
+        else {
+            return Ok(None);
+        };
+
+        if process_output.status.code() != Some(MEMCHECK_EXIT_CODE) {
+            return Ok(None);
+        }
+
+        let report = String::from_utf8_lossy(&process_output.stderr)
+            .trim()
+            .to_string();
+        Ok(Some(truncate(report)))
+    }
+
+    /// Whether the image's language can be run under valgrind
+    pub async fn supports_memcheck(&self) -> bool {
+        self.label(MEMCHECK_LABEL)
+            .await
+            .is_some_and(|l| l == "valgrind")
+    }
+
+    /// Runs the language's linter over the submission. Each line it prints is one finding.
+    ///
+    /// Ok(Some(findings)) => Linted \
+    /// Ok(None) => The language has no linter \
+    /// Err(e) => Error (with message)
+    pub async fn lint(&self, limits: &ResourceLimits) -> Result<Option<Vec<String>>, String> {
+        let Some(command) = self.label(LINT_LABEL).await else {
+            return Ok(None);
+        };
+
+        let scratch = ScratchDir::create(&[])?;
+        let command = ["sh", "-c", command.as_str()].map(String::from);
+
+        let process_output = match self
+            .run(&[], limits, &scratch, &[], &command, Some(LINT_TIMEOUT))
+            .await
+        {
+            Ok(output) => output,
+            Err(Stopped::TimedOut) => return Err("The linter timed out".into()),
+            Err(Stopped::OutputLimitExceeded) => {
+                return Err("The linter printed more than the output limit".into());
+            }
+        };
+
+        let findings = [process_output.stdout, process_output.stderr]
+            .iter()
+            .flat_map(|out| {
+                String::from_utf8_lossy(out)
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<String>>()
+            })
+            .collect();
+
+        Ok(Some(findings))
+    }
+
+    /// Plays the test's script (see [`interactive`]) against the program, step by step
+    ///
+    /// Err(e) => Error (with message)
+    pub async fn interact(&self, test: &Test, limits: &ResourceLimits) -> Result<Dialog, String> {
+        let steps = interactive::parse(&String::from_utf8_lossy(&test.input))?;
+        let scratch = ScratchDir::create(&test.fixtures)?;
+        let working_dir = if scratch.fixtures.is_empty() {
+            String::new()
+        } else {
+            self.working_dir().await?
+        };
+
+        let mut child = run_command(limits.isolation)
+            .args(["-i", "--cidfile"])
+            .arg(scratch.path.join("cid"))
+            .args(limits.args())
+            .args(self.sandbox_args(limits).await)
+            .args(run_args(&scratch, &working_dir, test))
+            .arg(&self.image_id)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{e}"))?;
+
+        let memory_limited = limits.memory_limit_mb.is_some();
+        let dialog = converse(&mut child, steps, test.timeout, memory_limited).await;
+        scratch.remove_container().await;
+        let _ = child.kill().await;
+
+        if let Dialog::TimedOut = dialog {
+            warn!("Container {} Timed Out", self.image_id);
+        }
+
+        Ok(dialog)
+    }
+
+    /// Runs a unit-test command in the submission's working directory, with the fixtures mounted
+    /// next to it. The output is the JUnit report the command wrote.
+    ///
+    /// Err(e) => Error (with message)
+    pub async fn report(
+        &self,
+        command: &str,
+        fixtures: &[TestFixture],
+        timeout: Option<Duration>,
+        limits: &ResourceLimits,
+    ) -> Result<RunOutcome, String> {
+        let scratch = ScratchDir::create(fixtures)?;
+        let working_dir = self.working_dir().await?;
+        let command = ["sh", "-c", command].map(String::from);
+
+        let process_output = match self
+            .run(
+                &[],
+                limits,
+                &scratch,
+                &scratch.mounts(&working_dir),
+                &command,
+                timeout,
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(stopped) => return Ok(stopped.into()),
+        };
+
+        if limits.memory_limit_mb.is_some()
+            && process_output.status.code() == Some(KILLED_EXIT_CODE)
+        {
+            warn!("Container {} ran out of memory", self.image_id);
+            return Ok(RunOutcome::OutOfMemory);
+        }
+
+        // Failing tests make the command fail too, so only a missing report is an error
+        scratch
+            .copy_artifact(&working_dir, junit::REPORT_FILE)
+            .await
+            .map(RunOutcome::Output)
+            .map_err(|e| {
+                let stderr = String::from_utf8_lossy(&process_output.stderr);
+                format!("{e}\n{}", truncate(stderr.trim().to_string()))
+            })
+    }
+
+    /// Starts a container with the extra `docker run` arguments, with `command` replacing the
+    /// image's own if it isn't empty, and feeds it `input`. The program is stopped if it runs past
+    /// `timeout` or prints more than `output_limit_bytes` to stdout or stderr.
+    async fn run(
+        &self,
+        input: &[u8],
+        limits: &ResourceLimits,
+        scratch: &ScratchDir,
+        args: &[String],
+        command: &[String],
+        timeout: Option<Duration>,
+    ) -> Result<Output, Stopped> {
+        let mut child = run_command(limits.isolation)
+            .args(["-i", "--cidfile"])
+            .arg(scratch.path.join("cid"))
+            .args(limits.args())
+            .args(self.sandbox_args(limits).await)
+            .args(args)
+            .arg(&self.image_id)
+            .args(command)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
             .unwrap();
 
-        let child_stdin = child.stdin.as_mut().unwrap();
-        child_stdin.write_all(stdin.as_ref()).unwrap();
-
-        let process_output = if let Some(_duration) = duration {
-            let timer = tokio::spawn(async move {
-                tokio::time::sleep(_duration).await;
-            });
+        let output = feed(&mut child, input, timeout).await;
+        match &output {
+            Ok(_) => return output,
+            Err(Stopped::TimedOut) => warn!("Container {} Timed Out", self.image_id),
+            Err(Stopped::OutputLimitExceeded) => warn!(
+                "Container {} printed more than {} bytes",
+                self.image_id,
+                config::get().output_limit_bytes
+            ),
+        }
 
-            let get_child_output = tokio::spawn(async { child.wait_with_output().unwrap() });
+        // Killing the client doesn't stop the container, so it is removed directly
+        scratch.remove_container().await;
+        output
+    }
+}
 
-            tokio::select! {
-                _ = timer => {
-                    warn!("Container {} Timed Out", self.image_id);
-                    return Ok(None);
-                },
-                output = get_child_output => {
-                    output.unwrap()
-                }
-            }
+impl Image {
+    /// `docker run` arguments that lock the container down as the language's manifest asks
+    async fn sandbox_args(&self, limits: &ResourceLimits) -> Vec<String> {
+        let working_dir = if self.sandbox.read_only {
+            self.working_dir()
+                .await
+                .inspect_err(|e| warn!("Could not keep the working directory writable: {e}"))
+                .ok()
         } else {
-            child.wait_with_output().unwrap()
+            None
         };
 
-        if !process_output.stderr.is_empty() {
-            let err_str = String::from_utf8(process_output.stderr)
-                .unwrap()
-                .trim()
-                .to_string();
-            warn!("Error running container {}: {}", self.image_id, err_str);
+        self.sandbox
+            .args(working_dir.as_deref(), limits.disk_limit_mb)
+    }
 
-            return Err(err_str);
+    /// The directory the image's program runs in
+    async fn working_dir(&self) -> Result<String, String> {
+        let inspect = runtime()
+            .command()
+            .args([
+                "image",
+                "inspect",
+                "-f",
+                "{{.Config.WorkingDir}}",
+                &self.image_id,
+            ])
+            .output()
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        match String::from_utf8_lossy(&inspect.stdout).trim() {
+            "" => Ok("/".into()),
+            dir => Ok(dir.to_string()),
+        }
+    }
+
+    /// The value of one of the image's labels, if it is set
+    async fn label(&self, name: &str) -> Option<String> {
+        let inspect = runtime()
+            .command()
+            .args([
+                "image",
+                "inspect",
+                "-f",
+                &format!("{{{{index .Config.Labels \"{name}\"}}}}"),
+                &self.image_id,
+            ])
+            .output()
+            .await
+            .ok()?;
+
+        match String::from_utf8_lossy(&inspect.stdout).trim() {
+            "" | "<no value>" => None,
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// The command the image runs, with any entrypoint in front of it
+    async fn command(&self) -> Result<Vec<String>, String> {
+        let inspect = runtime()
+            .command()
+            .args([
+                "image",
+                "inspect",
+                "-f",
+                "{{json .Config.Entrypoint}} {{json .Config.Cmd}}",
+                &self.image_id,
+            ])
+            .output()
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        let inspect = String::from_utf8_lossy(&inspect.stdout);
+        let (entrypoint, cmd) = inspect.trim().split_once(' ').unwrap_or(("null", "null"));
+
+        let mut command: Vec<String> = serde_json::from_str::<Option<Vec<String>>>(entrypoint)
+            .map_err(|e| format!("{e}"))?
+            .unwrap_or_default();
+        command.extend(
+            serde_json::from_str::<Option<Vec<String>>>(cmd)
+                .map_err(|e| format!("{e}"))?
+                .unwrap_or_default(),
+        );
+
+        Ok(command)
+    }
+}
+
+/// Files kept on the host for a single run: its fixtures, the id of its container, and the
+/// artifact copied out of it. Removed when dropped, along with the container.
+struct ScratchDir {
+    path: PathBuf,
+    /// Names of the fixtures written to `path/fixtures`
+    fixtures: Vec<String>,
+}
+
+impl ScratchDir {
+    fn create(fixtures: &[TestFixture]) -> Result<ScratchDir, String> {
+        let suffix: String = rand::random::<[u8; 8]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let mut dir = ScratchDir {
+            path: PathBuf::from(format!("{RUNS_ROOT}/{suffix}")),
+            fixtures: vec![],
+        };
+        create_dir_all(dir.path.join("fixtures")).map_err(|e| format!("{e}"))?;
+
+        for fixture in fixtures {
+            // Names are validated on upload, but never let one escape the directory
+            let Some(filename) = std::path::Path::new(&fixture.filename).file_name() else {
+                continue;
+            };
+
+            std::fs::write(dir.path.join("fixtures").join(filename), &fixture.contents)
+                .map_err(|e| format!("{e}"))?;
+            dir.fixtures.push(filename.to_string_lossy().into_owned());
         }
 
-        let output = String::from_utf8(process_output.stdout).unwrap();
+        Ok(dir)
+    }
+
+    /// `docker run` arguments that mount each fixture into `working_dir`
+    fn mounts(&self, working_dir: &str) -> Vec<String> {
+        let working_dir = working_dir.trim_end_matches('/');
+        self.fixtures
+            .iter()
+            .flat_map(|name| {
+                [
+                    "--mount".to_string(),
+                    format!(
+                        "type=bind,src={},dst={working_dir}/{name}",
+                        self.path.join("fixtures").join(name).display()
+                    ),
+                ]
+            })
+            .collect()
+    }
+
+    fn container_id(&self) -> Option<String> {
+        std::fs::read_to_string(self.path.join("cid"))
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+
+    /// Kills and removes the run's container, if it was started
+    async fn remove_container(&self) {
+        let Some(container_id) = self.container_id() else {
+            return;
+        };
 
-        Ok(Some(output))
+        let _ = runtime()
+            .command()
+            .args(["rm", "-f", "-v", &container_id])
+            .output()
+            .await;
+        let _ = std::fs::remove_file(self.path.join("cid"));
+    }
+
+    /// Copies a file the program wrote in `working_dir` out of the stopped container
+    async fn copy_artifact(&self, working_dir: &str, artifact: &str) -> Result<Vec<u8>, String> {
+        let Some(container_id) = self.container_id() else {
+            return Err("The container did not record its id".into());
+        };
+
+        let destination = self.path.join("artifact");
+        let copied = runtime()
+            .command()
+            .args([
+                "cp",
+                &format!(
+                    "{container_id}:{}/{artifact}",
+                    working_dir.trim_end_matches('/')
+                ),
+            ])
+            .arg(&destination)
+            .output()
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        if !copied.status.success() || !destination.is_file() {
+            return Err(format!("The program did not create {artifact}"));
+        }
+
+        std::fs::read(&destination).map_err(|e| format!("{e}"))
     }
 }
 
-impl Drop for Image {
+impl Drop for ScratchDir {
     fn drop(&mut self) {
-        // FIGURE OUT A WAY TO PRUNE OLD CONTAINERS
-
-        // info!("Removing image {} and associated containers.", self.image_id);
-        // Command::new("podman")
-        //     .args(["rmi", "-f", &self.image_id])
-        //     .spawn()
-        //     .unwrap();
-
-        // Command::new("podman")
-        //     .args(["image", "prune", "-af"])
-        //     .spawn()
-        //     .unwrap();
+        // Drop can't wait, so a container that is still around is removed in the background
+        if let Some(container_id) = self.container_id() {
+            let _ = runtime()
+                .command()
+                .args(["rm", "-f", "-v", &container_id])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+        }
+        let _ = remove_dir_all(&self.path);
     }
 }
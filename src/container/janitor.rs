@@ -0,0 +1,300 @@
+//! Cleans up what grading leaves behind
+//!
+//! Every `janitor_interval_secs`, the janitor prunes what has been lying around for longer than
+//! `janitor_max_age_secs`:
+//!
+//! - grading containers that exited without being removed, e.g. because the server crashed
+//! - dangling images, and submission images no container uses. Submission images are kept after
+//!   grading so resubmitting the same code doesn't build it again, so only old ones go.
+//! - working and scratch directories under `/tmp/securegrade`, and uploads that were never
+//!   finished
+//! - archives, materials and other files in blob storage that nothing refers to anymore
+//!
+//! What each pass removed and how much space it freed is kept for the admin `janitor` endpoint.
+
+use std::{
+    collections::HashMap,
+    fs::{read_dir, remove_dir_all, remove_file, symlink_metadata},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info, warn};
+
+use super::{
+    image::{RUNS_ROOT, SUBMISSION_LABEL},
+    runtime::{RUN_LABEL, runtime},
+    workdir,
+};
+use crate::{config, database, model::janitor_stats::JanitorStats, storage, upload};
+
+/// Where grading, test imports and downloads keep their files. Besides the working and scratch
+/// directories, which are looked inside, whatever is directly in it is removed once it's stale.
+const TMP_ROOT: &str = "/tmp/securegrade";
+
+static STATS: Mutex<Janitor> = Mutex::new(Janitor {
+    stats: JanitorStats {
+        passes: 0,
+        last_pass_secs: None,
+        containers_removed: 0,
+        images_removed: 0,
+        directories_removed: 0,
+        objects_removed: 0,
+        reclaimed_bytes: 0,
+        last_reclaimed_bytes: 0,
+    },
+    last_pass: None,
+});
+
+struct Janitor {
+    stats: JanitorStats,
+    last_pass: Option<Instant>,
+}
+
+/// What one kind of cleanup removed
+#[derive(Default)]
+struct Pruned {
+    removed: u64,
+    bytes: u64,
+}
+
+/// Cleans up every `janitor_interval_secs`, starting right away
+pub async fn janitor() -> ! {
+    loop {
+        let max_age = Duration::from_secs(config::get().janitor_max_age_secs);
+
+        let containers = prune(&["container", "prune", "-f"], &[RUN_LABEL], max_age).await;
+        // Web-service tasks' networks, left by servers that were never stopped
+        prune(&["network", "prune", "-f"], &[RUN_LABEL], max_age).await;
+        let dangling = prune(&["image", "prune", "-f"], &[], max_age).await;
+        let images = prune(
+            &["image", "prune", "-a", "-f"],
+            &[SUBMISSION_LABEL],
+            max_age,
+        )
+        .await;
+        let directories = remove_stale_directories(max_age);
+        let objects = remove_unused_objects(max_age).await;
+
+        let reclaimed =
+            containers.bytes + dangling.bytes + images.bytes + directories.bytes + objects.bytes;
+        let removed = containers.removed
+            + dangling.removed
+            + images.removed
+            + directories.removed
+            + objects.removed;
+        if removed > 0 {
+            info!(
+                "Janitor removed {} containers, {} images, {} directories and {} stored objects, freeing {} MB",
+                containers.removed,
+                dangling.removed + images.removed,
+                directories.removed,
+                objects.removed,
+                reclaimed / (1024 * 1024)
+            );
+        }
+
+        {
+            let mut janitor = STATS.lock().unwrap();
+            let stats = &mut janitor.stats;
+            stats.passes += 1;
+            stats.containers_removed += containers.removed;
+            stats.images_removed += dangling.removed + images.removed;
+            stats.directories_removed += directories.removed;
+            stats.objects_removed += objects.removed;
+            stats.reclaimed_bytes += reclaimed;
+            stats.last_reclaimed_bytes = reclaimed;
+            janitor.last_pass = Some(Instant::now());
+        }
+
+        tokio::time::sleep(Duration::from_secs(config::get().janitor_interval_secs)).await;
+    }
+}
+
+/// What the janitor has cleaned up since start-up
+pub fn janitor_stats() -> JanitorStats {
+    let janitor = STATS.lock().unwrap();
+    JanitorStats {
+        last_pass_secs: janitor.last_pass.map(|at| at.elapsed().as_secs_f64()),
+        ..janitor.stats.clone()
+    }
+}
+
+/// Runs a runtime `prune` command for what is older than `max_age` and has each of the labels
+async fn prune(command: &[&str], labels: &[&str], max_age: Duration) -> Pruned {
+    let runtime = runtime();
+    let output = match runtime
+        .command()
+        .args(command)
+        .args(["--filter", &format!("until={}s", max_age.as_secs())])
+        .args(
+            labels
+                .iter()
+                .flat_map(|label| ["--filter".to_string(), format!("label={label}")]),
+        )
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warn!(
+                "{} {} failed: {}",
+                runtime.program(),
+                command.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Pruned::default();
+        }
+        Err(e) => {
+            error!("Could not run {}: {e}", runtime.program());
+            return Pruned::default();
+        }
+    };
+
+    // Docker lists what it removed and ends with "Total reclaimed space: 1.5GB". Podman only
+    // lists ids.
+    let mut pruned = Pruned::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(size) = line.strip_prefix("Total reclaimed space: ") {
+            pruned.bytes = parse_size(size).unwrap_or_default();
+        } else if removed_id(line) {
+            pruned.removed += 1;
+        }
+    }
+    pruned
+}
+
+/// Whether a line of `prune`'s output is the id of something it removed
+fn removed_id(line: &str) -> bool {
+    let id = line.trim();
+    let id = id.strip_prefix("deleted: ").unwrap_or(id);
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    id.len() >= 12 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Bytes in a size as Docker prints it, e.g. `1.5GB` or `0B`
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier: f64 = match unit.trim() {
+        "B" | "" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        _ => return None,
+    };
+    Some((number.parse::<f64>().ok()? * multiplier) as u64)
+}
+
+/// Removes the working and scratch directories, uploads, and anything else under [`TMP_ROOT`],
+/// that haven't been touched in `max_age`
+fn remove_stale_directories(max_age: Duration) -> Pruned {
+    let mut pruned = Pruned::default();
+    let current = [
+        Path::new(workdir::ROOT),
+        Path::new(RUNS_ROOT),
+        Path::new(upload::ROOT),
+    ];
+
+    let entries = [workdir::ROOT, RUNS_ROOT, upload::ROOT, TMP_ROOT]
+        .into_iter()
+        .filter_map(|dir| read_dir(dir).ok())
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| !current.contains(&path.as_path()));
+
+    for path in entries {
+        let Ok(metadata) = symlink_metadata(&path) else {
+            continue;
+        };
+        let stale = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > max_age);
+        if !stale {
+            continue;
+        }
+
+        let bytes = disk_usage(&path);
+        let removed = if metadata.is_dir() {
+            remove_dir_all(&path)
+        } else {
+            remove_file(&path)
+        };
+        match removed {
+            Ok(()) => {
+                pruned.removed += 1;
+                pruned.bytes += bytes;
+            }
+            Err(e) => warn!("Could not remove {}: {e}", path.display()),
+        }
+    }
+
+    pruned
+}
+
+/// Removes the objects in blob storage older than `max_age` that no row refers to. Younger ones
+/// are left alone, as their row may not have been written yet.
+async fn remove_unused_objects(max_age: Duration) -> Pruned {
+    let mut pruned = Pruned::default();
+
+    let objects = match storage::list().await {
+        Ok(objects) => objects,
+        Err(e) => {
+            error!("Could not list blob storage: {e}");
+            return pruned;
+        }
+    };
+    let sizes = objects
+        .into_iter()
+        .filter(|object| object.age > max_age)
+        .map(|object| (object.key, object.size))
+        .collect::<HashMap<_, _>>();
+    if sizes.is_empty() {
+        return pruned;
+    }
+
+    let unused = match database::blob::unreferenced(sizes.keys().cloned().collect()).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            error!("Could not look up what refers to stored objects: {e}");
+            return pruned;
+        }
+    };
+
+    for key in unused {
+        match storage::delete(&key).await {
+            Ok(()) => {
+                pruned.removed += 1;
+                pruned.bytes += sizes.get(&key).copied().unwrap_or_default();
+            }
+            Err(e) => warn!("Could not remove {key} from blob storage: {e}"),
+        }
+    }
+
+    pruned
+}
+
+/// Bytes taken up by a file, or a directory and everything in it. Links aren't followed.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| disk_usage(&entry.path()))
+        .sum()
+}
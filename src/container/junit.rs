@@ -0,0 +1,75 @@
+//! Reads the JUnit XML reports written by unit-test frameworks
+//!
+//! Tasks graded this way run the instructor's test command inside the submission's container, and
+//! the command writes its results to [`REPORT_FILE`] (`pytest --junitxml=junit.xml`, Maven's
+//! surefire reports, `go-junit-report`, ...). Each of the task's tests is matched to the test case
+//! of the same name, either `name` alone or `classname.name`.
+
+/// Where the test command has to write its report, relative to the working directory
+pub const REPORT_FILE: &str = "junit.xml";
+
+/// How a single test case went
+pub enum CaseOutcome {
+    Passed,
+    /// An assertion failed. Holds the framework's message.
+    Failed(String),
+    /// The test itself crashed
+    Errored(String),
+    Skipped,
+}
+
+pub struct TestCase {
+    classname: String,
+    name: String,
+    pub outcome: CaseOutcome,
+}
+
+impl TestCase {
+    /// Whether this is the test case an instructor's test refers to
+    pub fn is_named(&self, test_name: &str) -> bool {
+        test_name == self.name
+            || test_name
+                .strip_prefix(self.classname.as_str())
+                .and_then(|rest| rest.strip_prefix('.').or_else(|| rest.strip_prefix("::")))
+                .is_some_and(|rest| rest == self.name)
+    }
+}
+
+/// Reads every test case in the report, however deeply its test suites are nested
+pub fn parse(report: &str) -> Result<Vec<TestCase>, String> {
+    let document = roxmltree::Document::parse(report)
+        .map_err(|e| format!("The test report is not valid XML: {e}"))?;
+
+    let cases = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testcase"))
+        .map(|case| {
+            let child = |tag: &str| case.children().find(|c| c.has_tag_name(tag));
+            let message = |node: roxmltree::Node| {
+                let text = node.text().unwrap_or("").trim();
+                match node.attribute("message") {
+                    Some(message) if text.is_empty() => message.to_string(),
+                    _ => text.to_string(),
+                }
+            };
+
+            let outcome = if let Some(failure) = child("failure") {
+                CaseOutcome::Failed(message(failure))
+            } else if let Some(error) = child("error") {
+                CaseOutcome::Errored(message(error))
+            } else if child("skipped").is_some() {
+                CaseOutcome::Skipped
+            } else {
+                CaseOutcome::Passed
+            };
+
+            TestCase {
+                classname: case.attribute("classname").unwrap_or("").to_string(),
+                name: case.attribute("name").unwrap_or("").to_string(),
+                outcome,
+            }
+        })
+        .collect();
+
+    Ok(cases)
+}
@@ -0,0 +1,227 @@
+//! Reads the manifest a language's directory carries next to its Dockerfile
+//!
+//! `dockerfiles/<lang>/lang.toml` describes the language to users and what its image expects of the
+//! grading run. Every setting is optional, and a language without a manifest is shown by its
+//! directory name and runs the way images always have: as the image's own user, with a writable
+//! filesystem.
+//!
+//! ```toml
+//! # Stops new submissions in the language. Those already made can still be regraded.
+//! disabled = false
+//! name = "C"
+//! version = "GCC 14.2"
+//! # How the Dockerfile compiles and runs a submission, as shown to students. Warm containers run
+//! # them as well.
+//! compile = "gcc -std=c17 -O1 -g -Wall -o main *.c -lm"
+//! run = "./main"
+//! # Where the file written in the browser editor goes in the submission
+//! source_file = "main.c"
+//!
+//! # Used by tasks that don't set their own limits, instead of the server's defaults
+//! [limits]
+//! cpus = 1.0
+//! pids_limit = 64
+//! memory_limit_mb = 256
+//! disk_limit_mb = 32
+//!
+//! [sandbox]
+//! # Run as this user instead of the image's. The working directory has to belong to it.
+//! user = "65534:65534"
+//! # Mount the image read-only. The working directory and /tmp stay writable, each up to the
+//! # task's disk limit. The working directory's files count towards it.
+//! read_only = true
+//!
+//! # Containers kept started from the base image, so sample runs skip the image build. Needs a
+//! # base stage and `run`. See `warm`.
+//! [warm]
+//! pool_size = 2
+//! ```
+//!
+//! Whatever the manifest says, runs get no capabilities and can't gain privileges.
+//!
+//! Manifests are read at start-up and whenever the configuration is reloaded.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{LazyLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::runtime::runtime;
+
+/// Name of the manifest inside a language's directory
+pub const MANIFEST_FILE: &str = "lang.toml";
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub disabled: bool,
+    /// Shown to users. `None` => the directory name.
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub compile: Option<String>,
+    pub run: Option<String>,
+    /// Path of the single file editor submissions are wrapped into. `None` => the language can't
+    /// be submitted from the editor.
+    pub source_file: Option<String>,
+    pub limits: Limits,
+    pub sandbox: Sandbox,
+    pub warm: Warm,
+}
+
+/// The language's defaults for tasks that don't set their own limits. `None` => the server's.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub cpus: Option<f32>,
+    pub pids_limit: Option<i32>,
+    /// In megabytes
+    pub memory_limit_mb: Option<i32>,
+    /// In megabytes
+    pub disk_limit_mb: Option<i32>,
+}
+
+/// How a language's image is locked down while it runs
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Sandbox {
+    /// `uid[:gid]` or a user name known to the image. `None` => the image's own user.
+    pub user: Option<String>,
+    pub read_only: bool,
+}
+
+/// The language's pool of warm containers
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Warm {
+    /// Containers kept ready. 0 => sample runs are built like any other submission.
+    pub pool_size: usize,
+}
+
+impl Manifest {
+    /// Reads the manifest in `container`, the language's directory. A missing manifest gives the
+    /// defaults.
+    pub fn load(container: &Path) -> Result<Manifest, String> {
+        let path = container.join(MANIFEST_FILE);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+        };
+
+        Manifest::parse(&contents).map_err(|e| format!("Invalid {}: {e}", path.display()))
+    }
+
+    /// Parses and checks the contents of a manifest
+    pub fn parse(contents: &str) -> Result<Manifest, String> {
+        let manifest: Manifest = toml::from_str(contents).map_err(|e| format!("{e}"))?;
+
+        let limits = manifest.limits;
+        if limits.cpus.is_some_and(|cpus| cpus <= 0.0)
+            || [
+                limits.pids_limit,
+                limits.memory_limit_mb,
+                limits.disk_limit_mb,
+            ]
+            .iter()
+            .any(|limit| limit.is_some_and(|limit| limit < 1))
+        {
+            return Err("limits must be positive".into());
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Sets `disabled` in the manifest in `container`, leaving the rest of the file as it was
+pub fn set_disabled(container: &Path, disabled: bool) -> Result<(), String> {
+    let path = container.join(MANIFEST_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+    };
+
+    // Top-level keys come before the first table, so `disabled` is looked for and added there
+    let mut in_table = false;
+    let mut lines: Vec<&str> = contents
+        .lines()
+        .filter(|line| {
+            in_table |= line.trim_start().starts_with('[');
+            in_table
+                || line
+                    .split_once('=')
+                    .is_none_or(|(key, _)| key.trim() != "disabled")
+        })
+        .collect();
+    if disabled {
+        lines.insert(0, "disabled = true");
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    Manifest::parse(&contents).map_err(|e| format!("Invalid {}: {e}", path.display()))?;
+
+    std::fs::write(&path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+/// Every language's manifest, by directory name
+static MANIFESTS: LazyLock<RwLock<BTreeMap<String, Manifest>>> =
+    LazyLock::new(|| RwLock::new(BTreeMap::new()));
+
+/// Reads the manifest of every language in `dockerfiles`. If any is invalid, the manifests
+/// already loaded are kept.
+pub fn load_all(dockerfiles: &Path) -> Result<(), String> {
+    let mut manifests = BTreeMap::new();
+    let entries = std::fs::read_dir(dockerfiles)
+        .map_err(|e| format!("Could not read {}: {e}", dockerfiles.display()))?;
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        if let Ok(lang) = entry.file_name().into_string() {
+            manifests.insert(lang, Manifest::load(&entry.path())?);
+        }
+    }
+
+    *MANIFESTS.write().unwrap() = manifests;
+    Ok(())
+}
+
+/// The manifest of `lang`, as last loaded. A language added since gives the defaults.
+pub fn get(lang: &str) -> Manifest {
+    MANIFESTS
+        .read()
+        .unwrap()
+        .get(lang)
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl Sandbox {
+    /// `docker run` arguments that lock the container down. `working_dir` is kept writable on a
+    /// read-only image by copying it into a fresh tmpfs of at most `disk_limit_mb` megabytes.
+    pub fn args(&self, working_dir: Option<&str>, disk_limit_mb: i32) -> Vec<String> {
+        let mut args = vec![
+            "--cap-drop=ALL".to_string(),
+            "--security-opt=no-new-privileges".to_string(),
+        ];
+
+        if let Some(user) = &self.user {
+            args.extend(["--user".to_string(), user.clone()]);
+        }
+
+        if self.read_only {
+            args.push("--read-only".to_string());
+            if let Some(working_dir) = working_dir.filter(|dir| !dir.is_empty() && *dir != "/") {
+                let working_dir = working_dir.trim_end_matches('/');
+                args.extend([
+                    "--mount".to_string(),
+                    runtime().sized_copy_mount(working_dir, disk_limit_mb),
+                ]);
+            }
+        }
+
+        args
+    }
+}
@@ -0,0 +1,399 @@
+//! The grading queue, and the fixed pool of workers that grade what's in it
+//!
+//! Queued submissions are kept in the database (see [`database::grading_job`]), so neither a restart
+//! nor a crash loses them. `grading_threads` workers claim them one at a time, sharing the queue
+//! fairly between classes and students. Once `grading_queue_capacity` submissions are waiting, new
+//! ones are turned away. Changing `grading_threads` starts new workers right away, while surplus
+//! workers stop once they finish the submission they're grading.
+//!
+//! On shutdown, workers stop claiming submissions and those still grading get [`SHUTDOWN_GRACE`] to
+//! finish. Submissions not finished by then, and those claimed by remote runners, go back in the
+//! queue. At start-up, anything a crash left running is put back as well, and the queue picks up
+//! where it left off.
+
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Notify, watch};
+use tracing::{error, info};
+
+use super::{ContainerEntry, Outcome, grade, progress, remote, warm};
+use crate::{
+    config,
+    database::{
+        self,
+        grading_job::{GradingJob, JobProgress},
+    },
+    model::{
+        grading_event::GradingEvent,
+        grading_pool_stats::{CurrentJob, GradingPoolStats, WorkerStats},
+        submission_status::GradingStatus,
+    },
+};
+
+/// How long submissions being graded are given to finish when the server shuts down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(60);
+
+/// How often idle workers look at the queue, in case a submission was queued without waking them
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wakes idle workers when a submission is queued
+static QUEUED: Notify = Notify::const_new();
+
+/// How many workers there should be
+static TARGET: LazyLock<watch::Sender<usize>> = LazyLock::new(|| watch::Sender::new(0));
+
+/// The running workers, by id. Held while workers are started or retired, so the two can't race.
+static WORKERS: Mutex<BTreeMap<usize, Arc<Worker>>> = Mutex::new(BTreeMap::new());
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct Worker {
+    completed: AtomicU64,
+    failed: AtomicU64,
+    /// Time spent grading, in milliseconds
+    busy_ms: AtomicU64,
+    current: Mutex<Option<Job>>,
+}
+
+/// The submission a worker is grading
+#[derive(Clone, Copy)]
+struct Job {
+    id: i32,
+    user_id: i32,
+    task_id: i32,
+    sample: bool,
+    started: Instant,
+}
+
+/// Puts submissions interrupted by the last shutdown back in the queue, and starts the workers
+pub async fn start_queue() {
+    match database::grading_job::release_interrupted().await {
+        Ok(0) => (),
+        Ok(n) => info!("Queueing {n} submissions interrupted by the last shutdown again"),
+        Err(e) => error!("Could not queue interrupted submissions again: {e}"),
+    }
+
+    if STARTED.swap(true, Ordering::SeqCst) {
+        error!("The grading queue was already started");
+        return;
+    }
+
+    spawn_workers(&mut WORKERS.lock().unwrap());
+}
+
+/// Adds a submission to the queue
+pub async fn queue(entry: ContainerEntry) -> Result<(), String> {
+    database::grading_job::enqueue(
+        entry.user_id,
+        entry.task_id,
+        entry.was_late,
+        &entry.lang,
+        entry.sample,
+        &entry.zip_file,
+    )
+    .await?;
+
+    if !entry.sample {
+        progress::publish(entry.user_id, entry.task_id, GradingEvent::Queued);
+    }
+    QUEUED.notify_waiters();
+    Ok(())
+}
+
+/// Whether `grading_queue_capacity` submissions are already waiting
+pub async fn queue_full() -> Result<bool, String> {
+    let queued = database::grading_job::queued_count().await?;
+    Ok(queued >= config::get().grading_queue_capacity)
+}
+
+/// Changes the number of submissions graded at once
+pub fn set_grading_threads(n: usize) {
+    let mut workers = WORKERS.lock().unwrap();
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return;
+    }
+
+    TARGET.send_replace(n);
+    if STARTED.load(Ordering::SeqCst) {
+        spawn_workers(&mut workers);
+    }
+
+    info!("Grading with {n} workers");
+}
+
+/// Starts every worker missing from the target number
+fn spawn_workers(workers: &mut BTreeMap<usize, Arc<Worker>>) {
+    for id in 0..*TARGET.borrow() {
+        if let Entry::Vacant(slot) = workers.entry(id) {
+            let worker = slot.insert(Arc::new(Worker::default())).clone();
+            tokio::spawn(work(id, worker));
+        }
+    }
+}
+
+/// Removes the worker if there are now more than needed. `true` => it should stop.
+fn retire(id: usize) -> bool {
+    let mut workers = WORKERS.lock().unwrap();
+    if id < *TARGET.borrow() {
+        return false;
+    }
+
+    workers.remove(&id);
+    true
+}
+
+async fn work(id: usize, worker: Arc<Worker>) {
+    let mut target = TARGET.subscribe();
+
+    loop {
+        if retire(id) {
+            return;
+        }
+
+        // Listening starts before the queue is looked at, so a submission queued in between
+        // isn't missed
+        let queued = QUEUED.notified();
+        tokio::pin!(queued);
+        queued.as_mut().enable();
+
+        let Some(grading_job) = next_job().await else {
+            tokio::select! {
+                _ = queued => (),
+                // Checked again, in case this worker has been retired
+                _ = target.changed() => (),
+                _ = tokio::time::sleep(POLL_INTERVAL) => (),
+            }
+            continue;
+        };
+
+        let job = Job {
+            id: grading_job.id,
+            user_id: grading_job.entry.user_id,
+            task_id: grading_job.entry.task_id,
+            sample: grading_job.entry.sample,
+            started: Instant::now(),
+        };
+        *worker.current.lock().unwrap() = Some(job);
+
+        // Graded in a task of its own, so a panic fails the submission rather than the worker
+        let attempts = grading_job.attempts;
+        let outcome = match tokio::spawn(grade(grading_job)).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("Grading {}-{} panicked: {e}", job.user_id, job.task_id);
+                match retry(job.id, attempts).await {
+                    true => Outcome::Retrying,
+                    false => Outcome::Failed,
+                }
+            }
+        };
+
+        // Left in place if the server gave up waiting for it during shutdown
+        if worker.current.lock().unwrap().take().is_none() {
+            return;
+        }
+
+        let counter = match outcome {
+            Outcome::Graded => {
+                finish(job.id, true).await;
+                &worker.completed
+            }
+            Outcome::Failed => {
+                finish(job.id, false).await;
+                &worker.failed
+            }
+            // Already back in the queue
+            Outcome::Retrying => &worker.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        worker
+            .busy_ms
+            .fetch_add(job.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Claims the next queued submission. `None` => there is none, or the server is shutting down.
+async fn next_job() -> Option<GradingJob> {
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    match database::grading_job::claim_next().await {
+        Ok(Some(job)) => {
+            if !job.entry.sample {
+                progress::publish(job.entry.user_id, job.entry.task_id, GradingEvent::Building);
+            }
+            Some(job)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Could not claim a submission from the grading queue: {e}");
+            None
+        }
+    }
+}
+
+/// Claims the next submission for a remote runner, waiting up to `wait` for one
+pub(super) async fn take(wait: Duration) -> Option<GradingJob> {
+    let deadline = tokio::time::Instant::now() + wait;
+
+    loop {
+        let queued = QUEUED.notified();
+        tokio::pin!(queued);
+        queued.as_mut().enable();
+
+        if let Some(job) = next_job().await {
+            return Some(job);
+        }
+
+        tokio::select! {
+            _ = queued => (),
+            _ = tokio::time::sleep(POLL_INTERVAL) => (),
+            _ = tokio::time::sleep_until(deadline) => return None,
+        }
+    }
+}
+
+/// Records that a claimed submission has been dealt with
+pub(super) async fn finish(job_id: i32, graded: bool) {
+    if let Err(e) = database::grading_job::finish(job_id, graded).await {
+        error!("Could not finish grading job {job_id}: {e}");
+    }
+
+    // Submissions held back by their class's quota may be claimable now
+    QUEUED.notify_waiters();
+}
+
+/// Queues a submission that failed for reasons outside itself again, each time after a longer delay.
+/// `false` => it has been retried `grading_retries` times already.
+pub(super) async fn retry(job_id: i32, attempts: i32) -> bool {
+    let config = config::get();
+    if attempts as u32 >= config.grading_retries {
+        return false;
+    }
+
+    let delay = config.grading_retry_delay_secs as f64 * 2f64.powi(attempts);
+    match database::grading_job::retry_later(job_id, delay).await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Could not queue grading job {job_id} to be tried again: {e}");
+            false
+        }
+    }
+}
+
+/// Puts a claimed submission back in the queue, for someone else to grade
+pub(super) async fn release(job_id: i32) {
+    if let Err(e) = database::grading_job::release(job_id).await {
+        error!("Could not queue grading job {job_id} again: {e}");
+        return;
+    }
+
+    QUEUED.notify_waiters();
+}
+
+/// Stops the workers, leaving whatever they didn't get to for the next start-up
+pub async fn shutdown_queue() {
+    {
+        let _workers = WORKERS.lock().unwrap();
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        TARGET.send_replace(0);
+    }
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    while !WORKERS.lock().unwrap().is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let mut unfinished: Vec<i32> = WORKERS
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|worker| worker.current.lock().unwrap().take())
+        .map(|job| job.id)
+        .collect();
+
+    // Remote runners' reports can't be received any more
+    unfinished.extend(remote::take_claims());
+
+    for &job_id in &unfinished {
+        release(job_id).await;
+    }
+
+    info!(
+        "Grading queue stopped with {} unfinished submissions left for the next start-up",
+        unfinished.len()
+    );
+}
+
+/// Seconds until a submission's results are likely in, judging by how long recent ones took.
+/// `None` => nothing has been graded recently to judge by.
+pub async fn estimated_wait(progress: &JobProgress) -> Option<u64> {
+    let average = match database::grading_job::recent_grading_secs().await {
+        Ok(average) => average?,
+        Err(e) => {
+            error!("Could not estimate grading times: {e}");
+            return None;
+        }
+    };
+
+    let wait = match progress.status {
+        // Each worker grades one submission at a time, so the queue moves in rounds
+        GradingStatus::Queued => {
+            let workers = (*TARGET.borrow()).max(1) as f64;
+            average * (progress.queue_position as f64 / workers).ceil()
+        }
+        _ => average - progress.elapsed_secs.unwrap_or_default(),
+    };
+
+    Some(wait.max(0.0).round() as u64)
+}
+
+/// A snapshot of the queue and each worker
+pub async fn queue_stats() -> GradingPoolStats {
+    let queued = database::grading_job::queued_count()
+        .await
+        .unwrap_or_else(|e| {
+            error!("Could not count queued submissions: {e}");
+            0
+        });
+
+    let workers = WORKERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&id, worker)| WorkerStats {
+            id,
+            completed: worker.completed.load(Ordering::Relaxed),
+            failed: worker.failed.load(Ordering::Relaxed),
+            busy_secs: worker.busy_ms.load(Ordering::Relaxed) as f64 / 1000.0,
+            current: worker.current.lock().unwrap().map(|job| CurrentJob {
+                job_id: job.id,
+                user_id: job.user_id,
+                task_id: job.task_id,
+                sample: job.sample,
+                running_secs: job.started.elapsed().as_secs_f64(),
+            }),
+        })
+        .collect();
+
+    GradingPoolStats {
+        target_workers: *TARGET.borrow(),
+        queued,
+        queue_capacity: config::get().grading_queue_capacity,
+        accepting: !SHUTTING_DOWN.load(Ordering::SeqCst),
+        workers,
+        runners: remote::runner_stats(),
+        warm_containers: warm::warm_containers(),
+    }
+}
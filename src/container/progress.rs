@@ -0,0 +1,68 @@
+//! Passes what happens to submissions in grading on to the students watching them
+//!
+//! Only graded submissions are followed, and only on this server: remote runners report nothing
+//! until they're done, so their jobs show the queue and the outcome but no tests.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use tokio::sync::broadcast;
+
+use crate::model::{grading_event::GradingEvent, submission_response::SubmissionResponse};
+
+/// Events a slow listener can fall behind by before it misses some
+const CAPACITY: usize = 64;
+
+/// The listeners of each student's submission to each task, by (user id, task id)
+static LISTENERS: Mutex<BTreeMap<(i32, i32), broadcast::Sender<GradingEvent>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Starts listening to the student's submissions to the task
+pub fn subscribe(user_id: i32, task_id: i32) -> broadcast::Receiver<GradingEvent> {
+    let mut listeners = LISTENERS.lock().unwrap();
+
+    // Students who stopped listening before anything happened leave their channel behind
+    listeners.retain(|_, tx| tx.receiver_count() > 0);
+
+    listeners
+        .entry((user_id, task_id))
+        .or_insert_with(|| broadcast::channel(CAPACITY).0)
+        .subscribe()
+}
+
+/// Tells whoever is listening what happened to the submission
+pub(super) fn publish(user_id: i32, task_id: i32, event: GradingEvent) {
+    let mut listeners = LISTENERS.lock().unwrap();
+    let Some(tx) = listeners.get(&(user_id, task_id)) else {
+        return;
+    };
+
+    if tx.send(event).is_err() {
+        listeners.remove(&(user_id, task_id));
+    }
+}
+
+/// Publishes test results as they're recorded
+pub(super) struct Reporter {
+    user_id: i32,
+    task_id: i32,
+    /// Tests already published
+    reported: usize,
+}
+
+impl Reporter {
+    pub fn new(user_id: i32, task_id: i32) -> Self {
+        Self {
+            user_id,
+            task_id,
+            reported: 0,
+        }
+    }
+
+    /// Publishes the tests recorded since the last call
+    pub fn report(&mut self, results: &SubmissionResponse) {
+        while let Some(test) = results.test_progress(self.reported) {
+            publish(self.user_id, self.task_id, GradingEvent::Test(test));
+            self.reported += 1;
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Remote runners: other machines that grade submissions from this server's queue
+//!
+//! A runner is this same program started with `grader runner <server url>` (see [`crate::runner`])
+//! and `RUNNER_TOKEN` set to the server's `runner_token`. It claims submissions from the queue
+//! alongside the local workers, builds and runs them with its own container runtime, and reports the
+//! results back. They're stored just as if they had been graded here.
+//!
+//! Runners send a heartbeat while they're up. A submission whose runner stays silent for
+//! `runner_timeout_secs` is put back in the queue for someone else, and a late report for it is
+//! ignored. Jobs are identified by their id in the grading queue.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::{ContainerEntry, check_runtime, pool, progress, run_with_details, store};
+use crate::{
+    config,
+    database::{self, assignment::TaskDetails, grading_job::GradingJob},
+    model::{
+        grading_event::GradingEvent, grading_pool_stats::RunnerStats,
+        submission_response::SubmissionResponse,
+    },
+};
+
+/// How long a claim waits for a submission before answering that there is none
+const CLAIM_WAIT: Duration = Duration::from_secs(20);
+
+/// How often runners that stopped sending heartbeats are looked for
+const MONITOR_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Submissions being graded by runners, by job id
+static CLAIMED: Mutex<BTreeMap<i32, Claim>> = Mutex::new(BTreeMap::new());
+
+/// Every runner heard from since start-up, by id
+static RUNNERS: Mutex<BTreeMap<String, Runner>> = Mutex::new(BTreeMap::new());
+
+struct Claim {
+    runner_id: String,
+    entry: ContainerEntry,
+    attempts: i32,
+}
+
+struct Runner {
+    last_seen: Instant,
+    completed: u64,
+    failed: u64,
+}
+
+/// What runners send with every request
+#[derive(Serialize, Deserialize)]
+pub struct RunnerRequest {
+    pub runner_id: String,
+    /// Only sent with a job's results
+    #[serde(default)]
+    pub report: Option<RunnerReport>,
+}
+
+/// A submission handed to a runner, with everything it needs to grade it
+#[derive(Serialize, Deserialize)]
+pub struct RunnerJob {
+    pub job_id: i32,
+    user_id: i32,
+    task_id: i32,
+    was_late: bool,
+    lang: String,
+    sample: bool,
+    zip_base64: String,
+    task: TaskDetails,
+}
+
+/// What a runner made of a job
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnerReport {
+    Graded(Box<SubmissionResponse>),
+    /// The submission couldn't be graded, with the reason
+    Failed(String),
+    /// The runner couldn't grade it after all, e.g. because its container runtime went down. It's
+    /// queued again for someone else.
+    Returned,
+}
+
+/// Records that the runner is up
+pub fn heartbeat(runner_id: &str) {
+    RUNNERS
+        .lock()
+        .unwrap()
+        .entry(runner_id.to_string())
+        .and_modify(|runner| runner.last_seen = Instant::now())
+        .or_insert_with(|| {
+            info!("Runner {runner_id} connected");
+            Runner {
+                last_seen: Instant::now(),
+                completed: 0,
+                failed: 0,
+            }
+        });
+}
+
+/// Hands the runner the next queued submission, waiting a while for one. `None` => the queue
+/// stayed empty.
+pub async fn claim(runner_id: &str) -> Option<RunnerJob> {
+    heartbeat(runner_id);
+    let GradingJob {
+        id: job_id,
+        entry,
+        attempts,
+    } = pool::take(CLAIM_WAIT).await?;
+
+    let task = match database::assignment::container_get_task_details(
+        entry.task_id,
+        entry.user_id,
+        entry.sample,
+        &entry.lang,
+    )
+    .await
+    {
+        Ok(task) => task,
+        Err(e) => {
+            store(
+                job_id,
+                entry.user_id,
+                entry.task_id,
+                &entry.lang,
+                entry.sample,
+                Err(e),
+            )
+            .await;
+            pool::finish(job_id, false).await;
+            return None;
+        }
+    };
+
+    let job = RunnerJob {
+        job_id,
+        user_id: entry.user_id,
+        task_id: entry.task_id,
+        was_late: entry.was_late,
+        lang: entry.lang.clone(),
+        sample: entry.sample,
+        zip_base64: base64::prelude::BASE64_STANDARD.encode(&entry.zip_file),
+        task,
+    };
+
+    CLAIMED.lock().unwrap().insert(
+        job_id,
+        Claim {
+            runner_id: runner_id.to_string(),
+            entry,
+            attempts,
+        },
+    );
+
+    Some(job)
+}
+
+/// Stores what the runner made of a job. `false` => the job isn't the runner's (any more), so the
+/// report was ignored.
+pub async fn report(runner_id: &str, job_id: i32, report: RunnerReport) -> bool {
+    heartbeat(runner_id);
+
+    let claim = {
+        let mut claimed = CLAIMED.lock().unwrap();
+        match claimed.get(&job_id) {
+            Some(claim) if claim.runner_id == runner_id => claimed.remove(&job_id),
+            _ => None,
+        }
+    };
+    let Some(Claim {
+        entry, attempts, ..
+    }) = claim
+    else {
+        return false;
+    };
+
+    let (user_id, task_id, sample) = (entry.user_id, entry.task_id, entry.sample);
+    let graded = match report {
+        RunnerReport::Graded(results) => {
+            store(job_id, user_id, task_id, &entry.lang, sample, Ok(*results)).await
+        }
+        RunnerReport::Failed(e) => {
+            warn!("Runner {runner_id} could not grade {user_id}-{task_id}: {e}");
+            if pool::retry(job_id, attempts).await {
+                if !sample {
+                    progress::publish(user_id, task_id, GradingEvent::Retrying);
+                }
+                return true;
+            }
+            store(job_id, user_id, task_id, &entry.lang, sample, Err(e)).await
+        }
+        RunnerReport::Returned => {
+            pool::release(job_id).await;
+            if !sample {
+                progress::publish(user_id, task_id, GradingEvent::Queued);
+            }
+            return true;
+        }
+    };
+    pool::finish(job_id, graded).await;
+
+    if let Some(runner) = RUNNERS.lock().unwrap().get_mut(runner_id) {
+        let counter = if graded {
+            &mut runner.completed
+        } else {
+            &mut runner.failed
+        };
+        *counter += 1;
+    }
+
+    true
+}
+
+/// Puts the jobs of runners that stopped sending heartbeats back in the queue
+pub async fn runner_monitor() -> ! {
+    loop {
+        tokio::time::sleep(MONITOR_INTERVAL).await;
+
+        let timeout = Duration::from_secs(config::get().runner_timeout_secs);
+        let abandoned: Vec<i32> = {
+            let runners = RUNNERS.lock().unwrap();
+            let silent = |runner_id: &str| {
+                runners
+                    .get(runner_id)
+                    .is_none_or(|runner| runner.last_seen.elapsed() > timeout)
+            };
+
+            let mut claimed = CLAIMED.lock().unwrap();
+            let job_ids: Vec<i32> = claimed
+                .iter()
+                .filter(|(_, claim)| silent(&claim.runner_id))
+                .map(|(&job_id, _)| job_id)
+                .collect();
+            for job_id in &job_ids {
+                if let Some(claim) = claimed.remove(job_id) {
+                    warn!(
+                        "Runner {} went silent; queueing {}-{} again",
+                        claim.runner_id, claim.entry.user_id, claim.entry.task_id
+                    );
+                }
+            }
+            job_ids
+        };
+
+        for job_id in abandoned {
+            pool::release(job_id).await;
+        }
+    }
+}
+
+/// Takes back every job runners haven't finished, for the server to queue again when it shuts down
+pub fn take_claims() -> Vec<i32> {
+    std::mem::take(&mut *CLAIMED.lock().unwrap())
+        .into_keys()
+        .collect()
+}
+
+/// Every runner heard from since start-up
+pub fn runner_stats() -> Vec<RunnerStats> {
+    let mut jobs: HashMap<String, Vec<i32>> = HashMap::new();
+    for (&job_id, claim) in CLAIMED.lock().unwrap().iter() {
+        jobs.entry(claim.runner_id.clone())
+            .or_default()
+            .push(job_id);
+    }
+
+    RUNNERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(runner_id, runner)| RunnerStats {
+            runner_id: runner_id.clone(),
+            last_seen_secs: runner.last_seen.elapsed().as_secs_f64(),
+            jobs: jobs.remove(runner_id).unwrap_or_default(),
+            completed: runner.completed,
+            failed: runner.failed,
+        })
+        .collect()
+}
+
+/// Grades a job claimed from the server, on this machine
+pub async fn grade_job(job: RunnerJob) -> RunnerReport {
+    let Ok(zip_file) = base64::prelude::BASE64_STANDARD.decode(&job.zip_base64) else {
+        return RunnerReport::Failed("The submission is not valid base64".into());
+    };
+
+    let entry = ContainerEntry {
+        zip_file: zip_file.into(),
+        user_id: job.user_id,
+        task_id: job.task_id,
+        was_late: job.was_late,
+        lang: job.lang,
+        sample: job.sample,
+    };
+    let result = run_with_details(entry, &job.task, None).await;
+
+    // Jobs that failed may have failed because the runtime did
+    if result.is_err() && !check_runtime().await {
+        error!(
+            "Container runtime is unavailable; returning job {}",
+            job.job_id
+        );
+        return RunnerReport::Returned;
+    }
+
+    match result {
+        Ok(results) => RunnerReport::Graded(Box::new(results)),
+        Err(e) => RunnerReport::Failed(e),
+    }
+}
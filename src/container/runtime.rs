@@ -0,0 +1,235 @@
+//! The container runtime submissions are built and run with
+//!
+//! Every runtime is driven through its command-line tool. Docker and Podman accept the same
+//! arguments for nearly everything, so a runtime only describes where they differ. Which one is
+//! used is set by `container_runtime` in the configuration.
+//!
+//! Either can start containers with an alternate OCI runtime set by `oci_runtime`, such as gVisor's
+//! `runsc` or `kata-runtime`, for isolation stronger than plain namespaces. The OCI runtime has to
+//! be installed and registered with the container runtime first.
+//!
+//! Classes that need more than that can be graded in microVMs instead. Each container is then
+//! started by `microvm_oci_runtime`, by default Kata Containers' `kata-fc`, which boots it as a
+//! Firecracker microVM with its own kernel. Builds, limits and cleanup are the same either way.
+//! `isolation` sets which one the deployment uses, and a class can override it.
+
+use std::process::Output;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::config;
+
+/// The runtimes that can be selected in the configuration
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    #[default]
+    Docker,
+    Podman,
+}
+
+/// What grading runs are isolated by
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Isolation {
+    /// Containers started by `oci_runtime`, or the container runtime's default
+    #[default]
+    Container,
+    /// Firecracker microVMs started by `microvm_oci_runtime`
+    Microvm,
+}
+
+impl Isolation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Isolation::Container => "container",
+            Isolation::Microvm => "microvm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Isolation> {
+        match name {
+            "container" => Some(Isolation::Container),
+            "microvm" => Some(Isolation::Microvm),
+            _ => None,
+        }
+    }
+}
+
+pub trait ContainerRuntime: Send + Sync {
+    /// The runtime's command-line tool
+    fn program(&self) -> &'static str;
+
+    /// Arguments that start an image build, before the build's own options
+    fn build_args(&self) -> &'static [&'static str];
+
+    /// `info --format` template that prints the runtime's version
+    fn version_template(&self) -> &'static str;
+
+    /// Everything a build printed, compiler errors included
+    fn build_log(&self, build: &Output) -> String {
+        String::from_utf8_lossy(&build.stderr).into_owned()
+    }
+
+    /// Where a failed build stopped, from the runtime's own messages rather than anything the
+    /// build's steps printed. `None` => it couldn't be told.
+    ///
+    /// BuildKit numbers each step and reports its failure as `#7 ERROR: ...`, while anything the
+    /// step printed has its elapsed time in front, so the step's output can't pass for either.
+    fn failed_step(&self, build: &Output) -> Option<FailedStep> {
+        let log = String::from_utf8_lossy(&build.stderr);
+        let (step, error) = log.lines().find_map(|line| {
+            let (step, rest) = line.split_once(' ')?;
+            Some((step, rest.strip_prefix("ERROR: ")?))
+        })?;
+
+        // The step's header comes first: `#7 [stage-1 3/3] RUN cargo build`
+        let instruction = log.lines().find_map(|line| {
+            let header = line.strip_prefix(step)?.strip_prefix(" [")?;
+            Some(header.split_once("] ")?.1)
+        })?;
+
+        Some(FailedStep {
+            instruction: instruction.to_string(),
+            exit_code: error
+                .rsplit_once("exit code: ")
+                .and_then(|(_, code)| code.trim().parse().ok()),
+            error: error.to_string(),
+        })
+    }
+
+    /// `--mount` value for a writable copy of the image's `dir`, held in memory and capped at
+    /// `size_mb` megabytes, the copy included
+    fn sized_copy_mount(&self, dir: &str, size_mb: i32) -> String {
+        format!(
+            "type=volume,dst={dir},volume-opt=type=tmpfs,volume-opt=device=tmpfs,\
+            volume-opt=o=size={size_mb}m"
+        )
+    }
+
+    /// Starts a command of the runtime's command-line tool
+    fn command(&self) -> Command {
+        Command::new(self.program())
+    }
+
+    /// Starts a `run` command, with containers started by `oci_runtime` if it is set
+    fn run_command(&self, oci_runtime: Option<&str>) -> Command {
+        let mut command = self.command();
+        command.arg("run");
+        if let Some(oci_runtime) = oci_runtime {
+            command.args(["--runtime", oci_runtime]);
+        }
+        command
+    }
+}
+
+/// The step a build failed at
+pub struct FailedStep {
+    /// The step's Dockerfile instruction, e.g. `RUN cargo build`, or what the runtime was doing
+    /// outside of one, like loading an image's metadata
+    pub instruction: String,
+    /// Exit status of the instruction's command. `None` => the step failed without one running
+    /// to the end, e.g. pulling an image or copying files.
+    pub exit_code: Option<i32>,
+    /// The runtime's reason
+    pub error: String,
+}
+
+struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn program(&self) -> &'static str {
+        "docker"
+    }
+
+    fn build_args(&self) -> &'static [&'static str] {
+        &["buildx", "build", "--progress=plain"]
+    }
+
+    fn version_template(&self) -> &'static str {
+        "{{.ServerVersion}}"
+    }
+}
+
+struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn program(&self) -> &'static str {
+        "podman"
+    }
+
+    fn build_args(&self) -> &'static [&'static str] {
+        &["build"]
+    }
+
+    fn version_template(&self) -> &'static str {
+        "{{.Version.Version}}"
+    }
+
+    /// Podman only takes the OCI runtime as a global option, before the subcommand
+    fn run_command(&self, oci_runtime: Option<&str>) -> Command {
+        let mut command = self.command();
+        if let Some(oci_runtime) = oci_runtime {
+            command.args(["--runtime", oci_runtime]);
+        }
+        command.arg("run");
+        command
+    }
+
+    /// Podman copies the image's files into a tmpfs itself, but mounts it `noexec` unless told
+    fn sized_copy_mount(&self, dir: &str, size_mb: i32) -> String {
+        format!("type=tmpfs,dst={dir},tmpfs-size={size_mb}m,tmpcopyup,exec")
+    }
+
+    /// Podman prints the output of each build step to stdout, and only its own errors to stderr:
+    /// `Error: building at STEP "RUN cargo build": while running runtime: exit status 101`
+    fn failed_step(&self, build: &Output) -> Option<FailedStep> {
+        let log = String::from_utf8_lossy(&build.stderr);
+        let failure = log
+            .lines()
+            .find_map(|line| line.strip_prefix("Error: building at STEP \""))?;
+        let (instruction, error) = failure.split_once("\": ")?;
+
+        Some(FailedStep {
+            instruction: instruction.to_string(),
+            exit_code: error
+                .rsplit_once("exit status ")
+                .and_then(|(_, code)| code.trim().parse().ok()),
+            error: error.to_string(),
+        })
+    }
+
+    /// Podman prints the output of each build step to stdout, and only its own errors to stderr
+    fn build_log(&self, build: &Output) -> String {
+        format!(
+            "{}{}",
+            String::from_utf8_lossy(&build.stdout),
+            String::from_utf8_lossy(&build.stderr)
+        )
+    }
+}
+
+/// Label of every grading container, so the janitor can find those left behind
+pub const RUN_LABEL: &str = "securegrade.run";
+
+/// Starts a `run` command of the configured runtime, with the OCI runtime configured for
+/// `isolation`
+pub fn run_command(isolation: Isolation) -> Command {
+    let config = config::get();
+    let oci_runtime = match isolation {
+        Isolation::Container => config.oci_runtime.as_deref(),
+        Isolation::Microvm => Some(config.microvm_oci_runtime.as_str()),
+    };
+    let mut command = runtime().run_command(oci_runtime);
+    command.args(["--label", RUN_LABEL]);
+    command
+}
+
+/// The runtime currently selected in the configuration
+pub fn runtime() -> &'static dyn ContainerRuntime {
+    match config::get().container_runtime {
+        RuntimeKind::Docker => &Docker,
+        RuntimeKind::Podman => &Podman,
+    }
+}
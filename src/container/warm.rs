@@ -0,0 +1,509 @@
+//! Warm containers: language containers started ahead of time, so sample runs skip the image build
+//!
+//! A language opts in with `pool_size` in its manifest's `[warm]` section. That many containers are
+//! kept idling on the language's base image, each with an empty working directory mounted at
+//! [`WORKSPACE`]. A sample run takes one, unpacks the submission into its directory, compiles it
+//! with the manifest's `compile` and runs each test with `run` through `exec`. Whatever is still
+//! running is killed after each test, and the container is removed once the run is done, so no two
+//! submissions ever share one.
+//!
+//! Only plain stdio tasks whose limits the container can take on run warm. Graded submissions,
+//! languages without a base stage or `run`, and tasks needing fixtures, artifacts, the network, the
+//! memory checker or the linter are built as usual.
+//!
+//! The pools are topped up in the background whenever a container is taken. Containers started
+//! from an older base image, ones that stopped, and ones old enough for the janitor to find their
+//! working directory stale are replaced.
+
+use std::{
+    collections::BTreeMap,
+    fs::{Permissions, create_dir_all, read_dir, set_permissions},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Output, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::{process::Command, sync::Notify};
+use tracing::{error, info, warn};
+
+use super::{
+    all_languages, base, get_container_for_language,
+    image::{
+        BuildError, Dialog, KILLED_EXIT_CODE, ResourceLimits, RunOutcome, Stopped, converse, feed,
+    },
+    manifest::{self, Manifest},
+    runtime::{Isolation, run_command, runtime},
+    runtime_available,
+    workdir::Workdir,
+};
+use crate::{
+    config,
+    database::assignment::{TaskDetails, Test},
+    model::{interactive, request::EnvVar, test_method::TestMethod},
+};
+
+/// Label on every warm container, so ones left behind by a crash can be found
+const WARM_LABEL: &str = "securegrade.warm";
+
+/// Where the submission is mounted in a warm container, and where its commands run
+const WORKSPACE: &str = "/workspace";
+
+/// What a warm container runs until it's taken: nothing, until it's stopped
+const IDLE: &str = "trap 'exit 0' TERM; while :; do sleep 3600; done";
+
+/// How long compiling a submission in a warm container may take
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the pools are checked when no container is taken
+const REFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Ready containers by language
+static POOLS: Mutex<BTreeMap<String, Vec<WarmContainer>>> = Mutex::new(BTreeMap::new());
+
+/// Wakes the refill loop when a container is taken
+static TAKEN: Notify = Notify::const_new();
+
+pub struct WarmContainer {
+    id: String,
+    lang: String,
+    /// Tag of the base image it was started from
+    image: String,
+    isolation: Isolation,
+    disk_limit_mb: i32,
+    started: Instant,
+    /// Holds the submission, mounted at [`WORKSPACE`]
+    workdir: Workdir,
+}
+
+impl Drop for WarmContainer {
+    fn drop(&mut self) {
+        // Drop can't wait, so the container is removed in the background
+        let _ = runtime()
+            .command()
+            .args(["rm", "-f", "-v", &self.id])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+    }
+}
+
+/// Whether the task's tests can run in a warm container. Every test needs a memory limit, which
+/// the container takes on before it runs.
+pub fn fits(task: &TaskDetails) -> bool {
+    task.test_method == TestMethod::Stdio
+        && !task.network_access
+        && !task.memory_check
+        && task.lint_weight.is_none()
+        && task.tests.iter().all(|test| {
+            test.fixtures.is_empty()
+                && test.artifact.is_none()
+                && test
+                    .memory_limit_mb
+                    .or(task.default_memory_limit_mb)
+                    .is_some()
+        })
+}
+
+/// Takes a warm container of `lang` that can run within `limits`, if one is ready. It's the
+/// caller's alone, and removed once dropped.
+pub async fn take(lang: &str, limits: &ResourceLimits) -> Option<WarmContainer> {
+    let container = {
+        let mut pools = POOLS.lock().unwrap();
+        let pool = pools.get_mut(lang)?;
+        let index = pool.iter().position(|c| {
+            c.isolation == limits.isolation && c.disk_limit_mb == limits.disk_limit_mb
+        })?;
+        pool.swap_remove(index)
+    };
+    TAKEN.notify_one();
+
+    let updated = container
+        .update(&[
+            "--cpus".into(),
+            limits.cpus.to_string(),
+            "--cpu-shares".into(),
+            limits.cpu_shares.to_string(),
+            "--pids-limit".into(),
+            limits.pids_limit.to_string(),
+        ])
+        .await;
+    match updated {
+        Ok(()) => Some(container),
+        Err(e) => {
+            warn!("Could not limit warm container {}: {e}", container.id);
+            None
+        }
+    }
+}
+
+impl WarmContainer {
+    /// Unpacks the submission into the container's working directory and compiles it with the
+    /// language's `compile`
+    pub async fn prepare(&self, submission: &[u8]) -> Result<(), BuildError> {
+        self.workdir.unpack(submission, "submission").await?;
+        let directory = format!("{}/submission", self.workdir.path());
+        open_up(Path::new(&directory))
+            .map_err(|e| BuildError::Runtime(format!("Could not open up {directory}: {e}")))?;
+
+        let Some(compile) = manifest::get(&self.lang).compile else {
+            return Ok(());
+        };
+
+        let output = self.run(&[], &compile, &[], Some(COMPILE_TIMEOUT)).await;
+        self.reset().await;
+        match output {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(BuildError::compile(format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ))),
+            Err(Stopped::TimedOut) => Err(BuildError::rejected(format!(
+                "Compiling took longer than {} seconds",
+                COMPILE_TIMEOUT.as_secs()
+            ))),
+            Err(Stopped::OutputLimitExceeded) => Err(BuildError::rejected(
+                "The compiler printed more than the output limit".into(),
+            )),
+        }
+    }
+
+    /// Runs the program with a test's input and environment, like `Image::exec`
+    ///
+    /// Err(e) => Error (with message)
+    pub async fn exec(&self, test: &Test, limits: &ResourceLimits) -> Result<RunOutcome, String> {
+        let run = self.run_command()?;
+        self.limit_memory(limits).await?;
+
+        let output = self.run(&test.env, &run, &test.input, test.timeout).await;
+        // Whatever is left running, e.g. a program that timed out, is stopped before the next test
+        self.reset().await;
+        let output = match output {
+            Ok(output) => output,
+            Err(stopped) => return Ok(stopped.into()),
+        };
+
+        if output.status.code() == Some(KILLED_EXIT_CODE) {
+            warn!("Warm container {} ran out of memory", self.id);
+            return Ok(RunOutcome::OutOfMemory);
+        }
+
+        if !output.stderr.is_empty() {
+            let err_str = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            warn!("Error running warm container {}: {}", self.id, err_str);
+            return Err(err_str);
+        }
+
+        Ok(RunOutcome::Output(output.stdout))
+    }
+
+    /// Runs the program through an interactive test's script, like `Image::interact`
+    ///
+    /// Err(e) => Error (with message)
+    pub async fn interact(&self, test: &Test, limits: &ResourceLimits) -> Result<Dialog, String> {
+        let steps = interactive::parse(&String::from_utf8_lossy(&test.input))?;
+        let run = self.run_command()?;
+        self.limit_memory(limits).await?;
+
+        let mut child = self
+            .exec_command(&test.env, &run)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("{e}"))?;
+
+        let dialog = converse(&mut child, steps, test.timeout, true).await;
+        let _ = child.kill().await;
+        self.reset().await;
+
+        if let Dialog::TimedOut = dialog {
+            warn!("Warm container {} Timed Out", self.id);
+        }
+
+        Ok(dialog)
+    }
+
+    fn run_command(&self) -> Result<String, String> {
+        manifest::get(&self.lang)
+            .run
+            .ok_or_else(|| format!("{} has no run command", self.lang))
+    }
+
+    /// `exec`s `script` in the working directory, with `env` set
+    fn exec_command(&self, env: &[EnvVar], script: &str) -> Command {
+        let mut command = runtime().command();
+        command.args(["exec", "-i", "-w", WORKSPACE]);
+        for var in env {
+            command.args(["--env", &format!("{}={}", var.name, var.value)]);
+        }
+        command.arg(&self.id).args(["sh", "-c", script]);
+        command
+    }
+
+    /// Runs `script` to completion, feeding it `input`
+    async fn run(
+        &self,
+        env: &[EnvVar],
+        script: &str,
+        input: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<Output, Stopped> {
+        let mut child = self
+            .exec_command(env, script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        feed(&mut child, input, timeout).await
+    }
+
+    /// Sets the container's memory limit to the test's. [`fits`] makes sure it has one.
+    async fn limit_memory(&self, limits: &ResourceLimits) -> Result<(), String> {
+        let Some(memory_limit_mb) = limits.memory_limit_mb else {
+            return Err("Warm containers only run tests with a memory limit".into());
+        };
+        let memory = format!("{memory_limit_mb}m");
+        self.update(&[
+            "--memory".into(),
+            memory.clone(),
+            "--memory-swap".into(),
+            memory,
+        ])
+        .await
+    }
+
+    async fn update(&self, args: &[String]) -> Result<(), String> {
+        let output = runtime()
+            .command()
+            .arg("update")
+            .args(args)
+            .arg(&self.id)
+            .output()
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        }
+    }
+
+    /// Kills every process in the container but the idle one
+    async fn reset(&self) {
+        let _ = runtime()
+            .command()
+            .args(["exec", &self.id, "sh", "-c", "kill -9 -1"])
+            .output()
+            .await;
+    }
+}
+
+/// Lets the container's user, whoever it is, write anywhere in `dir`
+fn open_up(dir: &Path) -> std::io::Result<()> {
+    set_permissions(dir, Permissions::from_mode(0o777))?;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            open_up(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Ready warm containers by language
+pub fn warm_containers() -> BTreeMap<String, usize> {
+    POOLS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(lang, pool)| (lang.clone(), pool.len()))
+        .collect()
+}
+
+/// Keeps every language's pool of warm containers full, starting with removing any left behind
+pub async fn warm_pools() -> ! {
+    remove_leftovers().await;
+
+    loop {
+        if runtime_available() {
+            refill().await;
+        }
+
+        tokio::select! {
+            _ = TAKEN.notified() => (),
+            _ = tokio::time::sleep(REFILL_INTERVAL) => (),
+        }
+    }
+}
+
+async fn refill() {
+    let languages = match all_languages() {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Could not list languages: {e}");
+            return;
+        }
+    };
+    let running = warm_container_ids(false).await;
+    let max_age = Duration::from_secs(config::get().janitor_max_age_secs / 2);
+
+    POOLS
+        .lock()
+        .unwrap()
+        .retain(|lang, _| languages.contains(lang));
+
+    for lang in languages {
+        let manifest = manifest::get(&lang);
+        let size = match manifest.disabled || manifest.run.is_none() {
+            true => 0,
+            false => manifest.warm.pool_size,
+        };
+        let image = match get_container_for_language(&lang) {
+            Some(container) if size > 0 => base::ensure(&container).await,
+            _ => None,
+        };
+        let Some(image) = image else {
+            POOLS.lock().unwrap().remove(&lang);
+            continue;
+        };
+
+        let limits = default_limits(&manifest);
+        let ready = {
+            let mut pools = POOLS.lock().unwrap();
+            let pool = pools.entry(lang.clone()).or_default();
+            pool.retain(|c| {
+                c.image == image
+                    && c.isolation == limits.isolation
+                    && c.disk_limit_mb == limits.disk_limit_mb
+                    && c.started.elapsed() < max_age
+                    && running.as_ref().is_none_or(|ids| ids.contains(&c.id))
+            });
+            pool.truncate(size);
+            pool.len()
+        };
+
+        for _ in ready..size {
+            match start(&lang, &image, &manifest, &limits).await {
+                Ok(container) => POOLS
+                    .lock()
+                    .unwrap()
+                    .entry(lang.clone())
+                    .or_default()
+                    .push(container),
+                Err(e) => {
+                    warn!("Could not start a warm {lang} container: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// What a warm container is started with, before it's taken: the language's default limits,
+/// without the network
+fn default_limits(manifest: &Manifest) -> ResourceLimits {
+    let config = config::get();
+    let limits = manifest.limits;
+    ResourceLimits {
+        memory_limit_mb: limits.memory_limit_mb.or(config.default_memory_limit_mb),
+        cpus: limits.cpus.unwrap_or(config.default_cpus),
+        cpu_shares: config.cpu_shares,
+        pids_limit: limits.pids_limit.unwrap_or(config.default_pids_limit),
+        disk_limit_mb: limits.disk_limit_mb.unwrap_or(config.default_disk_limit_mb),
+        network_access: false,
+        isolation: config.isolation,
+    }
+}
+
+async fn start(
+    lang: &str,
+    image: &str,
+    manifest: &Manifest,
+    limits: &ResourceLimits,
+) -> Result<WarmContainer, String> {
+    let workdir = Workdir::new(&format!("warm-{lang}"));
+    workdir.create().await.map_err(reason)?;
+    let submission = format!("{}/submission", workdir.path());
+    create_dir_all(&submission).map_err(|e| format!("{e}"))?;
+    open_up(Path::new(&submission)).map_err(|e| format!("{e}"))?;
+
+    let output = run_command(limits.isolation)
+        .args(["-d", "--label", WARM_LABEL])
+        .args(limits.args())
+        .args(manifest.sandbox.args(None, limits.disk_limit_mb))
+        .args([
+            "--mount",
+            &format!("type=bind,src={submission},dst={WORKSPACE}"),
+        ])
+        .args(["-w", WORKSPACE, "--entrypoint", "sh", image, "-c", IDLE])
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {e}", runtime().program()))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    info!("Started a warm {lang} container");
+    Ok(WarmContainer {
+        id: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        lang: lang.to_string(),
+        image: image.to_string(),
+        isolation: limits.isolation,
+        disk_limit_mb: limits.disk_limit_mb,
+        started: Instant::now(),
+        workdir,
+    })
+}
+
+fn reason(e: BuildError) -> String {
+    match e {
+        BuildError::Runtime(e) => e,
+        BuildError::Compile { log, .. } => log,
+    }
+}
+
+/// Ids of the warm containers, running ones only unless `all`. `None` => they couldn't be listed.
+async fn warm_container_ids(all: bool) -> Option<Vec<String>> {
+    let output = runtime()
+        .command()
+        .args(["ps", "-q", "--no-trunc", "--filter"])
+        .arg(format!("label={WARM_LABEL}"))
+        .args(all.then_some("-a"))
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|id| id.trim().to_string())
+            .collect(),
+    )
+}
+
+/// Removes the warm containers a previous run of the server left behind
+async fn remove_leftovers() {
+    let Some(ids) = warm_container_ids(true).await else {
+        return;
+    };
+    if ids.is_empty() {
+        return;
+    }
+
+    info!("Removing {} warm containers left behind", ids.len());
+    let _ = runtime()
+        .command()
+        .args(["rm", "-f", "-v"])
+        .args(ids)
+        .output()
+        .await;
+}
@@ -0,0 +1,149 @@
+//! Working directories that submissions are unpacked and built in
+//!
+//! Every build gets its own directory under [`ROOT`], so two builds of the same submission never
+//! share one, and the directory is removed when the build ends, however it ends. Before anything is
+//! written, the disk must have `workdir_min_free_mb` free. A submission is only unpacked if there's
+//! room for `workdir_quota_mb` on top of that, and unpacking stops if it grows past the quota.
+//!
+//! Running low on disk is the grader's problem, so the submission is graded again later. A
+//! submission too large to unpack is the student's problem, and they're told why.
+
+use std::{
+    fs::{create_dir_all, remove_dir_all},
+    path::PathBuf,
+};
+
+use tokio::process::Command;
+use tracing::{error, warn};
+
+use super::image::BuildError;
+use crate::{
+    archive::{self, ExtractError},
+    config,
+};
+
+/// Where every working directory is made
+pub const ROOT: &str = "/tmp/securegrade/builds";
+
+const MB: u64 = 1024 * 1024;
+
+pub struct Workdir {
+    path: String,
+}
+
+impl Workdir {
+    /// A new, unique working directory named after `name`. Nothing is written until
+    /// [`Workdir::create`].
+    pub fn new(name: &str) -> Workdir {
+        let suffix: String = rand::random::<[u8; 8]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        Workdir {
+            path: format!("{ROOT}/{name}-{suffix}"),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Makes the directory, if there is room on the disk for it
+    pub async fn create(&self) -> Result<(), BuildError> {
+        create_dir_all(ROOT).map_err(|e| runtime_error("create", ROOT, e))?;
+        ensure_free_space(0).await?;
+        create_dir_all(&self.path).map_err(|e| runtime_error("create", &self.path, e))
+    }
+
+    /// Writes `contents` to `name` in the directory
+    pub fn write(&self, name: &str, contents: &[u8]) -> Result<(), BuildError> {
+        let path = format!("{}/{name}", self.path);
+        std::fs::write(&path, contents).map_err(|e| runtime_error("write", &path, e))
+    }
+
+    /// Unpacks the submission into `destination` in the directory, within the limits set for
+    /// [`archive`]s
+    pub async fn unpack(&self, submission: &[u8], destination: &str) -> Result<(), BuildError> {
+        let limits = archive::Limits::from_config();
+        // There has to be room for the submission at its largest
+        ensure_free_space(limits.max_total_bytes).await?;
+
+        let destination = PathBuf::from(format!("{}/{destination}", self.path));
+        create_dir_all(&destination).map_err(|e| runtime_error("create", &self.path, e))?;
+
+        let submission = submission.to_vec();
+        let unpacked = tokio::task::spawn_blocking(move || {
+            archive::extract(&submission, &destination, &limits)
+        })
+        .await;
+
+        match unpacked {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(ExtractError::Rejected(reason))) => Err(BuildError::rejected(reason)),
+            Ok(Err(ExtractError::Io(e))) => Err(runtime_error("unpack into", &self.path, e)),
+            Err(e) => Err(BuildError::Runtime(format!("{e}"))),
+        }
+    }
+}
+
+impl Drop for Workdir {
+    fn drop(&mut self) {
+        if let Err(e) = remove_dir_all(&self.path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Could not remove working directory {}: {e}", self.path);
+        }
+    }
+}
+
+fn runtime_error(action: &str, path: &str, e: std::io::Error) -> BuildError {
+    error!("Could not {action} {path}: {e}");
+    BuildError::Runtime(format!("Could not {action} the working directory: {e}"))
+}
+
+/// Fails unless `needed` bytes can be written while leaving `workdir_min_free_mb` free
+async fn ensure_free_space(needed: u64) -> Result<(), BuildError> {
+    let free = free_space().await?;
+    let min_free = config::get().workdir_min_free_mb * MB;
+
+    if free < needed + min_free {
+        error!(
+            "Only {} MB free in {ROOT}; not unpacking another submission",
+            free / MB
+        );
+        return Err(BuildError::Runtime(format!(
+            "Not enough disk space to grade the submission ({} MB free)",
+            free / MB
+        )));
+    }
+
+    Ok(())
+}
+
+/// Bytes available on the disk [`ROOT`] is on
+async fn free_space() -> Result<u64, BuildError> {
+    let output = match Command::new("df").args(["-Pk", ROOT]).output().await {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Err(BuildError::Runtime(format!(
+                "Could not check free disk space: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Err(e) => {
+            return Err(BuildError::Runtime(format!(
+                "Could not check free disk space: {e}"
+            )));
+        }
+    };
+
+    // The second line holds the numbers, the fourth of which is the space available in KiB
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| BuildError::Runtime("Could not check free disk space".into()))
+}
@@ -0,0 +1,136 @@
+//! Writes grades as CSV, for import into campus gradebooks
+//!
+//! Besides the plain layout, there are layouts for Canvas's and Moodle's grade imports. Both match
+//! students by the SIS ID an admin recorded for them, or failing that by their username, which is
+//! assumed to be the campus login. Assignments are columns named after them; Canvas creates the
+//! ones it doesn't have, out of 100 points, and Moodle asks which grade item each one goes to.
+//!
+//! Fields are quoted as RFC 4180 describes. Names are written by students, so text that a
+//! spreadsheet would take for a formula is prefixed with `'` to keep it text. Scores are
+//! percentages, and blank where nothing was submitted.
+
+use std::borrow::Cow;
+
+use crate::model::{assignment_grade::AssignmentGrade, gradebook::Gradebook};
+
+/// One assignment's scores, a row per student
+pub fn assignment_grades(grades: &[AssignmentGrade]) -> String {
+    let mut csv = String::new();
+    push_row(&mut csv, ["username", "name", "score", "peer_score"]);
+
+    for grade in grades {
+        push_row(
+            &mut csv,
+            [
+                field(&grade.username),
+                field(&grade.name),
+                percent(Some(grade.score)).into(),
+                percent(grade.peer_score).into(),
+            ],
+        );
+    }
+
+    csv
+}
+
+/// Every assignment of the class, a column per assignment and a row per student
+pub fn gradebook(gradebook: &Gradebook) -> String {
+    let mut csv = String::new();
+
+    let header = ["username".into(), "name".into()]
+        .into_iter()
+        .chain(gradebook.assignments.iter().map(|a| field(&a.name)));
+    push_row(&mut csv, header);
+
+    for student in &gradebook.students {
+        let row = [field(&student.username), field(&student.name)]
+            .into_iter()
+            .chain(student.cells.iter().map(|c| percent(c.score).into()));
+        push_row(&mut csv, row);
+    }
+
+    csv
+}
+
+/// Canvas's Gradebook import layout. The row under the header gives each assignment's points.
+pub fn canvas(gradebook: &Gradebook, class_number: &str) -> String {
+    let mut csv = String::new();
+
+    let header = ["Student", "ID", "SIS User ID", "SIS Login ID", "Section"]
+        .map(Cow::from)
+        .into_iter()
+        .chain(gradebook.assignments.iter().map(|a| field(&a.name)));
+    push_row(&mut csv, header);
+
+    let points = ["    Points Possible", "", "", "", ""]
+        .map(Cow::from)
+        .into_iter()
+        .chain(gradebook.assignments.iter().map(|_| "100.00".into()));
+    push_row(&mut csv, points);
+
+    for student in &gradebook.students {
+        let sortable_name = format!("{}, {}", student.last_name, student.first_name);
+        let row = [
+            field(&sortable_name),
+            "".into(),
+            field(student.sis_id.as_deref().unwrap_or_default()),
+            field(&student.username),
+            field(class_number),
+        ]
+        .into_iter()
+        .chain(student.cells.iter().map(|c| percent(c.score).into()));
+        push_row(&mut csv, row);
+    }
+
+    csv
+}
+
+/// Moodle's grade import layout
+pub fn moodle(gradebook: &Gradebook) -> String {
+    let mut csv = String::new();
+
+    let header = ["First name", "Last name", "ID number", "Username"]
+        .map(Cow::from)
+        .into_iter()
+        .chain(gradebook.assignments.iter().map(|a| field(&a.name)));
+    push_row(&mut csv, header);
+
+    for student in &gradebook.students {
+        let row = [
+            field(&student.first_name),
+            field(&student.last_name),
+            field(student.sis_id.as_deref().unwrap_or_default()),
+            field(&student.username),
+        ]
+        .into_iter()
+        .chain(student.cells.iter().map(|c| percent(c.score).into()));
+        push_row(&mut csv, row);
+    }
+
+    csv
+}
+
+fn push_row<'a>(csv: &mut String, fields: impl IntoIterator<Item = impl Into<Cow<'a, str>>>) {
+    let fields: Vec<Cow<str>> = fields.into_iter().map(Into::into).collect();
+    csv.push_str(&fields.join(","));
+    csv.push_str("\r\n");
+}
+
+/// Escapes a text field
+fn field(value: &str) -> Cow<'_, str> {
+    let value = match value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => Cow::Owned(format!("'{value}")),
+        false => Cow::Borrowed(value),
+    };
+
+    match value.contains([',', '"', '\n', '\r']) {
+        true => Cow::Owned(format!("\"{}\"", value.replace('"', "\"\""))),
+        false => value,
+    }
+}
+
+fn percent(score: Option<f32>) -> String {
+    score
+        .map(|s| format!("{:.2}", s * 100.0))
+        .unwrap_or_default()
+}
@@ -4,18 +4,51 @@
 //! 
 //! Submodules are:
 //! - assignment
+//! - attachment
 //! - auth
+//! - category
+//! - deletion
+//! - email
+//! - export
+//! - honor
+//! - malware
+//! - notification
+//! - submission
 //! - user
 //! - operations (for generic operations, will be refactored out)
 
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use sqlx::{
+    Pool, Postgres,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
 use std::env::var;
+use std::str::FromStr;
 use std::sync::LazyLock;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+use crate::{config, model::pool_stats::PoolStats};
+
 pub mod assignment;
+pub mod attachment;
 pub mod auth;
+pub mod blob;
+pub mod category;
+pub mod curve;
+pub mod deletion;
+pub mod email;
+pub mod export;
+pub mod gradebook;
+pub mod grading_job;
+pub mod honor;
+pub mod language;
+pub mod malware;
+pub mod notification;
 pub mod operations;
+pub mod peer_review;
+pub mod sample_run;
+pub mod similarity;
+pub mod submission;
 pub mod user;
 
 /// Static, global postgres connection pool
@@ -56,9 +89,22 @@ pub async fn init_database() -> Result<(), String> {
         return Err("PSQL_PASS environment variable not present".into());
     };
 
+    let config = config::get();
+
+    let connect_options =
+        match PgConnectOptions::from_str(&format!("postgres://{}:{}@localhost", name, pass)) {
+            Ok(o) => o.options([(
+                "statement_timeout",
+                config.db_statement_timeout_ms.to_string(),
+            )]),
+            Err(e) => return Err(format!("{e}")),
+        };
+
     let pool = match PgPoolOptions::new()
-        .max_connections(10)
-        .connect(&format!("postgres://{}:{}@localhost", name, pass))
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(Duration::from_secs(config.db_acquire_timeout_secs))
+        .connect_with(connect_options)
         .await
     {
         Ok(p) => p,
@@ -197,8 +243,8 @@ pub async fn init_database() -> Result<(), String> {
                 id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
                 task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
                 test_name TEXT,
-                input TEXT NOT NULL,
-                output TEXT NOT NULL,
+                input BYTEA NOT NULL,
+                output BYTEA NOT NULL,
                 public BOOLEAN NOT NULL DEFAULT FALSE,
                 timeout INTEGER
             );",
@@ -209,6 +255,23 @@ pub async fn init_database() -> Result<(), String> {
             return Err(format!("Could not create test table: {e}"));
         }
 
+        // Test data used to be TEXT, which can't hold binary or non-UTF-8 data
+        for column in ["input", "output"] {
+            if let Err(e) = sqlx::query(&format!(
+                "DO $$ BEGIN
+                    IF (SELECT data_type FROM information_schema.columns
+                        WHERE table_name = 'tests' AND column_name = '{column}') = 'text' THEN
+                        ALTER TABLE tests ALTER COLUMN {column} TYPE BYTEA USING convert_to({column}, 'UTF8');
+                    END IF;
+                END $$;"
+            ))
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("Could not convert test {column} column to BYTEA: {e}"));
+            }
+        }
+
         // And assignment-class associations
         if let Err(e) = sqlx::query(
             "CREATE TABLE IF NOT EXISTS assignment_class (
@@ -241,24 +304,1075 @@ pub async fn init_database() -> Result<(), String> {
             return Err(format!("Could not create user_assignment_grade table: {e}"));
         }
 
+        // Set when an instructor changes a task's tests after this submission was graded
         if let Err(e) = sqlx::query(
-            "CREATE TABLE IF NOT EXISTS class_join_code (
-                join_code TEXT PRIMARY KEY,
-                class_number TEXT REFERENCES classes (class_number),
-                expiration TIMESTAMPTZ NOT NULL
-            );"
-        ).execute(&mut *transaction)
-        .await {
-            return Err(format!("Could not create class_join_code table: {e}"));
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS needs_regrade BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add needs_regrade column: {e}"));
         }
 
-        if let Err(e) = transaction.commit().await {
-            return Err(format!("Could not commit table-creation transaction: {e}"));
-        };
-    }
+        // Set when a submission couldn't be graded because the container runtime was down.
+        // The language is kept so the submission can be queued again once it is back.
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS grading_delayed BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add grading_delayed column: {e}"));
+        }
 
-    let mut lock = POSTGRES.write().await;
-    *lock = Some(pool);
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS submission_lang TEXT;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add submission_lang column: {e}"));
+        }
 
-    Ok(())
+        // Fingerprint of the language's container definition when the submission was graded
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS toolchain_version TEXT;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add toolchain_version column: {e}"));
+        }
+
+        // Placement of an earlier task in the same assignment that must be passed first
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS prerequisite_placement INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add prerequisite_placement column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS prerequisite_threshold REAL NOT NULL DEFAULT 1;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add prerequisite_threshold column: {e}"));
+        }
+
+        // One of `ComparisonMode::as_str`
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS comparison TEXT NOT NULL DEFAULT 'trimmed';",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add comparison column: {e}"));
+        }
+
+        // Tolerances for numeric comparison. NULL => `Tolerance::default()`
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS abs_tolerance DOUBLE PRECISION;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add abs_tolerance column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS rel_tolerance DOUBLE PRECISION;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add rel_tolerance column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS points REAL NOT NULL DEFAULT 1;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add points column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS partial_credit BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add partial_credit column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tests ADD COLUMN IF NOT EXISTS hint TEXT;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add hint column: {e}"));
+        }
+
+        // Position of the test within its task. Tests added before this existed are ordered by id.
+        if let Err(e) = sqlx::query("ALTER TABLE tests ADD COLUMN IF NOT EXISTS placement INTEGER;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add test placement column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tests ADD COLUMN IF NOT EXISTS memory_limit_mb INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add memory_limit_mb column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tests ADD COLUMN IF NOT EXISTS artifact TEXT;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add artifact column: {e}"));
+        }
+
+        // Sample tests only run in sample runs, and never count towards a grade
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS sample BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add sample column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tests ADD COLUMN IF NOT EXISTS interactive BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add interactive column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS cpus REAL;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add cpus column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS pids_limit INTEGER;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add pids_limit column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS ordered_tests BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add ordered_tests column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS stop_on_failure BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add stop_on_failure column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS memory_check BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add memory_check column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS memory_error_penalty REAL;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add memory_error_penalty column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS test_command TEXT;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add test_command column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS lint_weight REAL;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add lint_weight column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS network_access BOOLEAN NOT NULL DEFAULT FALSE;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add network_access column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS disk_limit_mb INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add disk_limit_mb column: {e}"));
+        }
+
+        // The instructor's solution expected outputs are generated from, as an uploaded zip
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS reference_solution BYTEA;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add reference_solution column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS reference_language TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add reference_language column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS submitted_at TIMESTAMPTZ;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add submitted_at column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS graded_at TIMESTAMPTZ;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add graded_at column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS template_filename TEXT;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add template_filename column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS variant_descriptions TEXT[] NOT NULL DEFAULT '{}';",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add variant_descriptions column: {e}"));
+        }
+
+        // NULL => the test runs for every variant of its task
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tests ADD COLUMN IF NOT EXISTS variant INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add variant column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE users ADD COLUMN IF NOT EXISTS preferred_language TEXT;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add preferred_language column: {e}"));
+        }
+
+        // NULL => any supported language may be used
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignments ADD COLUMN IF NOT EXISTS allowed_languages TEXT[];",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add allowed_languages column: {e}"));
+        }
+
+        // honor_pledge_mode = { 'none' | 'once' | 'per_submission' }
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE classes
+                ADD COLUMN IF NOT EXISTS honor_pledge TEXT,
+                ADD COLUMN IF NOT EXISTS honor_pledge_mode TEXT NOT NULL DEFAULT 'none';",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add honor pledge columns: {e}"));
+        }
+
+        // isolation = { 'container' | 'microvm' }, NULL => the deployment's default
+        if let Err(e) =
+            sqlx::query("ALTER TABLE classes ADD COLUMN IF NOT EXISTS isolation TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add isolation column: {e}"));
+        }
+
+        // The most of the class's submissions graded at once. NULL => no limit.
+        if let Err(e) =
+            sqlx::query("ALTER TABLE classes ADD COLUMN IF NOT EXISTS grading_quota INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add grading_quota column: {e}"));
+        }
+
+        // Course-wide acknowledgements have no assignment or task
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS honor_acknowledgements (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                class_number TEXT NOT NULL REFERENCES classes (class_number) ON UPDATE CASCADE ON DELETE CASCADE,
+                assignment_id INTEGER REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                acknowledged_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create honor_acknowledgements table: {e}"));
+        }
+
+        // Files attached to a whole assignment, as opposed to a single task's material
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS assignment_attachments (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                assignment_id INTEGER NOT NULL REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                filename TEXT NOT NULL,
+                data BYTEA NOT NULL,
+                uploaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create assignment_attachments table: {e}"));
+        }
+
+        // result_visibility = { 'score_only' | 'pass_fail' | 'public_diffs' | 'everything' }
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignments ADD COLUMN IF NOT EXISTS result_visibility TEXT NOT NULL DEFAULT 'public_diffs';",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add result_visibility column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignments ADD COLUMN IF NOT EXISTS grace_period_minutes INT NOT NULL DEFAULT 0;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add grace_period_minutes column: {e}"));
+        }
+
+        // JSON array of `LateTier`s
+        if let Err(e) =
+            sqlx::query("ALTER TABLE assignments ADD COLUMN IF NOT EXISTS late_tiers TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add late_tiers column: {e}"));
+        }
+
+        // Multiplier from the assignment's late tiers at submission time. NULL => no tiers.
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS late_multiplier REAL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add late_multiplier column: {e}"));
+        }
+
+        // Assignment groups, weighted when computing course grades
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS assignment_categories (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                class_number TEXT NOT NULL REFERENCES classes(class_number) ON UPDATE CASCADE ON DELETE CASCADE,
+                category_name TEXT NOT NULL,
+                weight REAL NOT NULL
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create assignment_categories table: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignments ADD COLUMN IF NOT EXISTS category_id INTEGER REFERENCES assignment_categories(id) ON DELETE SET NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add category_id column: {e}"));
+        }
+
+        // JSON `PeerReviewSettings`. NULL => not peer reviewed.
+        if let Err(e) =
+            sqlx::query("ALTER TABLE assignments ADD COLUMN IF NOT EXISTS peer_review TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add peer_review column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS class_join_code (
+                join_code TEXT PRIMARY KEY,
+                class_number TEXT REFERENCES classes (class_number),
+                expiration TIMESTAMPTZ NOT NULL
+            );"
+        ).execute(&mut *transaction)
+        .await {
+            return Err(format!("Could not create class_join_code table: {e}"));
+        }
+
+        // Background bulk-download jobs
+        // status = { 'queued' | 'building' | 'ready' | 'failed' }
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS export_jobs (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                class_number TEXT NOT NULL REFERENCES classes (class_number),
+                assignment_id INTEGER REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                status TEXT NOT NULL DEFAULT 'queued',
+                archive_path TEXT,
+                download_token TEXT UNIQUE,
+                error TEXT,
+                requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                expiration TIMESTAMPTZ
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create export_jobs table: {e}"));
+        }
+
+        // Research exports cover the whole class rather than one assignment
+        if let Err(e) =
+            sqlx::query("ALTER TABLE export_jobs ALTER COLUMN assignment_id DROP NOT NULL;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not update export_jobs table: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS notifications (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                message TEXT NOT NULL,
+                link TEXT,
+                created TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                seen BOOLEAN NOT NULL DEFAULT FALSE
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create notifications table: {e}"));
+        }
+
+        // Admin overrides of the built-in email templates, keyed by `EmailKind::as_str`
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS email_templates (
+                template_name TEXT PRIMARY KEY,
+                subject TEXT NOT NULL,
+                body TEXT NOT NULL
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create email_templates table: {e}"));
+        }
+
+        // scores = JSON array with one entry per rubric criterion. NULL => not reviewed yet.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS peer_reviews (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                assignment_id INTEGER NOT NULL REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                reviewer_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                reviewee_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                scores TEXT,
+                comments TEXT,
+                reviewed_at TIMESTAMPTZ,
+                UNIQUE (assignment_id, reviewer_id, reviewee_id)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create peer_reviews table: {e}"));
+        }
+
+        // Files placed in the container's working directory while a test runs
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS test_fixtures (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                test_id INTEGER NOT NULL REFERENCES tests(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                filename TEXT NOT NULL,
+                contents BYTEA NOT NULL,
+                UNIQUE (test_id, filename)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create test_fixtures table: {e}"));
+        }
+
+        // Environment variables set while a test runs
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS test_env_vars (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                test_id INTEGER NOT NULL REFERENCES tests(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                value TEXT NOT NULL,
+                secret BOOLEAN NOT NULL DEFAULT FALSE,
+                UNIQUE (test_id, name)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create test_env_vars table: {e}"));
+        }
+
+        // The latest sample run of each task by each student. json_results = NULL => still running.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS sample_runs (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                language TEXT NOT NULL,
+                submitted_at TIMESTAMPTZ NOT NULL,
+                json_results BYTEA,
+                PRIMARY KEY (user_id, task_id)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create sample_runs table: {e}"));
+        }
+
+        // Test IO too long to keep in a graded result, in full
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS full_outputs (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                test_index INTEGER NOT NULL,
+                field TEXT NOT NULL,
+                contents BYTEA NOT NULL,
+                PRIMARY KEY (user_id, task_id, test_index, field)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create full_outputs table: {e}"));
+        }
+
+        // The grading queue. The submission is let go once the job has run.
+        // status = { 'queued' | 'building' | 'running' | 'done' | 'failed' }
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS grading_jobs (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                was_late BOOLEAN NOT NULL DEFAULT FALSE,
+                lang TEXT NOT NULL,
+                sample BOOLEAN NOT NULL DEFAULT FALSE,
+                submission_zip BYTEA,
+                status TEXT NOT NULL DEFAULT 'queued',
+                queued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                started_at TIMESTAMPTZ,
+                finished_at TIMESTAMPTZ
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create grading_jobs table: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "CREATE INDEX IF NOT EXISTS grading_jobs_status ON grading_jobs (status, id);",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not index grading_jobs table: {e}"));
+        }
+
+        // Looked up for each claim, to share the queue fairly
+        if let Err(e) = sqlx::query(
+            "CREATE INDEX IF NOT EXISTS grading_jobs_started ON grading_jobs (started_at);",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not index grading_jobs table: {e}"));
+        }
+
+        // Jobs that failed for reasons outside the submission are retried, no sooner than run_after
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE grading_jobs ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not update grading_jobs table: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE grading_jobs ADD COLUMN IF NOT EXISTS run_after TIMESTAMPTZ;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not update grading_jobs table: {e}"));
+        }
+
+        // What the build printed, kept for instructors when a submission didn't compile
+        if let Err(e) =
+            sqlx::query("ALTER TABLE grading_jobs ADD COLUMN IF NOT EXISTS build_log TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not update grading_jobs table: {e}"));
+        }
+
+        // How long the build and the tests took, for grading metrics
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE grading_jobs
+                ADD COLUMN IF NOT EXISTS build_secs FLOAT8,
+                ADD COLUMN IF NOT EXISTS run_secs FLOAT8;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not update grading_jobs table: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "CREATE INDEX IF NOT EXISTS grading_jobs_finished ON grading_jobs (finished_at);",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not index grading_jobs table: {e}"));
+        }
+
+        // Submissions the malware scanner flagged. The upload itself isn't kept.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS malware_incidents (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                class_number TEXT NOT NULL REFERENCES classes (class_number) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                sample BOOLEAN NOT NULL DEFAULT FALSE,
+                signature TEXT NOT NULL,
+                sha256 TEXT NOT NULL,
+                size_bytes BIGINT NOT NULL,
+                detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create malware_incidents table: {e}"));
+        }
+
+        // Every submission of a task, numbered from 1 per student. user_task_grade holds the one
+        // that counts, which is the latest.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS submissions (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                attempt INTEGER NOT NULL,
+                assignment_id INTEGER NOT NULL REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                submission_zip BYTEA,
+                submission_lang TEXT,
+                was_late BOOLEAN,
+                late_multiplier REAL,
+                submitted_at TIMESTAMPTZ,
+                json_results BYTEA,
+                grade FLOAT4,
+                error TEXT,
+                graded_at TIMESTAMPTZ,
+                PRIMARY KEY (user_id, task_id, attempt)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create submissions table: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE user_task_grade ADD COLUMN IF NOT EXISTS attempt INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add attempt column: {e}"));
+        }
+
+        // Submissions made before attempts were kept become the first attempt
+        if let Err(e) = sqlx::query(
+            "INSERT INTO submissions (user_id, task_id, attempt, assignment_id, submission_zip, submission_lang, was_late, late_multiplier, submitted_at, json_results, grade, error, graded_at)
+            SELECT user_id, task_id, 1, assignment_id, submission_zip, submission_lang, was_late, late_multiplier, submitted_at, json_results, grade, error, graded_at
+            FROM user_task_grade
+            WHERE attempt IS NULL
+            ON CONFLICT DO NOTHING;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not copy submissions: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("UPDATE user_task_grade SET attempt = 1 WHERE attempt IS NULL;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not number submissions: {e}"));
+        }
+
+        // Submissions allowed per student and task. The task's own limit wins over the
+        // assignment's. NULL => unlimited.
+        if let Err(e) =
+            sqlx::query("ALTER TABLE assignments ADD COLUMN IF NOT EXISTS max_attempts INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add max_attempts column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS max_attempts INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add max_attempts column: {e}"));
+        }
+
+        // Limits instructors set for single students, in place of the task's. NULL => unlimited.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS attempt_overrides (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                max_attempts INTEGER,
+                PRIMARY KEY (user_id, task_id)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create attempt_overrides table: {e}"));
+        }
+
+        // Minutes between a student's submissions to the task. NULL => no wait.
+        if let Err(e) =
+            sqlx::query("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS cooldown_minutes INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add cooldown_minutes column: {e}"));
+        }
+
+        // When submissions to the assignment close for good. NULL => never.
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignments ADD COLUMN IF NOT EXISTS accept_until TIMESTAMPTZ;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add accept_until column: {e}"));
+        }
+
+        // Hex-encoded SHA-256 of each attempt's archive, handed to the student as a receipt
+        if let Err(e) = sqlx::query("ALTER TABLE submissions ADD COLUMN IF NOT EXISTS sha256 TEXT;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add sha256 column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "UPDATE submissions SET sha256 = encode(sha256(submission_zip), 'hex')
+            WHERE sha256 IS NULL AND submission_zip IS NOT NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not hash submissions: {e}"));
+        }
+
+        // Keys of archives, materials and other files in blob storage. The BYTEA columns they
+        // replace are only read to move what older versions stored there.
+        for (table, column) in [
+            ("submissions", "submission_key"),
+            ("user_task_grade", "submission_key"),
+            ("grading_jobs", "submission_key"),
+            ("tasks", "material_key"),
+            ("assignment_attachments", "attachment_key"),
+            ("tasks", "reference_key"),
+            ("test_fixtures", "contents_key"),
+            ("full_outputs", "contents_key"),
+        ] {
+            if let Err(e) = sqlx::query(&format!(
+                "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {column} TEXT;"
+            ))
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("Could not add {table} {column} column: {e}"));
+            }
+        }
+
+        // Checks of an assignment for submissions that look alike.
+        // status = { 'queued' | 'running' | 'done' | 'failed' }
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS similarity_reports (
+                id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+                assignment_id INTEGER NOT NULL REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                requested_by INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                status TEXT NOT NULL DEFAULT 'queued',
+                error TEXT,
+                requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                finished_at TIMESTAMPTZ
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create similarity_reports table: {e}"));
+        }
+
+        // The pairs each check found. matches = JSON array of the regions where they match.
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS similarity_pairs (
+                report_id INTEGER NOT NULL REFERENCES similarity_reports(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                task_id INTEGER NOT NULL REFERENCES tasks(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                user_a INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                user_b INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                similarity REAL NOT NULL,
+                matches TEXT NOT NULL,
+                PRIMARY KEY (report_id, task_id, user_a, user_b)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create similarity_pairs table: {e}"));
+        }
+
+        // The student's id in the campus student information system, for grade exports
+        if let Err(e) =
+            sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS sis_id TEXT UNIQUE;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add sis_id column: {e}"));
+        }
+
+        // How many of each student's lowest scores in the category don't count
+        if let Err(e) = sqlx::query(
+            "ALTER TABLE assignment_categories ADD COLUMN IF NOT EXISTS drop_lowest INTEGER NOT NULL DEFAULT 0;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not add drop_lowest column: {e}"));
+        }
+
+        // Scores instructors set for single students, in place of the ones their submissions earned
+        if let Err(e) = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS grade_overrides (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                assignment_id INTEGER NOT NULL REFERENCES assignments(id) ON UPDATE CASCADE ON DELETE CASCADE,
+                score REAL NOT NULL,
+                reason TEXT,
+                set_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                PRIMARY KEY (user_id, assignment_id)
+            );",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not create grade_overrides table: {e}"));
+        }
+
+        // JSON `Curve` applied to the assignment's scores when they're read. NULL => not curved.
+        if let Err(e) = sqlx::query("ALTER TABLE assignments ADD COLUMN IF NOT EXISTS curve TEXT;")
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add curve column: {e}"));
+        }
+
+        // Limits the task's tests fall back on, kept so they're exported with the task
+        for column in ["timeout", "memory_limit_mb"] {
+            if let Err(e) = sqlx::query(&format!(
+                "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS {column} INTEGER;"
+            ))
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("Could not add {column} column: {e}"));
+            }
+        }
+
+        // What can't be read back cheaply once the contents are in blob storage
+        if let Err(e) =
+            sqlx::query("ALTER TABLE assignment_attachments ADD COLUMN IF NOT EXISTS size INTEGER;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add size column: {e}"));
+        }
+
+        if let Err(e) =
+            sqlx::query("ALTER TABLE test_fixtures ADD COLUMN IF NOT EXISTS sha256 TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add sha256 column: {e}"));
+        }
+
+        // Emptied as their contents are moved to blob storage
+        for (table, column) in [
+            ("assignment_attachments", "data"),
+            ("test_fixtures", "contents"),
+            ("full_outputs", "contents"),
+        ] {
+            if let Err(e) = sqlx::query(&format!(
+                "ALTER TABLE {table} ALTER COLUMN {column} DROP NOT NULL;"
+            ))
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("Could not make {table}.{column} optional: {e}"));
+            }
+        }
+
+        if let Err(e) = sqlx::query(
+            "UPDATE assignment_attachments SET size = OCTET_LENGTH(data)
+            WHERE size IS NULL AND data IS NOT NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not record the size of attachments: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "UPDATE test_fixtures SET sha256 = encode(sha256(contents), 'hex')
+            WHERE sha256 IS NULL AND contents IS NOT NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not record the hash of fixtures: {e}"));
+        }
+
+        // Kept with each attempt, so an earlier one that counts again still shows what graded it
+        if let Err(e) =
+            sqlx::query("ALTER TABLE submissions ADD COLUMN IF NOT EXISTS toolchain_version TEXT;")
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("Could not add toolchain_version column: {e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "UPDATE submissions s SET toolchain_version = g.toolchain_version
+            FROM user_task_grade g
+            WHERE s.user_id = g.user_id AND s.task_id = g.task_id AND s.attempt = g.attempt
+                AND s.toolchain_version IS NULL AND g.toolchain_version IS NOT NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("Could not record the toolchain of attempts: {e}"));
+        }
+
+        // Followed from an assignment to its classes, students, tasks and tests when totalling scores
+        for index in [
+            "CREATE INDEX IF NOT EXISTS assignment_class_assignment ON assignment_class (assignment_id);",
+            "CREATE INDEX IF NOT EXISTS user_class_class ON user_class (class_number);",
+            "CREATE INDEX IF NOT EXISTS tasks_assignment ON tasks (assignment_id);",
+            "CREATE INDEX IF NOT EXISTS tests_task ON tests (task_id);",
+        ] {
+            if let Err(e) = sqlx::query(index).execute(&mut *transaction).await {
+                return Err(format!("Could not create index: {e}"));
+            }
+        }
+
+        if let Err(e) = transaction.commit().await {
+            return Err(format!("Could not commit table-creation transaction: {e}"));
+        };
+    }
+
+    let mut lock = POSTGRES.write().await;
+    *lock = Some(pool);
+
+    Ok(())
+}
+
+/// Returns how busy the connection pool is, or `None` if the database hasn't been initialized
+pub async fn pool_stats() -> Option<PoolStats> {
+    let postgres_pool = POSTGRES.read().await;
+    postgres_pool.as_ref().map(|pool| {
+        let size = pool.size();
+        let idle = pool.num_idle() as u32;
+        let max_connections = pool.options().get_max_connections();
+
+        PoolStats {
+            size,
+            idle,
+            in_use: size - idle.min(size),
+            max_connections,
+            saturated: idle == 0 && size >= max_connections,
+        }
+    })
 }
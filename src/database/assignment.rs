@@ -1,84 +1,141 @@
+use std::collections::HashMap;
 use std::time::Duration;
-use std::{io::Read, process::Command};
+use std::io::Read;
 
+use crate::model::request::{
+    AssignmentSettings, EnvVar, Fixture, LateTier, Prerequisite, text_or_base64, tier_multiplier,
+};
 use crate::model::request::Task as ReqTask;
 use crate::model::request::Test as ReqTest;
 
 use axum::body::Bytes;
 use base64::Engine;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-use sqlx::Row;
-
-// #[derive(Serialize)]
-// enum Method {
-//     Stdio,
-//     Http(u16),
-// }
-
-// impl<T> From<T> for Method
-// where
-//     T: Into<String>,
-// {
-//     fn from(value: T) -> Self {
-//         let value = value.into();
-//         if value == "stdio" {
-//             Method::Stdio
-//         } else {
-//             let [_, port] = &value.split(":").collect::<Vec<&str>>()[..] else {
-//                 panic!("Invalid port specified");
-//             };
-
-//             let p = port.parse::<u16>().unwrap();
-//             Method::Http(p)
-//         }
-//     }
-// }
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, Row, postgres::PgRow};
 
 #[derive(Serialize)]
 pub struct Assignment {
     assignment_id: i32,
     name: String,
     description: Option<String>,
+    description_html: Option<String>,
     tasks: Vec<Task>,
     deadline: String,
+    /// Submissions are turned away after this. `None` => never.
+    accept_until: Option<String>,
+    allowed_languages: Option<Vec<String>>,
+    /// The user's preferred language, if this assignment allows it
+    default_language: Option<String>,
+    late_tiers: Vec<LateTier>,
+    attachments: Vec<AttachmentInfo>,
 }
 
 #[derive(Serialize)]
 struct Task {
     description: Option<String>,
+    description_html: Option<String>,
     task_id: i32,
     placement: i32,
     allow_editor: bool,
     has_material: bool,
+    has_template: bool,
+    /// Starter code for the in-browser editor, only sent when the task allows the editor
+    template_text: Option<String>,
+    /// The variant this student was assigned, if the task has variants
+    variant: Option<i32>,
+    variant_description: Option<String>,
+    variant_description_html: Option<String>,
+    prerequisite: Option<Prerequisite>,
+    /// The prerequisite task hasn't been passed yet, so submissions are rejected
+    locked: bool,
+    /// `None` => unlimited
+    max_attempts: Option<i32>,
+    /// `None` => unlimited
+    remaining_attempts: Option<i32>,
+    /// Minutes between submissions. `None` => no wait.
+    cooldown_minutes: Option<i32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Test {
     pub test_name: Option<String>,
     pub public: bool,
-    pub output: String,
-    pub input: String,
+    pub output: Vec<u8>,
+    pub input: Vec<u8>,
     pub timeout: Option<Duration>,
+    /// In megabytes. `None` => no limit.
+    pub memory_limit_mb: Option<i32>,
+    pub comparison: ComparisonMode,
+    pub tolerance: Tolerance,
+    pub points: f32,
+    /// A failing output earns points in proportion to how many lines it got right
+    pub partial_credit: bool,
+    /// Shown to students when the test fails, in place of its input and output
+    pub hint: Option<String>,
+    pub fixtures: Vec<TestFixture>,
+    pub env: Vec<EnvVar>,
+    /// File the program must write. Compared with the expected output in place of stdout.
+    pub artifact: Option<String>,
+    /// The input is a script of prompts and replies. See [`crate::model::interactive`].
+    pub interactive: bool,
+}
+
+/// A file placed in the container's working directory while a test runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestFixture {
+    pub filename: String,
+    pub contents: Vec<u8>,
+}
+
+/// What the grading loop needs to know about a task. Sent to remote runners along with the
+/// submission.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskDetails {
+    pub tests: Vec<Test>,
+    pub stop_on_failure: bool,
+    pub test_method: TestMethod,
+    pub cpus: f32,
+    pub pids_limit: i32,
+    pub disk_limit_mb: i32,
+    /// For tests without a memory limit of their own. `None` => no limit.
+    pub default_memory_limit_mb: Option<i32>,
+    pub memory_check: bool,
+    pub memory_error_penalty: Option<f32>,
+    pub test_command: Option<String>,
+    pub lint_weight: Option<f32>,
+    pub network_access: bool,
+    /// The class's choice, or the deployment's when it has none
+    pub isolation: Isolation,
 }
 
 #[derive(Serialize)]
 pub struct FullAssignmentInfo {
     assignment_name: String,
     deadline: String,
-    tasks: Vec<ReqTask>,
+    #[serde(flatten)]
+    settings: AssignmentSettings,
+    pub tasks: Vec<ReqTask>,
 }
 
 use crate::{
-    database::POSTGRES,
+    archive, config,
+    container::{self, ContainerEntry, Isolation},
+    database::{POSTGRES, attachment, curve, gradebook, peer_review, submission},
+    markdown,
     model::{
-        assignment_grade::AssignmentGrade, class_info::AssignmentInfo,
-        submission_response::SubmissionResponse,
+        assignment_archive::{ARCHIVE_FORMAT_VERSION, ArchivedAttachment, AssignmentArchive},
+        assignment_grade::AssignmentGrade, attachment::AttachmentInfo, class_info::AssignmentInfo,
+        comparison::{ComparisonMode, Tolerance},
+        submission_receipt::SubmissionReceipt,
+        submission_response::{FullOutput, ResultVisibility, SubmissionResponse},
+        test_method::TestMethod,
     },
-    postgres_lock,
+    postgres_lock, storage,
 };
 
-pub async fn get_assignment_info(assignment_id: i32) -> Result<Assignment, String> {
+pub async fn get_assignment_info(assignment_id: i32, user_id: i32) -> Result<Assignment, String> {
     postgres_lock!(transaction, {
         let assignment_row = match sqlx::query("SELECT * FROM assignments WHERE id = $1;")
             .bind(assignment_id)
@@ -92,10 +149,31 @@ pub async fn get_assignment_info(assignment_id: i32) -> Result<Assignment, Strin
         let assignment_name: String = assignment_row.get("assignment_name");
         let assignment_desc: Option<String> = assignment_row.get("assignment_description");
         let assignment_deadline: DateTime<Utc> = assignment_row.get("deadline");
+        let allowed_languages: Option<Vec<String>> = assignment_row.get("allowed_languages");
+        let late_tiers = decode_late_tiers(assignment_row.get("late_tiers"));
 
-        let task_rows = match sqlx::query("SELECT task_description, allow_editor, placement, id, supplementary_material IS NOT NULL has_material
-            FROM tasks WHERE assignment_id = $1;"
-        )
+        let preferred_language: Option<String> =
+            match sqlx::query("SELECT preferred_language FROM users WHERE id = $1;")
+                .bind(user_id)
+                .fetch_one(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.get("preferred_language"),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        // Fall back to the first allowed language if the preference can't be used here
+        let default_language = match (&allowed_languages, preferred_language) {
+            (None, preferred) => preferred,
+            (Some(allowed), Some(preferred)) if allowed.contains(&preferred) => Some(preferred),
+            (Some(allowed), _) => allowed.first().cloned(),
+        };
+
+        let task_rows = match sqlx::query(&format!("SELECT task_description, allow_editor, placement, id, material_key IS NOT NULL has_material, template, variant_descriptions,
+                prerequisite_placement, prerequisite_threshold, cooldown_minutes, {}
+            FROM tasks WHERE assignment_id = $2;", submission::ATTEMPTS
+        ))
+            .bind(user_id)
             .bind(assignment_id)
             .fetch_all(&mut *transaction)
             .await
@@ -104,6 +182,24 @@ pub async fn get_assignment_info(assignment_id: i32) -> Result<Assignment, Strin
             Err(e) => return Err(format!("{e}")),
         };
 
+        // placement => the user's grade on that task, used to work out which tasks are unlocked
+        let grades = match sqlx::query(
+            "SELECT t.placement, g.grade FROM user_task_grade g
+            JOIN tasks t ON t.id = g.task_id
+            WHERE g.user_id = $1 AND g.assignment_id = $2;",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r
+                .iter()
+                .map(|r| (r.get("placement"), r.get("grade")))
+                .collect::<HashMap<i32, Option<f32>>>(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
         let tasks = task_rows
             .iter()
             .map(|row| {
@@ -112,32 +208,203 @@ pub async fn get_assignment_info(assignment_id: i32) -> Result<Assignment, Strin
                 let placement: i32 = row.get("placement");
                 let task_id: i32 = row.get("id");
                 let has_material: bool = row.get("has_material");
+                let template: Option<Vec<u8>> = row.get("template");
+                let variant_descriptions: Vec<String> = row.get("variant_descriptions");
+                let prerequisite = row
+                    .get::<Option<i32>, _>("prerequisite_placement")
+                    .map(|task_placement| Prerequisite {
+                        task_placement,
+                        threshold: row.get("prerequisite_threshold"),
+                    });
+                let locked = prerequisite.as_ref().is_some_and(|p| {
+                    grades.get(&p.task_placement).copied().flatten().unwrap_or(0.0) < p.threshold
+                });
+
+                let variant = assigned_variant(user_id, task_id, variant_descriptions.len());
+                let variant_description =
+                    variant.and_then(|v| variant_descriptions.get(v as usize).cloned());
+
+                let template_text = template
+                    .as_ref()
+                    .filter(|_| allow_editor)
+                    .and_then(|t| String::from_utf8(t.clone()).ok());
 
                 Task {
+                    description_html: task_desc.as_deref().map(markdown::render),
                     description: task_desc,
                     task_id,
                     allow_editor,
                     placement,
                     has_material,
+                    has_template: template.is_some(),
+                    template_text,
+                    variant,
+                    variant_description_html: variant_description.as_deref().map(markdown::render),
+                    variant_description,
+                    prerequisite,
+                    locked,
+                    max_attempts: row.get("max_attempts"),
+                    remaining_attempts: submission::remaining(row),
+                    cooldown_minutes: row.get("cooldown_minutes"),
                 }
             })
             .collect::<Vec<Task>>();
 
+        let attachments = attachment::attachments_of(&mut transaction, assignment_id).await?;
+
         return Ok(Assignment {
             assignment_id,
             name: assignment_name,
+            description_html: assignment_desc.as_deref().map(markdown::render),
             description: assignment_desc,
             tasks,
             deadline: assignment_deadline.to_string(),
+            accept_until: assignment_row
+                .get::<Option<DateTime<Utc>>, _>("accept_until")
+                .map(|t| t.to_string()),
+            allowed_languages,
+            default_language,
+            late_tiers,
+            attachments,
         });
     });
 
     Err("Failed to acquire database lock".into())
 }
 
-pub async fn container_get_task_details(task_id: i32) -> Result<Vec<Test>, String> {
+/// Returns the tests a user's submission to the task is graded against: the shared tests plus
+/// those of the user's variant. Sample runs get only the sample tests, and submissions every other
+/// test. Limits are resolved for the submission's language.
+pub async fn container_get_task_details(
+    task_id: i32,
+    user_id: i32,
+    sample: bool,
+    lang: &str,
+) -> Result<TaskDetails, String> {
+    postgres_lock!(transaction, {
+        let task_row = match sqlx::query(&format!(
+            "SELECT cardinality(variant_descriptions) n, ordered_tests, stop_on_failure, test_method,
+                cpus, pids_limit, memory_check, memory_error_penalty, test_command,
+                lint_weight, network_access, disk_limit_mb, {CLASS_ISOLATION}
+            FROM tasks WHERE id = $1;"
+        ))
+        .bind(task_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let n_variants: i32 = task_row.get("n");
+        let stop_on_failure: bool = task_row.get("stop_on_failure");
+        let ordered = stop_on_failure || task_row.get::<bool, _>("ordered_tests");
+
+        let variant = assigned_variant(user_id, task_id, n_variants as usize);
+
+        let order = if ordered {
+            " ORDER BY placement, id"
+        } else {
+            ""
+        };
+        let rows = match sqlx::query(&format!(
+            "SELECT * FROM tests
+            WHERE task_id = $1 AND (variant IS NULL OR variant = $2) AND sample = $3{order};"
+        ))
+        .bind(task_id)
+        .bind(variant)
+        .bind(sample)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let tests = tests_from_rows(&mut transaction, &rows).await?;
+        transaction.commit().await.unwrap();
+
+        return Ok(task_details(&task_row, tests, lang));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// A task's reference solution, with every test of the task (across variants and sample tests) in
+/// `details`
+pub struct ReferenceSolution {
+    pub zip_file: Vec<u8>,
+    pub lang: String,
+    /// Ids of the tests in `details`, in the same order
+    pub test_ids: Vec<i32>,
+    pub details: TaskDetails,
+}
+
+/// Saves the solution the task's expected outputs are generated from. Returns `Ok(None)` if the
+/// task isn't part of the assignment, or the assignment isn't the class's.
+pub async fn store_reference_solution(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    zip_file: &[u8],
+    lang: &str,
+) -> Result<Option<()>, String> {
+    let key = storage::new_key("solutions", Some(archive::extension(zip_file)));
+    storage::put(&key, zip_file).await?;
+
+    postgres_lock!(transaction, {
+        let updated = match sqlx::query(
+            "UPDATE tasks SET reference_key = $1, reference_language = $2
+            WHERE id = $3 AND assignment_id = $4
+            AND assignment_id IN (SELECT assignment_id FROM assignment_class WHERE class_number = $5);",
+        )
+        .bind(&key)
+        .bind(lang)
+        .bind(task_id)
+        .bind(assignment_id)
+        .bind(class_number)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok((updated > 0).then_some(()));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns `Ok(None)` if the task isn't part of the assignment, the assignment isn't the class's,
+/// or the task has no reference solution
+pub async fn get_reference_solution(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+) -> Result<Option<ReferenceSolution>, String> {
     postgres_lock!(transaction, {
-        let rows = match sqlx::query("SELECT * FROM tests WHERE task_id = $1;")
+        let task_row = match sqlx::query(&format!(
+            "SELECT reference_key, reference_language, stop_on_failure, test_method, cpus,
+                pids_limit, memory_check, memory_error_penalty, test_command, lint_weight,
+                network_access, disk_limit_mb, {CLASS_ISOLATION}
+            FROM tasks
+            WHERE id = $1 AND assignment_id = $2 AND reference_key IS NOT NULL
+            AND assignment_id IN (SELECT assignment_id FROM assignment_class WHERE class_number = $3);"
+        ))
+        .bind(task_id)
+        .bind(assignment_id)
+        .bind(class_number)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let rows = match sqlx::query("SELECT * FROM tests WHERE task_id = $1 ORDER BY placement, id;")
             .bind(task_id)
             .fetch_all(&mut *transaction)
             .await
@@ -146,35 +413,227 @@ pub async fn container_get_task_details(task_id: i32) -> Result<Vec<Test>, Strin
             Err(e) => return Err(format!("{e}")),
         };
 
+        let tests = tests_from_rows(&mut transaction, &rows).await?;
         transaction.commit().await.unwrap();
 
-        let tests = rows
-            .iter()
-            .map(|row| {
-                let input: String = row.get("input");
-                let output: String = row.get("output");
-                let public: bool = row.get("public");
-                let timeout: Option<i32> = row.get("timeout");
-                let test_name: Option<String> = row.get("test_name");
-
-                let timeout = timeout.map(|f| std::time::Duration::from_secs(f as u64));
-
-                Test {
-                    test_name,
-                    input,
-                    output,
-                    public,
-                    timeout,
-                }
-            })
-            .collect::<Vec<Test>>();
+        let lang: String = task_row.get("reference_language");
+        return Ok(Some(ReferenceSolution {
+            zip_file: storage::get(task_row.get("reference_key")).await?,
+            details: task_details(&task_row, tests, &lang),
+            lang,
+            test_ids: rows.iter().map(|r| r.get("id")).collect(),
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Replaces the expected output of each `(test id, output)` pair. Students graded against a test
+/// whose output changed are flagged for regrading; returns how many were flagged. `Ok(None)` =>
+/// the task isn't one of the class's.
+pub async fn set_expected_outputs(
+    class_number: &str,
+    task_id: i32,
+    outputs: &[(i32, Vec<u8>)],
+) -> Result<Option<u64>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query(
+            "SELECT 1 FROM tasks t
+            JOIN assignment_class ac ON ac.assignment_id = t.assignment_id
+            WHERE t.id = $1 AND ac.class_number = $2;",
+        )
+        .bind(task_id)
+        .bind(class_number)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(_)) => (),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        }
+
+        let mut changed = 0;
+        for (test_id, output) in outputs {
+            changed += match sqlx::query(
+                "UPDATE tests SET output = $1
+                WHERE id = $2 AND task_id = $3 AND output IS DISTINCT FROM $1;",
+            )
+            .bind(output)
+            .bind(test_id)
+            .bind(task_id)
+            .execute(&mut *transaction)
+            .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => return Err(format!("{e}")),
+            };
+        }
+
+        let flagged = if changed > 0 {
+            match sqlx::query(
+                "UPDATE user_task_grade
+                SET needs_regrade = TRUE
+                WHERE task_id = $1 AND grade IS NOT NULL;",
+            )
+            .bind(task_id)
+            .execute(&mut *transaction)
+            .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => return Err(format!("{e}")),
+            }
+        } else {
+            0
+        };
 
-        return Ok(tests);
+        transaction.commit().await.unwrap();
+        return Ok(Some(flagged));
     });
 
     Err("Failed to acquire database lock".into())
 }
 
+/// Selects the isolation chosen by the class a row of `tasks` belongs to
+const CLASS_ISOLATION: &str = "(SELECT c.isolation
+        FROM assignment_class ac JOIN classes c ON c.class_number = ac.class_number
+        WHERE ac.assignment_id = tasks.assignment_id LIMIT 1) isolation";
+
+/// Settings the grading loop reads from a row of `tasks`. Limits the task doesn't set come from
+/// the manifest of `lang`, then the server's defaults.
+fn task_details(task_row: &sqlx::postgres::PgRow, tests: Vec<Test>, lang: &str) -> TaskDetails {
+    let config = config::get();
+    let language = container::language_limits(lang);
+    TaskDetails {
+        tests,
+        stop_on_failure: task_row.get("stop_on_failure"),
+        test_method: task_row
+            .get::<Option<String>, _>("test_method")
+            .map(TestMethod::from)
+            .unwrap_or_default(),
+        cpus: task_row
+            .get::<Option<f32>, _>("cpus")
+            .or(language.cpus)
+            .unwrap_or(config.default_cpus),
+        pids_limit: task_row
+            .get::<Option<i32>, _>("pids_limit")
+            .or(language.pids_limit)
+            .unwrap_or(config.default_pids_limit),
+        disk_limit_mb: task_row
+            .get::<Option<i32>, _>("disk_limit_mb")
+            .or(language.disk_limit_mb)
+            .unwrap_or(config.default_disk_limit_mb),
+        default_memory_limit_mb: language
+            .memory_limit_mb
+            .or(config.default_memory_limit_mb),
+        memory_check: task_row.get("memory_check"),
+        memory_error_penalty: task_row.get("memory_error_penalty"),
+        test_command: task_row.get("test_command"),
+        lint_weight: task_row.get("lint_weight"),
+        network_access: task_row.get("network_access"),
+        isolation: task_row
+            .get::<Option<String>, _>("isolation")
+            .as_deref()
+            .and_then(Isolation::from_name)
+            .unwrap_or(config.isolation),
+    }
+}
+
+/// Reads rows of `tests`, along with their fixtures and environment variables
+async fn tests_from_rows(
+    conn: &mut PgConnection,
+    rows: &[sqlx::postgres::PgRow],
+) -> Result<Vec<Test>, String> {
+    let test_ids: Vec<i32> = rows.iter().map(|r| r.get("id")).collect();
+    let fixture_rows = match sqlx::query(
+        "SELECT test_id, filename, contents_key FROM test_fixtures WHERE test_id = ANY($1);",
+    )
+    .bind(&test_ids)
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let mut env = env_vars(conn, &test_ids).await?;
+
+    let mut fixtures: HashMap<i32, Vec<TestFixture>> = HashMap::new();
+    for row in fixture_rows {
+        fixtures
+            .entry(row.get("test_id"))
+            .or_default()
+            .push(TestFixture {
+                filename: row.get("filename"),
+                contents: storage::get(row.get("contents_key")).await?,
+            });
+    }
+
+    let tests = rows
+        .iter()
+        .map(|row| {
+            let input: Vec<u8> = row.get("input");
+            let output: Vec<u8> = row.get("output");
+            let public: bool = row.get("public");
+            let timeout: Option<i32> = row.get("timeout");
+            let test_name: Option<String> = row.get("test_name");
+            let comparison: String = row.get("comparison");
+            let tolerance = decode_tolerance(row.get("abs_tolerance"), row.get("rel_tolerance"));
+
+            let timeout = timeout.map(|f| std::time::Duration::from_secs(f as u64));
+
+            Test {
+                test_name,
+                input,
+                output,
+                public,
+                timeout,
+                memory_limit_mb: row.get("memory_limit_mb"),
+                comparison: ComparisonMode::from(comparison),
+                tolerance: tolerance.unwrap_or_default(),
+                points: row.get("points"),
+                partial_credit: row.get("partial_credit"),
+                hint: row.get("hint"),
+                fixtures: fixtures.remove(&row.get("id")).unwrap_or_default(),
+                env: env.remove(&row.get("id")).unwrap_or_default(),
+                artifact: row.get("artifact"),
+                interactive: row.get("interactive"),
+            }
+        })
+        .collect();
+
+    Ok(tests)
+}
+
+/// The environment variables of each test, by test id
+async fn env_vars(
+    conn: &mut PgConnection,
+    test_ids: &[i32],
+) -> Result<HashMap<i32, Vec<EnvVar>>, String> {
+    let rows = match sqlx::query(
+        "SELECT test_id, name, value, secret FROM test_env_vars
+        WHERE test_id = ANY($1)
+        ORDER BY name;",
+    )
+    .bind(test_ids)
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let mut env: HashMap<i32, Vec<EnvVar>> = HashMap::new();
+    for row in rows {
+        env.entry(row.get("test_id")).or_default().push(EnvVar {
+            name: row.get("name"),
+            value: row.get("value"),
+            secret: row.get("secret"),
+        });
+    }
+
+    Ok(env)
+}
+
 pub async fn get_assignments_for_class(
     class_number: String,
     user_id: i32,
@@ -244,6 +703,21 @@ pub async fn retrieve_full_assignment_info(
 
         let deadline: DateTime<Utc> = assignment_row.get("deadline");
         let assignment_name: String = assignment_row.get("assignment_name");
+        let result_visibility: String = assignment_row.get("result_visibility");
+        let settings = AssignmentSettings {
+            allowed_languages: assignment_row.get("allowed_languages"),
+            result_visibility: ResultVisibility::from(result_visibility),
+            grace_period_minutes: assignment_row.get("grace_period_minutes"),
+            late_tiers: decode_late_tiers(assignment_row.get("late_tiers")),
+            category_id: assignment_row.get("category_id"),
+            peer_review: assignment_row
+                .get::<Option<String>, _>("peer_review")
+                .and_then(|p| serde_json::from_str(&p).ok()),
+            max_attempts: assignment_row.get("max_attempts"),
+            accept_until: assignment_row
+                .get::<Option<DateTime<Utc>>, _>("accept_until")
+                .map(|t| t.to_rfc3339()),
+        };
 
         let task_rows = match sqlx::query(
             "SELECT * FROM tasks
@@ -261,14 +735,35 @@ pub async fn retrieve_full_assignment_info(
         let mut tasks = vec![];
         for task in task_rows {
             let task_id: i32 = task.get("id");
-            let timeout = None::<i32>;
-            let material_vec: Option<Vec<u8>> = task.get("supplementary_material");
+            let material_key: Option<String> = task.get("material_key");
+            let material_vec = match material_key {
+                Some(key) => Some(storage::get(&key).await?),
+                None => None,
+            };
 
             let material_base64 = material_vec.map(|f| base64::prelude::BASE64_STANDARD.encode(f));
+            let template_vec: Option<Vec<u8>> = task.get("template");
+            let template_base64 = template_vec.map(|f| base64::prelude::BASE64_STANDARD.encode(f));
 
             let test_rows = match sqlx::query(
                 "SELECT * FROM tests
-                WHERE task_id = $1;",
+                WHERE task_id = $1
+                ORDER BY placement, id ASC;",
+            )
+            .bind(task_id)
+            .fetch_all(&mut *transaction)
+            .await
+            {
+                Ok(r) => r,
+                Err(e) => return Err(format!("{e}")),
+            };
+
+            let fixture_rows = match sqlx::query(
+                "SELECT f.test_id, f.filename, f.contents_key
+                FROM test_fixtures f
+                JOIN tests t ON t.id = f.test_id
+                WHERE t.task_id = $1
+                ORDER BY f.filename;",
             )
             .bind(task_id)
             .fetch_all(&mut *transaction)
@@ -278,38 +773,97 @@ pub async fn retrieve_full_assignment_info(
                 Err(e) => return Err(format!("{e}")),
             };
 
+            let mut fixtures: HashMap<i32, Vec<Fixture>> = HashMap::new();
+            for row in fixture_rows {
+                let contents = storage::get(row.get("contents_key")).await?;
+                fixtures.entry(row.get("test_id")).or_default().push(Fixture {
+                    filename: row.get("filename"),
+                    data_base64: base64::prelude::BASE64_STANDARD.encode(contents),
+                });
+            }
+
+            let test_ids: Vec<i32> = test_rows.iter().map(|t| t.get("id")).collect();
+            let mut env = env_vars(&mut transaction, &test_ids).await?;
+
             let tests = test_rows
                 .iter()
                 .map(|test| {
+                    let test_id: i32 = test.get("id");
                     let test_name: Option<String> = test.get("test_name");
-                    let input: String = test.get("input");
-                    let output: String = test.get("output");
+                    let (input, input_file_base64) = text_or_base64(test.get("input"));
+                    let (output, output_file_base64) = text_or_base64(test.get("output"));
                     let is_public: bool = test.get("public");
+                    let variant: Option<i32> = test.get("variant");
+                    let comparison: String = test.get("comparison");
+                    let tolerance =
+                        decode_tolerance(test.get("abs_tolerance"), test.get("rel_tolerance"));
 
                     ReqTest {
+                        test_id: Some(test_id),
                         test_name,
                         is_public,
-                        input: Some(input),
-                        output: Some(output),
-                        input_file_base64: None,
-                        output_file_base64: None,
+                        input,
+                        output,
+                        input_file_base64,
+                        output_file_base64,
+                        variant,
+                        comparison: ComparisonMode::from(comparison),
+                        tolerance,
+                        points: test.get("points"),
+                        partial_credit: test.get("partial_credit"),
+                        hint: test.get("hint"),
+                        fixtures: fixtures.remove(&test_id).unwrap_or_default(),
+                        env: env.remove(&test_id).unwrap_or_default(),
+                        timeout: test.get("timeout"),
+                        memory_limit_mb: test.get("memory_limit_mb"),
+                        artifact: test.get("artifact"),
+                        sample: test.get("sample"),
+                        interactive: test.get("interactive"),
                     }
                 })
                 .collect::<Vec<ReqTest>>();
 
             tasks.push(ReqTask {
+                task_id: Some(task_id),
                 task_description: task.get("task_description"),
                 allow_editor: task.get("allow_editor"),
                 material_base64,
                 material_filename: task.get("supplementary_filename"),
-                timeout,
+                template_base64,
+                template_filename: task.get("template_filename"),
+                timeout: task.get("timeout"),
                 tests,
+                variant_descriptions: task.get("variant_descriptions"),
+                prerequisite: task
+                    .get::<Option<i32>, _>("prerequisite_placement")
+                    .map(|task_placement| Prerequisite {
+                        task_placement,
+                        threshold: task.get("prerequisite_threshold"),
+                    }),
+                memory_limit_mb: task.get("memory_limit_mb"),
+                cpus: task.get("cpus"),
+                pids_limit: task.get("pids_limit"),
+                disk_limit_mb: task.get("disk_limit_mb"),
+                ordered_tests: task.get("ordered_tests"),
+                stop_on_failure: task.get("stop_on_failure"),
+                test_method: task
+                    .get::<Option<String>, _>("test_method")
+                    .map(TestMethod::from)
+                    .unwrap_or_default(),
+                memory_check: task.get("memory_check"),
+                memory_error_penalty: task.get("memory_error_penalty"),
+                test_command: task.get("test_command"),
+                lint_weight: task.get("lint_weight"),
+                network_access: task.get("network_access"),
+                max_attempts: task.get("max_attempts"),
+                cooldown_minutes: task.get("cooldown_minutes"),
             });
         }
 
         let fai = FullAssignmentInfo {
             assignment_name,
             deadline: deadline.to_string(),
+            settings,
             tasks,
         };
 
@@ -319,27 +873,109 @@ pub async fn retrieve_full_assignment_info(
     Err("Failed to acquire database lock".into())
 }
 
+/// Packs an assignment and its attachments into a portable archive
+pub async fn export_assignment(assignment_id: i32) -> Result<AssignmentArchive, String> {
+    let FullAssignmentInfo {
+        assignment_name,
+        deadline: _,
+        mut settings,
+        mut tasks,
+    } = retrieve_full_assignment_info(assignment_id).await?;
+
+    // Categories belong to the class the assignment came from
+    settings.category_id = None;
+    for task in &mut tasks {
+        task.task_id = None;
+        for test in &mut task.tests {
+            test.test_id = None;
+        }
+    }
+
+    let (assignment_description, deadline) = description_and_deadline(assignment_id).await?;
+
+    let mut attachments = vec![];
+    for info in attachment::list_attachments(assignment_id).await? {
+        if let Some((data_base64, filename)) =
+            attachment::download_attachment(assignment_id, info.attachment_id).await?
+        {
+            attachments.push(ArchivedAttachment {
+                filename,
+                data_base64,
+            });
+        }
+    }
+
+    Ok(AssignmentArchive {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        assignment_name,
+        assignment_description,
+        deadline,
+        settings,
+        tasks,
+        attachments,
+    })
+}
+
+async fn description_and_deadline(assignment_id: i32) -> Result<(Option<String>, String), String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT assignment_description, deadline FROM assignments WHERE id = $1;",
+        )
+        .bind(assignment_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let deadline: DateTime<Utc> = row.get("deadline");
+        return Ok((row.get("assignment_description"), deadline.to_rfc3339()));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Creates an assignment in the class, along with its tasks, tests and `attachments`, given as
+/// filename and contents
 pub async fn add_assignment(
     class_number: String,
     assignment_name: String,
     assignment_description: Option<String>,
     deadline: String,
+    settings: AssignmentSettings,
     tasks: Vec<ReqTask>,
-) -> Result<(), String> {
+    attachments: Vec<(String, Vec<u8>)>,
+) -> Result<i32, String> {
     postgres_lock!(transaction, {
         let deadline_date_time: DateTime<Utc> = match deadline.parse() {
             Ok(d) => d,
             Err(e) => return Err(format!("Could not parse deadline: {e}")),
         };
+        let accept_until = settings.accept_until()?;
 
         let new_assignment_id: i32 = match sqlx::query(
-            "INSERT INTO assignments (assignment_name, assignment_description, deadline)
-            VALUES ($1, $2, $3)
+            "INSERT INTO assignments (assignment_name, assignment_description, deadline, allowed_languages, result_visibility, grace_period_minutes, late_tiers, category_id, peer_review, max_attempts, accept_until)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, (SELECT id FROM assignment_categories WHERE id = $8 AND class_number = $9), $10, $11, $12)
             RETURNING id;",
         )
         .bind(assignment_name)
         .bind(assignment_description)
         .bind(deadline_date_time)
+        .bind(settings.allowed_languages)
+        .bind(settings.result_visibility.as_str())
+        .bind(settings.grace_period_minutes.max(0))
+        .bind(serde_json::to_string(&settings.late_tiers).unwrap())
+        .bind(settings.category_id)
+        .bind(&class_number)
+        .bind(
+            settings
+                .peer_review
+                .as_ref()
+                .map(|p| serde_json::to_string(p).unwrap()),
+        )
+        .bind(settings.max_attempts)
+        .bind(accept_until)
         .fetch_one(&mut *transaction)
         .await
         {
@@ -357,76 +993,90 @@ pub async fn add_assignment(
         }
 
         for (placement, task) in tasks.iter().enumerate() {
-            let material = task
-                .material_base64
-                .as_ref()
-                .and_then(|f| base64::prelude::BASE64_STANDARD.decode(f).ok());
-
-            let new_task_id: i32 = match sqlx::query(
-                "INSERT INTO tasks (assignment_id, task_description, allow_editor, placement, template, supplementary_material, supplementary_filename, test_method)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-                RETURNING id;",
-            )
-            .bind(new_assignment_id)
-            .bind(&task.task_description)
-            .bind(task.allow_editor)
-            .bind(placement as i32)
-            .bind(None::<Vec<u8>>)
-            .bind(material)
-            .bind(&task.material_filename)
-            .bind("stdio")
-            .fetch_one(&mut *transaction)
-            .await
-            {
-                Ok(r) => r.get("id"),
-                Err(e) => return Err(format!("{e}")),
-            };
-
-            for test in &task.tests {
-                let input = if let Some(i_f) = &test.input_file_base64 {
-                    base64::prelude::BASE64_STANDARD
-                        .decode(i_f)
-                        .map(|f| String::from_utf8(f).unwrap())
-                        .unwrap()
-                } else {
-                    test.input.clone().unwrap()
-                };
-
-                let output = if let Some(o_f) = &test.output_file_base64 {
-                    base64::prelude::BASE64_STANDARD
-                        .decode(o_f)
-                        .map(|f| String::from_utf8(f).unwrap())
-                        .unwrap()
-                } else {
-                    test.output.clone().unwrap()
-                };
-
-                if let Err(e) = sqlx::query(
-                    "INSERT INTO tests (task_id, input, output, public, timeout, test_name)
-                    VALUES ($1, $2, $3, $4, $5, $6);",
+            let new_task_id = insert_task(&mut transaction, new_assignment_id, placement, task).await?;
+
+            for (test_placement, test) in task.tests.iter().enumerate() {
+                insert_test(
+                    &mut transaction,
+                    new_task_id,
+                    test_placement,
+                    test,
+                    test.timeout.or(task.timeout),
+                    test.memory_limit_mb.or(task.memory_limit_mb),
                 )
-                .bind(new_task_id)
-                .bind(input)
-                .bind(output)
-                .bind(test.is_public)
-                .bind(task.timeout)
-                .bind(&test.test_name)
-                .execute(&mut *transaction)
-                .await
-                {
-                    return Err(format!("{e}"));
-                }
+                .await?;
             }
         }
 
+        for (filename, data) in attachments {
+            attachment::insert_attachment(&mut transaction, new_assignment_id, filename, &data)
+                .await?;
+        }
+
         transaction.commit().await.unwrap();
 
-        return Ok(());
+        return Ok(new_assignment_id);
     });
 
     Err("Failed to acquire database lock".into())
 }
 
+/// Works out the late multiplier of the assignment's late submissions again from its deadline and
+/// late tiers, so changes to either apply to what has already been submitted
+async fn reprice_late_submissions(
+    conn: &mut PgConnection,
+    assignment_id: i32,
+    deadline: DateTime<Utc>,
+    tiers: &[LateTier],
+) -> Result<(), String> {
+    for table in ["submissions", "user_task_grade"] {
+        let rows = match sqlx::query(&format!(
+            "SELECT user_id, task_id, attempt, submitted_at FROM {table}
+            WHERE assignment_id = $1 AND was_late AND submitted_at IS NOT NULL;"
+        ))
+        .bind(assignment_id)
+        .fetch_all(&mut *conn)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let multipliers = rows
+            .iter()
+            .map(|r| {
+                let submitted_at: DateTime<Utc> = r.get("submitted_at");
+                let hours_late = (submitted_at - deadline).num_seconds() as f64 / 3600.0;
+                tier_multiplier(tiers, hours_late)
+            })
+            .collect::<Vec<Option<f32>>>();
+
+        if let Err(e) = sqlx::query(&format!(
+            "UPDATE {table} t SET late_multiplier = m.multiplier
+            FROM UNNEST($2::INTEGER[], $3::INTEGER[], $4::INTEGER[], $5::REAL[])
+                AS m(user_id, task_id, attempt, multiplier)
+            WHERE t.assignment_id = $1 AND t.user_id = m.user_id AND t.task_id = m.task_id
+                AND t.attempt IS NOT DISTINCT FROM m.attempt;"
+        ))
+        .bind(assignment_id)
+        .bind(rows.iter().map(|r| r.get("user_id")).collect::<Vec<i32>>())
+        .bind(rows.iter().map(|r| r.get("task_id")).collect::<Vec<i32>>())
+        .bind(
+            rows.iter()
+                .map(|r| r.get("attempt"))
+                .collect::<Vec<Option<i32>>>(),
+        )
+        .bind(multipliers)
+        .execute(&mut *conn)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+    }
+
+    Ok(())
+}
+
 /// Returns if the submission was late
 pub async fn mark_as_submitted(
     user_id: i32,
@@ -434,40 +1084,102 @@ pub async fn mark_as_submitted(
     task_id: i32,
     submission_time: DateTime<Utc>,
     zip_file: Bytes,
-) -> Result<bool, String> {
+    lang: &str,
+) -> Result<SubmissionReceipt, String> {
+    let sha256: String = Sha256::digest(&zip_file)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+    let key = storage::new_key("submissions", Some(archive::extension(&zip_file)));
+    storage::put(&key, &zip_file).await?;
+
     postgres_lock!(transaction, {
-        let deadline: DateTime<Utc> =
-            match sqlx::query("SELECT deadline FROM assignments WHERE id = $1;")
-                .bind(assignment_id)
-                .fetch_one(&mut *transaction)
-                .await
+        // Submissions within the grace period absorb clock skew and upload time
+        let (deadline, grace_deadline, late_tiers): (DateTime<Utc>, DateTime<Utc>, Option<String>) =
+            match sqlx::query(
+                "SELECT deadline, deadline + make_interval(mins => grace_period_minutes) grace_deadline, late_tiers
+                FROM assignments WHERE id = $1;",
+            )
+            .bind(assignment_id)
+            .fetch_one(&mut *transaction)
+            .await
             {
-                Ok(r) => r.get("deadline"),
+                Ok(r) => (r.get("deadline"), r.get("grace_deadline"), r.get("late_tiers")),
                 Err(e) => return Err(format!("{e}")),
             };
 
-        let was_late = submission_time >= deadline;
+        let was_late = submission_time >= grace_deadline;
 
-        if let Err(e) = sqlx::query(
-            "INSERT INTO user_task_grade (user_id, task_id, assignment_id, was_late, submission_zip)
-            VALUES ($1, $2, $3, $4, $5);",
+        let late_multiplier = if was_late {
+            let hours_late = (submission_time - deadline).num_seconds() as f64 / 3600.0;
+            tier_multiplier(&decode_late_tiers(late_tiers), hours_late)
+        } else {
+            None
+        };
+
+        // Earlier attempts stay in submissions; only the one that counts is replaced
+        let attempt: i32 = match sqlx::query(
+            "INSERT INTO submissions (user_id, task_id, attempt, assignment_id, was_late, submission_key, submission_lang, late_multiplier, submitted_at, sha256)
+            SELECT $1, $2, COALESCE(MAX(attempt), 0) + 1, $3, $4, $5, $6, $7, $8, $9
+            FROM submissions WHERE user_id = $1 AND task_id = $2
+            RETURNING attempt;",
         )
         .bind(user_id)
         .bind(task_id)
         .bind(assignment_id)
         .bind(was_late)
-        .bind(zip_file.to_vec())
-        .execute(&mut *transaction)
+        .bind(&key)
+        .bind(lang)
+        .bind(late_multiplier)
+        .bind(submission_time)
+        .bind(&sha256)
+        .fetch_one(&mut *transaction)
         .await
+        {
+            Ok(r) => r.get("attempt"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        if let Err(e) =
+            sqlx::query("DELETE FROM user_task_grade WHERE user_id = $1 AND task_id = $2;")
+                .bind(user_id)
+                .bind(task_id)
+                .execute(&mut *transaction)
+                .await
         {
             return Err(format!("{e}"));
         }
 
-        transaction.commit().await.unwrap();
-
-        return Ok(was_late);
-    });
-
+        if let Err(e) = sqlx::query(
+            "INSERT INTO user_task_grade (user_id, task_id, assignment_id, was_late, submission_key, submission_lang, late_multiplier, submitted_at, attempt)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(assignment_id)
+        .bind(was_late)
+        .bind(&key)
+        .bind(lang)
+        .bind(late_multiplier)
+        .bind(submission_time)
+        .bind(attempt)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+
+        return Ok(SubmissionReceipt {
+            attempt,
+            submitted_at: submission_time.to_string(),
+            sha256,
+            was_late,
+        });
+    });
+
     Err("Failed to acquire database lock".into())
 }
 
@@ -476,23 +1188,64 @@ pub async fn container_add_task_grade(
     task_id: i32,
     results: &[u8],
     grade: f32,
+    toolchain_version: Option<&str>,
 ) -> Result<(), String> {
     postgres_lock!(transaction, {
         if let Err(e) = sqlx::query(
             "UPDATE user_task_grade
-            SET json_results = $1, grade = $2
+            SET json_results = $1, grade = $2, needs_regrade = FALSE, grading_delayed = FALSE, toolchain_version = $5,
+                graded_at = NOW(), error = NULL
             WHERE user_id = $3 AND task_id = $4;",
         )
         .bind(results)
         .bind(grade)
         .bind(user_id)
         .bind(task_id)
+        .bind(toolchain_version)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        submission::copy_results(&mut transaction, user_id, task_id).await?;
+
+        transaction.commit().await.unwrap();
+
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records that the submission couldn't be graded. It gets a grade of zero, so the student can
+/// submit again.
+pub async fn container_add_task_error(
+    user_id: i32,
+    task_id: i32,
+    error: &str,
+) -> Result<(), String> {
+    let json_results = serde_json::to_vec(&SubmissionResponse::default()).unwrap();
+
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE user_task_grade
+            SET json_results = $1, grade = 0, error = $2, needs_regrade = FALSE, grading_delayed = FALSE,
+                graded_at = NOW()
+            WHERE user_id = $3 AND task_id = $4;",
+        )
+        .bind(json_results)
+        .bind(error)
+        .bind(user_id)
+        .bind(task_id)
         .execute(&mut *transaction)
         .await
         {
             return Err(format!("{e}"));
         }
 
+        submission::copy_results(&mut transaction, user_id, task_id).await?;
+
         transaction.commit().await.unwrap();
 
         return Ok(());
@@ -501,29 +1254,147 @@ pub async fn container_add_task_grade(
     Err("Failed to acquire database lock".into())
 }
 
+/// Replaces the full text of the student's cut-short test IO with that of their latest grading
+pub async fn store_full_outputs(
+    user_id: i32,
+    task_id: i32,
+    full_outputs: &[FullOutput],
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("DELETE FROM full_outputs WHERE user_id = $1 AND task_id = $2;")
+            .bind(user_id)
+            .bind(task_id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        for output in full_outputs {
+            let key = storage::new_key("outputs", None);
+            storage::put(&key, output.contents.as_bytes()).await?;
+
+            if let Err(e) = sqlx::query(
+                "INSERT INTO full_outputs (user_id, task_id, test_index, field, contents_key)
+                VALUES ($1, $2, $3, $4, $5);",
+            )
+            .bind(user_id)
+            .bind(task_id)
+            .bind(output.test_index)
+            .bind(output.field)
+            .bind(&key)
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("{e}"));
+            }
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns the full text of a cut-short field of the student's graded result, if the assignment's
+/// result visibility lets them see it
+pub async fn get_full_output(
+    user_id: i32,
+    task_id: i32,
+    test_index: usize,
+    field: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    postgres_lock!(transaction, {
+        let (json_results, visibility): (Vec<u8>, String) = match sqlx::query(
+            "SELECT g.json_results, a.result_visibility
+            FROM user_task_grade g
+            JOIN assignments a ON a.id = g.assignment_id
+            WHERE g.user_id = $1 AND g.task_id = $2 AND g.json_results IS NOT NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => (r.get("json_results"), r.get("result_visibility")),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let results: SubmissionResponse =
+            serde_json::from_slice(&json_results).map_err(|e| format!("{e}"))?;
+        if !results.io_visible(test_index, ResultVisibility::from(visibility)) {
+            return Ok(None);
+        }
+
+        let key: Option<String> = match sqlx::query(
+            "SELECT contents_key FROM full_outputs
+            WHERE user_id = $1 AND task_id = $2 AND test_index = $3 AND field = $4;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(test_index as i32)
+        .bind(field)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.map(|r| r.get("contents_key")),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return match key {
+            Some(key) => storage::get(&key).await.map(Some),
+            None => Ok(None),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
 pub async fn get_task_score(
     user_id: i32,
     task_id: i32,
 ) -> Result<Option<SubmissionResponse>, String> {
     postgres_lock!(transaction, {
-        let json_results: Vec<u8> = match sqlx::query(
-            "SELECT json_results FROM user_task_grade
-            WHERE user_id = $1 AND task_id = $2;",
+        let (json_results, visibility, lang, toolchain, error): (
+            Vec<u8>,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ) = match sqlx::query(
+            "SELECT g.json_results, a.result_visibility, g.submission_lang, g.toolchain_version, g.error
+            FROM user_task_grade g
+            JOIN assignments a ON a.id = g.assignment_id
+            WHERE g.user_id = $1 AND g.task_id = $2;",
         )
         .bind(user_id)
         .bind(task_id)
         .fetch_optional(&mut *transaction)
         .await
         {
-            Ok(Some(r)) => r.get("json_results"),
+            Ok(Some(r)) => (
+                r.get("json_results"),
+                r.get("result_visibility"),
+                r.get("submission_lang"),
+                r.get("toolchain_version"),
+                r.get("error"),
+            ),
             Ok(None) => return Ok(None),
             Err(e) => return Err(format!("{e}")),
         };
 
         transaction.commit().await.unwrap();
 
-        let sr = serde_json::from_slice(&json_results).unwrap();
-        return Ok(sr);
+        // Visibility is applied here, so the frontend never receives more than it may show
+        let sr: SubmissionResponse = serde_json::from_slice(&json_results).unwrap();
+        return Ok(Some(
+            sr.redact(ResultVisibility::from(visibility))
+                .with_toolchain(lang, toolchain)
+                .with_error(error),
+        ));
     });
 
     Err("Failed to acquire database lock".into())
@@ -559,9 +1430,10 @@ pub async fn get_assignment_score(
         let name = format!("{} {}", first_name, last_name);
 
         let tasks = match sqlx::query(
-            "SELECT task_id, COUNT(tests.id) n_tests
+            "SELECT task_id, SUM(tests.points)::REAL task_points
             FROM tests
             JOIN tasks ON tasks.id = tests.task_id AND tasks.assignment_id = $1
+            WHERE NOT tests.sample
             GROUP BY task_id;",
         )
         .bind(assignment_id)
@@ -572,15 +1444,19 @@ pub async fn get_assignment_score(
             Err(e) => return Err(format!("{e}")),
         };
 
-        let mut sum_tests = 0;
+        let late_multiplier = config::get().late_multiplier;
+        let mut languages: Vec<String> = vec![];
+        let mut toolchain_outdated = false;
+        let mut graded = false;
+        let mut sum_points = 0.0;
         let mut sum_grade = 0.0;
 
         for task in tasks {
-            let n_tests: i64 = task.get("n_tests");
+            let task_points: f32 = task.get("task_points");
             let task_id: i32 = task.get("task_id");
 
-            let (grade, was_late) = match sqlx::query(
-                "SELECT grade, was_late
+            let (grade, multiplier) = match sqlx::query(
+                "SELECT grade, was_late, late_multiplier, submission_lang, toolchain_version
                 FROM user_task_grade
                 WHERE user_id = $1 AND task_id = $2;",
             )
@@ -590,22 +1466,63 @@ pub async fn get_assignment_score(
             .await
             {
                 Ok(Some(r)) => {
+                    graded = true;
                     let grade: f32 = r.get("grade");
                     let was_late: bool = r.get("was_late");
-                    (grade, was_late)
+                    let tiered: Option<f32> = r.get("late_multiplier");
+                    let lang: Option<String> = r.get("submission_lang");
+                    let toolchain: Option<String> = r.get("toolchain_version");
+                    if let Some(lang) = lang {
+                        toolchain_outdated |= toolchain.is_some_and(|t| {
+                            container::toolchain_version(&lang).is_some_and(|current| current != t)
+                        });
+                        if !languages.contains(&lang) {
+                            languages.push(lang);
+                        }
+                    }
+                    (grade, tiered.unwrap_or(if was_late { late_multiplier } else { 1.0 }))
                 }
-                Ok(None) => (0.0, false),
+                Ok(None) => (0.0, 1.0),
                 Err(e) => return Err(format!("{e}")),
             };
 
-            sum_tests += n_tests;
-            sum_grade += (grade * if was_late { 0.5 } else { 1.0 }) * n_tests as f32;
+            sum_points += task_points;
+            sum_grade += grade * multiplier * task_points;
         }
 
+        let override_score: Option<f32> = match sqlx::query(
+            "SELECT score FROM grade_overrides WHERE user_id = $1 AND assignment_id = $2;",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.map(|r| r.get("score")),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let peer = peer_review::peer_scores(&mut transaction, None, Some(assignment_id))
+            .await?
+            .remove(&(user_id, assignment_id));
+        let curve = curve::curves(&mut transaction, None, Some(assignment_id))
+            .await?
+            .remove(&assignment_id)
+            .filter(|_| (graded || peer.is_some()) && override_score.is_none());
+        let autograded = sum_grade / sum_points;
+        let uncurved = peer
+            .as_ref()
+            .map_or(autograded, |(p, settings)| settings.blend(autograded, *p));
+
         let total_grade = AssignmentGrade {
             name,
             username,
-            score: sum_grade / sum_tests as f32,
+            score: override_score.unwrap_or_else(|| curve.map_or(uncurved, |c| c.apply(uncurved))),
+            uncurved_score: curve.map(|_| uncurved),
+            languages,
+            toolchain_outdated,
+            peer_score: peer.map(|(p, _)| p),
+            overridden: override_score.is_some(),
         };
 
         return Ok(Some(total_grade));
@@ -614,17 +1531,42 @@ pub async fn get_assignment_score(
     Err("Failed to acquire database lock".into())
 }
 
+/// Every student's score on the assignment, weighting tasks by their points. Grade overrides
+/// replace the scores their submissions earned.
 pub async fn get_assignment_scores(assignment_id: i32) -> Result<Vec<AssignmentGrade>, String> {
     postgres_lock!(transaction, {
-        let rows = match sqlx::query(
-            "SELECT id, first_name, last_name, user_name
-            FROM users
-            JOIN user_class c ON c.user_id = id
-            JOIN assignment_class ac ON ac.class_number = c.class_number
-            WHERE c.is_instructor = FALSE AND ac.assignment_id = $1;
-        ",
-        )
+        // The languages and toolchains of each student's graded submissions line up, in task order
+        let rows = match sqlx::query(&format!(
+            "{},
+            students AS (
+                SELECT DISTINCT c.user_id FROM user_class c
+                JOIN assignment_class ac ON ac.class_number = c.class_number
+                WHERE c.is_instructor = FALSE AND ac.assignment_id = $1
+            ),
+            languages AS (
+                SELECT g.user_id,
+                    ARRAY_AGG(g.submission_lang ORDER BY t.task_id) languages,
+                    ARRAY_AGG(g.toolchain_version ORDER BY t.task_id) toolchains
+                FROM user_task_grade g
+                JOIN task_points t ON t.task_id = g.task_id
+                WHERE g.submission_lang IS NOT NULL
+                GROUP BY g.user_id
+            )
+            SELECT u.id, u.first_name, u.last_name, u.user_name,
+                CASE WHEN tt.total_points > 0 THEN COALESCE(sc.score, 0) END score,
+                COALESCE(sc.graded, FALSE) graded, o.score override_score,
+                l.languages, l.toolchains
+            FROM students s
+            JOIN users u ON u.id = s.user_id
+            LEFT JOIN totals tt ON tt.assignment_id = $1
+            LEFT JOIN assignment_scores sc ON sc.user_id = u.id
+            LEFT JOIN languages l ON l.user_id = u.id
+            LEFT JOIN grade_overrides o ON o.user_id = u.id AND o.assignment_id = $1
+            ORDER BY u.last_name, u.first_name, u.id;",
+            gradebook::score_ctes("SELECT $1::INTEGER")
+        ))
         .bind(assignment_id)
+        .bind(config::get().late_multiplier)
         .fetch_all(&mut *transaction)
         .await
         {
@@ -632,67 +1574,62 @@ pub async fn get_assignment_scores(assignment_id: i32) -> Result<Vec<AssignmentG
             Err(e) => return Err(format!("{e}")),
         };
 
-        let mut grades = vec![];
+        let mut peer_scores =
+            peer_review::peer_scores(&mut transaction, None, Some(assignment_id)).await?;
+        let curve = curve::curves(&mut transaction, None, Some(assignment_id))
+            .await?
+            .remove(&assignment_id);
 
+        // Fingerprinting a toolchain reads its container definition, so it's done once a language
+        let mut current_toolchains: HashMap<String, Option<String>> = HashMap::new();
+        let mut grades = vec![];
         for row in rows {
             let user_id: i32 = row.get("id");
             let first_name: String = row.get("first_name");
             let last_name: String = row.get("last_name");
-            let username: String = row.get("user_name");
-
-            let name = format!("{} {}", first_name, last_name);
-
-            let tasks = match sqlx::query(
-                "SELECT task_id, COUNT(tests.id) n_tests
-                FROM tests
-                JOIN tasks ON tasks.id = tests.task_id AND tasks.assignment_id = $1
-                GROUP BY task_id;",
-            )
-            .bind(assignment_id)
-            .fetch_all(&mut *transaction)
-            .await
-            {
-                Ok(r) => r,
-                Err(e) => return Err(format!("{e}")),
-            };
 
-            let mut sum_tests = 0;
-            let mut sum_grade = 0.0;
-
-            for task in tasks {
-                let n_tests: i64 = task.get("n_tests");
-                let task_id: i32 = task.get("task_id");
-
-                let (grade, was_late) = match sqlx::query(
-                    "SELECT grade, was_late
-                    FROM user_task_grade
-                    WHERE user_id = $1 AND task_id = $2;",
-                )
-                .bind(user_id)
-                .bind(task_id)
-                .fetch_optional(&mut *transaction)
-                .await
-                {
-                    Ok(Some(r)) => {
-                        let grade: f32 = r.get("grade");
-                        let was_late: bool = r.get("was_late");
-                        (grade, was_late)
-                    }
-                    Ok(None) => (0.0, false),
-                    Err(e) => return Err(format!("{e}")),
-                };
+            let submitted: Vec<String> = row
+                .get::<Option<Vec<String>>, _>("languages")
+                .unwrap_or_default();
+            let toolchains: Vec<Option<String>> = row
+                .get::<Option<Vec<Option<String>>>, _>("toolchains")
+                .unwrap_or_default();
 
-                sum_tests += n_tests;
-                sum_grade += (grade * if was_late { 0.5 } else { 1.0 }) * n_tests as f32;
+            let toolchain_outdated = submitted.iter().zip(&toolchains).any(|(lang, toolchain)| {
+                let current = current_toolchains
+                    .entry(lang.clone())
+                    .or_insert_with(|| container::toolchain_version(lang));
+                toolchain.is_some() && current.is_some() && toolchain != current
+            });
+            let mut languages: Vec<String> = vec![];
+            for lang in submitted {
+                if !languages.contains(&lang) {
+                    languages.push(lang);
+                }
             }
 
-            let total_grade = AssignmentGrade {
-                name,
-                username,
-                score: sum_grade / sum_tests as f32,
-            };
-
-            grades.push(total_grade);
+            // An override replaces the curved score too
+            let override_score: Option<f32> = row.get("override_score");
+            let peer = peer_scores.remove(&(user_id, assignment_id));
+            let curve = curve.filter(|_| {
+                (row.get::<bool, _>("graded") || peer.is_some()) && override_score.is_none()
+            });
+            let autograded = row.get::<Option<f64>, _>("score").unwrap_or(0.0) as f32;
+            let uncurved = peer
+                .as_ref()
+                .map_or(autograded, |(p, settings)| settings.blend(autograded, *p));
+
+            grades.push(AssignmentGrade {
+                name: format!("{} {}", first_name, last_name),
+                username: row.get("user_name"),
+                score: override_score
+                    .unwrap_or_else(|| curve.map_or(uncurved, |c| c.apply(uncurved))),
+                uncurved_score: curve.map(|_| uncurved),
+                languages,
+                toolchain_outdated,
+                peer_score: peer.map(|(p, _)| p),
+                overridden: override_score.is_some(),
+            });
         }
 
         transaction.commit().await.unwrap();
@@ -718,9 +1655,9 @@ pub async fn download_submission(
         let user_id: i32 = user_row.get("id");
 
         let rows = sqlx::query(
-            "SELECT task_id, task_description, submission_zip FROM user_task_grade
+            "SELECT task_id, task_description, submission_key FROM user_task_grade
             JOIN tasks ON tasks.id = task_id
-            WHERE user_id = $1 AND tasks.assignment_id = $2;",
+            WHERE user_id = $1 AND tasks.assignment_id = $2 AND submission_key IS NOT NULL;",
         )
         .bind(user_id)
         .bind(assignment_id)
@@ -738,20 +1675,21 @@ pub async fn download_submission(
         std::fs::create_dir_all(&workdir).unwrap();
 
         for row in &rows {
-            let file: Vec<u8> = row.get("submission_zip");
+            let key: String = row.get("submission_key");
             let task_id: i32 = row.get("task_id");
-            std::fs::write(format!("{}/Task{}.zip", workdir, task_id), file).unwrap();
+            let file = storage::get(&key).await?;
+            let extension = storage::extension(&key);
+            std::fs::write(format!("{}/Task{}.{extension}", workdir, task_id), file).unwrap();
         }
 
-        Command::new("zip")
+        tokio::process::Command::new("zip")
             .args([
                 "-rj",
                 &format!("{}/{}-{}.zip", workdir, username, assignment_id),
                 &workdir,
             ])
-            .spawn()
-            .unwrap()
-            .wait()
+            .status()
+            .await
             .unwrap();
 
         let mut zip_file = vec![];
@@ -770,7 +1708,7 @@ pub async fn download_submission(
 pub async fn download_material(task_id: i32) -> Result<Option<(String, String)>, String> {
     postgres_lock!(transaction, {
         let row = match sqlx::query(
-            "SELECT supplementary_material, supplementary_filename FROM tasks
+            "SELECT material_key, supplementary_filename FROM tasks
             WHERE id = $1;",
         )
         .bind(task_id)
@@ -782,20 +1720,128 @@ pub async fn download_material(task_id: i32) -> Result<Option<(String, String)>,
             Err(e) => return Err(format!("{e}")),
         };
 
-        let material: Option<Vec<u8>> = row.get("supplementary_material");
+        let material_key: Option<String> = row.get("material_key");
         let filename: String = row.get("supplementary_filename");
 
-        let material_base64 =
-            base64::prelude::BASE64_STANDARD.encode(material.as_ref().unwrap_or(&vec![]));
-
         transaction.commit().await.unwrap();
 
+        let material = match material_key {
+            Some(key) => storage::get(&key).await?,
+            None => vec![],
+        };
+        let material_base64 = base64::prelude::BASE64_STANDARD.encode(material);
+
         return Ok(Some((material_base64, filename)));
     });
 
     Err("Failed to acquire database lock".into())
 }
 
+/// Returns true if the assignment accepts submissions in the given language, and it hasn't been
+/// disabled
+pub async fn language_allowed(assignment_id: i32, lang: &str) -> Result<bool, String> {
+    if !container::language_enabled(lang) {
+        return Ok(false);
+    }
+
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT allowed_languages IS NULL OR $1 = ANY(allowed_languages) allowed
+            FROM assignments WHERE id = $2;",
+        )
+        .bind(lang)
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => Ok(r.get("allowed")),
+            Ok(None) => Ok(false),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// When submissions to the assignment close for good. `None` => never.
+pub async fn accept_until(assignment_id: i32) -> Result<Option<DateTime<Utc>>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query("SELECT accept_until FROM assignments WHERE id = $1;")
+            .bind(assignment_id)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(r.and_then(|r| r.get("accept_until"))),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns true if the task belongs to the assignment
+pub async fn task_exists(assignment_id: i32, task_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query("SELECT 1 FROM tasks WHERE id = $1 AND assignment_id = $2;")
+            .bind(task_id)
+            .bind(assignment_id)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(r.is_some()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns true if the task belongs to the assignment and can be submitted from the editor
+pub async fn allows_editor(assignment_id: i32, task_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT allow_editor FROM tasks WHERE id = $1 AND assignment_id = $2;",
+        )
+        .bind(task_id)
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => Ok(r.get::<Option<bool>, _>("allow_editor").unwrap_or(false)),
+            Ok(None) => Ok(false),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn download_template(task_id: i32) -> Result<Option<(String, String)>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT template, template_filename FROM tasks
+            WHERE id = $1 AND template IS NOT NULL;",
+        )
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let template: Vec<u8> = row.get("template");
+        let filename: Option<String> = row.get("template_filename");
+
+        let template_base64 = base64::prelude::BASE64_STANDARD.encode(template);
+
+        return Ok(Some((template_base64, filename.unwrap_or("template".into()))));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
 pub async fn submission_in_progress(user_id: i32, task_id: i32) -> bool {
     postgres_lock!(transaction, {
         return matches!(sqlx::query(
@@ -812,142 +1858,825 @@ pub async fn submission_in_progress(user_id: i32, task_id: i32) -> bool {
     false
 }
 
-pub async fn remove_old_grade(user_id: i32, task_id: i32) -> Result<(), String> {
+/// How much of the results of the task's assignment students may see
+pub async fn task_result_visibility(task_id: i32) -> Result<ResultVisibility, String> {
     postgres_lock!(transaction, {
-        sqlx::query("DELETE FROM user_task_grade WHERE user_id = $1 AND task_id = $2;")
-            .bind(user_id)
-            .bind(task_id)
-            .execute(&mut *transaction)
-            .await
-            .unwrap();
+        return match sqlx::query(
+            "SELECT a.result_visibility FROM tasks t
+            JOIN assignments a ON a.id = t.assignment_id
+            WHERE t.id = $1;",
+        )
+        .bind(task_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.get::<String, _>("result_visibility").into()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn submission_graded(user_id: i32, task_id: i32) -> bool {
+    postgres_lock!(transaction, {
+        return matches!(sqlx::query(
+                "SELECT * FROM user_task_grade WHERE user_id = $1 AND task_id = $2 AND grade IS NOT NULL;"
+            )
+                .bind(user_id)
+                .bind(task_id)
+                .fetch_optional(&mut *transaction)
+                .await,
+            Ok(Some(_))
+        );
+    });
+
+    false
+}
+
+pub async fn grading_delayed(user_id: i32, task_id: i32) -> bool {
+    postgres_lock!(transaction, {
+        return matches!(sqlx::query(
+                "SELECT * FROM user_task_grade
+                WHERE user_id = $1 AND task_id = $2 AND grade IS NULL AND grading_delayed = TRUE;"
+            )
+                .bind(user_id)
+                .bind(task_id)
+                .fetch_optional(&mut *transaction)
+                .await,
+            Ok(Some(_))
+        );
+    });
+
+    false
+}
+
+/// Deterministically picks a variant for the user, so they always see the same one
+fn assigned_variant(user_id: i32, task_id: i32, n_variants: usize) -> Option<i32> {
+    if n_variants == 0 {
+        return None;
+    }
+
+    let digest = Sha256::digest(format!("{user_id}:{task_id}"));
+    let seed = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    Some((seed % n_variants as u64) as i32)
+}
+
+fn decode_tolerance(absolute: Option<f64>, relative: Option<f64>) -> Option<Tolerance> {
+    let default = Tolerance::default();
+    (absolute.is_some() || relative.is_some()).then(|| Tolerance {
+        absolute: absolute.unwrap_or(default.absolute),
+        relative: relative.unwrap_or(default.relative),
+    })
+}
+
+fn decode_late_tiers(late_tiers: Option<String>) -> Vec<LateTier> {
+    late_tiers
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default()
+}
+
+/// Flags a submission to be graded once the container runtime is available again
+pub async fn mark_grading_delayed(user_id: i32, task_id: i32) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE user_task_grade SET grading_delayed = TRUE WHERE user_id = $1 AND task_id = $2;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
 
         transaction.commit().await.unwrap();
         return Ok(());
     });
 
-    Err("Failed to acquire transaction lock".into())
+    Err("Failed to acquire database lock".into())
+}
+
+/// Clears the delayed flag on every delayed submission and returns them, ready to be queued again
+pub async fn take_delayed_submissions() -> Result<Vec<ContainerEntry>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "UPDATE user_task_grade
+            SET grading_delayed = FALSE
+            WHERE grading_delayed = TRUE AND grade IS NULL
+            RETURNING user_id, task_id, was_late, submission_key, submission_lang;",
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        // Read before committing, so the flags stay put if storage can't be reached
+        let mut entries = vec![];
+        for row in &rows {
+            entries.push(container_entry(row).await?);
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(entries);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Flags every graded submission to the assignment for regrade and returns them, ready to be
+/// queued again. Their grades stay in place until the new ones come in.
+///
+/// Returns `None` if the assignment doesn't belong to the class.
+pub async fn take_regrade_submissions(
+    class_number: &str,
+    assignment_id: i32,
+) -> Result<Option<Vec<ContainerEntry>>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query(
+            "SELECT 1 FROM assignment_class WHERE assignment_id = $1 AND class_number = $2;",
+        )
+        .bind(assignment_id)
+        .bind(class_number)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(_)) => (),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        }
+
+        let rows = match sqlx::query(
+            "UPDATE user_task_grade
+            SET needs_regrade = TRUE
+            WHERE assignment_id = $1 AND grade IS NOT NULL AND submission_key IS NOT NULL
+            RETURNING user_id, task_id, was_late, submission_key, submission_lang;",
+        )
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        // Read before committing, so the flags stay put if storage can't be reached
+        let mut entries = vec![];
+        for row in &rows {
+            entries.push(container_entry(row).await?);
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(entries));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Rebuilds the queue entry of a stored submission
+async fn container_entry(r: &PgRow) -> Result<ContainerEntry, String> {
+    let zip_file = storage::get(r.get::<&str, _>("submission_key")).await?;
+    let lang: Option<String> = r.get("submission_lang");
+    Ok(ContainerEntry::new(
+        zip_file.into(),
+        r.get("user_id"),
+        r.get("task_id"),
+        r.get::<Option<bool>, _>("was_late").unwrap_or(false),
+        lang.unwrap_or_default(),
+    ))
 }
 
+/// Updates an assignment in place, returning the number of submissions flagged for regrade
+///
+/// Tasks and tests carrying an id are edited rather than recreated, so existing grades survive.
+/// Tasks missing from the request are removed. Submissions to a task whose tests changed are
+/// flagged with `needs_regrade` instead of being deleted.
 pub async fn update_assignment(
     assignment_id: i32,
     assignment_name: String,
     assignment_description: Option<String>,
     deadline: String,
+    settings: AssignmentSettings,
     tasks: Vec<ReqTask>,
-) -> Result<(), String> {
+) -> Result<u64, String> {
     postgres_lock!(transaction, {
         let Ok(deadline) = deadline.parse::<DateTime<Utc>>() else {
             return Err("Invalid deadline date string.".into());
         };
+        let accept_until = settings.accept_until()?;
 
         if let Err(e) = sqlx::query(
             "UPDATE assignments
-            SET assignment_name = $1, assignment_description = $2, deadline = $3
-            WHERE id = $4;",
+            SET assignment_name = $1, assignment_description = $2, deadline = $3, allowed_languages = $4, result_visibility = $5, grace_period_minutes = $6, late_tiers = $7,
+                category_id = (
+                    SELECT c.id FROM assignment_categories c
+                    JOIN assignment_class ac ON ac.class_number = c.class_number
+                    WHERE c.id = $8 AND ac.assignment_id = $9
+                ),
+                peer_review = $10, max_attempts = $11, accept_until = $12
+            WHERE id = $9;",
         )
         .bind(assignment_name)
         .bind(assignment_description)
         .bind(deadline)
+        .bind(settings.allowed_languages)
+        .bind(settings.result_visibility.as_str())
+        .bind(settings.grace_period_minutes.max(0))
+        .bind(serde_json::to_string(&settings.late_tiers).unwrap())
+        .bind(settings.category_id)
         .bind(assignment_id)
+        .bind(
+            settings
+                .peer_review
+                .as_ref()
+                .map(|p| serde_json::to_string(p).unwrap()),
+        )
+        .bind(settings.max_attempts)
+        .bind(accept_until)
         .execute(&mut *transaction)
         .await
         {
             return Err(format!("{e}"));
         }
 
-        if let Err(e) = sqlx::query(
-            "DELETE FROM tasks
-            WHERE assignment_id = $1;",
+        reprice_late_submissions(
+            &mut transaction,
+            assignment_id,
+            deadline,
+            &settings.late_tiers,
         )
-        .bind(assignment_id)
-        .execute(&mut *transaction)
-        .await
-        {
-            return Err(format!("{e}"));
-        }
+        .await?;
 
-        for (
-            i,
-            ReqTask {
-                task_description,
-                allow_editor,
-                material_base64,
-                material_filename,
-                timeout,
-                tests,
-            },
-        ) in tasks.iter().enumerate()
-        {
-            let material_bytes = material_base64
-                .as_ref()
-                .map(|f| base64::prelude::BASE64_STANDARD.decode(f).unwrap());
-
-            let task_row = match sqlx::query(
-                "INSERT INTO tasks (assignment_id, task_description, allow_editor, placement, supplementary_material, supplementary_filename)
-                VALUES ($1, $2, $3, $4, $5, $6)
-                RETURNING id;"
-            ).bind(assignment_id)
-            .bind(task_description)
-            .bind(allow_editor)
-            .bind(i as i32)
-            .bind(material_bytes)
-            .bind(material_filename)
-            .fetch_one(&mut *transaction)
-            .await {
-                Ok(r) => r,
+        let existing_task_ids: Vec<i32> =
+            match sqlx::query("SELECT id FROM tasks WHERE assignment_id = $1;")
+                .bind(assignment_id)
+                .fetch_all(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.iter().map(|r| r.get("id")).collect(),
                 Err(e) => return Err(format!("{e}")),
             };
 
-            let task_id: i32 = task_row.get("id");
+        let mut kept_task_ids = vec![];
+        let mut flagged = 0;
 
-            for ReqTest {
-                test_name,
-                is_public,
-                input,
-                output,
-                input_file_base64,
-                output_file_base64,
-            } in tests
-            {
-                let input = if let Some(i_f) = &input_file_base64 {
-                    base64::prelude::BASE64_STANDARD
-                        .decode(i_f)
-                        .map(|f| String::from_utf8(f).unwrap())
-                        .unwrap()
-                } else {
-                    input.clone().unwrap()
-                };
+        for (placement, task) in tasks.iter().enumerate() {
+            let task_id = match task.task_id {
+                Some(task_id) if existing_task_ids.contains(&task_id) => {
+                    update_task(&mut transaction, task_id, placement, task).await?;
+                    task_id
+                }
+                _ => insert_task(&mut transaction, assignment_id, placement, task).await?,
+            };
+            kept_task_ids.push(task_id);
 
-                let output = if let Some(o_f) = &output_file_base64 {
-                    base64::prelude::BASE64_STANDARD
-                        .decode(o_f)
-                        .map(|f| String::from_utf8(f).unwrap())
-                        .unwrap()
-                } else {
-                    output.clone().unwrap()
+            let existing_test_ids: Vec<i32> =
+                match sqlx::query("SELECT id FROM tests WHERE task_id = $1;")
+                    .bind(task_id)
+                    .fetch_all(&mut *transaction)
+                    .await
+                {
+                    Ok(r) => r.iter().map(|r| r.get("id")).collect(),
+                    Err(e) => return Err(format!("{e}")),
                 };
 
-                if let Err(e) = sqlx::query(
-                    "INSERT INTO tests (task_id, test_name, input, output, public, timeout)
-                    VALUES ($1, $2, $3, $4, $5, $6);",
+            let mut kept_test_ids = vec![];
+            let mut tests_changed = false;
+
+            for (test_placement, test) in task.tests.iter().enumerate() {
+                match test.test_id {
+                    Some(test_id) if existing_test_ids.contains(&test_id) => {
+                        tests_changed |= update_test(
+                            &mut transaction,
+                            test_id,
+                            test_placement,
+                            test,
+                            test.timeout.or(task.timeout),
+                            test.memory_limit_mb.or(task.memory_limit_mb),
+                        )
+                        .await?;
+                        kept_test_ids.push(test_id);
+                    }
+                    _ => {
+                        insert_test(
+                            &mut transaction,
+                            task_id,
+                            test_placement,
+                            test,
+                            test.timeout.or(task.timeout),
+                            test.memory_limit_mb.or(task.memory_limit_mb),
+                        )
+                        .await?;
+                        tests_changed = true;
+                    }
+                }
+            }
+
+            let removed_tests = match sqlx::query(
+                "DELETE FROM tests
+                WHERE task_id = $1 AND NOT (id = ANY($2));",
+            )
+            .bind(task_id)
+            .bind(&kept_test_ids)
+            .execute(&mut *transaction)
+            .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => return Err(format!("{e}")),
+            };
+            tests_changed |= removed_tests > 0;
+
+            if tests_changed {
+                flagged += match sqlx::query(
+                    "UPDATE user_task_grade
+                    SET needs_regrade = TRUE
+                    WHERE task_id = $1 AND grade IS NOT NULL;",
                 )
                 .bind(task_id)
-                .bind(test_name)
-                .bind(input)
-                .bind(output)
-                .bind(is_public)
-                .bind(timeout)
                 .execute(&mut *transaction)
                 .await
                 {
-                    return Err(format!("{e}"));
-                }
+                    Ok(r) => r.rows_affected(),
+                    Err(e) => return Err(format!("{e}")),
+                };
             }
         }
 
+        // Only tasks the instructor actually removed are deleted (along with their grades)
+        if let Err(e) = sqlx::query(
+            "DELETE FROM tasks
+            WHERE assignment_id = $1 AND NOT (id = ANY($2));",
+        )
+        .bind(assignment_id)
+        .bind(&kept_task_ids)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
         transaction.commit().await.unwrap();
-        return Ok(());
+        return Ok(flagged);
     });
 
     Err("Failed to acquire transaction lock".into())
 }
+
+/// Decodes a test's input and output, preferring the base64 file fields over the plain text ones.
+/// Missing input or output is stored as empty. Tests of `junit` tasks don't need either, and
+/// validation rejects other tests without them.
+fn decode_test_io(test: &ReqTest) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let decode = |file: &Option<String>, text: &Option<String>| -> Result<Vec<u8>, String> {
+        if let Some(f) = file {
+            base64::prelude::BASE64_STANDARD
+                .decode(f)
+                .map_err(|e| format!("Invalid base64 test file: {e}"))
+        } else {
+            Ok(text.clone().map(String::into_bytes).unwrap_or_default())
+        }
+    };
+
+    Ok((
+        decode(&test.input_file_base64, &test.input)?,
+        decode(&test.output_file_base64, &test.output)?,
+    ))
+}
+
+/// Puts the task's material, if it has one, in blob storage and returns its key
+async fn store_material(task: &ReqTask) -> Result<Option<String>, String> {
+    let Some(material) = task
+        .material_base64
+        .as_ref()
+        .and_then(|f| base64::prelude::BASE64_STANDARD.decode(f).ok())
+    else {
+        return Ok(None);
+    };
+
+    let key = storage::new_key("materials", None);
+    storage::put(&key, &material).await?;
+    Ok(Some(key))
+}
+
+async fn insert_task(
+    conn: &mut PgConnection,
+    assignment_id: i32,
+    placement: usize,
+    task: &ReqTask,
+) -> Result<i32, String> {
+    let material_key = store_material(task).await?;
+    let template = task
+        .template_base64
+        .as_ref()
+        .and_then(|f| base64::prelude::BASE64_STANDARD.decode(f).ok());
+
+    match sqlx::query(
+        "INSERT INTO tasks (assignment_id, task_description, allow_editor, placement, template, template_filename, material_key, supplementary_filename, test_method, variant_descriptions, prerequisite_placement, prerequisite_threshold, ordered_tests, stop_on_failure, cpus, pids_limit, memory_check, memory_error_penalty, test_command, lint_weight, network_access, disk_limit_mb, max_attempts, cooldown_minutes, timeout, memory_limit_mb)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26)
+        RETURNING id;",
+    )
+    .bind(assignment_id)
+    .bind(&task.task_description)
+    .bind(task.allow_editor)
+    .bind(placement as i32)
+    .bind(template)
+    .bind(&task.template_filename)
+    .bind(material_key)
+    .bind(&task.material_filename)
+    .bind(String::from(task.test_method))
+    .bind(&task.variant_descriptions)
+    .bind(task.prerequisite.as_ref().map(|p| p.task_placement))
+    .bind(task.prerequisite.as_ref().map_or(1.0, |p| p.threshold))
+    .bind(task.ordered_tests)
+    .bind(task.stop_on_failure)
+    .bind(task.cpus)
+    .bind(task.pids_limit)
+    .bind(task.memory_check)
+    .bind(task.memory_error_penalty)
+    .bind(&task.test_command)
+    .bind(task.lint_weight)
+    .bind(task.network_access)
+    .bind(task.disk_limit_mb)
+    .bind(task.max_attempts)
+    .bind(task.cooldown_minutes)
+    .bind(task.timeout)
+    .bind(task.memory_limit_mb)
+    .fetch_one(conn)
+    .await
+    {
+        Ok(r) => Ok(r.get("id")),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+async fn update_task(
+    conn: &mut PgConnection,
+    task_id: i32,
+    placement: usize,
+    task: &ReqTask,
+) -> Result<(), String> {
+    let material_key = store_material(task).await?;
+    let template = task
+        .template_base64
+        .as_ref()
+        .and_then(|f| base64::prelude::BASE64_STANDARD.decode(f).ok());
+
+    if let Err(e) = sqlx::query(
+        "UPDATE tasks
+        SET task_description = $1, allow_editor = $2, placement = $3, material_key = $4, supplementary_filename = $5, template = $6, template_filename = $7, variant_descriptions = $8,
+            prerequisite_placement = $10, prerequisite_threshold = $11, ordered_tests = $12, stop_on_failure = $13,
+            test_method = $14, cpus = $15, pids_limit = $16, memory_check = $17, memory_error_penalty = $18,
+            test_command = $19, lint_weight = $20, network_access = $21,
+            disk_limit_mb = $22, max_attempts = $23, cooldown_minutes = $24,
+            timeout = $25, memory_limit_mb = $26
+        WHERE id = $9;",
+    )
+    .bind(&task.task_description)
+    .bind(task.allow_editor)
+    .bind(placement as i32)
+    .bind(material_key)
+    .bind(&task.material_filename)
+    .bind(template)
+    .bind(&task.template_filename)
+    .bind(&task.variant_descriptions)
+    .bind(task_id)
+    .bind(task.prerequisite.as_ref().map(|p| p.task_placement))
+    .bind(task.prerequisite.as_ref().map_or(1.0, |p| p.threshold))
+    .bind(task.ordered_tests)
+    .bind(task.stop_on_failure)
+    .bind(String::from(task.test_method))
+    .bind(task.cpus)
+    .bind(task.pids_limit)
+    .bind(task.memory_check)
+    .bind(task.memory_error_penalty)
+    .bind(&task.test_command)
+    .bind(task.lint_weight)
+    .bind(task.network_access)
+    .bind(task.disk_limit_mb)
+    .bind(task.max_attempts)
+    .bind(task.cooldown_minutes)
+    .bind(task.timeout)
+    .bind(task.memory_limit_mb)
+    .execute(conn)
+    .await
+    {
+        return Err(format!("{e}"));
+    }
+
+    Ok(())
+}
+
+async fn insert_test(
+    conn: &mut PgConnection,
+    task_id: i32,
+    placement: usize,
+    test: &ReqTest,
+    timeout: Option<i32>,
+    memory_limit_mb: Option<i32>,
+) -> Result<(), String> {
+    let (input, output) = decode_test_io(test)?;
+
+    let test_id: i32 = match sqlx::query(
+        "INSERT INTO tests (task_id, test_name, input, output, public, timeout, variant, comparison, abs_tolerance, rel_tolerance, points, partial_credit, hint, placement, memory_limit_mb, artifact, sample, interactive)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+        RETURNING id;",
+    )
+    .bind(task_id)
+    .bind(&test.test_name)
+    .bind(input)
+    .bind(output)
+    .bind(test.is_public)
+    .bind(timeout)
+    .bind(test.variant)
+    .bind(test.comparison.as_str())
+    .bind(test.tolerance.map(|t| t.absolute))
+    .bind(test.tolerance.map(|t| t.relative))
+    .bind(test.points)
+    .bind(test.partial_credit)
+    .bind(&test.hint)
+    .bind(placement as i32)
+    .bind(memory_limit_mb)
+    .bind(&test.artifact)
+    .bind(test.sample)
+    .bind(test.interactive)
+    .fetch_one(&mut *conn)
+    .await
+    {
+        Ok(r) => r.get("id"),
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    replace_fixtures(conn, test_id, &test.fixtures).await?;
+    replace_env_vars(conn, test_id, &test.env).await?;
+
+    Ok(())
+}
+
+/// Updates a test in place. Returns true if anything about the test actually changed.
+async fn update_test(
+    conn: &mut PgConnection,
+    test_id: i32,
+    placement: usize,
+    test: &ReqTest,
+    timeout: Option<i32>,
+    memory_limit_mb: Option<i32>,
+) -> Result<bool, String> {
+    let (input, output) = decode_test_io(test)?;
+
+    let test_changed = match sqlx::query(
+        "UPDATE tests
+        SET test_name = $1, input = $2, output = $3, public = $4, timeout = $5, variant = $6, comparison = $8,
+            abs_tolerance = $9, rel_tolerance = $10, points = $11, partial_credit = $12, hint = $13, placement = $14,
+            memory_limit_mb = $15, artifact = $16, sample = $17, interactive = $18
+        WHERE id = $7
+            AND (test_name, input, output, public, timeout, variant, comparison, abs_tolerance, rel_tolerance, points, partial_credit, hint, placement, memory_limit_mb, artifact, sample, interactive)
+                IS DISTINCT FROM ($1, $2, $3, $4, $5, $6, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18);",
+    )
+    .bind(&test.test_name)
+    .bind(input)
+    .bind(output)
+    .bind(test.is_public)
+    .bind(timeout)
+    .bind(test.variant)
+    .bind(test_id)
+    .bind(test.comparison.as_str())
+    .bind(test.tolerance.map(|t| t.absolute))
+    .bind(test.tolerance.map(|t| t.relative))
+    .bind(test.points)
+    .bind(test.partial_credit)
+    .bind(&test.hint)
+    .bind(placement as i32)
+    .bind(memory_limit_mb)
+    .bind(&test.artifact)
+    .bind(test.sample)
+    .bind(test.interactive)
+    .execute(&mut *conn)
+    .await
+    {
+        Ok(r) => r.rows_affected() > 0,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let fixtures_changed = replace_fixtures(conn, test_id, &test.fixtures).await?;
+    let env_changed = replace_env_vars(conn, test_id, &test.env).await?;
+
+    Ok(test_changed || fixtures_changed || env_changed)
+}
+
+/// Replaces a test's fixtures. Returns true if they differ from the ones it had.
+async fn replace_fixtures(
+    conn: &mut PgConnection,
+    test_id: i32,
+    fixtures: &[Fixture],
+) -> Result<bool, String> {
+    let mut new = fixtures
+        .iter()
+        .map(|f| {
+            base64::prelude::BASE64_STANDARD
+                .decode(&f.data_base64)
+                .map(|contents| (f.filename.clone(), contents))
+                .map_err(|e| format!("Invalid base64 fixture {}: {e}", f.filename))
+        })
+        .collect::<Result<Vec<(String, Vec<u8>)>, String>>()?;
+    new.sort();
+
+    // Compared by hash, so the old contents needn't be read back from blob storage
+    let old = match sqlx::query(
+        "SELECT filename, sha256 FROM test_fixtures WHERE test_id = $1 ORDER BY filename;",
+    )
+    .bind(test_id)
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(r) => r
+            .iter()
+            .map(|r| (r.get("filename"), r.get("sha256")))
+            .collect::<Vec<(String, String)>>(),
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let sha256 = |contents: &[u8]| -> String {
+        Sha256::digest(contents)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    };
+    let new_hashes = new
+        .iter()
+        .map(|(filename, contents)| (filename.clone(), sha256(contents)))
+        .collect::<Vec<(String, String)>>();
+    if old == new_hashes {
+        return Ok(false);
+    }
+
+    if let Err(e) = sqlx::query("DELETE FROM test_fixtures WHERE test_id = $1;")
+        .bind(test_id)
+        .execute(&mut *conn)
+        .await
+    {
+        return Err(format!("{e}"));
+    }
+
+    for (filename, contents) in new {
+        let key = storage::new_key("fixtures", None);
+        storage::put(&key, &contents).await?;
+
+        if let Err(e) = sqlx::query(
+            "INSERT INTO test_fixtures (test_id, filename, contents_key, sha256)
+            VALUES ($1, $2, $3, $4);",
+        )
+        .bind(test_id)
+        .bind(filename)
+        .bind(&key)
+        .bind(sha256(&contents))
+        .execute(&mut *conn)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+    }
+
+    Ok(true)
+}
+
+/// Replaces a test's environment variables. Returns true if they differ from the ones it had.
+async fn replace_env_vars(
+    conn: &mut PgConnection,
+    test_id: i32,
+    env: &[EnvVar],
+) -> Result<bool, String> {
+    let mut new = env.to_vec();
+    new.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let old = env_vars(conn, &[test_id])
+        .await?
+        .remove(&test_id)
+        .unwrap_or_default();
+
+    if old == new {
+        return Ok(false);
+    }
+
+    if let Err(e) = sqlx::query("DELETE FROM test_env_vars WHERE test_id = $1;")
+        .bind(test_id)
+        .execute(&mut *conn)
+        .await
+    {
+        return Err(format!("{e}"));
+    }
+
+    for var in new {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO test_env_vars (test_id, name, value, secret) VALUES ($1, $2, $3, $4);",
+        )
+        .bind(test_id)
+        .bind(var.name)
+        .bind(var.value)
+        .bind(var.secret)
+        .execute(&mut *conn)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns the prerequisite of the task if the user hasn't passed it yet, or `None` if the task
+/// is open to them
+pub async fn locked_by(user_id: i32, task_id: i32) -> Result<Option<Prerequisite>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT t.prerequisite_placement, t.prerequisite_threshold, g.grade
+            FROM tasks t
+            LEFT JOIN tasks p ON p.assignment_id = t.assignment_id AND p.placement = t.prerequisite_placement
+            LEFT JOIN user_task_grade g ON g.task_id = p.id AND g.user_id = $1
+            WHERE t.id = $2 AND t.prerequisite_placement IS NOT NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        return Ok(row.and_then(|r| {
+            let grade: Option<f32> = r.get("grade");
+            let prerequisite = Prerequisite {
+                task_placement: r.get("prerequisite_placement"),
+                threshold: r.get("prerequisite_threshold"),
+            };
+            (grade.unwrap_or(0.0) < prerequisite.threshold).then_some(prerequisite)
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Adds tests to a task, each with its own timeout, and flags existing grades on the task for
+/// regrading. Returns the number of grades flagged, or `None` if the task isn't in the assignment.
+pub async fn add_tests(
+    assignment_id: i32,
+    task_id: i32,
+    tests: Vec<ReqTest>,
+) -> Result<Option<u64>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query("SELECT id FROM tasks WHERE id = $1 AND assignment_id = $2;")
+            .bind(task_id)
+            .bind(assignment_id)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some(_)) => (),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        }
+
+        // Imported tests go after the task's existing ones
+        let first_placement: i32 = match sqlx::query(
+            "SELECT COALESCE(MAX(placement) + 1, COUNT(*)::INTEGER) next FROM tests WHERE task_id = $1;",
+        )
+        .bind(task_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("next"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        for (i, test) in tests.iter().enumerate() {
+            let placement = first_placement as usize + i;
+            insert_test(
+                &mut transaction,
+                task_id,
+                placement,
+                test,
+                test.timeout,
+                test.memory_limit_mb,
+            )
+            .await?;
+        }
+
+        let flagged = match sqlx::query(
+            "UPDATE user_task_grade
+            SET needs_regrade = TRUE
+            WHERE task_id = $1 AND grade IS NOT NULL;",
+        )
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(flagged));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
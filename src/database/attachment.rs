@@ -0,0 +1,144 @@
+//! Contains database operations associated with files attached to a whole assignment (e.g. a PDF spec)
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use sqlx::{PgConnection, Row};
+
+use crate::{database::POSTGRES, model::attachment::AttachmentInfo, postgres_lock, storage};
+
+/// Stores a new attachment on an assignment, returning its id
+pub async fn add_attachment(
+    assignment_id: i32,
+    filename: String,
+    data: Vec<u8>,
+) -> Result<i32, String> {
+    postgres_lock!(transaction, {
+        let id = insert_attachment(&mut transaction, assignment_id, filename, &data).await?;
+
+        transaction.commit().await.unwrap();
+        return Ok(id);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// [`add_attachment`], on a connection the caller already holds
+pub(super) async fn insert_attachment(
+    conn: &mut PgConnection,
+    assignment_id: i32,
+    filename: String,
+    data: &[u8],
+) -> Result<i32, String> {
+    let key = storage::new_key("attachments", None);
+    storage::put(&key, data).await?;
+
+    match sqlx::query(
+        "INSERT INTO assignment_attachments (assignment_id, filename, attachment_key, size)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id;",
+    )
+    .bind(assignment_id)
+    .bind(filename)
+    .bind(&key)
+    .bind(data.len() as i32)
+    .fetch_one(conn)
+    .await
+    {
+        Ok(r) => Ok(r.get("id")),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Removes an attachment. Returns false if the assignment has no such attachment.
+pub async fn remove_attachment(assignment_id: i32, attachment_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let removed = match sqlx::query(
+            "DELETE FROM assignment_attachments WHERE id = $1 AND assignment_id = $2;",
+        )
+        .bind(attachment_id)
+        .bind(assignment_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(removed);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Lists attachment metadata for an assignment, without the file contents
+pub async fn list_attachments(assignment_id: i32) -> Result<Vec<AttachmentInfo>, String> {
+    postgres_lock!(transaction, {
+        return attachments_of(&mut transaction, assignment_id).await;
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// [`list_attachments`], on a connection the caller already holds
+pub(super) async fn attachments_of(
+    conn: &mut PgConnection,
+    assignment_id: i32,
+) -> Result<Vec<AttachmentInfo>, String> {
+    let rows = match sqlx::query(
+        "SELECT id, filename, size FROM assignment_attachments
+        WHERE assignment_id = $1
+        ORDER BY id ASC;",
+    )
+    .bind(assignment_id)
+    .fetch_all(conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    Ok(rows
+        .iter()
+        .map(|r| AttachmentInfo {
+            attachment_id: r.get("id"),
+            filename: r.get("filename"),
+            size: r.get("size"),
+        })
+        .collect())
+}
+
+/// Returns (base64 contents, filename) of an attachment
+pub async fn download_attachment(
+    assignment_id: i32,
+    attachment_id: i32,
+) -> Result<Option<(String, String)>, String> {
+    let Some((key, filename)) = attachment_key(assignment_id, attachment_id).await? else {
+        return Ok(None);
+    };
+
+    let data = storage::get(&key).await?;
+    Ok(Some((BASE64_STANDARD.encode(data), filename)))
+}
+
+/// Returns (key in blob storage, filename) of an attachment
+async fn attachment_key(
+    assignment_id: i32,
+    attachment_id: i32,
+) -> Result<Option<(String, String)>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT attachment_key, filename FROM assignment_attachments
+            WHERE id = $1 AND assignment_id = $2;",
+        )
+        .bind(attachment_id)
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.map(|r| (r.get("attachment_key"), r.get("filename")))),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
@@ -0,0 +1,165 @@
+//! Contains database operations associated with blob storage
+
+use sqlx::Row;
+
+use crate::{archive, database::POSTGRES, postgres_lock, storage};
+
+/// Rows moved to blob storage per transaction
+const BATCH: i64 = 20;
+
+/// Where older versions kept archives, materials and other files in Postgres:
+/// (table, BYTEA column, key column, key prefix)
+const LEGACY_COLUMNS: [(&str, &str, &str, &str); 8] = [
+    (
+        "submissions",
+        "submission_zip",
+        "submission_key",
+        "submissions",
+    ),
+    (
+        "user_task_grade",
+        "submission_zip",
+        "submission_key",
+        "submissions",
+    ),
+    ("grading_jobs", "submission_zip", "submission_key", "jobs"),
+    (
+        "tasks",
+        "supplementary_material",
+        "material_key",
+        "materials",
+    ),
+    (
+        "assignment_attachments",
+        "data",
+        "attachment_key",
+        "attachments",
+    ),
+    ("tasks", "reference_solution", "reference_key", "solutions"),
+    ("test_fixtures", "contents", "contents_key", "fixtures"),
+    ("full_outputs", "contents", "contents_key", "outputs"),
+];
+
+/// Key prefixes of the objects that are archives, and get their format's extension
+const ARCHIVE_PREFIXES: [&str; 3] = ["submissions", "jobs", "solutions"];
+
+/// Moves the files older versions kept in Postgres to blob storage, returning how many were moved.
+/// Meant to run at start-up, before anything else uses the tables.
+pub async fn move_to_storage() -> Result<u64, String> {
+    let mut moved = 0;
+
+    for (table, blob_column, key_column, prefix) in LEGACY_COLUMNS {
+        // The submission that counts is also an attempt, which has just been moved
+        if table == "user_task_grade" {
+            share_attempt_keys().await?;
+        }
+
+        loop {
+            let n = move_batch(table, blob_column, key_column, prefix).await?;
+            if n == 0 {
+                break;
+            }
+            moved += n;
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Points submissions that count at the object of the attempt they are
+async fn share_attempt_keys() -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE user_task_grade g
+            SET submission_key = s.submission_key, submission_zip = NULL
+            FROM submissions s
+            WHERE s.user_id = g.user_id AND s.task_id = g.task_id AND s.attempt = g.attempt
+                AND s.submission_key IS NOT NULL AND g.submission_zip IS NOT NULL;",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Moves up to [`BATCH`] blobs of the column, returning how many were moved. Rows are found again
+/// by `ctid`, which stays put as nothing else writes to the table in the meantime.
+async fn move_batch(
+    table: &str,
+    blob_column: &str,
+    key_column: &str,
+    prefix: &str,
+) -> Result<u64, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(&format!(
+            "SELECT ctid::TEXT row_id, {blob_column} blob FROM {table}
+            WHERE {blob_column} IS NOT NULL
+            LIMIT $1;"
+        ))
+        .bind(BATCH)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        for row in &rows {
+            let blob: Vec<u8> = row.get("blob");
+            let extension = ARCHIVE_PREFIXES
+                .contains(&prefix)
+                .then(|| archive::extension(&blob));
+            let key = storage::new_key(prefix, extension);
+            storage::put(&key, &blob).await?;
+
+            if let Err(e) = sqlx::query(&format!(
+                "UPDATE {table} SET {key_column} = $1, {blob_column} = NULL
+                WHERE ctid = $2::TID;"
+            ))
+            .bind(&key)
+            .bind(row.get::<String, _>("row_id"))
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("{e}"));
+            }
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(rows.len() as u64);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The keys no row refers to
+pub async fn unreferenced(keys: Vec<String>) -> Result<Vec<String>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT k FROM UNNEST($1::TEXT[]) k
+            WHERE NOT EXISTS (SELECT 1 FROM submissions WHERE submission_key = k)
+                AND NOT EXISTS (SELECT 1 FROM user_task_grade WHERE submission_key = k)
+                AND NOT EXISTS (SELECT 1 FROM grading_jobs WHERE submission_key = k)
+                AND NOT EXISTS (SELECT 1 FROM tasks WHERE material_key = k OR reference_key = k)
+                AND NOT EXISTS (SELECT 1 FROM assignment_attachments WHERE attachment_key = k)
+                AND NOT EXISTS (SELECT 1 FROM test_fixtures WHERE contents_key = k)
+                AND NOT EXISTS (SELECT 1 FROM full_outputs WHERE contents_key = k);",
+        )
+        .bind(keys)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.iter().map(|r| r.get("k")).collect()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
@@ -0,0 +1,396 @@
+//! Contains database operations associated with assignment categories and the course grades they weight
+
+use std::collections::HashMap;
+
+use sqlx::{PgConnection, Row};
+
+use crate::{
+    config,
+    database::{POSTGRES, curve, gradebook, peer_review},
+    model::category::{Category, CategoryScore, CourseGrade},
+    postgres_lock,
+};
+
+pub async fn list_categories(class_number: String) -> Result<Vec<Category>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT id, category_name, weight, drop_lowest FROM assignment_categories
+            WHERE class_number = $1
+            ORDER BY id;",
+        )
+        .bind(class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let categories = rows
+            .iter()
+            .map(|r| Category {
+                category_id: r.get("id"),
+                name: r.get("category_name"),
+                weight: r.get("weight"),
+                drop_lowest: r.get("drop_lowest"),
+            })
+            .collect::<Vec<Category>>();
+
+        return Ok(categories);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn add_category(
+    class_number: String,
+    name: String,
+    weight: f32,
+    drop_lowest: i32,
+) -> Result<i32, String> {
+    postgres_lock!(transaction, {
+        let category_id: i32 = match sqlx::query(
+            "INSERT INTO assignment_categories (class_number, category_name, weight, drop_lowest)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id;",
+        )
+        .bind(class_number)
+        .bind(name)
+        .bind(weight)
+        .bind(drop_lowest)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("id"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(category_id);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns `Ok(false)` if the category does not exist in the class
+pub async fn update_category(
+    class_number: String,
+    category_id: i32,
+    name: String,
+    weight: f32,
+    drop_lowest: i32,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated = match sqlx::query(
+            "UPDATE assignment_categories SET category_name = $1, weight = $2, drop_lowest = $3
+            WHERE id = $4 AND class_number = $5;",
+        )
+        .bind(name)
+        .bind(weight)
+        .bind(drop_lowest)
+        .bind(category_id)
+        .bind(class_number)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Removes a category. Its assignments stay, but no longer count towards the course grade.
+///
+/// Returns `Ok(false)` if the category does not exist in the class
+pub async fn remove_category(class_number: String, category_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let removed = match sqlx::query(
+            "DELETE FROM assignment_categories WHERE id = $1 AND class_number = $2;",
+        )
+        .bind(category_id)
+        .bind(class_number)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(removed);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Computes the weighted course grade of every student in the class, or of only `user_id`.
+///
+/// A category's score is the average of its assignments' scores, less the lowest ones the category
+/// drops. The course grade is the weighted average of the categories that have assignments;
+/// uncategorized assignments are not counted.
+///
+/// It's a running grade: an assignment only counts once it's past its deadline, the student has a
+/// graded submission to it, or an instructor has overridden their score, which replaces the one
+/// their submissions earned. Assignment curves are applied to the rest.
+///
+/// An assignment without autograded tests is scored by its peer reviews, and one with neither
+/// only counts once its score is overridden.
+pub async fn course_grades(
+    class_number: String,
+    user_id: Option<i32>,
+) -> Result<Vec<CourseGrade>, String> {
+    postgres_lock!(transaction, {
+        let category_rows = match sqlx::query(
+            "SELECT id, category_name, weight, drop_lowest FROM assignment_categories
+            WHERE class_number = $1
+            ORDER BY id;",
+        )
+        .bind(&class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let student_rows = match sqlx::query(
+            "SELECT u.id, u.first_name, u.last_name, u.user_name
+            FROM users u
+            JOIN user_class uc ON uc.user_id = u.id
+            WHERE uc.class_number = $1 AND uc.is_instructor = FALSE
+            AND ($2::INTEGER IS NULL OR u.id = $2)
+            ORDER BY u.last_name, u.first_name;",
+        )
+        .bind(&class_number)
+        .bind(user_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        // Each student's score on each categorized assignment, weighting tasks by their points, and
+        // whether it counts yet: it's past its deadline, they have a graded submission, or it has
+        // an override
+        let score_rows = match sqlx::query(&format!(
+            "{}
+            SELECT uc.user_id, a.id assignment_id, a.category_id,
+                CASE WHEN tt.total_points > 0 THEN COALESCE(sc.score, 0) END score,
+                o.score override_score, COALESCE(sc.graded, FALSE) graded,
+                a.deadline <= NOW() OR COALESCE(sc.graded, FALSE) OR o.score IS NOT NULL counted
+            FROM user_class uc
+            JOIN assignment_class ac ON ac.class_number = uc.class_number
+            JOIN assignments a ON a.id = ac.assignment_id
+            LEFT JOIN totals tt ON tt.assignment_id = a.id
+            LEFT JOIN assignment_scores sc ON sc.user_id = uc.user_id AND sc.assignment_id = a.id
+            LEFT JOIN grade_overrides o ON o.user_id = uc.user_id AND o.assignment_id = a.id
+            WHERE uc.class_number = $1 AND uc.is_instructor = FALSE AND a.category_id IS NOT NULL
+            AND ($3::INTEGER IS NULL OR uc.user_id = $3);",
+            gradebook::score_ctes(
+                "SELECT assignment_id FROM assignment_class WHERE class_number = $1"
+            )
+        ))
+        .bind(&class_number)
+        .bind(config::get().late_multiplier)
+        .bind(user_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let peer_scores =
+            peer_review::peer_scores(&mut transaction, Some(&class_number), None).await?;
+        let curves = curve::curves(&mut transaction, Some(&class_number), None).await?;
+
+        // (user_id, category_id) => assignment scores
+        let mut scores: HashMap<(i32, i32), Vec<f64>> = HashMap::new();
+        for row in score_rows.iter().filter(|r| r.get::<bool, _>("counted")) {
+            let user_id: i32 = row.get("user_id");
+            let assignment_id: i32 = row.get("assignment_id");
+            let override_score = row.get::<Option<f32>, _>("override_score");
+
+            // An assignment without autograded points is scored by its peer reviews alone
+            let peer = peer_scores.get(&(user_id, assignment_id));
+            let mut score = match (row.get::<Option<f64>, _>("score"), peer) {
+                (Some(score), Some((peer, settings))) => settings.blend(score as f32, *peer) as f64,
+                (Some(score), None) => score,
+                (None, Some((peer, _))) => *peer as f64,
+                (None, None) if override_score.is_some() => 0.0,
+                (None, None) => continue,
+            };
+
+            // Curves are for the students who submitted something to be curved
+            if let Some(curve) = curves.get(&assignment_id)
+                && (row.get::<bool, _>("graded") || peer.is_some())
+            {
+                score = curve.apply(score as f32) as f64;
+            }
+
+            if let Some(override_score) = override_score {
+                score = override_score as f64;
+            }
+
+            scores
+                .entry((user_id, row.get("category_id")))
+                .or_default()
+                .push(score);
+        }
+
+        let grades = student_rows
+            .iter()
+            .map(|student| {
+                let student_id: i32 = student.get("id");
+                let first_name: String = student.get("first_name");
+                let last_name: String = student.get("last_name");
+
+                let categories = category_rows
+                    .iter()
+                    .map(|c| {
+                        let category_id: i32 = c.get("id");
+                        let drop_lowest: i32 = c.get("drop_lowest");
+
+                        // The lowest scores go, but never the last one
+                        let mut kept = scores
+                            .get(&(student_id, category_id))
+                            .cloned()
+                            .unwrap_or_default();
+                        kept.sort_by(f64::total_cmp);
+                        let dropped =
+                            (drop_lowest.max(0) as usize).min(kept.len().saturating_sub(1));
+                        let kept = &kept[dropped..];
+
+                        CategoryScore {
+                            category_id,
+                            name: c.get("category_name"),
+                            weight: c.get("weight"),
+                            dropped,
+                            score: (!kept.is_empty())
+                                .then(|| (kept.iter().sum::<f64>() / kept.len() as f64) as f32),
+                        }
+                    })
+                    .collect::<Vec<CategoryScore>>();
+
+                let (weighted, total_weight) = categories
+                    .iter()
+                    .filter_map(|c| c.score.map(|s| (s * c.weight, c.weight)))
+                    .fold((0.0, 0.0), |(ws, tw), (s, w)| (ws + s, tw + w));
+
+                CourseGrade {
+                    name: format!("{} {}", first_name, last_name),
+                    username: student.get("user_name"),
+                    score: (total_weight > 0.0).then(|| weighted / total_weight),
+                    categories,
+                }
+            })
+            .collect::<Vec<CourseGrade>>();
+
+        return Ok(grades);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Sets the student's score on the assignment, in place of the one their submissions earned.
+/// `Ok(false)` => there is no such student of the class, or the assignment isn't the class's.
+pub async fn set_grade_override(
+    class_number: &str,
+    assignment_id: i32,
+    username: &str,
+    score: f32,
+    reason: Option<String>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) =
+            student_of(&mut transaction, class_number, assignment_id, username).await?
+        else {
+            return Ok(false);
+        };
+
+        if let Err(e) = sqlx::query(
+            "INSERT INTO grade_overrides (user_id, assignment_id, score, reason)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, assignment_id)
+            DO UPDATE SET score = EXCLUDED.score, reason = EXCLUDED.reason, set_at = NOW();",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .bind(score)
+        .bind(reason)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(true);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Puts a student back on the score their submissions earned. `Ok(false)` => they had no override.
+pub async fn remove_grade_override(
+    class_number: &str,
+    assignment_id: i32,
+    username: &str,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) =
+            student_of(&mut transaction, class_number, assignment_id, username).await?
+        else {
+            return Ok(false);
+        };
+
+        let removed = match sqlx::query(
+            "DELETE FROM grade_overrides WHERE user_id = $1 AND assignment_id = $2;",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(removed);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The id of the student of the class with the username, if the assignment is the class's
+pub(super) async fn student_of(
+    conn: &mut PgConnection,
+    class_number: &str,
+    assignment_id: i32,
+    username: &str,
+) -> Result<Option<i32>, String> {
+    match sqlx::query(
+        "SELECT u.id
+        FROM users u
+        JOIN user_class uc ON uc.user_id = u.id
+        JOIN assignment_class ac ON ac.class_number = uc.class_number
+        WHERE u.user_name = $1 AND uc.class_number = $2 AND ac.assignment_id = $3
+        AND uc.is_instructor = FALSE;",
+    )
+    .bind(username)
+    .bind(class_number)
+    .bind(assignment_id)
+    .fetch_optional(conn)
+    .await
+    {
+        Ok(r) => Ok(r.map(|r| r.get("id"))),
+        Err(e) => Err(format!("{e}")),
+    }
+}
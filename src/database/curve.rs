@@ -0,0 +1,95 @@
+//! Contains database operations associated with assignment curves
+
+use std::collections::HashMap;
+
+use sqlx::{PgConnection, Row};
+
+use crate::{database::POSTGRES, model::curve::Curve, postgres_lock};
+
+/// The assignment's curve. `Ok(None)` => the assignment isn't the class's.
+pub async fn get_curve(
+    class_number: &str,
+    assignment_id: i32,
+) -> Result<Option<Option<Curve>>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT a.curve FROM assignments a
+            JOIN assignment_class ac ON ac.assignment_id = a.id
+            WHERE a.id = $1 AND ac.class_number = $2;",
+        )
+        .bind(assignment_id)
+        .bind(class_number)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.map(|r| {
+                r.get::<Option<String>, _>("curve")
+                    .and_then(|c| serde_json::from_str(&c).ok())
+            })),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Sets the assignment's curve, or takes it off. `Ok(false)` => the assignment isn't the class's.
+pub async fn set_curve(
+    class_number: &str,
+    assignment_id: i32,
+    curve: Option<Curve>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated = match sqlx::query(
+            "UPDATE assignments SET curve = $1
+            WHERE id = $2
+            AND EXISTS (SELECT 1 FROM assignment_class WHERE assignment_id = $2 AND class_number = $3);",
+        )
+        .bind(curve.map(|c| serde_json::to_string(&c).unwrap()))
+        .bind(assignment_id)
+        .bind(class_number)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The curve of every curved assignment, by assignment id. Limited to one class and/or one
+/// assignment.
+pub(super) async fn curves(
+    conn: &mut PgConnection,
+    class_number: Option<&str>,
+    assignment_id: Option<i32>,
+) -> Result<HashMap<i32, Curve>, String> {
+    let rows = match sqlx::query(
+        "SELECT a.id, a.curve FROM assignments a
+        JOIN assignment_class ac ON ac.assignment_id = a.id
+        WHERE a.curve IS NOT NULL
+            AND ($1::TEXT IS NULL OR ac.class_number = $1)
+            AND ($2::INTEGER IS NULL OR a.id = $2);",
+    )
+    .bind(class_number)
+    .bind(assignment_id)
+    .fetch_all(conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    Ok(rows
+        .iter()
+        .filter_map(|r| {
+            let curve: String = r.get("curve");
+            serde_json::from_str(&curve).ok().map(|c| (r.get("id"), c))
+        })
+        .collect())
+}
@@ -0,0 +1,269 @@
+//! Contains the destructive operations used by admins to remove classes, assignments, and users
+//!
+//! Every operation first counts what it is about to remove. In a dry run only the counts are
+//! returned and nothing is deleted. Archives and materials left in blob storage are removed by the
+//! janitor.
+
+use sqlx::{PgConnection, Postgres, Row};
+
+use crate::{database::POSTGRES, model::deletion_summary::DeletionSummary, postgres_lock};
+
+async fn count<'a, T>(conn: &mut PgConnection, query: &'a str, param: T) -> Result<i64, String>
+where
+    T: 'a + Send + sqlx::Encode<'a, Postgres> + sqlx::Type<Postgres>,
+{
+    match sqlx::query(query).bind(param).fetch_one(conn).await {
+        Ok(r) => Ok(r.get("n")),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+async fn execute<'a, T>(conn: &mut PgConnection, query: &'a str, param: T) -> Result<(), String>
+where
+    T: 'a + Send + sqlx::Encode<'a, Postgres> + sqlx::Type<Postgres>,
+{
+    match sqlx::query(query).bind(param).execute(conn).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Deletes a class along with all of its assignments, enrollments, and join codes.
+///
+/// Returns `Ok(None)` if the class does not exist.
+pub async fn delete_class(
+    class_number: String,
+    dry_run: bool,
+) -> Result<Option<DeletionSummary>, String> {
+    postgres_lock!(transaction, {
+        let classes = count(
+            &mut transaction,
+            "SELECT COUNT(*) n FROM classes WHERE class_number = $1;",
+            &class_number,
+        )
+        .await?;
+
+        if classes == 0 {
+            return Ok(None);
+        }
+
+        let summary = DeletionSummary {
+            dry_run,
+            classes,
+            assignments: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM assignment_class WHERE class_number = $1;",
+                &class_number,
+            )
+            .await?,
+            tasks: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM tasks
+                JOIN assignment_class ac ON ac.assignment_id = tasks.assignment_id
+                WHERE ac.class_number = $1;",
+                &class_number,
+            )
+            .await?,
+            enrollments: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM user_class WHERE class_number = $1;",
+                &class_number,
+            )
+            .await?,
+            submissions: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM submissions s
+                JOIN assignment_class ac ON ac.assignment_id = s.assignment_id
+                WHERE ac.class_number = $1 AND s.submission_key IS NOT NULL;",
+                &class_number,
+            )
+            .await?,
+            grades: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM user_task_grade g
+                JOIN assignment_class ac ON ac.assignment_id = g.assignment_id
+                WHERE ac.class_number = $1 AND g.grade IS NOT NULL;",
+                &class_number,
+            )
+            .await?,
+            users: 0,
+        };
+
+        if dry_run {
+            return Ok(Some(summary));
+        }
+
+        let assignment_ids: Vec<i32> =
+            match sqlx::query("SELECT assignment_id FROM assignment_class WHERE class_number = $1;")
+                .bind(&class_number)
+                .fetch_all(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.iter().map(|r| r.get("assignment_id")).collect(),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        for statement in [
+            "DELETE FROM assignment_class WHERE class_number = $1;",
+            "DELETE FROM user_class WHERE class_number = $1;",
+            "DELETE FROM class_join_code WHERE class_number = $1;",
+            "DELETE FROM export_jobs WHERE class_number = $1;",
+        ] {
+            execute(&mut transaction, statement, &class_number).await?;
+        }
+
+        // Tasks, tests, and grades cascade from the assignments
+        execute(
+            &mut transaction,
+            "DELETE FROM assignments WHERE id = ANY($1);",
+            &assignment_ids,
+        )
+        .await?;
+
+        execute(
+            &mut transaction,
+            "DELETE FROM classes WHERE class_number = $1;",
+            &class_number,
+        )
+        .await?;
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(summary));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Deletes an assignment along with its tasks, tests, and every submission to it.
+///
+/// Returns `Ok(None)` if the assignment does not exist.
+pub async fn delete_assignment(
+    assignment_id: i32,
+    dry_run: bool,
+) -> Result<Option<DeletionSummary>, String> {
+    postgres_lock!(transaction, {
+        let assignments = count(
+            &mut transaction,
+            "SELECT COUNT(*) n FROM assignments WHERE id = $1;",
+            assignment_id,
+        )
+        .await?;
+
+        if assignments == 0 {
+            return Ok(None);
+        }
+
+        let summary = DeletionSummary {
+            dry_run,
+            assignments,
+            tasks: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM tasks WHERE assignment_id = $1;",
+                assignment_id,
+            )
+            .await?,
+            submissions: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM submissions
+                WHERE assignment_id = $1 AND submission_key IS NOT NULL;",
+                assignment_id,
+            )
+            .await?,
+            grades: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM user_task_grade
+                WHERE assignment_id = $1 AND grade IS NOT NULL;",
+                assignment_id,
+            )
+            .await?,
+            ..Default::default()
+        };
+
+        if dry_run {
+            return Ok(Some(summary));
+        }
+
+        execute(
+            &mut transaction,
+            "DELETE FROM assignment_class WHERE assignment_id = $1;",
+            assignment_id,
+        )
+        .await?;
+        execute(
+            &mut transaction,
+            "DELETE FROM assignments WHERE id = $1;",
+            assignment_id,
+        )
+        .await?;
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(summary));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Deletes a user account along with its enrollments, sessions, and submissions.
+///
+/// Returns `Ok(None)` if the user does not exist.
+pub async fn delete_user(
+    user_name: String,
+    dry_run: bool,
+) -> Result<Option<DeletionSummary>, String> {
+    postgres_lock!(transaction, {
+        let user_id: i32 = match sqlx::query("SELECT id FROM users WHERE user_name = $1;")
+            .bind(&user_name)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some(r)) => r.get("id"),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let summary = DeletionSummary {
+            dry_run,
+            users: 1,
+            enrollments: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM user_class WHERE user_id = $1;",
+                user_id,
+            )
+            .await?,
+            submissions: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM submissions
+                WHERE user_id = $1 AND submission_key IS NOT NULL;",
+                user_id,
+            )
+            .await?,
+            grades: count(
+                &mut transaction,
+                "SELECT COUNT(*) n FROM user_task_grade
+                WHERE user_id = $1 AND grade IS NOT NULL;",
+                user_id,
+            )
+            .await?,
+            ..Default::default()
+        };
+
+        if dry_run {
+            return Ok(Some(summary));
+        }
+
+        // Grades, notifications, and exports cascade from the user
+        for statement in [
+            "DELETE FROM user_class WHERE user_id = $1;",
+            "DELETE FROM user_session WHERE user_id = $1;",
+            "DELETE FROM user_auth WHERE user_id = $1;",
+            "DELETE FROM users WHERE id = $1;",
+        ] {
+            execute(&mut transaction, statement, user_id).await?;
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(summary));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
@@ -0,0 +1,101 @@
+//! Contains database operations associated with outbound email templates and the details that fill them
+
+use sqlx::Row;
+
+use crate::{database::POSTGRES, postgres_lock};
+
+/// Returns the admin's (subject, body) override for a template, if there is one
+pub async fn get_email_template(template_name: &str) -> Result<Option<(String, String)>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query("SELECT subject, body FROM email_templates WHERE template_name = $1;")
+            .bind(template_name)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some(r)) => return Ok(Some((r.get("subject"), r.get("body")))),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        }
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn set_email_template(
+    template_name: &str,
+    subject: String,
+    body: String,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO email_templates (template_name, subject, body)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (template_name) DO UPDATE SET subject = $2, body = $3;",
+        )
+        .bind(template_name)
+        .bind(subject)
+        .bind(body)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Removes the admin's override so the built-in default is used again
+pub async fn reset_email_template(template_name: &str) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("DELETE FROM email_templates WHERE template_name = $1;")
+            .bind(template_name)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns (email, first_name, assignment_name, class_number) for a graded task
+pub async fn grade_email_details(
+    user_id: i32,
+    task_id: i32,
+) -> Result<Option<(String, String, String, String)>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query(
+            "SELECT u.email, u.first_name, a.assignment_name, ac.class_number
+            FROM users u, tasks t
+            JOIN assignments a ON a.id = t.assignment_id
+            JOIN assignment_class ac ON ac.assignment_id = a.id
+            WHERE u.id = $1 AND t.id = $2;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => {
+                return Ok(Some((
+                    r.get("email"),
+                    r.get("first_name"),
+                    r.get("assignment_name"),
+                    r.get("class_number"),
+                )));
+            }
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        }
+    });
+
+    Err("Failed to acquire database lock".into())
+}
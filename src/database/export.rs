@@ -0,0 +1,320 @@
+//! Contains database operations associated with background bulk-download (export) jobs
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{
+    database::POSTGRES,
+    model::{
+        research_record::ResearchRecord,
+        submission_response::{ResultVisibility, SubmissionResponse},
+    },
+    postgres_lock, storage,
+};
+
+/// Creates a new queued export job, returning the job id. `assignment_id` is `None` for a
+/// research export of the whole class.
+pub async fn create_export_job(
+    user_id: i32,
+    class_number: String,
+    assignment_id: Option<i32>,
+) -> Result<i32, String> {
+    postgres_lock!(transaction, {
+        let job_id: i32 = match sqlx::query(
+            "INSERT INTO export_jobs (user_id, class_number, assignment_id)
+            VALUES ($1, $2, $3)
+            RETURNING id;",
+        )
+        .bind(user_id)
+        .bind(class_number)
+        .bind(assignment_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("id"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(job_id);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns true if the user already has an export that has not finished building.
+pub async fn export_in_progress(user_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT id FROM export_jobs
+            WHERE user_id = $1 AND status IN ('queued', 'building');",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.is_some()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn set_export_status(job_id: i32, status: &str) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("UPDATE export_jobs SET status = $1 WHERE id = $2;")
+            .bind(status)
+            .bind(job_id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Marks an export as ready for download until `expiration`.
+pub async fn finish_export_job(
+    job_id: i32,
+    archive_path: String,
+    download_token: String,
+    expiration: DateTime<Utc>,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE export_jobs
+            SET status = 'ready', archive_path = $1, download_token = $2, expiration = $3
+            WHERE id = $4;",
+        )
+        .bind(archive_path)
+        .bind(download_token)
+        .bind(expiration)
+        .bind(job_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn fail_export_job(job_id: i32, error: String) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) =
+            sqlx::query("UPDATE export_jobs SET status = 'failed', error = $1 WHERE id = $2;")
+                .bind(error)
+                .bind(job_id)
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns (user_id, user_name) of every student who submitted something for the assignment.
+pub async fn get_export_submitters(assignment_id: i32) -> Result<Vec<(i32, String)>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT DISTINCT users.id, users.user_name
+            FROM user_task_grade
+            JOIN users ON users.id = user_task_grade.user_id
+            WHERE user_task_grade.assignment_id = $1 AND submission_key IS NOT NULL;",
+        )
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let submitters = rows
+            .iter()
+            .map(|r| (r.get("id"), r.get("user_name")))
+            .collect::<Vec<(i32, String)>>();
+
+        return Ok(submitters);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns (task_id, submitted archive) for a single student. Fetched one student at a time so a
+/// large export never holds every submission in memory at once.
+pub async fn get_export_submissions(
+    user_id: i32,
+    assignment_id: i32,
+) -> Result<Vec<(i32, Vec<u8>)>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT task_id, submission_key FROM user_task_grade
+            WHERE user_id = $1 AND assignment_id = $2 AND submission_key IS NOT NULL;",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+
+        let mut submissions = vec![];
+        for row in &rows {
+            let zip_file = storage::get(row.get::<&str, _>("submission_key")).await?;
+            submissions.push((row.get("task_id"), zip_file));
+        }
+
+        return Ok(submissions);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Looks up a ready, unexpired export by its download token. Returns the archive path.
+pub async fn get_export_archive(
+    class_number: String,
+    download_token: String,
+) -> Result<Option<String>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT archive_path FROM export_jobs
+            WHERE class_number = $1 AND download_token = $2
+                AND status = 'ready' AND expiration > NOW();",
+        )
+        .bind(class_number)
+        .bind(download_token)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.map(|r| r.get("archive_path"))),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Marks expired exports as such, returning the archive paths that should be removed from disk.
+pub async fn expire_exports() -> Result<Vec<String>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "UPDATE export_jobs
+            SET status = 'expired'
+            WHERE status = 'ready' AND expiration <= NOW()
+            RETURNING archive_path;",
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+
+        let paths = rows
+            .iter()
+            .filter_map(|r| r.get::<Option<String>, _>("archive_path"))
+            .collect::<Vec<String>>();
+
+        return Ok(paths);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Every graded attempt by a student in the class, for a research export. Returns
+/// (user_id, record) so the caller can fetch the submitted code; the record itself only carries
+/// the student's pseudonym.
+pub async fn get_research_records(
+    class_number: String,
+    pseudonym: impl Fn(i32) -> String,
+) -> Result<Vec<(i32, ResearchRecord)>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT g.user_id, g.assignment_id, g.task_id, g.was_late, g.late_multiplier, g.grade,
+                g.submission_lang, g.toolchain_version, g.submitted_at, g.graded_at, g.json_results,
+                g.submission_key, a.deadline
+            FROM user_task_grade g
+            JOIN assignment_class ac ON ac.assignment_id = g.assignment_id
+            JOIN assignments a ON a.id = g.assignment_id
+            JOIN user_class uc ON uc.user_id = g.user_id AND uc.class_number = ac.class_number
+            WHERE ac.class_number = $1 AND uc.is_instructor = FALSE
+            ORDER BY g.assignment_id, g.task_id, g.user_id;",
+        )
+        .bind(class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let records = rows
+            .iter()
+            .map(|r| {
+                let user_id: i32 = r.get("user_id");
+                let assignment_id: i32 = r.get("assignment_id");
+                let task_id: i32 = r.get("task_id");
+                let deadline: DateTime<Utc> = r.get("deadline");
+                let submitted_at: Option<DateTime<Utc>> = r.get("submitted_at");
+                let graded_at: Option<DateTime<Utc>> = r.get("graded_at");
+                let json_results: Option<Vec<u8>> = r.get("json_results");
+                let submission_key: Option<String> = r.get("submission_key");
+                let student = pseudonym(user_id);
+
+                let results = json_results
+                    .and_then(|j| serde_json::from_slice::<SubmissionResponse>(&j).ok())
+                    .map(|sr| sr.redact(ResultVisibility::PassFail));
+
+                let record = ResearchRecord {
+                    submission: submission_key.map(|key| {
+                        format!(
+                            "submissions/{student}/Assignment{assignment_id}/Task{task_id}.{}",
+                            storage::extension(&key)
+                        )
+                    }),
+                    student,
+                    assignment_id,
+                    task_id,
+                    language: r.get("submission_lang"),
+                    toolchain_version: r.get("toolchain_version"),
+                    deadline: deadline.to_rfc3339(),
+                    submitted_at: submitted_at.map(|t| t.to_rfc3339()),
+                    graded_at: graded_at.map(|t| t.to_rfc3339()),
+                    seconds_before_deadline: submitted_at.map(|t| (deadline - t).num_seconds()),
+                    was_late: r.get("was_late"),
+                    late_multiplier: r.get("late_multiplier"),
+                    grade: r.get("grade"),
+                    results,
+                };
+
+                (user_id, record)
+            })
+            .collect::<Vec<(i32, ResearchRecord)>>();
+
+        return Ok(records);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
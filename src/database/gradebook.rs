@@ -0,0 +1,440 @@
+//! Contains database operations associated with the gradebook and what's behind its scores
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{
+    config,
+    database::{POSTGRES, category, curve, peer_review},
+    model::{
+        assignment_stats::{AssignmentStats, DailySubmissions, HistogramBucket, TaskStats},
+        breakdown::{StudentBreakdown, TaskBreakdown},
+        gradebook::{Gradebook, GradebookAssignment, GradebookCell, GradebookRow},
+        submission_response::SubmissionResponse,
+    },
+    postgres_lock,
+};
+
+/// Buckets of the score histogram
+const HISTOGRAM_BUCKETS: i32 = 10;
+
+/// The CTEs behind every score: `task_points`, the points of each task of the assignments selected
+/// by `assignments`, their `totals` by assignment, and `assignment_scores`, each user's autograded
+/// score on each of those assignments, weighting tasks by their points, and whether any of their
+/// submissions is graded yet. Takes the late multiplier as `$2`. An assignment without points has
+/// no score.
+pub(super) fn score_ctes(assignments: &str) -> String {
+    format!(
+        "WITH task_points AS (
+            SELECT tasks.id task_id, tasks.assignment_id, SUM(tests.points)::FLOAT8 task_points
+            FROM tasks
+            JOIN tests ON tests.task_id = tasks.id AND NOT tests.sample
+            WHERE tasks.assignment_id IN ({assignments})
+            GROUP BY tasks.id
+        ),
+        totals AS (
+            SELECT assignment_id, SUM(task_points) total_points FROM task_points
+            GROUP BY assignment_id
+        ),
+        assignment_scores AS (
+            SELECT g.user_id, t.assignment_id,
+                SUM((COALESCE(g.grade, 0)
+                    * COALESCE(g.late_multiplier, CASE WHEN g.was_late THEN $2 ELSE 1 END))::FLOAT8
+                    * t.task_points)
+                / NULLIF(MAX(tt.total_points), 0) score,
+                COUNT(g.grade) > 0 graded,
+                BOOL_OR(COALESCE(g.was_late, FALSE)) late
+            FROM user_task_grade g
+            JOIN task_points t ON t.task_id = g.task_id
+            JOIN totals tt ON tt.assignment_id = t.assignment_id
+            GROUP BY g.user_id, t.assignment_id
+        )"
+    )
+}
+
+/// The students of the assignment's classes, and the autograded score of each one with a graded
+/// submission. Takes the assignment id as `$1` and the late multiplier as `$2`.
+fn student_scores() -> String {
+    format!(
+        "{},
+        students AS (
+            SELECT DISTINCT uc.user_id FROM user_class uc
+            JOIN assignment_class ac ON ac.class_number = uc.class_number
+            WHERE ac.assignment_id = $1 AND uc.is_instructor = FALSE
+        ),
+        scores AS (
+            SELECT sc.user_id, sc.score
+            FROM assignment_scores sc
+            JOIN students s ON s.user_id = sc.user_id
+            WHERE sc.graded
+        )",
+        score_ctes("SELECT $1::INTEGER")
+    )
+}
+
+/// Every student's score on every assignment of the class, or only on `assignment_id`, weighting
+/// tasks by their points
+pub async fn gradebook(
+    class_number: String,
+    assignment_id: Option<i32>,
+) -> Result<Gradebook, String> {
+    postgres_lock!(transaction, {
+        let assignment_rows = match sqlx::query(
+            "SELECT a.id, a.assignment_name, a.deadline
+            FROM assignments a
+            JOIN assignment_class ac ON ac.assignment_id = a.id
+            WHERE ac.class_number = $1 AND ($2::INTEGER IS NULL OR a.id = $2)
+            ORDER BY a.deadline, a.id;",
+        )
+        .bind(&class_number)
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        // One row per student and assignment, in the order of the rows and columns. Students of a
+        // class without assignments get a single row without one.
+        let cell_rows = match sqlx::query(&format!(
+            "{},
+            class_assignments AS (
+                SELECT a.id, a.deadline FROM assignments a
+                JOIN assignment_class ac ON ac.assignment_id = a.id
+                WHERE ac.class_number = $1 AND ($3::INTEGER IS NULL OR a.id = $3)
+            ),
+            attempts AS (
+                SELECT s.user_id, s.assignment_id, COUNT(*) attempts
+                FROM submissions s
+                JOIN class_assignments ca ON ca.id = s.assignment_id
+                GROUP BY s.user_id, s.assignment_id
+            )
+            SELECT u.id user_id, u.first_name, u.last_name, u.user_name, u.sis_id,
+                ca.id assignment_id, sc.score, o.score override_score,
+                COALESCE(sc.late, FALSE) late, COALESCE(at.attempts, 0) attempts
+            FROM users u
+            JOIN user_class uc ON uc.user_id = u.id
+            LEFT JOIN class_assignments ca ON TRUE
+            LEFT JOIN assignment_scores sc ON sc.user_id = u.id AND sc.assignment_id = ca.id
+            LEFT JOIN attempts at ON at.user_id = u.id AND at.assignment_id = ca.id
+            LEFT JOIN grade_overrides o ON o.user_id = u.id AND o.assignment_id = ca.id
+            WHERE uc.class_number = $1 AND uc.is_instructor = FALSE
+            ORDER BY u.last_name, u.first_name, u.id, ca.deadline, ca.id;",
+            score_ctes(
+                "SELECT assignment_id FROM assignment_class
+                WHERE class_number = $1 AND ($3::INTEGER IS NULL OR assignment_id = $3)"
+            )
+        ))
+        .bind(&class_number)
+        .bind(config::get().late_multiplier)
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let peer_scores =
+            peer_review::peer_scores(&mut transaction, Some(&class_number), None).await?;
+        let curves = curve::curves(&mut transaction, Some(&class_number), assignment_id).await?;
+
+        let mut students: Vec<(i32, GradebookRow)> = vec![];
+        for row in &cell_rows {
+            let user_id: i32 = row.get("user_id");
+            if students.last().is_none_or(|(id, _)| *id != user_id) {
+                let first_name: String = row.get("first_name");
+                let last_name: String = row.get("last_name");
+                students.push((
+                    user_id,
+                    GradebookRow {
+                        name: format!("{} {}", first_name, last_name),
+                        first_name,
+                        last_name,
+                        username: row.get("user_name"),
+                        sis_id: row.get("sis_id"),
+                        cells: vec![],
+                    },
+                ));
+            }
+
+            let Some(assignment_id) = row.get::<Option<i32>, _>("assignment_id") else {
+                continue;
+            };
+
+            let mut score = row.get::<Option<f64>, _>("score").map(|s| s as f32);
+            if let Some((peer, settings)) = peer_scores.get(&(user_id, assignment_id)) {
+                score = Some(settings.blend(score.unwrap_or(0.0), *peer));
+            }
+
+            let curve = curves.get(&assignment_id).filter(|_| score.is_some());
+            if let Some(curve) = curve {
+                score = score.map(|s| curve.apply(s));
+            }
+
+            let override_score: Option<f32> = row.get("override_score");
+            let (_, student) = students.last_mut().unwrap();
+            student.cells.push(GradebookCell {
+                assignment_id,
+                score: override_score.or(score),
+                curved: curve.is_some() && override_score.is_none(),
+                overridden: override_score.is_some(),
+                late: row.get("late"),
+                attempts: row.get("attempts"),
+            });
+        }
+
+        let assignments = assignment_rows
+            .iter()
+            .map(|r| GradebookAssignment {
+                assignment_id: r.get("id"),
+                name: r.get("assignment_name"),
+                deadline: r.get::<DateTime<Utc>, _>("deadline").to_rfc3339(),
+            })
+            .collect::<Vec<GradebookAssignment>>();
+
+        return Ok(Gradebook {
+            assignments,
+            students: students.into_iter().map(|(_, student)| student).collect(),
+        });
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The student's grade, lateness, attempts and full results on each task of the assignment, and
+/// their peer reviewers' comments. `None` => there is no such student of the class, or the
+/// assignment isn't the class's.
+pub async fn breakdown(
+    class_number: &str,
+    assignment_id: i32,
+    username: &str,
+) -> Result<Option<StudentBreakdown>, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) =
+            category::student_of(&mut transaction, class_number, assignment_id, username).await?
+        else {
+            return Ok(None);
+        };
+
+        let student = match sqlx::query("SELECT first_name, last_name FROM users WHERE id = $1;")
+            .bind(user_id)
+            .fetch_one(&mut *transaction)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let task_rows = match sqlx::query(
+            "SELECT t.id task_id, COALESCE(tp.points, 0) points, g.grade,
+                COALESCE(g.was_late, FALSE) late,
+                CASE WHEN g.user_id IS NOT NULL
+                    THEN COALESCE(g.late_multiplier, CASE WHEN g.was_late THEN $3 ELSE 1 END)
+                END late_multiplier,
+                (SELECT COUNT(*) FROM submissions s WHERE s.user_id = $1 AND s.task_id = t.id) attempts,
+                g.attempt, g.submitted_at, g.graded_at, g.json_results, g.submission_lang,
+                g.toolchain_version, g.error
+            FROM tasks t
+            LEFT JOIN (
+                SELECT task_id, SUM(points)::REAL points FROM tests
+                WHERE NOT sample
+                GROUP BY task_id
+            ) tp ON tp.task_id = t.id
+            LEFT JOIN user_task_grade g ON g.task_id = t.id AND g.user_id = $1
+            WHERE t.assignment_id = $2
+            ORDER BY t.placement;",
+        )
+        .bind(user_id)
+        .bind(assignment_id)
+        .bind(config::get().late_multiplier)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let peer_comments = match sqlx::query(
+            "SELECT comments FROM peer_reviews
+            WHERE assignment_id = $1 AND reviewee_id = $2 AND scores IS NOT NULL
+            AND comments IS NOT NULL AND comments <> ''
+            ORDER BY reviewed_at;",
+        )
+        .bind(assignment_id)
+        .bind(user_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.iter().map(|r| r.get("comments")).collect(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let tasks = task_rows
+            .iter()
+            .map(|r| {
+                let results = r
+                    .get::<Option<Vec<u8>>, _>("json_results")
+                    .and_then(|j| serde_json::from_slice::<SubmissionResponse>(&j).ok())
+                    .map(|sr| {
+                        sr.with_toolchain(r.get("submission_lang"), r.get("toolchain_version"))
+                            .with_error(r.get("error"))
+                    });
+
+                TaskBreakdown {
+                    task_id: r.get("task_id"),
+                    points: r.get("points"),
+                    grade: r.get("grade"),
+                    late: r.get("late"),
+                    late_multiplier: r.get("late_multiplier"),
+                    attempts: r.get("attempts"),
+                    counted_attempt: r.get("attempt"),
+                    submitted_at: r
+                        .get::<Option<DateTime<Utc>>, _>("submitted_at")
+                        .map(|t| t.to_rfc3339()),
+                    graded_at: r
+                        .get::<Option<DateTime<Utc>>, _>("graded_at")
+                        .map(|t| t.to_rfc3339()),
+                    results,
+                }
+            })
+            .collect::<Vec<TaskBreakdown>>();
+
+        let first_name: String = student.get("first_name");
+        let last_name: String = student.get("last_name");
+        return Ok(Some(StudentBreakdown {
+            name: format!("{} {}", first_name, last_name),
+            username: username.to_string(),
+            tasks,
+            peer_comments,
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Score statistics, task pass rates and daily submission counts of the assignment
+pub async fn assignment_stats(assignment_id: i32) -> Result<AssignmentStats, String> {
+    let late_multiplier = config::get().late_multiplier;
+    let student_scores = student_scores();
+
+    postgres_lock!(transaction, {
+        let summary = match sqlx::query(&format!(
+            "{student_scores}
+            SELECT (SELECT COUNT(*) FROM students) students, COUNT(score) submitted,
+                AVG(score) mean,
+                PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY score) median,
+                STDDEV_POP(score) std_dev
+            FROM scores;"
+        ))
+        .bind(assignment_id)
+        .bind(late_multiplier)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let bucket_rows = match sqlx::query(&format!(
+            "{student_scores}
+            SELECT LEAST(GREATEST(FLOOR(score * $3::INTEGER), 0), $3::INTEGER - 1)::INTEGER bucket,
+                COUNT(*) students
+            FROM scores
+            WHERE score IS NOT NULL
+            GROUP BY bucket;"
+        ))
+        .bind(assignment_id)
+        .bind(late_multiplier)
+        .bind(HISTOGRAM_BUCKETS)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let task_rows = match sqlx::query(&format!(
+            "{student_scores}
+            SELECT t.id task_id, COUNT(g.user_id) attempted,
+                COUNT(*) FILTER (WHERE g.grade >= 1) passed,
+                COUNT(*) FILTER (WHERE g.grade >= 1)::FLOAT8 / NULLIF(COUNT(g.user_id), 0)
+                    pass_rate,
+                AVG(g.grade)::FLOAT8 mean
+            FROM tasks t
+            LEFT JOIN user_task_grade g ON g.task_id = t.id AND g.grade IS NOT NULL
+                AND g.user_id IN (SELECT user_id FROM students)
+            WHERE t.assignment_id = $1
+            GROUP BY t.id, t.placement
+            ORDER BY t.placement, t.id;"
+        ))
+        .bind(assignment_id)
+        .bind(late_multiplier)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let day_rows = match sqlx::query(&format!(
+            "{student_scores}
+            SELECT TO_CHAR(DATE_TRUNC('day', s.submitted_at), 'YYYY-MM-DD') day,
+                COUNT(*) submissions, COUNT(DISTINCT s.user_id) students
+            FROM submissions s
+            WHERE s.assignment_id = $1 AND s.submitted_at IS NOT NULL
+                AND s.user_id IN (SELECT user_id FROM students)
+            GROUP BY day
+            ORDER BY day;"
+        ))
+        .bind(assignment_id)
+        .bind(late_multiplier)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let histogram = (0..HISTOGRAM_BUCKETS)
+            .map(|bucket| HistogramBucket {
+                from: bucket as f32 / HISTOGRAM_BUCKETS as f32,
+                to: (bucket + 1) as f32 / HISTOGRAM_BUCKETS as f32,
+                students: bucket_rows
+                    .iter()
+                    .find(|r| r.get::<i32, _>("bucket") == bucket)
+                    .map_or(0, |r| r.get("students")),
+            })
+            .collect();
+
+        return Ok(AssignmentStats {
+            students: summary.get("students"),
+            submitted: summary.get("submitted"),
+            mean: summary.get("mean"),
+            median: summary.get("median"),
+            std_dev: summary.get("std_dev"),
+            histogram,
+            tasks: task_rows
+                .iter()
+                .map(|r| TaskStats {
+                    task_id: r.get("task_id"),
+                    attempted: r.get("attempted"),
+                    passed: r.get("passed"),
+                    pass_rate: r.get("pass_rate"),
+                    mean: r.get("mean"),
+                })
+                .collect(),
+            submissions_by_day: day_rows
+                .iter()
+                .map(|r| DailySubmissions {
+                    day: r.get("day"),
+                    submissions: r.get("submissions"),
+                    students: r.get("students"),
+                })
+                .collect(),
+        });
+    });
+
+    Err("Failed to acquire database lock".into())
+}
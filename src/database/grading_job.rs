@@ -0,0 +1,677 @@
+//! Contains database operations associated with the grading queue
+//!
+//! Every submission waiting to be graded, or being graded, is a row of `grading_jobs`, so the
+//! queue outlives the server. Workers claim jobs with `FOR UPDATE SKIP LOCKED`, so no two take the
+//! same one.
+//!
+//! The queue is shared fairly rather than strictly first come, first served. Classes with the
+//! fewest submissions being graded go first, then the students among them with the fewest. Ties
+//! go to whoever was served least recently, so classes and students take turns, and then to the
+//! oldest submission. A student submitting over and over only ever holds up their own work.
+//!
+//! Classes with a `grading_quota` never have more jobs than that being graded at once. Their
+//! other jobs wait, even while workers are free. Workers claiming in such a class take turns, so
+//! two of them can't both take its last free place.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Row, postgres::PgRow};
+
+use crate::{
+    archive,
+    container::ContainerEntry,
+    database::POSTGRES,
+    model::{
+        build_log::BuildLog,
+        grading_metrics::{
+            AssignmentMetrics, GradingMetrics, LanguageMetrics, Percentiles, TaskMetrics,
+            TimingStats,
+        },
+        submission_response::GradingTimes,
+        submission_status::GradingStatus,
+    },
+    postgres_lock, storage,
+};
+
+/// How many of the latest graded submissions grading times are estimated from
+const RECENT_JOBS: i64 = 50;
+
+/// First key of the advisory locks that claims in a class with a quota hold, the second being the
+/// class's hash
+const QUOTA_LOCK: i32 = 0x5147;
+
+/// The 50th and 95th percentiles of the queue wait, build and run times of the jobs finished in
+/// the last `$1` days, in the class `$2` (or every class if it's `NULL`). `{group}` is replaced
+/// with the columns they're grouped by.
+const METRICS_QUERY: &str = "
+    SELECT {group}, COUNT(*) jobs,
+        percentile_cont(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM j.started_at - j.queued_at)::FLOAT8) queue_wait_p50,
+        percentile_cont(0.95) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM j.started_at - j.queued_at)::FLOAT8) queue_wait_p95,
+        percentile_cont(0.5) WITHIN GROUP (ORDER BY j.build_secs) build_p50,
+        percentile_cont(0.95) WITHIN GROUP (ORDER BY j.build_secs) build_p95,
+        percentile_cont(0.5) WITHIN GROUP (ORDER BY j.run_secs) run_p50,
+        percentile_cont(0.95) WITHIN GROUP (ORDER BY j.run_secs) run_p95
+    FROM grading_jobs j
+    JOIN tasks t ON t.id = j.task_id
+    JOIN assignments a ON a.id = t.assignment_id
+    WHERE j.status IN ('done', 'failed') AND j.finished_at > NOW() - make_interval(days => $1)
+        AND ($2::TEXT IS NULL OR EXISTS (
+            SELECT 1 FROM assignment_class ac
+            WHERE ac.assignment_id = t.assignment_id AND ac.class_number = $2))
+    GROUP BY {group}
+    ORDER BY {group};";
+
+/// A job taken from the queue
+pub struct GradingJob {
+    pub id: i32,
+    pub entry: ContainerEntry,
+    /// How many times it has been retried
+    pub attempts: i32,
+}
+
+/// A submission that is queued or being graded
+pub struct JobProgress {
+    pub status: GradingStatus,
+    /// Queued jobs at least as old, itself included. Only counts while queued.
+    pub queue_position: i64,
+    /// Time since a worker took it, once one has
+    pub elapsed_secs: Option<f64>,
+}
+
+/// Adds a submission to the end of the queue, returning the job id
+pub async fn enqueue(
+    user_id: i32,
+    task_id: i32,
+    was_late: bool,
+    lang: &str,
+    sample: bool,
+    zip_file: &[u8],
+) -> Result<i32, String> {
+    let key = storage::new_key("jobs", Some(archive::extension(zip_file)));
+    storage::put(&key, zip_file).await?;
+
+    postgres_lock!(transaction, {
+        let job_id: i32 = match sqlx::query(
+            "INSERT INTO grading_jobs (user_id, task_id, was_late, lang, sample, submission_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(was_late)
+        .bind(lang)
+        .bind(sample)
+        .bind(&key)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("id"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(job_id);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Marks the next queued job, in fair order, as being built and returns it. `None` => the queue
+/// is empty.
+pub async fn claim_next() -> Result<Option<GradingJob>, String> {
+    postgres_lock!(transaction, {
+        // Classes found to be at their quota once it was their turn to claim
+        let mut full: Vec<String> = vec![];
+
+        let row = loop {
+            let candidate = match sqlx::query(
+                "WITH served AS (
+                    SELECT j.user_id, ac.class_number, j.status, j.started_at
+                    FROM grading_jobs j
+                    JOIN tasks t ON t.id = j.task_id
+                    JOIN assignment_class ac ON ac.assignment_id = t.assignment_id
+                    WHERE j.status IN ('building', 'running') OR j.started_at > NOW() - INTERVAL '1 hour'
+                )
+                SELECT j.id, ac.class_number, c.grading_quota FROM grading_jobs j
+                JOIN tasks t ON t.id = j.task_id
+                LEFT JOIN assignment_class ac ON ac.assignment_id = t.assignment_id
+                LEFT JOIN classes c ON c.class_number = ac.class_number
+                WHERE j.status = 'queued' AND (j.run_after IS NULL OR j.run_after <= NOW())
+                    AND (ac.class_number IS NULL OR ac.class_number <> ALL($1))
+                    AND (c.grading_quota IS NULL
+                        OR (SELECT COUNT(*) FROM served s
+                            WHERE s.class_number = ac.class_number
+                                AND s.status IN ('building', 'running')) < c.grading_quota)
+                ORDER BY
+                    (SELECT COUNT(*) FROM served s
+                        WHERE s.class_number = ac.class_number
+                            AND s.status IN ('building', 'running')),
+                    (SELECT COUNT(*) FROM served s
+                        WHERE s.user_id = j.user_id AND s.status IN ('building', 'running')),
+                    (SELECT MAX(s.started_at) FROM served s
+                        WHERE s.class_number = ac.class_number) NULLS FIRST,
+                    (SELECT MAX(s.started_at) FROM served s
+                        WHERE s.user_id = j.user_id) NULLS FIRST,
+                    j.id
+                LIMIT 1
+                FOR UPDATE OF j SKIP LOCKED;",
+            )
+            .bind(&full)
+            .fetch_optional(&mut *transaction)
+            .await
+            {
+                Ok(Some(r)) => r,
+                Ok(None) => break None,
+                Err(e) => return Err(format!("{e}")),
+            };
+
+            // Another worker may be claiming in the same class right now, unseen until it commits.
+            // Claims in a class with a quota take turns, and count again once it's theirs.
+            let class_number: Option<String> = candidate.get("class_number");
+            let quota: Option<i32> = candidate.get("grading_quota");
+            if let (Some(class_number), Some(quota)) = (class_number, quota) {
+                if let Err(e) = sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2));")
+                    .bind(QUOTA_LOCK)
+                    .bind(&class_number)
+                    .execute(&mut *transaction)
+                    .await
+                {
+                    return Err(format!("{e}"));
+                }
+
+                let busy: i64 = match sqlx::query(
+                    "SELECT COUNT(*) busy FROM grading_jobs j
+                    JOIN tasks t ON t.id = j.task_id
+                    JOIN assignment_class ac ON ac.assignment_id = t.assignment_id
+                    WHERE ac.class_number = $1 AND j.status IN ('building', 'running');",
+                )
+                .bind(&class_number)
+                .fetch_one(&mut *transaction)
+                .await
+                {
+                    Ok(r) => r.get("busy"),
+                    Err(e) => return Err(format!("{e}")),
+                };
+
+                if busy >= quota as i64 {
+                    full.push(class_number);
+                    continue;
+                }
+            }
+
+            break match sqlx::query(
+                "UPDATE grading_jobs
+                SET status = 'building', started_at = NOW()
+                WHERE id = $1
+                RETURNING id, user_id, task_id, was_late, lang, sample, submission_key, attempts;",
+            )
+            .bind(candidate.get::<i32, _>("id"))
+            .fetch_one(&mut *transaction)
+            .await
+            {
+                Ok(r) => Some(r),
+                Err(e) => return Err(format!("{e}")),
+            };
+        };
+
+        // Read before committing, so the job stays queued if storage can't be reached
+        let job = match &row {
+            Some(row) => Some(grading_job(row).await?),
+            None => None,
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(job);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records that a job's submission has been built, and its tests are running
+pub async fn mark_running(job_id: i32) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE grading_jobs SET status = 'running' WHERE id = $1 AND status = 'building';",
+        )
+        .bind(job_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records how a claimed job ended. Its copy of the submission is let go, as the job won't run
+/// again, and the janitor removes it from blob storage.
+pub async fn finish(job_id: i32, graded: bool) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE grading_jobs
+            SET status = $1, finished_at = NOW(), submission_key = NULL
+            WHERE id = $2;",
+        )
+        .bind(if graded { "done" } else { "failed" })
+        .bind(job_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Keeps what the job's build printed, for instructors to look at
+pub async fn set_build_log(job_id: i32, log: &str) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("UPDATE grading_jobs SET build_log = $1 WHERE id = $2;")
+            .bind(log)
+            .bind(job_id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records how long the job's build and tests took
+pub async fn set_times(job_id: i32, times: GradingTimes) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) =
+            sqlx::query("UPDATE grading_jobs SET build_secs = $1, run_secs = $2 WHERE id = $3;")
+                .bind(times.build_secs)
+                .bind(times.run_secs)
+                .bind(job_id)
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// How long grading took over the last `days`, for one class or, with `None`, every class
+pub async fn metrics(class_number: Option<String>, days: i32) -> Result<GradingMetrics, String> {
+    postgres_lock!(transaction, {
+        let query = |group: &str| METRICS_QUERY.replace("{group}", group);
+
+        let by_language = match sqlx::query(&query("j.lang"))
+            .bind(days)
+            .bind(&class_number)
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let by_assignment = match sqlx::query(&query("t.assignment_id, a.assignment_name"))
+            .bind(days)
+            .bind(&class_number)
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let by_task = match sqlx::query(&query("t.assignment_id, j.task_id"))
+            .bind(days)
+            .bind(&class_number)
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        return Ok(GradingMetrics {
+            days,
+            by_language: by_language
+                .iter()
+                .map(|r| LanguageMetrics {
+                    lang: r.get("lang"),
+                    timings: timing_stats(r),
+                })
+                .collect(),
+            by_assignment: by_assignment
+                .iter()
+                .map(|r| AssignmentMetrics {
+                    assignment_id: r.get("assignment_id"),
+                    assignment_name: r.get("assignment_name"),
+                    timings: timing_stats(r),
+                })
+                .collect(),
+            by_task: by_task
+                .iter()
+                .map(|r| TaskMetrics {
+                    assignment_id: r.get("assignment_id"),
+                    task_id: r.get("task_id"),
+                    timings: timing_stats(r),
+                })
+                .collect(),
+        });
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+fn timing_stats(r: &PgRow) -> TimingStats {
+    TimingStats {
+        jobs: r.get("jobs"),
+        queue_wait: Percentiles {
+            p50: r.get("queue_wait_p50"),
+            p95: r.get("queue_wait_p95"),
+        },
+        build: Percentiles {
+            p50: r.get("build_p50"),
+            p95: r.get("build_p95"),
+        },
+        run: Percentiles {
+            p50: r.get("run_p50"),
+            p95: r.get("run_p95"),
+        },
+    }
+}
+
+/// The latest build log kept for the student's submissions of the task, if the task is in the
+/// class's assignment. `None` => none of them failed to build.
+pub async fn latest_build_log(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    username: &str,
+) -> Result<Option<BuildLog>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT j.id, j.sample, j.queued_at, j.build_log
+            FROM grading_jobs j
+            JOIN users u ON u.id = j.user_id
+            JOIN tasks t ON t.id = j.task_id
+            JOIN assignment_class ac ON ac.assignment_id = t.assignment_id
+            WHERE u.user_name = $1 AND j.task_id = $2 AND t.assignment_id = $3
+                AND ac.class_number = $4 AND j.build_log IS NOT NULL
+            ORDER BY j.id DESC
+            LIMIT 1;",
+        )
+        .bind(username)
+        .bind(task_id)
+        .bind(assignment_id)
+        .bind(class_number)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        return Ok(row.map(|r| {
+            let submitted_at: DateTime<Utc> = r.get("queued_at");
+            BuildLog {
+                job_id: r.get("id"),
+                sample: r.get("sample"),
+                submitted_at: submitted_at.to_string(),
+                log: r.get("build_log"),
+            }
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Puts a claimed job back in the queue after a failure, to be tried again once `delay_secs` have
+/// passed
+pub async fn retry_later(job_id: i32, delay_secs: f64) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE grading_jobs
+            SET status = 'queued', started_at = NULL, attempts = attempts + 1,
+                run_after = NOW() + make_interval(secs => $2)
+            WHERE id = $1;",
+        )
+        .bind(job_id)
+        .bind(delay_secs)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Puts a claimed job back in the queue, in its original place
+pub async fn release(job_id: i32) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE grading_jobs SET status = 'queued', started_at = NULL
+            WHERE id = $1 AND status IN ('building', 'running');",
+        )
+        .bind(job_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Puts every claimed job back in the queue, returning how many there were. Only called at
+/// start-up, when nothing can be running yet.
+pub async fn release_interrupted() -> Result<u64, String> {
+    postgres_lock!(transaction, {
+        let released = match sqlx::query(
+            "UPDATE grading_jobs SET status = 'queued', started_at = NULL
+            WHERE status IN ('building', 'running');",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(released);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The number of jobs waiting for a worker
+pub async fn queued_count() -> Result<usize, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query("SELECT COUNT(*) FROM grading_jobs WHERE status = 'queued';")
+            .fetch_one(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(r.get::<i64, _>(0) as usize),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Takes the student's submission of the task out of the queue, along with the submission itself,
+/// as long as no worker has taken it yet. Their latest graded attempt, if any, counts again.
+/// Regrades of graded submissions can't be cancelled.
+/// `false` => there was nothing to cancel.
+pub async fn cancel_queued(user_id: i32, task_id: i32) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let cancelled = match sqlx::query(
+            "DELETE FROM grading_jobs j
+            USING user_task_grade g
+            WHERE j.user_id = $1 AND j.task_id = $2 AND j.status = 'queued' AND NOT j.sample
+                AND g.user_id = j.user_id AND g.task_id = j.task_id AND g.grade IS NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        if !cancelled {
+            return Ok(false);
+        }
+
+        // The cancelled attempt goes from the history as well, as if it was never made
+        if let Err(e) = sqlx::query(
+            "DELETE FROM submissions s
+            USING user_task_grade g
+            WHERE g.user_id = $1 AND g.task_id = $2 AND g.grade IS NULL
+                AND s.user_id = g.user_id AND s.task_id = g.task_id AND s.attempt = g.attempt;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        if let Err(e) = sqlx::query(
+            "DELETE FROM user_task_grade WHERE user_id = $1 AND task_id = $2 AND grade IS NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        // The latest graded attempt left counts again, as it did before the cancelled one was made
+        if let Err(e) = sqlx::query(
+            "INSERT INTO user_task_grade (user_id, task_id, assignment_id, json_results, grade, error, was_late, submission_key, submission_lang, late_multiplier, submitted_at, graded_at, attempt, toolchain_version)
+            SELECT user_id, task_id, assignment_id, json_results, grade, error, was_late, submission_key, submission_lang, late_multiplier, submitted_at, graded_at, attempt, toolchain_version
+            FROM submissions
+            WHERE user_id = $1 AND task_id = $2 AND grade IS NOT NULL
+            ORDER BY attempt DESC
+            LIMIT 1;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(true);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Where the student's latest submission of the task is in the queue. `None` => it isn't queued
+/// or being graded.
+pub async fn submission_progress(
+    user_id: i32,
+    task_id: i32,
+) -> Result<Option<JobProgress>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT j.status,
+                (SELECT COUNT(*) FROM grading_jobs q WHERE q.status = 'queued' AND q.id <= j.id)
+                    queue_position,
+                EXTRACT(EPOCH FROM NOW() - j.started_at)::FLOAT8 elapsed_secs
+            FROM grading_jobs j
+            WHERE j.user_id = $1 AND j.task_id = $2 AND NOT j.sample
+                AND j.status IN ('queued', 'building', 'running')
+            ORDER BY j.id DESC
+            LIMIT 1;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        return Ok(row.map(|r| JobProgress {
+            status: GradingStatus::from(r.get::<String, _>("status")),
+            queue_position: r.get("queue_position"),
+            elapsed_secs: r.get("elapsed_secs"),
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Average seconds it took to grade the latest submissions. `None` => none has been graded yet.
+pub async fn recent_grading_secs() -> Result<Option<f64>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT AVG(EXTRACT(EPOCH FROM finished_at - started_at))::FLOAT8 average
+            FROM (
+                SELECT started_at, finished_at FROM grading_jobs
+                WHERE status = 'done' AND started_at IS NOT NULL
+                ORDER BY id DESC
+                LIMIT $1
+            ) recent;",
+        )
+        .bind(RECENT_JOBS)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.get("average")),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+async fn grading_job(r: &PgRow) -> Result<GradingJob, String> {
+    let zip_file = storage::get(r.get::<&str, _>("submission_key")).await?;
+    let (user_id, task_id, lang): (i32, i32, String) =
+        (r.get("user_id"), r.get("task_id"), r.get("lang"));
+
+    let entry = if r.get("sample") {
+        ContainerEntry::sample(zip_file.into(), user_id, task_id, lang)
+    } else {
+        ContainerEntry::new(zip_file.into(), user_id, task_id, r.get("was_late"), lang)
+    };
+
+    Ok(GradingJob {
+        id: r.get("id"),
+        entry,
+        attempts: r.get("attempts"),
+    })
+}
@@ -0,0 +1,144 @@
+//! Contains database operations associated with per-class honor pledges and their acknowledgements
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{
+    database::POSTGRES,
+    model::honor::{HonorAcknowledgement, HonorPledge, HonorPledgeMode},
+    postgres_lock,
+};
+
+pub async fn set_honor_pledge(
+    class_number: String,
+    pledge: Option<String>,
+    mode: HonorPledgeMode,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE classes SET honor_pledge = $1, honor_pledge_mode = $2 WHERE class_number = $3;",
+        )
+        .bind(pledge)
+        .bind(mode.as_str())
+        .bind(class_number)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns the class's pledge and whether the user has acknowledged it for the course
+pub async fn get_honor_pledge(
+    class_number: String,
+    user_id: i32,
+) -> Result<Option<HonorPledge>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT honor_pledge, honor_pledge_mode,
+                EXISTS (
+                    SELECT 1 FROM honor_acknowledgements h
+                    WHERE h.class_number = classes.class_number AND h.user_id = $2
+                ) acknowledged
+            FROM classes WHERE class_number = $1;",
+        )
+        .bind(class_number)
+        .bind(user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let mode: String = row.get("honor_pledge_mode");
+        let mode = HonorPledgeMode::from(mode);
+
+        if mode == HonorPledgeMode::None {
+            return Ok(None);
+        }
+
+        return Ok(Some(HonorPledge {
+            text: row.get::<Option<String>, _>("honor_pledge").unwrap_or_default(),
+            mode,
+            acknowledged: row.get("acknowledged"),
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records that a user accepted the class's pledge. Submission-level acknowledgements also record the task.
+pub async fn record_acknowledgement(
+    user_id: i32,
+    class_number: String,
+    assignment_id: Option<i32>,
+    task_id: Option<i32>,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO honor_acknowledgements (user_id, class_number, assignment_id, task_id)
+            VALUES ($1, $2, $3, $4);",
+        )
+        .bind(user_id)
+        .bind(class_number)
+        .bind(assignment_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Lists every acknowledgement recorded for a class, for academic integrity review
+pub async fn list_acknowledgements(
+    class_number: String,
+) -> Result<Vec<HonorAcknowledgement>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT users.user_name, h.assignment_id, h.task_id, h.acknowledged_at
+            FROM honor_acknowledgements h
+            JOIN users ON users.id = h.user_id
+            WHERE h.class_number = $1
+            ORDER BY h.acknowledged_at ASC;",
+        )
+        .bind(class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let acknowledgements = rows
+            .iter()
+            .map(|r| {
+                let acknowledged_at: DateTime<Utc> = r.get("acknowledged_at");
+                HonorAcknowledgement {
+                    username: r.get("user_name"),
+                    assignment_id: r.get("assignment_id"),
+                    task_id: r.get("task_id"),
+                    acknowledged_at: acknowledged_at.to_string(),
+                }
+            })
+            .collect::<Vec<HonorAcknowledgement>>();
+
+        return Ok(acknowledgements);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
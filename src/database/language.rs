@@ -0,0 +1,63 @@
+//! Contains database operations associated with how languages are used
+
+use std::collections::HashMap;
+
+use sqlx::Row;
+
+use crate::{database::POSTGRES, model::language_info::LanguageUsage, postgres_lock};
+
+/// How much each language has been used, by language. Languages never used are left out.
+pub async fn usage_counts() -> Result<HashMap<String, LanguageUsage>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT submission_lang lang, COUNT(*) submissions, COUNT(DISTINCT user_id) students
+            FROM user_task_grade
+            WHERE submission_lang IS NOT NULL
+            GROUP BY submission_lang;",
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let mut usage: HashMap<String, LanguageUsage> = rows
+            .iter()
+            .map(|r| {
+                (
+                    r.get("lang"),
+                    LanguageUsage {
+                        submissions: r.get("submissions"),
+                        students: r.get("students"),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+
+        let rows = match sqlx::query(
+            "SELECT lang, COUNT(*) assignments
+            FROM assignments, unnest(allowed_languages) lang
+            GROUP BY lang;",
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        for r in &rows {
+            usage
+                .entry(r.get("lang"))
+                .or_default()
+                .restricted_assignments = r.get("assignments");
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(usage);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
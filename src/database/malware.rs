@@ -0,0 +1,85 @@
+//! Contains database operations associated with submissions the malware scanner flagged
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{database::POSTGRES, model::malware_incident::MalwareIncident, postgres_lock};
+
+/// Records a flagged upload. Only its hash and size are kept, not the upload itself.
+pub async fn record_incident(
+    user_id: i32,
+    class_number: &str,
+    task_id: i32,
+    sample: bool,
+    signature: &str,
+    sha256: &str,
+    size_bytes: i64,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO malware_incidents (user_id, class_number, task_id, sample, signature, sha256, size_bytes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7);",
+        )
+        .bind(user_id)
+        .bind(class_number)
+        .bind(task_id)
+        .bind(sample)
+        .bind(signature)
+        .bind(sha256)
+        .bind(size_bytes)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Lists every incident recorded for a class, newest first
+pub async fn list_incidents(class_number: String) -> Result<Vec<MalwareIncident>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT m.id, users.user_name, tasks.assignment_id, m.task_id, m.sample, m.signature,
+                m.sha256, m.size_bytes, m.detected_at
+            FROM malware_incidents m
+            JOIN users ON users.id = m.user_id
+            JOIN tasks ON tasks.id = m.task_id
+            WHERE m.class_number = $1
+            ORDER BY m.detected_at DESC;",
+        )
+        .bind(class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let incidents = rows
+            .iter()
+            .map(|r| {
+                let detected_at: DateTime<Utc> = r.get("detected_at");
+                MalwareIncident {
+                    id: r.get("id"),
+                    username: r.get("user_name"),
+                    assignment_id: r.get("assignment_id"),
+                    task_id: r.get("task_id"),
+                    sample: r.get("sample"),
+                    signature: r.get("signature"),
+                    sha256: r.get("sha256"),
+                    size_bytes: r.get("size_bytes"),
+                    detected_at: detected_at.to_string(),
+                }
+            })
+            .collect::<Vec<MalwareIncident>>();
+
+        return Ok(incidents);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
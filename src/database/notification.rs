@@ -0,0 +1,68 @@
+//! Contains database operations associated with in-app user notifications
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{database::POSTGRES, model::notification::Notification, postgres_lock};
+
+/// Queues a notification for a user, optionally carrying a link for the frontend to follow.
+pub async fn add_notification(
+    user_id: i32,
+    message: impl Into<String>,
+    link: Option<String>,
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) =
+            sqlx::query("INSERT INTO notifications (user_id, message, link) VALUES ($1, $2, $3);")
+                .bind(user_id)
+                .bind(message.into())
+                .bind(link)
+                .execute(&mut *transaction)
+                .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns every unseen notification for the user and marks them as seen.
+pub async fn take_notifications(user_id: i32) -> Result<Vec<Notification>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "UPDATE notifications
+            SET seen = TRUE
+            WHERE user_id = $1 AND seen = FALSE
+            RETURNING message, link, created;",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+
+        let notifications = rows
+            .iter()
+            .map(|r| {
+                let created: DateTime<Utc> = r.get("created");
+                Notification {
+                    message: r.get("message"),
+                    link: r.get("link"),
+                    created: created.to_string(),
+                }
+            })
+            .collect::<Vec<Notification>>();
+
+        return Ok(notifications);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
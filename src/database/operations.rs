@@ -1,5 +1,6 @@
 //! Contains uncategorized database operations (TODO: Refactor them later)
 
+use crate::container::Isolation;
 use crate::database::POSTGRES;
 use crate::model::class_info::InstructorInfo;
 use crate::model::class_item::ClassItem;
@@ -53,6 +54,56 @@ pub async fn new_class(obj: ClientRequest) -> Result<(), String> {
     Ok(())
 }
 
+/// Sets what the class's submissions are graded in. `None` => the deployment's default.
+/// Returns `false` if there is no such class.
+pub async fn set_class_isolation(
+    class_number: String,
+    isolation: Option<Isolation>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated =
+            match sqlx::query("UPDATE classes SET isolation = $1 WHERE class_number = $2;")
+                .bind(isolation.map(|i| i.as_str()))
+                .bind(class_number)
+                .execute(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated > 0);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Caps how many of the class's submissions are graded at once. `None` => no cap.
+/// Returns `false` if there is no such class.
+pub async fn set_class_grading_quota(
+    class_number: String,
+    grading_quota: Option<i32>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated =
+            match sqlx::query("UPDATE classes SET grading_quota = $1 WHERE class_number = $2;")
+                .bind(grading_quota)
+                .bind(class_number)
+                .execute(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated > 0);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
 /// Manually adds a new student to an existing class
 pub async fn add_student(obj: ClientRequest) -> Result<(), String> {
     let Some((class_number, student_user_name)) = obj.get_new_student() else {
@@ -290,3 +341,18 @@ pub async fn join_class(user_id: i32, join_code: String) -> Result<bool, String>
 
     Err("Failed to acquire transaction lock".into())
 }
+
+pub async fn class_exists(class_number: String) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query("SELECT class_number FROM classes WHERE class_number = $1;")
+            .bind(class_number)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(r.is_some()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
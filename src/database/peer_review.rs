@@ -0,0 +1,303 @@
+//! Contains database operations associated with peer-reviewed assignments
+//!
+//! Reviews are handed out the first time anyone asks for them after the deadline. Students who
+//! had submitted by then are ordered by a hash of their id, and each reviews the next
+//! `reviews_per_student` students in that order, wrapping around. Nobody reviews themselves, and
+//! students who submit after reviews were handed out are not reviewed.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, Row};
+
+use crate::{
+    database::POSTGRES,
+    model::peer_review::{PeerReview, PeerReviewSettings, ReceivedReview},
+    postgres_lock, storage,
+};
+
+/// Returns the assignment's peer review settings once reviews are open, handing out reviews if
+/// that hasn't happened yet. `None` => the assignment isn't peer reviewed, or its deadline
+/// hasn't passed.
+pub async fn open_reviews(assignment_id: i32) -> Result<Option<PeerReviewSettings>, String> {
+    postgres_lock!(transaction, {
+        let (settings, closed): (Option<String>, bool) = match sqlx::query(
+            "SELECT peer_review, deadline <= NOW() closed FROM assignments WHERE id = $1;",
+        )
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => (r.get("peer_review"), r.get("closed")),
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let Some(settings) =
+            settings.and_then(|s| serde_json::from_str::<PeerReviewSettings>(&s).ok())
+        else {
+            return Ok(None);
+        };
+
+        if !closed {
+            return Ok(None);
+        }
+
+        let handed_out =
+            match sqlx::query("SELECT id FROM peer_reviews WHERE assignment_id = $1 LIMIT 1;")
+                .bind(assignment_id)
+                .fetch_optional(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.is_some(),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        if !handed_out {
+            let mut submitters: Vec<i32> = match sqlx::query(
+                "SELECT DISTINCT g.user_id
+                FROM user_task_grade g
+                JOIN assignment_class ac ON ac.assignment_id = g.assignment_id
+                JOIN user_class uc ON uc.user_id = g.user_id AND uc.class_number = ac.class_number
+                WHERE g.assignment_id = $1 AND g.submission_key IS NOT NULL AND uc.is_instructor = FALSE;",
+            )
+            .bind(assignment_id)
+            .fetch_all(&mut *transaction)
+            .await
+            {
+                Ok(r) => r.iter().map(|r| r.get("user_id")).collect(),
+                Err(e) => return Err(format!("{e}")),
+            };
+
+            submitters.sort_by_cached_key(|id| Sha256::digest(format!("{assignment_id}:{id}")));
+
+            let n = submitters.len();
+            let per_student =
+                (settings.reviews_per_student.max(0) as usize).min(n.saturating_sub(1));
+
+            for (i, reviewer) in submitters.iter().enumerate() {
+                for offset in 1..=per_student {
+                    let reviewee = submitters[(i + offset) % n];
+
+                    if let Err(e) = sqlx::query(
+                        "INSERT INTO peer_reviews (assignment_id, reviewer_id, reviewee_id)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT DO NOTHING;",
+                    )
+                    .bind(assignment_id)
+                    .bind(reviewer)
+                    .bind(reviewee)
+                    .execute(&mut *transaction)
+                    .await
+                    {
+                        return Err(format!("{e}"));
+                    }
+                }
+            }
+
+            transaction.commit().await.unwrap();
+        }
+
+        return Ok(Some(settings));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The submissions the user has been asked to review
+pub async fn list_reviews(assignment_id: i32, reviewer_id: i32) -> Result<Vec<PeerReview>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT id, scores, comments FROM peer_reviews
+            WHERE assignment_id = $1 AND reviewer_id = $2
+            ORDER BY id;",
+        )
+        .bind(assignment_id)
+        .bind(reviewer_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let reviews = rows
+            .iter()
+            .map(|r| {
+                let scores = r
+                    .get::<Option<String>, _>("scores")
+                    .and_then(|s| serde_json::from_str::<Vec<f32>>(&s).ok());
+
+                PeerReview {
+                    review_id: r.get("id"),
+                    completed: scores.is_some(),
+                    scores,
+                    comments: r.get("comments"),
+                }
+            })
+            .collect::<Vec<PeerReview>>();
+
+        return Ok(reviews);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns the submitted code for one task of a submission the user was asked to review
+pub async fn review_submission(
+    assignment_id: i32,
+    reviewer_id: i32,
+    review_id: i32,
+    task_id: i32,
+) -> Result<Option<Vec<u8>>, String> {
+    postgres_lock!(transaction, {
+        let key: Option<String> = match sqlx::query(
+            "SELECT g.submission_key FROM peer_reviews r
+            JOIN user_task_grade g ON g.user_id = r.reviewee_id AND g.assignment_id = r.assignment_id
+            WHERE r.id = $1 AND r.assignment_id = $2 AND r.reviewer_id = $3 AND g.task_id = $4
+                AND g.submission_key IS NOT NULL;",
+        )
+        .bind(review_id)
+        .bind(assignment_id)
+        .bind(reviewer_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.map(|r| r.get("submission_key")),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+
+        return match key {
+            Some(key) => storage::get(&key).await.map(Some),
+            None => Ok(None),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Records (or replaces) a review. Returns `Ok(false)` if the review isn't the user's.
+pub async fn submit_review(
+    assignment_id: i32,
+    reviewer_id: i32,
+    review_id: i32,
+    scores: Vec<f32>,
+    comments: Option<String>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated = match sqlx::query(
+            "UPDATE peer_reviews SET scores = $1, comments = $2, reviewed_at = NOW()
+            WHERE id = $3 AND assignment_id = $4 AND reviewer_id = $5;",
+        )
+        .bind(serde_json::to_string(&scores).unwrap())
+        .bind(comments)
+        .bind(review_id)
+        .bind(assignment_id)
+        .bind(reviewer_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Completed reviews of the user's submission
+pub async fn received_reviews(
+    assignment_id: i32,
+    reviewee_id: i32,
+) -> Result<Vec<ReceivedReview>, String> {
+    postgres_lock!(transaction, {
+        let rows = match sqlx::query(
+            "SELECT scores, comments FROM peer_reviews
+            WHERE assignment_id = $1 AND reviewee_id = $2 AND scores IS NOT NULL
+            ORDER BY reviewed_at;",
+        )
+        .bind(assignment_id)
+        .bind(reviewee_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let reviews = rows
+            .iter()
+            .filter_map(|r| {
+                let scores: String = r.get("scores");
+                Some(ReceivedReview {
+                    scores: serde_json::from_str(&scores).ok()?,
+                    comments: r.get("comments"),
+                })
+            })
+            .collect::<Vec<ReceivedReview>>();
+
+        return Ok(reviews);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Each student's average peer score, keyed by (user_id, assignment_id), with the settings of
+/// the assignment it belongs to. Limited to one class and/or one assignment.
+pub(super) async fn peer_scores(
+    conn: &mut PgConnection,
+    class_number: Option<&str>,
+    assignment_id: Option<i32>,
+) -> Result<HashMap<(i32, i32), (f32, PeerReviewSettings)>, String> {
+    let rows = match sqlx::query(
+        "SELECT r.reviewee_id, r.assignment_id, r.scores, a.peer_review
+        FROM peer_reviews r
+        JOIN assignments a ON a.id = r.assignment_id
+        JOIN assignment_class ac ON ac.assignment_id = a.id
+        WHERE r.scores IS NOT NULL AND a.peer_review IS NOT NULL
+            AND ($1::TEXT IS NULL OR ac.class_number = $1)
+            AND ($2::INTEGER IS NULL OR r.assignment_id = $2);",
+    )
+    .bind(class_number)
+    .bind(assignment_id)
+    .fetch_all(conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    // (user_id, assignment_id) => (review fractions, settings)
+    let mut reviews: HashMap<(i32, i32), (Vec<f32>, PeerReviewSettings)> = HashMap::new();
+    for row in rows {
+        let settings: String = row.get("peer_review");
+        let scores: String = row.get("scores");
+        let (Ok(settings), Ok(scores)) = (
+            serde_json::from_str::<PeerReviewSettings>(&settings),
+            serde_json::from_str::<Vec<f32>>(&scores),
+        ) else {
+            continue;
+        };
+
+        let fraction = settings.fraction(&scores);
+        reviews
+            .entry((row.get("reviewee_id"), row.get("assignment_id")))
+            .or_insert((vec![], settings))
+            .0
+            .push(fraction);
+    }
+
+    Ok(reviews
+        .into_iter()
+        .map(|(key, (fractions, settings))| {
+            let average = fractions.iter().sum::<f32>() / fractions.len() as f32;
+            (key, (average, settings))
+        })
+        .collect())
+}
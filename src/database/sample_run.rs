@@ -0,0 +1,130 @@
+//! Contains database operations associated with sample runs
+//!
+//! A sample run grades a submission against only the task's sample tests. Nothing about it counts
+//! towards the student's grade, and only the latest run of each task is kept.
+
+use sqlx::Row;
+
+use crate::{
+    database::POSTGRES,
+    model::submission_response::{ResultVisibility, SubmissionResponse},
+    postgres_lock,
+};
+
+/// Records that a sample run has been queued, replacing the previous one.
+///
+/// Returns `Ok(false)` if the student's previous sample run of the task hasn't finished yet
+pub async fn start_sample_run(user_id: i32, task_id: i32, lang: &str) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let started = match sqlx::query(
+            "INSERT INTO sample_runs (user_id, task_id, language, submitted_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (user_id, task_id) DO UPDATE
+            SET language = $3, submitted_at = NOW(), json_results = NULL
+            WHERE sample_runs.json_results IS NOT NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(lang)
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(started);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn store_sample_results(
+    user_id: i32,
+    task_id: i32,
+    results: &[u8],
+) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE sample_runs SET json_results = $1 WHERE user_id = $2 AND task_id = $3;",
+        )
+        .bind(results)
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Forgets a sample run that couldn't be completed, so the student can start another
+pub async fn discard_sample_run(user_id: i32, task_id: i32) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "DELETE FROM sample_runs
+            WHERE user_id = $1 AND task_id = $2 AND json_results IS NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns the results of the student's latest sample run, with the assignment's result
+/// visibility applied. `Some(None)` => the run hasn't finished yet.
+pub async fn get_sample_results(
+    user_id: i32,
+    task_id: i32,
+) -> Result<Option<Option<SubmissionResponse>>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT s.json_results, s.language, a.result_visibility
+            FROM sample_runs s
+            JOIN tasks t ON t.id = s.task_id
+            JOIN assignments a ON a.id = t.assignment_id
+            WHERE s.user_id = $1 AND s.task_id = $2;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let Some(json_results) = row.get::<Option<Vec<u8>>, _>("json_results") else {
+            return Ok(Some(None));
+        };
+
+        let results: SubmissionResponse =
+            serde_json::from_slice(&json_results).map_err(|e| format!("{e}"))?;
+        let visibility = ResultVisibility::from(row.get::<String, _>("result_visibility"));
+
+        return Ok(Some(Some(
+            results
+                .redact(visibility)
+                .with_toolchain(row.get("language"), None),
+        )));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
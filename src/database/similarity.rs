@@ -0,0 +1,282 @@
+//! Contains database operations associated with similarity checks
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+
+use crate::{
+    database::POSTGRES,
+    model::similarity_report::{MatchedRegion, SimilarPair, SimilarityReport},
+    postgres_lock,
+    similarity::Pair,
+};
+
+/// Starts a report on the assignment, returning its id. `None` => one is already being made.
+pub async fn create_report(assignment_id: i32, user_id: i32) -> Result<Option<i32>, String> {
+    postgres_lock!(transaction, {
+        let in_progress = match sqlx::query(
+            "SELECT id FROM similarity_reports
+            WHERE assignment_id = $1 AND status IN ('queued', 'running');",
+        )
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.is_some(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        if in_progress {
+            return Ok(None);
+        }
+
+        let report_id: i32 = match sqlx::query(
+            "INSERT INTO similarity_reports (assignment_id, requested_by)
+            VALUES ($1, $2)
+            RETURNING id;",
+        )
+        .bind(assignment_id)
+        .bind(user_id)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("id"),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(Some(report_id));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn set_status(report_id: i32, status: &str) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("UPDATE similarity_reports SET status = $1 WHERE id = $2;")
+            .bind(status)
+            .bind(report_id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn finish_report(report_id: i32) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE similarity_reports SET status = 'done', finished_at = NOW() WHERE id = $1;",
+        )
+        .bind(report_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn fail_report(report_id: i32, error: String) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query(
+            "UPDATE similarity_reports SET status = 'failed', error = $1, finished_at = NOW()
+            WHERE id = $2;",
+        )
+        .bind(error)
+        .bind(report_id)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Fails the reports the last shutdown interrupted, so they can be asked for again. Returns how
+/// many there were.
+pub async fn fail_interrupted() -> Result<u64, String> {
+    postgres_lock!(transaction, {
+        let failed = match sqlx::query(
+            "UPDATE similarity_reports
+            SET status = 'failed', error = 'Interrupted by a restart', finished_at = NOW()
+            WHERE status IN ('queued', 'running');",
+        )
+        .execute(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(failed);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns (task_id, user_id, storage key) of the submission that counts of every student to the
+/// assignment
+pub async fn latest_submissions(assignment_id: i32) -> Result<Vec<(i32, i32, String)>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT DISTINCT g.task_id, g.user_id, g.submission_key
+            FROM user_task_grade g
+            JOIN assignment_class ac ON ac.assignment_id = g.assignment_id
+            JOIN user_class uc ON uc.user_id = g.user_id AND uc.class_number = ac.class_number
+            WHERE g.assignment_id = $1 AND g.submission_key IS NOT NULL AND uc.is_instructor = FALSE;",
+        )
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r
+                .iter()
+                .map(|r| (r.get("task_id"), r.get("user_id"), r.get("submission_key")))
+                .collect()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The template and its filename of every task of the assignment that has one, by task id
+pub async fn templates(assignment_id: i32) -> Result<HashMap<i32, (Vec<u8>, String)>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT id, template, template_filename FROM tasks
+            WHERE assignment_id = $1 AND template IS NOT NULL;",
+        )
+        .bind(assignment_id)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r
+                .iter()
+                .map(|r| {
+                    let filename: Option<String> = r.get("template_filename");
+                    (
+                        r.get("id"),
+                        (r.get("template"), filename.unwrap_or_default()),
+                    )
+                })
+                .collect()),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+pub async fn add_pairs(report_id: i32, task_id: i32, pairs: Vec<Pair>) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        for pair in pairs {
+            if let Err(e) = sqlx::query(
+                "INSERT INTO similarity_pairs (report_id, task_id, user_a, user_b, similarity, matches)
+                VALUES ($1, $2, $3, $4, $5, $6);",
+            )
+            .bind(report_id)
+            .bind(task_id)
+            .bind(pair.user_a)
+            .bind(pair.user_b)
+            .bind(pair.similarity)
+            .bind(serde_json::to_string(&pair.matches).unwrap())
+            .execute(&mut *transaction)
+            .await
+            {
+                return Err(format!("{e}"));
+            }
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The latest report on the assignment, with the pairs at least `min_similarity` alike. `None` =>
+/// the assignment has never been checked.
+pub async fn latest_report(
+    assignment_id: i32,
+    min_similarity: f32,
+) -> Result<Option<SimilarityReport>, String> {
+    postgres_lock!(transaction, {
+        let report = match sqlx::query(
+            "SELECT id, status, error, requested_at, finished_at FROM similarity_reports
+            WHERE assignment_id = $1
+            ORDER BY id DESC
+            LIMIT 1;",
+        )
+        .bind(assignment_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let report_id: i32 = report.get("id");
+        let pair_rows = match sqlx::query(
+            "SELECT p.task_id, a.user_name student_a, b.user_name student_b, p.similarity, p.matches
+            FROM similarity_pairs p
+            JOIN users a ON a.id = p.user_a
+            JOIN users b ON b.id = p.user_b
+            WHERE p.report_id = $1 AND p.similarity >= $2
+            ORDER BY p.similarity DESC, p.task_id;",
+        )
+        .bind(report_id)
+        .bind(min_similarity)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let pairs = pair_rows
+            .iter()
+            .map(|r| SimilarPair {
+                task_id: r.get("task_id"),
+                student_a: r.get("student_a"),
+                student_b: r.get("student_b"),
+                similarity: r.get("similarity"),
+                matches: serde_json::from_str::<Vec<MatchedRegion>>(r.get("matches"))
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        return Ok(Some(SimilarityReport {
+            report_id,
+            status: report.get("status"),
+            requested_at: report.get::<DateTime<Utc>, _>("requested_at").to_rfc3339(),
+            finished_at: report
+                .get::<Option<DateTime<Utc>>, _>("finished_at")
+                .map(|t| t.to_rfc3339()),
+            error: report.get("error"),
+            pairs,
+        }));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
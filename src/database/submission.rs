@@ -0,0 +1,386 @@
+//! Contains database operations associated with the history of a student's submissions
+//!
+//! Every submission of a task is kept in `submissions` as a numbered attempt, with its results once
+//! it's graded. `user_task_grade` holds the attempt that counts, which is always the latest.
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, Row, postgres::PgRow};
+
+use crate::{
+    database::POSTGRES,
+    model::{
+        submission_history::{Attempt, SubmissionHistory},
+        submission_response::{ResultVisibility, SubmissionResponse},
+    },
+    postgres_lock,
+};
+
+/// Selects, for a row of `tasks`, the most submissions user `$1` may make to it (`max_attempts`,
+/// NULL => unlimited) and how many they've made (`used_attempts`). A limit set for the student
+/// wins over the task's, which wins over the assignment's. Attempts that couldn't be graded
+/// because of the grader don't count.
+pub const ATTEMPTS: &str = "CASE
+        WHEN EXISTS (SELECT 1 FROM attempt_overrides o WHERE o.user_id = $1 AND o.task_id = tasks.id)
+        THEN (SELECT o.max_attempts FROM attempt_overrides o WHERE o.user_id = $1 AND o.task_id = tasks.id)
+        ELSE COALESCE(tasks.max_attempts, (SELECT a.max_attempts FROM assignments a WHERE a.id = tasks.assignment_id))
+    END max_attempts,
+    (SELECT COUNT(*) FROM submissions s
+        WHERE s.user_id = $1 AND s.task_id = tasks.id AND s.error IS NULL) used_attempts";
+
+/// Submissions left from a row selected with [`ATTEMPTS`]. `None` => unlimited.
+pub fn remaining(row: &PgRow) -> Option<i32> {
+    let max_attempts: Option<i32> = row.get("max_attempts");
+    let used: i64 = row.get("used_attempts");
+    max_attempts.map(|max| (max as i64 - used).max(0) as i32)
+}
+
+/// Submissions the student has left for the task. `None` => unlimited.
+pub async fn remaining_attempts(user_id: i32, task_id: i32) -> Result<Option<i32>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(&format!("SELECT {ATTEMPTS} FROM tasks WHERE id = $2;"))
+            .bind(user_id)
+            .bind(task_id)
+            .fetch_one(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(remaining(&r)),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Seconds until the student may submit to the task again. `None` => they may now. Attempts that
+/// couldn't be graded because of the grader don't start a cooldown.
+pub async fn cooldown_remaining(user_id: i32, task_id: i32) -> Result<Option<i64>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT CEIL(EXTRACT(EPOCH FROM
+                    MAX(s.submitted_at) + make_interval(mins => t.cooldown_minutes) - NOW()
+                ))::BIGINT retry_after
+            FROM tasks t
+            JOIN submissions s ON s.task_id = t.id AND s.user_id = $1 AND s.error IS NULL
+            WHERE t.id = $2 AND t.cooldown_minutes IS NOT NULL
+            GROUP BY t.cooldown_minutes;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r
+                .and_then(|r| r.get::<Option<i64>, _>("retry_after"))
+                .filter(|secs| *secs > 0)),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Sets how many submissions a student of the class may make to the task, in place of the task's
+/// limit. `max_attempts` of `None` => unlimited. `Ok(false)` => there is no such student.
+pub async fn set_attempt_override(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    username: &str,
+    max_attempts: Option<i32>,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) = student_of(
+            &mut transaction,
+            class_number,
+            assignment_id,
+            task_id,
+            username,
+        )
+        .await?
+        else {
+            return Ok(false);
+        };
+
+        if let Err(e) = sqlx::query(
+            "INSERT INTO attempt_overrides (user_id, task_id, max_attempts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, task_id) DO UPDATE SET max_attempts = EXCLUDED.max_attempts;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(max_attempts)
+        .execute(&mut *transaction)
+        .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(true);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Puts a student back on the task's own limit. `Ok(false)` => they had no override.
+pub async fn remove_attempt_override(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    username: &str,
+) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) = student_of(
+            &mut transaction,
+            class_number,
+            assignment_id,
+            task_id,
+            username,
+        )
+        .await?
+        else {
+            return Ok(false);
+        };
+
+        let removed =
+            match sqlx::query("DELETE FROM attempt_overrides WHERE user_id = $1 AND task_id = $2;")
+                .bind(user_id)
+                .bind(task_id)
+                .execute(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.rows_affected() > 0,
+                Err(e) => return Err(format!("{e}")),
+            };
+
+        transaction.commit().await.unwrap();
+        return Ok(removed);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Copies the results of the attempt that counts into its place in the history
+pub async fn copy_results(
+    conn: &mut PgConnection,
+    user_id: i32,
+    task_id: i32,
+) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE submissions s
+        SET json_results = g.json_results, grade = g.grade, error = g.error, graded_at = g.graded_at,
+            toolchain_version = g.toolchain_version
+        FROM user_task_grade g
+        WHERE g.user_id = $1 AND g.task_id = $2
+            AND s.user_id = g.user_id AND s.task_id = g.task_id AND s.attempt = g.attempt;",
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .execute(conn)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Every attempt the student made at the task
+pub async fn history(user_id: i32, task_id: i32) -> Result<SubmissionHistory, String> {
+    postgres_lock!(transaction, {
+        return attempts(&mut transaction, user_id, task_id).await;
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Every attempt a student of the class made at the task. `None` => there is no such student.
+pub async fn history_of(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    username: &str,
+) -> Result<Option<SubmissionHistory>, String> {
+    postgres_lock!(transaction, {
+        let Some(user_id) = student_of(
+            &mut transaction,
+            class_number,
+            assignment_id,
+            task_id,
+            username,
+        )
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        return attempts(&mut transaction, user_id, task_id).await.map(Some);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The id of `username`, as long as they're in the class and the task is one of its assignment's
+async fn student_of(
+    conn: &mut PgConnection,
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    username: &str,
+) -> Result<Option<i32>, String> {
+    match sqlx::query(
+        "SELECT u.id
+        FROM users u
+        JOIN user_class uc ON uc.user_id = u.id
+        JOIN assignment_class ac ON ac.class_number = uc.class_number
+        JOIN tasks t ON t.assignment_id = ac.assignment_id
+        WHERE u.user_name = $1 AND uc.class_number = $2 AND ac.assignment_id = $3 AND t.id = $4;",
+    )
+    .bind(username)
+    .bind(class_number)
+    .bind(assignment_id)
+    .bind(task_id)
+    .fetch_optional(conn)
+    .await
+    {
+        Ok(r) => Ok(r.map(|r| r.get("id"))),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+async fn attempts(
+    conn: &mut PgConnection,
+    user_id: i32,
+    task_id: i32,
+) -> Result<SubmissionHistory, String> {
+    let counted_attempt: Option<i32> = match sqlx::query(
+        "SELECT attempt FROM user_task_grade WHERE user_id = $1 AND task_id = $2;",
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .fetch_optional(&mut *conn)
+    .await
+    {
+        Ok(r) => r.and_then(|r| r.get("attempt")),
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let (max_attempts, remaining_attempts) =
+        match sqlx::query(&format!("SELECT {ATTEMPTS} FROM tasks WHERE id = $2;"))
+            .bind(user_id)
+            .bind(task_id)
+            .fetch_one(&mut *conn)
+            .await
+        {
+            Ok(r) => (r.get("max_attempts"), remaining(&r)),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+    let rows = match sqlx::query(
+        "SELECT attempt, submission_lang, submitted_at, was_late, sha256, grade, error, graded_at
+        FROM submissions
+        WHERE user_id = $1 AND task_id = $2
+        ORDER BY attempt;",
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .fetch_all(&mut *conn)
+    .await
+    {
+        Ok(r) => r,
+        Err(e) => return Err(format!("{e}")),
+    };
+
+    let attempts = rows
+        .iter()
+        .map(|r| {
+            let submitted_at: Option<DateTime<Utc>> = r.get("submitted_at");
+            let graded_at: Option<DateTime<Utc>> = r.get("graded_at");
+            Attempt {
+                attempt: r.get("attempt"),
+                lang: r.get("submission_lang"),
+                submitted_at: submitted_at.map(|t| t.to_string()),
+                was_late: r.get("was_late"),
+                sha256: r.get("sha256"),
+                grade: r.get("grade"),
+                error: r.get("error"),
+                graded_at: graded_at.map(|t| t.to_string()),
+            }
+        })
+        .collect();
+
+    Ok(SubmissionHistory {
+        counted_attempt,
+        max_attempts,
+        remaining_attempts,
+        attempts,
+    })
+}
+
+/// The storage key of the archive the student submitted as an attempt at the task, and that
+/// attempt's number. `attempt` of `None` => the latest. `None` => there is no such attempt.
+pub async fn archive(
+    user_id: i32,
+    task_id: i32,
+    attempt: Option<i32>,
+) -> Result<Option<(i32, String)>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query(
+            "SELECT attempt, submission_key
+            FROM submissions
+            WHERE user_id = $1 AND task_id = $2 AND ($3::INTEGER IS NULL OR attempt = $3)
+                AND submission_key IS NOT NULL
+            ORDER BY attempt DESC
+            LIMIT 1;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(attempt)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(r) => Ok(r.map(|r| (r.get("attempt"), r.get("submission_key")))),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// The results of one of the student's attempts, as much of them as the assignment's result
+/// visibility shows. `None` => there is no such attempt, or it hasn't been graded.
+pub async fn attempt_results(
+    user_id: i32,
+    task_id: i32,
+    attempt: i32,
+) -> Result<Option<SubmissionResponse>, String> {
+    postgres_lock!(transaction, {
+        let row = match sqlx::query(
+            "SELECT s.json_results, a.result_visibility, s.error
+            FROM submissions s
+            JOIN assignments a ON a.id = s.assignment_id
+            WHERE s.user_id = $1 AND s.task_id = $2 AND s.attempt = $3
+                AND s.json_results IS NOT NULL;",
+        )
+        .bind(user_id)
+        .bind(task_id)
+        .bind(attempt)
+        .fetch_optional(&mut *transaction)
+        .await
+        {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        let json_results: Vec<u8> = row.get("json_results");
+        let visibility: String = row.get("result_visibility");
+        let results: SubmissionResponse = serde_json::from_slice(&json_results).unwrap();
+        return Ok(Some(
+            results
+                .redact(ResultVisibility::from(visibility))
+                .with_error(row.get("error")),
+        ));
+    });
+
+    Err("Failed to acquire database lock".into())
+}
@@ -39,6 +39,150 @@ pub async fn get_user_from_session(session_base: impl AsRef<[u8]>) -> Option<i32
     None
 }
 
+pub async fn set_preferred_language(user_id: i32, lang: String) -> Result<(), String> {
+    postgres_lock!(transaction, {
+        if let Err(e) = sqlx::query("UPDATE users SET preferred_language = $1 WHERE id = $2;")
+            .bind(lang)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("{e}"));
+        }
+
+        transaction.commit().await.unwrap();
+        return Ok(());
+    });
+
+    Err("Failed to acquire transaction lock".into())
+}
+
+/// Returns the ids of every admin account
+pub async fn admin_ids() -> Result<Vec<i32>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query("SELECT id FROM users WHERE is_admin = TRUE;")
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Ok(rows) => return Ok(rows.iter().map(|r| r.get("id")).collect()),
+            Err(e) => return Err(format!("{e}")),
+        }
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Returns the ids of the class's instructors
+pub async fn instructor_ids(class_number: &str) -> Result<Vec<i32>, String> {
+    postgres_lock!(transaction, {
+        match sqlx::query(
+            "SELECT user_id FROM user_class WHERE class_number = $1 AND is_instructor = TRUE;",
+        )
+        .bind(class_number)
+        .fetch_all(&mut *transaction)
+        .await
+        {
+            Ok(rows) => return Ok(rows.iter().map(|r| r.get("user_id")).collect()),
+            Err(e) => return Err(format!("{e}")),
+        }
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Creates a user directly, bypassing the signup endpoint. Used by the admin CLI.
+pub async fn create_user(
+    first_name: String,
+    last_name: String,
+    user_name: String,
+    email: String,
+    pass: String,
+    is_admin: bool,
+) -> Result<i32, String> {
+    let hash = create_hash(user_name.clone(), pass);
+
+    postgres_lock!(transaction, {
+        let id: i32 = match sqlx::query(
+            "INSERT INTO users (first_name, last_name, user_name, email, is_admin) VALUES ($1, $2, $3, $4, $5) RETURNING id;",
+        )
+        .bind(first_name)
+        .bind(last_name)
+        .bind(user_name)
+        .bind(email)
+        .bind(is_admin)
+        .fetch_one(&mut *transaction)
+        .await
+        {
+            Ok(r) => r.get("id"),
+            Err(e) => return Err(format!("Could not insert into database: {e}")),
+        };
+
+        if let Err(e) = sqlx::query("INSERT INTO user_auth (hash, user_id) VALUES ($1, $2);")
+            .bind(hash)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add to authentication table: {e}"));
+        }
+
+        if let Err(e) = transaction.commit().await {
+            return Err(format!("Could not commit database transaction: {e}"));
+        }
+
+        return Ok(id);
+    });
+
+    Err("Failed to acquire transaction lock".into())
+}
+
+/// Replaces a user's password and ends all of their sessions. Returns false if the user does not exist.
+pub async fn reset_password(user_name: String, pass: String) -> Result<bool, String> {
+    let hash = create_hash(user_name.clone(), pass);
+
+    postgres_lock!(transaction, {
+        let id: i32 = match sqlx::query("SELECT id FROM users WHERE user_name = $1;")
+            .bind(&user_name)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some(r)) => r.get("id"),
+            Ok(None) => return Ok(false),
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        for statement in [
+            "DELETE FROM user_auth WHERE user_id = $1;",
+            "DELETE FROM user_session WHERE user_id = $1;",
+        ] {
+            if let Err(e) = sqlx::query(statement)
+                .bind(id)
+                .execute(&mut *transaction)
+                .await
+            {
+                return Err(format!("{e}"));
+            }
+        }
+
+        if let Err(e) = sqlx::query("INSERT INTO user_auth (hash, user_id) VALUES ($1, $2);")
+            .bind(hash)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await
+        {
+            return Err(format!("Could not add to authentication table: {e}"));
+        }
+
+        if let Err(e) = transaction.commit().await {
+            return Err(format!("Could not commit database transaction: {e}"));
+        }
+
+        return Ok(true);
+    });
+
+    Err("Failed to acquire transaction lock".into())
+}
+
 /// Registers a new user provided their credentials.
 pub async fn register_user(new_user: ClientRequest) -> Result<[u8; 16], String> {
     let Some((user_name, pass)) = new_user.get_login() else {
@@ -140,3 +284,39 @@ pub async fn login_user(user: ClientRequest) -> Result<[u8; 16], String> {
 
     Err("Failed to acquire transaction lock".into())
 }
+
+/// The username of whoever has the SIS ID, if anyone does
+pub async fn sis_id_owner(sis_id: &str) -> Result<Option<String>, String> {
+    postgres_lock!(transaction, {
+        return match sqlx::query("SELECT user_name FROM users WHERE sis_id = $1;")
+            .bind(sis_id)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(r) => Ok(r.map(|r| r.get("user_name"))),
+            Err(e) => Err(format!("{e}")),
+        };
+    });
+
+    Err("Failed to acquire database lock".into())
+}
+
+/// Sets or, with `None`, clears the user's SIS ID. Returns `Ok(false)` if there is no such user.
+pub async fn set_sis_id(user_name: String, sis_id: Option<String>) -> Result<bool, String> {
+    postgres_lock!(transaction, {
+        let updated = match sqlx::query("UPDATE users SET sis_id = $1 WHERE user_name = $2;")
+            .bind(sis_id)
+            .bind(user_name)
+            .execute(&mut *transaction)
+            .await
+        {
+            Ok(r) => r.rows_affected() > 0,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+        transaction.commit().await.unwrap();
+        return Ok(updated);
+    });
+
+    Err("Failed to acquire database lock".into())
+}
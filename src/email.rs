@@ -0,0 +1,177 @@
+//! Outbound email, rendered from admin-editable templates
+//!
+//! Every kind of email has a built-in template that admins can override through the admin
+//! endpoints. Templates use `{{variable}}` placeholders. Besides its own variables, every template
+//! can use the deployment's branding from the configuration: `institution_name`,
+//! `institution_logo_url`, and `institution_url`. Values are HTML-escaped before being placed in
+//! the body, which is sent as HTML.
+//!
+//! Nothing is sent unless `smtp_url` is set in the configuration.
+
+use std::sync::Mutex;
+
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::header::ContentType,
+};
+use tracing::error;
+
+use crate::{config, database, model::email_template::EmailTemplate};
+
+#[derive(Debug, Clone, Copy)]
+pub enum EmailKind {
+    GradeNotification,
+}
+
+impl EmailKind {
+    pub const ALL: [EmailKind; 1] = [EmailKind::GradeNotification];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::GradeNotification => "grade_notification",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == name)
+    }
+
+    /// The variables this kind of email is sent with, not counting the branding variables
+    fn variables(&self) -> &'static [&'static str] {
+        match self {
+            EmailKind::GradeNotification => {
+                &["first_name", "assignment_name", "class_number", "score"]
+            }
+        }
+    }
+
+    fn default_subject(&self) -> &'static str {
+        match self {
+            EmailKind::GradeNotification => "{{assignment_name}} has been graded",
+        }
+    }
+
+    fn default_body(&self) -> &'static str {
+        match self {
+            EmailKind::GradeNotification => {
+                "<p><img src=\"{{institution_logo_url}}\" alt=\"{{institution_name}}\"></p>
+<p>Hi {{first_name}},</p>
+<p>Your submission to <b>{{assignment_name}}</b> in {{class_number}} has been graded. You scored {{score}}.</p>
+<p><a href=\"{{institution_url}}\">{{institution_name}}</a></p>"
+            }
+        }
+    }
+
+    /// Returns the template currently in effect: the admin's override, or the built-in default
+    pub async fn template(&self) -> Result<EmailTemplate, String> {
+        let custom = database::email::get_email_template(self.as_str()).await?;
+        let customized = custom.is_some();
+        let (subject, body) =
+            custom.unwrap_or((self.default_subject().into(), self.default_body().into()));
+
+        let variables = [
+            "institution_name",
+            "institution_logo_url",
+            "institution_url",
+        ]
+        .iter()
+        .chain(self.variables())
+        .map(|v| v.to_string())
+        .collect();
+
+        Ok(EmailTemplate {
+            template_name: self.as_str().into(),
+            subject,
+            body,
+            customized,
+            variables,
+        })
+    }
+}
+
+/// The transport last built and the URL it was built from, so its connections are reused
+static MAILER: Mutex<Option<(String, AsyncSmtpTransport<Tokio1Executor>)>> = Mutex::new(None);
+
+/// Returns the transport for `smtp_url`, building it only if the URL has changed
+fn mailer(smtp_url: &str) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let mut mailer = MAILER.lock().unwrap();
+    if let Some((url, transport)) = mailer.as_ref()
+        && url == smtp_url
+    {
+        return Ok(transport.clone());
+    }
+
+    let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+        .map_err(|e| format!("{e}"))?
+        .build();
+    *mailer = Some((smtp_url.to_string(), transport.clone()));
+    Ok(transport)
+}
+
+/// Replaces every `{{name}}` in the template with its value
+fn render(template: &str, vars: &[(&str, String)], escape: bool) -> String {
+    vars.iter()
+        .fold(template.to_string(), |acc, (name, value)| {
+            let value = if escape {
+                ammonia::clean_text(value)
+            } else {
+                value.clone()
+            };
+            acc.replace(&format!("{{{{{name}}}}}"), &value)
+        })
+}
+
+/// Renders and sends an email. Does nothing if no SMTP server is configured.
+pub async fn send(kind: EmailKind, to: &str, vars: Vec<(&str, String)>) -> Result<(), String> {
+    let config = config::get();
+    let Some(smtp_url) = config.smtp_url else {
+        return Ok(());
+    };
+
+    let template = kind.template().await?;
+
+    let mut vars = vars;
+    vars.push(("institution_name", config.institution_name));
+    vars.push(("institution_logo_url", config.institution_logo_url));
+    vars.push(("institution_url", config.institution_url));
+
+    let message = Message::builder()
+        .from(config.email_from.parse().map_err(|e| format!("{e}"))?)
+        .to(to.parse().map_err(|e| format!("{e}"))?)
+        .subject(render(&template.subject, &vars, false))
+        .header(ContentType::TEXT_HTML)
+        .body(render(&template.body, &vars, true))
+        .map_err(|e| format!("{e}"))?;
+
+    match mailer(&smtp_url)?.send(message).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Emails a student that one of their submissions has been graded
+pub async fn send_grade_notification(user_id: i32, task_id: i32, score: f32) {
+    if config::get().smtp_url.is_none() {
+        return;
+    }
+
+    let (email, first_name, assignment_name, class_number) =
+        match database::email::grade_email_details(user_id, task_id).await {
+            Ok(Some(details)) => details,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Could not look up grade email details: {e}");
+                return;
+            }
+        };
+
+    let vars = vec![
+        ("first_name", first_name),
+        ("assignment_name", assignment_name),
+        ("class_number", class_number),
+        ("score", format!("{:.0}%", score * 100.0)),
+    ];
+
+    if let Err(e) = send(EmailKind::GradeNotification, &email, vars).await {
+        error!("Could not send grade email to {email}: {e}");
+    }
+}
@@ -10,13 +10,14 @@ use axum::{
 };
 
 use crate::{
-    OK_JSON,
+    OK_JSON, container,
     database::{self, auth::Session},
     model::request::ClientRequest,
 };
 
 pub mod admin;
 pub mod instructor;
+pub mod runner;
 pub mod student;
 
 /// Adds the user to a class as a student, using the provided join code
@@ -110,22 +111,18 @@ pub async fn list_all_students(class_number: Option<Path<String>>) -> Response<B
         .unwrap()
 }
 
-/// Returns a list of languages the backend supports
+/// Returns a list of languages the backend supports, with the name, version, commands and default
+/// limits from each one's manifest
 /// 
 /// This way the frontend does not need to be statically updated with languages when new ones are added
 pub async fn supported_languages() -> Response<Body> {
-    let Ok(dir) = std::fs::read_dir("dockerfiles") else {
+    let Ok(items) = container::languages() else {
         return Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Internal Server Error.".into())
             .unwrap();
     };
 
-    let items = dir
-        .filter_map(|f| f.ok())
-        .filter_map(|f| f.file_name().into_string().ok())
-        .collect::<Vec<String>>();
-
     let item_json = serde_json::to_string(&items).unwrap();
 
     Response::builder()
@@ -134,6 +131,48 @@ pub async fn supported_languages() -> Response<Body> {
         .unwrap()
 }
 
+/// Sets the language pre-selected for the user when they open an assignment
+pub async fn set_preferred_language(
+    parts: Parts,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let ClientRequest { lang: Some(lang), .. } = client_req else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    if !container::supported_languages()
+        .map(|l| l.contains(&lang))
+        .unwrap_or(false)
+    {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Unsupported language.".into())
+            .unwrap();
+    }
+
+    let auth_header = parts.headers.get(&AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(auth_header)
+        .await
+        .unwrap();
+
+    match database::user::set_preferred_language(user_id, lang).await {
+        Ok(()) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Server Error.".into())
+                .unwrap()
+        }
+    }
+}
+
 /// Logins a user provided their username and password
 /// 
 /// Returns a session token to be used for subsequent operations. By default, this token expires after an hour.
@@ -180,3 +219,25 @@ pub async fn signup(Json(signup_req): Json<ClientRequest>) -> Response<Body> {
         }
     }
 }
+
+/// Returns the user's unseen notifications (e.g. finished exports), marking them as seen
+pub async fn get_notifications(parts: Parts) -> Response<Body> {
+    let auth_header = parts.headers.get(&AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(auth_header)
+        .await
+        .unwrap();
+
+    match database::notification::take_notifications(user_id).await {
+        Ok(notifications) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&notifications).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Server Error.".into())
+                .unwrap()
+        }
+    }
+}
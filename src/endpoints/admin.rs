@@ -1,6 +1,27 @@
-use axum::{Json, body::Body, http::{Response, StatusCode}};
+use axum::{
+    Json,
+    body::Body,
+    extract::Query,
+    http::{
+        Response, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        request::Parts,
+    },
+};
+use tokio_util::io::ReaderStream;
 
-use crate::{OK_JSON, database, model::request::ClientRequest};
+use crate::{
+    EXPORT_TX, OK_JSON, config,
+    container::{self, Isolation},
+    database,
+    email::EmailKind,
+    export::ExportEntry,
+    model::{
+        deletion_summary::DeletionSummary,
+        language_info::AdminLanguageInfo,
+        request::{ClientRequest, DeleteQuery, EmailTemplateQuery, ExportQuery, MetricsQuery},
+    },
+};
 
 pub async fn create_class(Json(client_req): Json<ClientRequest>) -> Response<Body> {
     if let Err(e) = database::operations::new_class(client_req).await {
@@ -14,4 +35,586 @@ pub async fn create_class(Json(client_req): Json<ClientRequest>) -> Response<Bod
         .status(StatusCode::OK)
         .body(OK_JSON.into())
         .unwrap()
-}
\ No newline at end of file
+}
+
+/// Chooses whether a class is graded in containers or microVMs. An `isolation` of `default`
+/// returns it to the deployment's setting.
+pub async fn set_class_isolation(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    let (Some(class_number), Some(isolation)) = (client_req.class_number, client_req.isolation)
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing class_number or isolation.".into())
+            .unwrap();
+    };
+
+    let isolation = match isolation.as_str() {
+        "default" => None,
+        name => match Isolation::from_name(name) {
+            Some(i) => Some(i),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("isolation must be container, microvm or default.".into())
+                    .unwrap();
+            }
+        },
+    };
+
+    match database::operations::set_class_isolation(class_number, isolation).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such class.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set class isolation: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Caps how many of a class's submissions are graded at once, so a large class can't take every
+/// worker. A `grading_quota` of 0 removes the cap.
+pub async fn set_class_grading_quota(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    let (Some(class_number), Some(grading_quota)) =
+        (client_req.class_number, client_req.grading_quota)
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing class_number or grading_quota.".into())
+            .unwrap();
+    };
+
+    if grading_quota < 0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("grading_quota can't be negative.".into())
+            .unwrap();
+    }
+
+    let grading_quota = (grading_quota > 0).then_some(grading_quota);
+    match database::operations::set_class_grading_quota(class_number, grading_quota).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such class.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set class grading quota: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Records the user's id in the campus student information system, which grade exports for Canvas
+/// and Moodle identify students by. An empty `sis_id` clears it.
+pub async fn set_sis_id(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    let (Some(user_name), Some(sis_id)) = (client_req.user_name, client_req.sis_id) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing user_name or sis_id.".into())
+            .unwrap();
+    };
+
+    let sis_id = sis_id.trim().to_string();
+    if !sis_id.is_empty() {
+        match database::user::sis_id_owner(&sis_id).await {
+            Ok(Some(owner)) if owner != user_name => {
+                return Response::builder()
+                    .status(StatusCode::CONFLICT)
+                    .body(format!("{owner} already has that SIS ID.").into())
+                    .unwrap();
+            }
+            Ok(_) => (),
+            Err(e) => {
+                tracing::error!("Could not look up SIS ID: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap();
+            }
+        }
+    }
+
+    let sis_id = (!sis_id.is_empty()).then_some(sis_id);
+    match database::user::set_sis_id(user_name, sis_id).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such user.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set SIS ID: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists every language, disabled ones included, with how much each is used
+pub async fn list_languages() -> Response<Body> {
+    let languages = match container::all_languages() {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("Could not list languages: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    let mut usage = match database::language::usage_counts().await {
+        Ok(u) => u,
+        Err(e) => {
+            tracing::error!("Could not count language usage: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    let mut languages: Vec<AdminLanguageInfo> = languages
+        .into_iter()
+        .map(|lang| AdminLanguageInfo {
+            disabled: !container::language_enabled(&lang),
+            usage: usage.remove(&lang).unwrap_or_default(),
+            info: container::language_info(lang),
+        })
+        .collect();
+    languages.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(serde_json::to_string(&languages).unwrap().into())
+        .unwrap()
+}
+
+/// Adds a language from its Dockerfile and manifest, or replaces the definition of an existing
+/// one. Its base image is built in the background.
+pub async fn upload_language(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    let (Some(lang), Some(dockerfile)) = (client_req.lang, client_req.dockerfile) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing lang or dockerfile.".into())
+            .unwrap();
+    };
+
+    if !container::valid_language_name(&lang) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("lang may only contain lowercase letters, digits, _, - and +.".into())
+            .unwrap();
+    }
+
+    if dockerfile.trim().is_empty() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("The Dockerfile is empty.".into())
+            .unwrap();
+    }
+
+    let manifest = client_req.language_manifest.unwrap_or_default();
+    if let Err(e) = container::install_language(&lang, &dockerfile, &manifest) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    tracing::info!("Language {lang} uploaded");
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
+
+/// Stops new submissions in a language. Submissions already made can still be regraded.
+pub async fn disable_language(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    set_language_disabled(client_req, true)
+}
+
+/// Accepts new submissions in a disabled language again
+pub async fn enable_language(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    set_language_disabled(client_req, false)
+}
+
+fn set_language_disabled(client_req: ClientRequest, disabled: bool) -> Response<Body> {
+    let Some(lang) = client_req.lang else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing lang.".into())
+            .unwrap();
+    };
+
+    match container::set_language_disabled(&lang, disabled) {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such language.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not change language {lang}: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Deletes a class and everything in it. Pass `dry_run=true` to only see what would be removed.
+pub async fn delete_class(Query(query): Query<DeleteQuery>) -> Response<Body> {
+    let Some(class_number) = query.class_number else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing class_number query parameter.".into())
+            .unwrap();
+    };
+
+    deletion_response(database::deletion::delete_class(class_number, query.dry_run).await)
+}
+
+/// Deletes an assignment and its submissions. Pass `dry_run=true` to only see what would be removed.
+pub async fn delete_assignment(Query(query): Query<DeleteQuery>) -> Response<Body> {
+    let Some(assignment_id) = query.assignment_id else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing assignment_id query parameter.".into())
+            .unwrap();
+    };
+
+    deletion_response(database::deletion::delete_assignment(assignment_id, query.dry_run).await)
+}
+
+/// Deletes a user and their submissions. Pass `dry_run=true` to only see what would be removed.
+pub async fn delete_user(Query(query): Query<DeleteQuery>) -> Response<Body> {
+    let Some(user_name) = query.user_name else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing user_name query parameter.".into())
+            .unwrap();
+    };
+
+    deletion_response(database::deletion::delete_user(user_name, query.dry_run).await)
+}
+
+/// Re-reads the configuration file and language manifests, the same as sending the server a SIGHUP
+pub async fn reload_config() -> Response<Body> {
+    match config::reload() {
+        Ok(()) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not reload configuration: {e}");
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(e.into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists every email template with the variables it can use
+pub async fn list_email_templates() -> Response<Body> {
+    let mut templates = vec![];
+    for kind in EmailKind::ALL {
+        match kind.template().await {
+            Ok(t) => templates.push(t),
+            Err(e) => {
+                tracing::error!("Could not retrieve email template: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap();
+            }
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(serde_json::to_string(&templates).unwrap().into())
+        .unwrap()
+}
+
+pub async fn set_email_template(Json(client_req): Json<ClientRequest>) -> Response<Body> {
+    let (Some(template_name), Some(subject), Some(body)) = (
+        client_req.email_template_name,
+        client_req.email_subject,
+        client_req.email_body,
+    ) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing email_template_name, email_subject, or email_body.".into())
+            .unwrap();
+    };
+
+    let Some(kind) = EmailKind::from_name(&template_name) else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Unknown email template.".into())
+            .unwrap();
+    };
+
+    if let Err(e) = database::email::set_email_template(kind.as_str(), subject, body).await {
+        tracing::error!("Could not set email template: {e}");
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Error".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
+
+/// Reverts an email template to its built-in default
+pub async fn reset_email_template(Query(query): Query<EmailTemplateQuery>) -> Response<Body> {
+    let Some(kind) = query
+        .template_name
+        .as_deref()
+        .and_then(EmailKind::from_name)
+    else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Unknown email template.".into())
+            .unwrap();
+    };
+
+    if let Err(e) = database::email::reset_email_template(kind.as_str()).await {
+        tracing::error!("Could not reset email template: {e}");
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Error".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
+
+/// Reports how many database connections are open and in use
+pub async fn pool_status() -> Response<Body> {
+    match database::pool_stats().await {
+        Some(stats) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&stats).unwrap().into())
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("Database not initialized.".into())
+            .unwrap(),
+    }
+}
+
+/// Returns how full the grading queue is, and what each worker is doing
+pub async fn grading_queue_status() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(serde_json::to_string(&container::queue_stats().await).unwrap().into())
+        .unwrap()
+}
+
+/// Reports what the janitor has cleaned up since start-up
+pub async fn janitor_status() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(serde_json::to_string(&container::janitor_stats()).unwrap().into())
+        .unwrap()
+}
+
+/// Reports how long grading has taken, by language, assignment and task, in one class or across
+/// every class
+pub async fn grading_metrics(Query(query): Query<MetricsQuery>) -> Response<Body> {
+    let days = query.days();
+    if days < 1 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("days must be at least 1.".into())
+            .unwrap();
+    }
+
+    match database::grading_job::metrics(query.class_number, days).await {
+        Ok(metrics) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&metrics).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Queues an export of the class's submissions, results, and timing data with students
+/// pseudonymized, for research use. The admin is notified with a download link when it is ready.
+pub async fn request_research_export(
+    Query(query): Query<ExportQuery>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(class_number) = query.class_number else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing class_number query parameter.".into())
+            .unwrap();
+    };
+
+    match database::operations::class_exists(class_number.clone()).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Not Found.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match database::export::export_in_progress(user_id).await {
+        Ok(false) => (),
+        Ok(true) => {
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body("An export is already being prepared. You will be notified when it is ready.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let job_id =
+        match database::export::create_export_job(user_id, class_number.clone(), None).await {
+            Ok(j) => j,
+            Err(e) => {
+                tracing::error!(e);
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap();
+            }
+        };
+
+    let entry = ExportEntry::research(job_id, user_id, class_number);
+
+    if let Some(tx) = EXPORT_TX.get()
+        && let Ok(perm) = tx.try_reserve()
+    {
+        perm.send(entry);
+    } else {
+        let _ = database::export::fail_export_job(job_id, "Export queue full".into()).await;
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("Too many exports are queued. Try again later.".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(format!(r#"{{ "job_id": {job_id} }}"#).into())
+        .unwrap()
+}
+
+pub async fn download_export(Query(query): Query<ExportQuery>) -> Response<Body> {
+    let (Some(class_number), Some(download_token)) = (query.class_number, query.download_token)
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing class_number or download_token query parameter.".into())
+            .unwrap();
+    };
+
+    let archive_path =
+        match database::export::get_export_archive(class_number, download_token).await {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Export not found or link expired.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!(e);
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap();
+            }
+        };
+
+    let Ok(file) = tokio::fs::File::open(archive_path).await else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Export not found or link expired.".into())
+            .unwrap();
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/zip")
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .unwrap()
+}
+
+fn deletion_response(result: Result<Option<DeletionSummary>, String>) -> Response<Body> {
+    match result {
+        Ok(Some(summary)) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&summary).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not delete: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
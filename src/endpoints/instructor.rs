@@ -1,11 +1,35 @@
 use axum::{
     Json,
     body::Body,
-    extract::Path,
-    http::{Response, StatusCode, header::CONTENT_TYPE},
+    extract::{Path, Query},
+    http::{
+        Response, StatusCode,
+        header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE},
+        request::Parts,
+    },
 };
+use base64::Engine;
+use tokio_util::io::ReaderStream;
 
-use crate::{OK_JSON, database, model::request::ClientRequest};
+use crate::{
+    EXPORT_TX, OK_JSON,
+    container::{self, Workdir},
+    csv, database,
+    export::ExportEntry,
+    model::{
+        assignment_archive::{ARCHIVE_FORMAT_VERSION, AssignmentArchive},
+        curve::Curve,
+        gradebook::Gradebook,
+        honor::HonorPledgeMode,
+        request::{
+            AttemptOverride, ClientRequest, CsvFormat, CsvQuery, GradeOverride, MetricsQuery,
+            SimilarityQuery,
+        },
+        test_method::TestMethod,
+        validation::AssignmentValidation,
+    },
+    similarity, test_import,
+};
 
 pub async fn add_instructor(Json(client_req): Json<ClientRequest>) -> Response<Body> {
     if let Err(e) = database::operations::add_instructor(client_req).await {
@@ -55,6 +79,357 @@ pub async fn download_submission(Path(path_params): Path<Vec<String>>) -> Respon
         .unwrap()
 }
 
+/// Returns what the build printed the last time the student's submission of the task failed to
+/// compile
+pub async fn build_log(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id, task_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::grading_job::latest_build_log(class_number, assignment_id, task_id, username)
+        .await
+    {
+        Ok(Some(build_log)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&build_log).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No failed build to show.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not retrieve build log: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists every attempt a student made at the task, and which one counts
+pub async fn list_attempts(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id, task_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::submission::history_of(class_number, assignment_id, task_id, username).await {
+        Ok(Some(history)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&history).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such student.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not retrieve submission history: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Sets how many submissions a student may make to the task, whatever the task allows others
+pub async fn set_attempt_override(
+    Path(path_params): Path<Vec<String>>,
+    Json(attempt_override): Json<AttemptOverride>,
+) -> Response<Body> {
+    let [class_number, assignment_id, task_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    if attempt_override.max_attempts.is_some_and(|n| n < 1) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("max_attempts must be at least 1.".into())
+            .unwrap();
+    }
+
+    match database::submission::set_attempt_override(
+        class_number,
+        assignment_id,
+        task_id,
+        username,
+        attempt_override.max_attempts,
+    )
+    .await
+    {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such student.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set attempt override: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Puts a student back on the task's own limit on submissions
+pub async fn remove_attempt_override(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id, task_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::submission::remove_attempt_override(
+        class_number,
+        assignment_id,
+        task_id,
+        username,
+    )
+    .await
+    {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("The student has no override for this task.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not remove attempt override: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Sets a student's score on the assignment, in place of the one their submissions earned
+pub async fn set_grade_override(
+    Path(path_params): Path<Vec<String>>,
+    Json(grade_override): Json<GradeOverride>,
+) -> Response<Body> {
+    let [class_number, assignment_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    if !grade_override.score.is_finite() || grade_override.score < 0.0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("score must be at least 0.".into())
+            .unwrap();
+    }
+
+    match database::category::set_grade_override(
+        class_number,
+        assignment_id,
+        username,
+        grade_override.score,
+        grade_override.reason,
+    )
+    .await
+    {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such student.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set grade override: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Puts a student back on the score their submissions earned
+pub async fn remove_grade_override(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::category::remove_grade_override(class_number, assignment_id, username).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("The student has no override for this assignment.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not remove grade override: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// The assignment's curve, or `null` if it has none
+pub async fn get_curve(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::curve::get_curve(class_number, assignment_id).await {
+        Ok(Some(curve)) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&curve).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such assignment.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Curves the assignment's scores, in place of any curve it had
+pub async fn set_curve(
+    Path(path_params): Path<Vec<String>>,
+    Json(curve): Json<Curve>,
+) -> Response<Body> {
+    if let Err(e) = curve.validate() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    update_curve(path_params, Some(curve)).await
+}
+
+/// Puts the assignment back on the scores as they were earned
+pub async fn remove_curve(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    update_curve(path_params, None).await
+}
+
+async fn update_curve(path_params: Vec<String>, curve: Option<Curve>) -> Response<Body> {
+    let [class_number, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::curve::set_curve(class_number, assignment_id, curve).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such assignment.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not set curve: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
 pub async fn generate_join_code(Path(class_number): Path<String>) -> Response<Body> {
     let join_code = rand::random_iter::<u8>()
         .take(6)
@@ -112,6 +487,133 @@ pub async fn retrieve_scores(Path(path_params): Path<Vec<String>>) -> Response<B
         .unwrap()
 }
 
+/// Score statistics, task pass rates and submissions per day of the assignment
+pub async fn assignment_stats(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    match database::gradebook::assignment_stats(assignment_id).await {
+        Ok(stats) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&stats).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Downloads the assignment's scores as CSV, laid out for the gradebook in `format`
+pub async fn export_csv(
+    Path(path_params): Path<Vec<String>>,
+    Query(query): Query<CsvQuery>,
+) -> Response<Body> {
+    let [class_number, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let filename = format!("{class_number}_{assignment_id}");
+
+    // The plain layout has room for peer scores, which the gradebook doesn't keep apart
+    if query.format == CsvFormat::Plain {
+        return match database::assignment::get_assignment_scores(assignment_id).await {
+            Ok(grades) => csv_response(&filename, csv::assignment_grades(&grades)),
+            Err(e) => {
+                tracing::error!(e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap()
+            }
+        };
+    }
+
+    match database::gradebook::gradebook(class_number.clone(), Some(assignment_id)).await {
+        Ok(gradebook) => {
+            let csv = gradebook_csv(&gradebook, query.format, class_number);
+            csv_response(&filename, csv)
+        }
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Downloads the scores of every assignment of the class as CSV, laid out for the gradebook in
+/// `format`
+pub async fn export_class_csv(
+    Path(class_number): Path<String>,
+    Query(query): Query<CsvQuery>,
+) -> Response<Body> {
+    match database::gradebook::gradebook(class_number.clone(), None).await {
+        Ok(gradebook) => csv_response(
+            &class_number,
+            gradebook_csv(&gradebook, query.format, &class_number),
+        ),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+fn gradebook_csv(gradebook: &Gradebook, format: CsvFormat, class_number: &str) -> String {
+    match format {
+        CsvFormat::Plain => csv::gradebook(gradebook),
+        CsvFormat::Canvas => csv::canvas(gradebook, class_number),
+        CsvFormat::Moodle => csv::moodle(gradebook),
+    }
+}
+
+fn csv_response(name: &str, csv: String) -> Response<Body> {
+    let filename = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}.csv\""),
+        )
+        .body(csv.into())
+        .unwrap()
+}
+
 pub async fn retrieve_full_assignment_info(Path(path_params): Path<Vec<String>>) -> Response<Body> {
     let [_, assignment_id, ..] = &path_params[..] else {
         return Response::builder()
@@ -145,37 +647,170 @@ pub async fn retrieve_full_assignment_info(Path(path_params): Path<Vec<String>>)
         .unwrap()
 }
 
-pub async fn add_assignment(
-    Path(path_params): Path<Vec<String>>,
-    Json(client_req): Json<ClientRequest>,
-) -> Response<Body> {
-    let [class_number, ..] = &path_params[..] else {
+/// Downloads the assignment as a JSON archive that can be imported into another class
+pub async fn export_assignment(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [_, assignment_id, ..] = &path_params[..] else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
-            .body("Bad Request.".into())
+            .body("Invalid URL parameters.".into())
             .unwrap();
     };
 
-    let ClientRequest {
-        assignment_name: Some(assignment_name),
-        assignment_description,
-        deadline: Some(deadline),
-        tasks: Some(tasks),
-        ..
-    } = client_req
-    else {
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
-            .body("Missing required fields assignment_name or deadline.".into())
+            .body("Invalid URL parameters.".into())
             .unwrap();
     };
 
-    if let Err(e) = database::assignment::add_assignment(
-        class_number.into(),
-        assignment_name,
-        assignment_description,
+    let archive = match database::assignment::export_assignment(assignment_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    let filename = archive
+        .assignment_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}.json\""),
+        )
+        .body(serde_json::to_string(&archive).unwrap().into())
+        .unwrap()
+}
+
+/// Creates a new assignment in the class from an archive made by `export_assignment`
+pub async fn import_assignment(
+    Path(class_number): Path<String>,
+    Json(archive): Json<AssignmentArchive>,
+) -> Response<Body> {
+    if archive.format_version > ARCHIVE_FORMAT_VERSION {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("The archive was made by a newer version of the server.".into())
+            .unwrap();
+    }
+
+    let validation = AssignmentValidation::check(&archive.tasks);
+    if !validation.is_valid() {
+        return Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(serde_json::to_string(&validation).unwrap().into())
+            .unwrap();
+    }
+
+    if let Err(e) = archive.settings.validate() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    let mut attachments = vec![];
+    for attachment in archive.attachments {
+        let Ok(data) = base64::prelude::BASE64_STANDARD.decode(attachment.data_base64) else {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Attachment {} is not valid base64.", attachment.filename).into())
+                .unwrap();
+        };
+        attachments.push((attachment.filename, data));
+    }
+
+    let assignment_id = match database::assignment::add_assignment(
+        class_number,
+        archive.assignment_name,
+        archive.assignment_description,
+        archive.deadline,
+        archive.settings,
+        archive.tasks,
+        attachments,
+    )
+    .await
+    {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Could not import assignment: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(
+            format!(
+                r#"{{ "assignment_id": {assignment_id}, "warnings": {} }}"#,
+                serde_json::to_string(&validation.warnings).unwrap()
+            )
+            .into(),
+        )
+        .unwrap()
+}
+
+pub async fn add_assignment(
+    Path(path_params): Path<Vec<String>>,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let [class_number, ..] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let ClientRequest {
+        assignment_name: Some(assignment_name),
+        assignment_description,
+        deadline: Some(deadline),
+        tasks: Some(tasks),
+        settings,
+        ..
+    } = client_req
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required fields assignment_name or deadline.".into())
+            .unwrap();
+    };
+
+    let validation = AssignmentValidation::check(&tasks);
+    if !validation.is_valid() {
+        return Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(serde_json::to_string(&validation).unwrap().into())
+            .unwrap();
+    }
+
+    if let Err(e) = settings.validate() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    if let Err(e) = database::assignment::add_assignment(
+        class_number.into(),
+        assignment_name,
+        assignment_description,
         deadline,
+        settings,
         tasks,
+        vec![],
     )
     .await
     {
@@ -188,7 +823,13 @@ pub async fn add_assignment(
 
     Response::builder()
         .status(StatusCode::OK)
-        .body(OK_JSON.into())
+        .body(
+            format!(
+                r#"{{ "message": "OK", "warnings": {} }}"#,
+                serde_json::to_string(&validation.warnings).unwrap()
+            )
+            .into(),
+        )
         .unwrap()
 }
 
@@ -215,6 +856,7 @@ pub async fn update_assignment(
         assignment_description,
         deadline: Some(deadline),
         tasks: Some(tasks),
+        settings,
         ..
     } = client_req
     else {
@@ -224,23 +866,1004 @@ pub async fn update_assignment(
             .unwrap();
     };
 
-    if let Err(e) = database::assignment::update_assignment(
+    let validation = AssignmentValidation::check(&tasks);
+    if !validation.is_valid() {
+        return Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(serde_json::to_string(&validation).unwrap().into())
+            .unwrap();
+    }
+
+    if let Err(e) = settings.validate() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    let flagged = match database::assignment::update_assignment(
         assignment_id,
         assignment_name,
         assignment_description,
         deadline,
+        settings,
         tasks,
     )
     .await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(
+            format!(
+                r#"{{ "flagged_for_regrade": {flagged}, "warnings": {} }}"#,
+                serde_json::to_string(&validation.warnings).unwrap()
+            )
+            .into(),
+        )
+        .unwrap()
+}
+
+/// Queues a background export of every submission for an assignment
+///
+/// The archive is built off the request path; the instructor is notified with a download link once it is ready.
+pub async fn request_export(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let [class_number, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match database::export::export_in_progress(user_id).await {
+        Ok(false) => (),
+        Ok(true) => {
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body("An export is already being prepared. You will be notified when it is ready.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    }
+
+    let job_id = match database::export::create_export_job(
+        user_id,
+        class_number.clone(),
+        Some(assignment_id),
+    )
+    .await
+    {
+        Ok(j) => j,
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    let entry = ExportEntry::new(job_id, user_id, class_number, assignment_id);
+
+    if let Some(tx) = EXPORT_TX.get()
+        && let Ok(perm) = tx.try_reserve()
+    {
+        perm.send(entry);
+    } else {
+        let _ = database::export::fail_export_job(job_id, "Export queue full".into()).await;
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("Too many exports are queued. Try again later.".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(format!(r#"{{ "job_id": {job_id} }}"#).into())
+        .unwrap()
+}
+
+pub async fn download_export(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, download_token] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let archive_path =
+        match database::export::get_export_archive(class_number.clone(), download_token.clone())
+            .await
+        {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Export not found or link expired.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!(e);
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap();
+            }
+        };
+
+    let Ok(file) = tokio::fs::File::open(archive_path).await else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Export not found or link expired.".into())
+            .unwrap();
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/zip")
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .unwrap()
+}
+
+/// Sets the class's honor pledge text and how often students must accept it
+pub async fn set_honor_pledge(
+    Path(class_number): Path<String>,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let ClientRequest {
+        honor_pledge,
+        honor_pledge_mode: Some(mode),
+        ..
+    } = client_req
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required field honor_pledge_mode.".into())
+            .unwrap();
+    };
+
+    let mode = HonorPledgeMode::from(mode);
+
+    if mode != HonorPledgeMode::None && honor_pledge.as_ref().is_none_or(|p| p.trim().is_empty()) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("An honor pledge requires pledge text.".into())
+            .unwrap();
+    }
+
+    if let Err(e) = database::honor::set_honor_pledge(class_number, honor_pledge, mode).await {
         tracing::error!(e);
         return Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Internal Error.".into())
             .unwrap();
-    };
+    }
 
     Response::builder()
         .status(StatusCode::OK)
         .body(OK_JSON.into())
         .unwrap()
 }
+
+/// Lists every honor pledge acknowledgement in the class, for academic integrity review
+pub async fn list_honor_acknowledgements(Path(class_number): Path<String>) -> Response<Body> {
+    match database::honor::list_acknowledgements(class_number).await {
+        Ok(acknowledgements) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&acknowledgements).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Reports how long grading the class's submissions has taken, by language, assignment and task
+pub async fn grading_metrics(
+    Path(class_number): Path<String>,
+    Query(query): Query<MetricsQuery>,
+) -> Response<Body> {
+    let days = query.days();
+    if days < 1 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("days must be at least 1.".into())
+            .unwrap();
+    }
+
+    match database::grading_job::metrics(Some(class_number), days).await {
+        Ok(metrics) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&metrics).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists the submissions to the class that the malware scanner turned away
+pub async fn list_malware_incidents(Path(class_number): Path<String>) -> Response<Body> {
+    match database::malware::list_incidents(class_number).await {
+        Ok(incidents) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&incidents).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Attaches a file (e.g. a PDF spec) to the whole assignment
+pub async fn upload_attachment(
+    Path(path_params): Path<Vec<String>>,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid assignment_id parameter.".into())
+            .unwrap();
+    };
+
+    let ClientRequest {
+        attachment_base64: Some(attachment_base64),
+        attachment_filename: Some(attachment_filename),
+        ..
+    } = client_req
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required fields attachment_base64 or attachment_filename.".into())
+            .unwrap();
+    };
+
+    let Ok(data) = base64::prelude::BASE64_STANDARD.decode(attachment_base64) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Attachment is not valid base64.".into())
+            .unwrap();
+    };
+
+    match database::attachment::add_attachment(assignment_id, attachment_filename, data).await {
+        Ok(attachment_id) => Response::builder()
+            .status(StatusCode::OK)
+            .body(format!(r#"{{ "attachment_id": {attachment_id} }}"#).into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+pub async fn remove_attachment(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [_, assignment_id, attachment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(attachment_id)) =
+        (assignment_id.parse::<i32>(), attachment_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    match database::attachment::remove_attachment(assignment_id, attachment_id).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No attachment found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Creates an assignment category (e.g. "Homework", weighted 40)
+pub async fn add_category(
+    Path(class_number): Path<String>,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let (Some(name), Some(weight)) = (client_req.category_name, client_req.category_weight) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required fields category_name or category_weight.".into())
+            .unwrap();
+    };
+
+    if weight < 0.0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Category weights cannot be negative.".into())
+            .unwrap();
+    }
+
+    let drop_lowest = client_req.category_drop_lowest.unwrap_or(0);
+    if drop_lowest < 0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("category_drop_lowest cannot be negative.".into())
+            .unwrap();
+    }
+
+    match database::category::add_category(class_number, name, weight, drop_lowest).await {
+        Ok(category_id) => Response::builder()
+            .status(StatusCode::OK)
+            .body(format!(r#"{{ "category_id": {category_id} }}"#).into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+pub async fn update_category(
+    Path(path_params): Path<Vec<String>>,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let [class_number, category_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(category_id) = category_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Some(name), Some(weight)) = (client_req.category_name, client_req.category_weight) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required fields category_name or category_weight.".into())
+            .unwrap();
+    };
+
+    if weight < 0.0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Category weights cannot be negative.".into())
+            .unwrap();
+    }
+
+    let drop_lowest = client_req.category_drop_lowest.unwrap_or(0);
+    if drop_lowest < 0 {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("category_drop_lowest cannot be negative.".into())
+            .unwrap();
+    }
+
+    match database::category::update_category(
+        class_number.clone(),
+        category_id,
+        name,
+        weight,
+        drop_lowest,
+    )
+    .await
+    {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No category found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Removes a category. Its assignments are kept but stop counting towards the course grade.
+pub async fn remove_category(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, category_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(category_id) = category_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    match database::category::remove_category(class_number.clone(), category_id).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No category found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Returns the weighted course grade of every student in the class
+pub async fn course_grades(Path(class_number): Path<String>) -> Response<Body> {
+    match database::category::course_grades(class_number, None).await {
+        Ok(grades) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&grades).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Every student's score on every assignment of the class, with late flags and attempt counts
+pub async fn gradebook(Path(class_number): Path<String>) -> Response<Body> {
+    match database::gradebook::gradebook(class_number, None).await {
+        Ok(gradebook) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&gradebook).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// One student's grades, test results, lateness, attempts and peer comments on each task of the
+/// assignment
+pub async fn student_breakdown(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id, username] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::gradebook::breakdown(class_number, assignment_id, username).await {
+        Ok(Some(breakdown)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&breakdown).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such student.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not retrieve student breakdown: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Adds tests to a task from a zip of `NAME.in`/`NAME.out` pairs. See [`crate::test_import`].
+pub async fn import_tests(
+    Path(path_params): Path<Vec<String>>,
+    zip_file: axum::body::Bytes,
+) -> Response<Body> {
+    let [_, assignment_id, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    // Every import gets its own directory, removed once the tests are read
+    let workdir = Workdir::new(&format!("test-import-{task_id}"));
+    if workdir.create().await.is_err() {
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Error.".into())
+            .unwrap();
+    }
+
+    let tests = match tokio::task::spawn_blocking(move || {
+        test_import::read_test_zip(&zip_file, workdir.path())
+    })
+    .await
+    {
+        Ok(Ok(t)) => t,
+        Ok(Err(e)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(e.into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!("Test import panicked: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    // The imported tests have to make sense alongside the task's existing ones
+    let mut tasks = match database::assignment::retrieve_full_assignment_info(assignment_id).await {
+        Ok(info) => info.tasks,
+        Err(e) => {
+            tracing::error!("Could not retrieve assignment to import tests into: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+    let Some(task_index) = tasks.iter().position(|t| t.task_id == Some(task_id)) else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap();
+    };
+
+    let existing = tasks[task_index].tests.len();
+    tasks[task_index].tests.extend(tests);
+    let validation = AssignmentValidation::check(&tasks);
+    if !validation.is_valid() {
+        return Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(serde_json::to_string(&validation).unwrap().into())
+            .unwrap();
+    }
+    let tests = tasks[task_index].tests.split_off(existing);
+
+    let imported = tests.len();
+    match database::assignment::add_tests(assignment_id, task_id, tests).await {
+        Ok(Some(flagged)) => Response::builder()
+            .status(StatusCode::OK)
+            .body(
+                format!(r#"{{ "imported": {imported}, "flagged_for_regrade": {flagged} }}"#).into(),
+            )
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Could not import tests: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Generates the task's expected outputs by running a reference solution against its test inputs.
+/// A zip in the body replaces the stored solution (its language given in the `Language` header);
+/// an empty body reruns the stored one, e.g. after importing more tests.
+pub async fn generate_expected_outputs(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+    zip_file: axum::body::Bytes,
+) -> Response<Body> {
+    let [class_number, assignment_id, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    if !zip_file.is_empty() {
+        let Some(lang) = parts.headers.get("Language").and_then(|f| f.to_str().ok()) else {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Language Header Missing".into())
+                .unwrap();
+        };
+
+        match database::assignment::store_reference_solution(
+            class_number,
+            assignment_id,
+            task_id,
+            &zip_file,
+            lang,
+        )
+        .await
+        {
+            Ok(Some(())) => (),
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Not Found.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!("Could not store reference solution: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap();
+            }
+        }
+    }
+
+    let solution =
+        match database::assignment::get_reference_solution(class_number, assignment_id, task_id)
+            .await
+        {
+            Ok(Some(s)) => s,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("The task has no reference solution.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!("Could not retrieve reference solution: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap();
+            }
+        };
+
+    if solution.details.test_method != TestMethod::Stdio {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Expected outputs can only be generated for stdio tasks.".into())
+            .unwrap();
+    }
+
+    let outputs = match container::run_reference_solution(task_id, &solution).await {
+        Ok(o) => o,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::UNPROCESSABLE_ENTITY)
+                .body(e.into())
+                .unwrap();
+        }
+    };
+
+    let generated: Vec<(i32, Vec<u8>)> = outputs.iter().filter_map(|o| o.generated()).collect();
+    let flagged =
+        match database::assignment::set_expected_outputs(class_number, task_id, &generated).await {
+            Ok(Some(f)) => f,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Not Found.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!("Could not store generated outputs: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap();
+            }
+        };
+
+    let outputs_json = serde_json::to_string(&outputs).unwrap();
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(
+            format!(r#"{{ "tests": {outputs_json}, "flagged_for_regrade": {flagged} }}"#).into(),
+        )
+        .unwrap()
+}
+
+/// Grades the uploaded code against every test of the task and returns the results, e.g. so an
+/// instructor can try their own solution. Nothing is recorded, so no grade is touched.
+pub async fn test_run(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+    zip_file: axum::body::Bytes,
+) -> Response<Body> {
+    let [_, assignment_id, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    let Some(lang) = parts.headers.get("Language").and_then(|f| f.to_str().ok()) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Language Header Missing".into())
+            .unwrap();
+    };
+
+    if !container::language_enabled(lang) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Language not supported.".into())
+            .unwrap();
+    }
+
+    match database::assignment::task_exists(assignment_id, task_id).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Not Found.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!("Could not look up task: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    }
+
+    if !container::runtime_available() {
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body("The container runtime is unavailable. Try again later.".into())
+            .unwrap();
+    }
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match container::test_run(zip_file, user_id, task_id, lang).await {
+        Ok(results) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&results).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("Test run of task {task_id} failed: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(format!("The test run could not be completed: {e}").into())
+                .unwrap()
+        }
+    }
+}
+
+/// Grades every submission to the assignment again, e.g. after a broken test was fixed. Grades
+/// are updated as each submission finishes.
+pub async fn regrade_assignment(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [class_number, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    let entries =
+        match database::assignment::take_regrade_submissions(class_number, assignment_id).await {
+            Ok(Some(e)) => e,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Not Found.".into())
+                    .unwrap();
+            }
+            Err(e) => {
+                tracing::error!("Could not retrieve submissions to regrade: {e}");
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error.".into())
+                    .unwrap();
+            }
+        };
+
+    let queued = entries.len();
+    tracing::info!("Regrading {queued} submissions to assignment {assignment_id}");
+    tokio::spawn(container::requeue(entries));
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(format!(r#"{{ "queued": {queued} }}"#).into())
+        .unwrap()
+}
+
+/// Starts a check of how alike students' submissions to the assignment are
+pub async fn request_similarity_check(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    let report_id = match database::similarity::create_report(assignment_id, user_id).await {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .body("A similarity check of this assignment is already running.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    similarity::start(report_id, assignment_id, user_id);
+
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(format!(r#"{{ "report_id": {report_id} }}"#).into())
+        .unwrap()
+}
+
+/// The latest similarity report on the assignment, with the pairs of students whose submissions
+/// look alike
+pub async fn similarity_report(
+    Path(path_params): Path<Vec<String>>,
+    Query(query): Query<SimilarityQuery>,
+) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL parameters.".into())
+            .unwrap();
+    };
+
+    match database::similarity::latest_report(assignment_id, query.min_similarity()).await {
+        Ok(Some(report)) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&report).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("This assignment has not been checked.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Endpoints remote runners use to take part in grading. See [`crate::container`]'s `remote` module.
+
+use axum::{
+    Json,
+    body::Body,
+    extract::Path,
+    http::{Response, StatusCode},
+};
+
+use crate::{
+    OK_JSON,
+    container::{self, RunnerRequest},
+};
+
+/// Hands the runner a queued submission. Waits a while for one before answering 204 No Content.
+pub async fn claim_job(Json(runner_req): Json<RunnerRequest>) -> Response<Body> {
+    match container::claim(&runner_req.runner_id).await {
+        Some(job) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&job).unwrap().into())
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+pub async fn heartbeat(Json(runner_req): Json<RunnerRequest>) -> Response<Body> {
+    container::heartbeat(&runner_req.runner_id);
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
+
+/// Takes a runner's results for a job. 409 if the job was given to someone else in the meantime.
+pub async fn report_job(
+    Path(job_id): Path<i32>,
+    Json(runner_req): Json<RunnerRequest>,
+) -> Response<Body> {
+    let Some(report) = runner_req.report else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing report.".into())
+            .unwrap();
+    };
+
+    if !container::report(&runner_req.runner_id, job_id, report).await {
+        return Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body("The job is no longer assigned to this runner.".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
@@ -1,12 +1,223 @@
+use std::time::Duration;
+
 use axum::{
-    body::Body,
-    extract::Path,
-    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+    Json,
+    body::{Body, Bytes},
+    extract::{Path, Query},
+    http::{
+        StatusCode,
+        header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, RETRY_AFTER},
+        request::Parts,
+    },
     response::Response,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream},
+    sync::broadcast::error::RecvError,
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    OK_JSON, SupplementaryMaterial, archive, config,
+    container::{self, ContainerEntry},
+    database,
+    model::{
+        class_info::ClassInfo,
+        grading_event::GradingEvent,
+        honor::HonorPledgeMode,
+        request::{AttemptQuery, ChunkQuery, ClientRequest, UploadRequest},
+        submission_object::SubmissionObject,
+        submission_receipt::SubmissionAccepted,
+        submission_response::ResultVisibility,
+        submission_status::{GradingStatus, SubmissionStatus},
+        upload_status::UploadStatus,
+    },
+    scan::{self, Verdict},
+    storage,
+    upload::{self, Upload, UploadError},
+};
+
+/// Shown while the container runtime is down. The submission is saved and graded once it is back.
+const GRADING_DELAYED: &str = "Grading is temporarily delayed. Your submission has been saved and will be graded automatically.";
+
+/// How long a student turned away by a full grading queue is asked to wait
+const QUEUE_FULL_RETRY_SECS: u64 = 30;
+
+/// How long a student is asked to wait when their upload couldn't be scanned for malware
+const SCAN_RETRY_SECS: u64 = 60;
+
+/// How often a grading progress stream with nothing to report says it's still there
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// 503 while the grading queue is full, so the student can try again shortly. `None` => there's
+/// room.
+async fn queue_full() -> Option<Response<Body>> {
+    match container::queue_full().await {
+        Ok(false) => None,
+        Ok(true) => Some(
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(RETRY_AFTER, QUEUE_FULL_RETRY_SECS)
+                .body("The grading queue is full. Please try again in a minute.".into())
+                .unwrap(),
+        ),
+        Err(e) => {
+            tracing::error!(e);
+            Some(
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Could not add submission to queue".into())
+                    .unwrap(),
+            )
+        }
+    }
+}
+
+/// The archive a submission was uploaded as. Submissions can be sent as JSON instead, either as a
+/// tree of files or, if the task allows the editor, as a single source file. Either is packed into
+/// a zip and graded like one.
+async fn upload_archive(
+    parts: &Parts,
+    body: Bytes,
+    assignment_id: i32,
+    task_id: i32,
+    lang: &str,
+) -> Result<Bytes, Response<Body>> {
+    let is_json = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .is_some_and(|t| t.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return Ok(body);
+    }
+
+    let submission = match serde_json::from_slice::<SubmissionObject>(&body) {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid submission: {e}").into())
+                .unwrap());
+        }
+    };
+
+    if submission.source.is_some() {
+        match database::assignment::allows_editor(assignment_id, task_id).await {
+            Ok(true) => (),
+            Ok(false) => {
+                return Err(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("This task does not accept submissions from the editor.".into())
+                    .unwrap());
+            }
+            Err(e) => {
+                tracing::error!(e);
+                return Err(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap());
+            }
+        }
+    }
+
+    let files = match submission.into_files(container::source_file(lang)) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(e.into())
+                .unwrap());
+        }
+    };
+
+    match archive::pack(&files) {
+        Ok(zip) => Ok(zip.into()),
+        Err(e) => {
+            tracing::error!("Could not pack editor submission: {e}");
+            Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap())
+        }
+    }
+}
+
+/// Scans an upload for malware before anything is recorded. Infected uploads are turned away with
+/// 422, recorded as incidents and reported to the class's instructors. `None` => it's clean, or
+/// scanning is off.
+async fn scan_upload(
+    user_id: i32,
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    sample: bool,
+    upload: &[u8],
+) -> Option<Response<Body>> {
+    let signature = match scan::scan(upload).await {
+        Ok(Verdict::Clean) => return None,
+        Ok(Verdict::Infected(signature)) => signature,
+        Err(e) => {
+            tracing::error!(e);
+            return Some(
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(RETRY_AFTER, SCAN_RETRY_SECS)
+                    .body("Your submission could not be scanned for malware. Please try again in a minute.".into())
+                    .unwrap(),
+            );
+        }
+    };
+
+    tracing::warn!("Upload by user {user_id} for task {task_id} flagged as {signature}");
+
+    let sha256: String = Sha256::digest(upload)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    if let Err(e) = database::malware::record_incident(
+        user_id,
+        class_number,
+        task_id,
+        sample,
+        &signature,
+        &sha256,
+        upload.len() as i64,
+    )
+    .await
+    {
+        tracing::error!("Could not record the malware incident of {user_id}-{task_id}: {e}");
+    }
+
+    match database::user::instructor_ids(class_number).await {
+        Ok(instructors) => {
+            let message = format!(
+                "A submission for task {task_id} of assignment {assignment_id} was flagged as malware ({signature}) and turned away."
+            );
+            let link = format!("/instructor/{class_number}/malware_incidents");
+            for instructor_id in instructors {
+                if let Err(e) = database::notification::add_notification(
+                    instructor_id,
+                    message.clone(),
+                    Some(link.clone()),
+                )
+                .await
+                {
+                    tracing::error!("Could not notify instructor {instructor_id}: {e}");
+                }
+            }
+        }
+        Err(e) => tracing::error!("Could not look up instructors of {class_number}: {e}"),
+    }
 
-use crate::{OK_JSON, SupplementaryMaterial, TX, container::ContainerEntry, database, model::class_info::ClassInfo};
+    Some(
+        Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .body(format!("Your submission was flagged by the malware scanner ({signature}) and was not accepted. Your instructor has been notified.").into())
+            .unwrap(),
+    )
+}
 
 pub async fn download_material(Path(path_params): Path<Vec<String>>) -> Response<Body> {
     let [_, _, task_id] = &path_params[..] else {
@@ -43,13 +254,84 @@ pub async fn download_material(Path(path_params): Path<Vec<String>>) -> Response
         .unwrap()
 }
 
+pub async fn download_template(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Some((material, filename)) = database::assignment::download_template(task_id)
+        .await
+        .unwrap()
+    else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No template found.".into())
+            .unwrap();
+    };
+
+    let template_resp = SupplementaryMaterial { material, filename };
+    let template_resp_json = serde_json::to_string(&template_resp).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(template_resp_json.into())
+        .unwrap()
+}
+
+pub async fn download_attachment(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+    let [_, assignment_id, attachment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(attachment_id)) =
+        (assignment_id.parse::<i32>(), attachment_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request.".into())
+            .unwrap();
+    };
+
+    let Some((material, filename)) =
+        database::attachment::download_attachment(assignment_id, attachment_id)
+            .await
+            .unwrap()
+    else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No attachment found.".into())
+            .unwrap();
+    };
+
+    let attachment_resp = SupplementaryMaterial { material, filename };
+    let attachment_resp_json = serde_json::to_string(&attachment_resp).unwrap();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(attachment_resp_json.into())
+        .unwrap()
+}
+
 pub async fn handle_submission(
     Path(path_params): Path<Vec<String>>,
     parts: Parts,
     zip_file: axum::body::Bytes,
 ) -> Response<Body> {
     let submission_time = Utc::now();
-    let [_, assignment_id, task_id] = &path_params[..] else {
+    let [class_number, assignment_id, task_id] = &path_params[..] else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
             .body("Bad Request".into())
@@ -59,7 +341,7 @@ pub async fn handle_submission(
     let assignment_id = assignment_id.parse::<i32>().unwrap();
     let task_id = task_id.parse::<i32>().unwrap();
 
-    let Some(auth_header) = parts.headers.get(&AUTHORIZATION) else {
+    if parts.headers.get(&AUTHORIZATION).is_none() {
         return Response::builder()
             .status(StatusCode::FORBIDDEN)
             .body("Not Authorized".into())
@@ -77,9 +359,147 @@ pub async fn handle_submission(
             .unwrap();
     };
 
-    let token = auth_header.to_str().unwrap().to_owned();
+    match database::assignment::language_allowed(assignment_id, &lang).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("This assignment does not accept submissions in that language.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let zip_file = match upload_archive(&parts, zip_file, assignment_id, task_id, &lang).await {
+        Ok(z) => z,
+        Err(response) => return response,
+    };
+
+    submit(
+        class_number,
+        assignment_id,
+        task_id,
+        &parts,
+        lang,
+        zip_file,
+        submission_time,
+    )
+    .await
+}
+
+/// Records a submission and queues it for grading, once the student is allowed to make it
+async fn submit(
+    class_number: &str,
+    assignment_id: i32,
+    task_id: i32,
+    parts: &Parts,
+    lang: String,
+    zip_file: Bytes,
+    submission_time: DateTime<Utc>,
+) -> Response<Body> {
+    match database::assignment::accept_until(assignment_id).await {
+        Ok(Some(accept_until)) if submission_time > accept_until => {
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(format!("Submissions to this assignment closed at {accept_until}.").into())
+                .unwrap();
+        }
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let token = parts.headers[&AUTHORIZATION].to_str().unwrap().to_owned();
     let user_id = database::user::get_user_from_session(token).await.unwrap();
 
+    match database::assignment::locked_by(user_id, task_id).await {
+        Ok(None) => (),
+        Ok(Some(prerequisite)) => {
+            return Response::builder()
+                .status(StatusCode::LOCKED)
+                .body(
+                    format!(
+                        "This task is locked until task {} is passed with at least {:.0}%.",
+                        prerequisite.task_placement + 1,
+                        prerequisite.threshold * 100.0
+                    )
+                    .into(),
+                )
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    // Enforce the class's honor pledge, if it has one
+    let pledge_accepted = parts
+        .headers
+        .get("Honor-Pledge")
+        .is_some_and(|f| f.as_bytes().eq_ignore_ascii_case(b"accepted"));
+
+    let honor_pledge = match database::honor::get_honor_pledge(class_number.into(), user_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    if let Some(pledge) = honor_pledge {
+        let needs_acknowledgement = match pledge.mode {
+            HonorPledgeMode::PerSubmission => true,
+            _ => !pledge.acknowledged,
+        };
+
+        if needs_acknowledgement {
+            if !pledge_accepted {
+                return Response::builder()
+                    .status(StatusCode::PRECONDITION_REQUIRED)
+                    .body("The honor pledge must be accepted before submitting.".into())
+                    .unwrap();
+            }
+
+            let (ack_assignment, ack_task) = match pledge.mode {
+                HonorPledgeMode::PerSubmission => (Some(assignment_id), Some(task_id)),
+                _ => (None, None),
+            };
+
+            if let Err(e) = database::honor::record_acknowledgement(
+                user_id,
+                class_number.into(),
+                ack_assignment,
+                ack_task,
+            )
+            .await
+            {
+                tracing::error!(e);
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal Error".into())
+                    .unwrap();
+            }
+        }
+    }
+
     if database::assignment::submission_in_progress(user_id, assignment_id).await {
         return Response::builder()
             .status(StatusCode::TOO_EARLY)
@@ -87,24 +507,78 @@ pub async fn handle_submission(
             .unwrap();
     }
 
-    if let Err(e) = database::assignment::remove_old_grade(user_id, task_id).await {
-        tracing::error!(e);
+    let remaining_attempts = match database::submission::remaining_attempts(user_id, task_id).await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    if remaining_attempts == Some(0) {
         return Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(e.into())
+            .status(StatusCode::FORBIDDEN)
+            .body("No attempts left for this task.".into())
             .unwrap();
     }
 
-    let was_late = match database::assignment::mark_as_submitted(
+    match database::submission::cooldown_remaining(user_id, task_id).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, retry_after)
+                .body(
+                    format!(
+                        r#"{{ "message": "Please wait before submitting again.", "retry_after": {retry_after} }}"#
+                    )
+                    .into(),
+                )
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    // The queue is checked before anything is recorded, so a full queue turns the submission
+    // away instead of losing it
+    if let Some(response) = queue_full().await {
+        return response;
+    }
+
+    if let Some(response) = scan_upload(
+        user_id,
+        class_number,
+        assignment_id,
+        task_id,
+        false,
+        &zip_file,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let receipt = match database::assignment::mark_as_submitted(
         user_id,
         assignment_id,
         task_id,
         submission_time,
         zip_file.clone(),
+        &lang,
     )
     .await
     {
-        Ok(w) => w,
+        Ok(r) => r,
         Err(e) => {
             tracing::error!("{e}");
             return Response::builder()
@@ -114,89 +588,1108 @@ pub async fn handle_submission(
         }
     };
 
-    let container_entry = ContainerEntry::new(zip_file, user_id, task_id, was_late, lang);
+    let container_entry = ContainerEntry::new(zip_file, user_id, task_id, receipt.was_late, lang);
 
     // Add to container queue
-    if let Some(tx) = TX.get()
-        && let Ok(perm) = tx.reserve().await
-    {
-        perm.send(container_entry);
-    } else {
+    if let Err(e) = container::queue(container_entry).await {
+        tracing::error!(e);
         return Response::builder()
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .body("Could not add submission to queue".into())
             .unwrap();
     }
 
+    let (status, message) = if container::runtime_available() {
+        (StatusCode::OK, "OK")
+    } else {
+        (StatusCode::ACCEPTED, GRADING_DELAYED)
+    };
+
+    let accepted = SubmissionAccepted {
+        message,
+        receipt,
+        // This submission used one of them
+        remaining_attempts: remaining_attempts.map(|remaining| remaining - 1),
+    };
+
     Response::builder()
-        .status(StatusCode::OK)
-        .body(OK_JSON.into())
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&accepted).unwrap().into())
         .unwrap()
 }
 
-pub async fn retrieve_task_score(
+/// Starts a resumable upload of a submission. The archive is then sent in chunks, and submitted
+/// once it has all arrived.
+pub async fn start_upload(
     Path(path_params): Path<Vec<String>>,
     parts: Parts,
+    Json(upload_req): Json<UploadRequest>,
 ) -> Response<Body> {
-    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+    let [_, assignment_id, task_id] = &path_params[..] else {
         return Response::builder()
-            .status(StatusCode::FORBIDDEN)
-            .body("Access Denied.".into())
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
             .unwrap();
     };
 
-    let [_, _, task_id] = &path_params[..] else {
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
-            .body("Invalid URL".into())
+            .body("Invalid Request.".into())
             .unwrap();
     };
 
-    let token = auth_header.to_str().unwrap().to_string();
-    let Some(user_id) = database::user::get_user_from_session(token).await else {
+    let Some(auth_header) = parts.headers.get(&AUTHORIZATION) else {
         return Response::builder()
             .status(StatusCode::FORBIDDEN)
-            .body("Access Denied.".into())
+            .body("Not Authorized".into())
             .unwrap();
     };
 
-    let Ok(task_id) = task_id.parse::<i32>() else {
+    let Some(lang) = parts
+        .headers
+        .get("Language")
+        .and_then(|f| f.to_str().map(|f| f.to_owned()).ok())
+    else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
-            .body("Invalid Request.".into())
+            .body("Language Header Missing".into())
             .unwrap();
     };
 
-    if database::assignment::submission_in_progress(user_id, task_id).await {
-        return Response::builder()
-            .status(StatusCode::TOO_EARLY)
-            .body("Submission in progress".into())
-            .unwrap();
+    match database::assignment::language_allowed(assignment_id, &lang).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("This assignment does not accept submissions in that language.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
     }
 
-    match database::assignment::get_task_score(user_id, task_id).await {
-        Ok(Some(res)) => {
+    let token = auth_header.to_str().unwrap().to_owned();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized".into())
+            .unwrap();
+    };
+
+    let upload = Upload {
+        user_id,
+        assignment_id,
+        task_id,
+        lang,
+        size: upload_req.size,
+        sha256: upload_req.sha256,
+    };
+
+    match upload::start(&upload) {
+        Ok(upload_id) => upload_status_response(upload_id, &upload, 0),
+        Err(UploadError::Rejected(reason)) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(reason.into())
+            .unwrap(),
+        Err(UploadError::Io(e)) => {
+            tracing::error!("Could not start upload: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Returns how much of an upload has arrived, so it can be resumed from there
+pub async fn upload_status(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let (upload_id, upload) = match find_upload(&path_params, &parts).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    match upload::received(&upload_id) {
+        Ok(received) => upload_status_response(upload_id, &upload, received),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Writes one chunk of an upload at the offset it's sent with
+pub async fn upload_chunk(
+    Path(path_params): Path<Vec<String>>,
+    Query(query): Query<ChunkQuery>,
+    parts: Parts,
+    chunk: Bytes,
+) -> Response<Body> {
+    let (upload_id, upload) = match find_upload(&path_params, &parts).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    match upload::write_chunk(&upload_id, &upload, query.offset, &chunk) {
+        Ok(received) => upload_status_response(upload_id, &upload, received),
+        Err(UploadError::Rejected(reason)) => Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(reason.into())
+            .unwrap(),
+        Err(UploadError::Io(e)) => {
+            tracing::error!("Could not write upload chunk: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Checks that the whole upload arrived intact, then submits it. It counts as submitted when its
+/// last chunk arrived, or now if that was longer than [`upload::FINISH_WINDOW`] ago. The upload is
+/// kept if the submission is turned away, so finishing can be tried again.
+pub async fn finish_upload(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let (upload_id, upload) = match find_upload(&path_params, &parts).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let (archive, completed_at) = match upload::assemble(&upload_id, &upload) {
+        Ok(a) => a,
+        Err(UploadError::Rejected(reason)) => {
+            return Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(reason.into())
+                .unwrap();
+        }
+        Err(UploadError::Io(e)) => {
+            tracing::error!("Could not read upload: {e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    let response = submit(
+        &path_params[0],
+        upload.assignment_id,
+        upload.task_id,
+        &parts,
+        upload.lang,
+        archive.into(),
+        completed_at,
+    )
+    .await;
+
+    if response.status().is_success()
+        && let Err(e) = upload::remove(&upload_id)
+    {
+        tracing::error!("Could not remove finished upload {upload_id}: {e}");
+    }
+
+    response
+}
+
+/// The student's upload named by the path, as long as it's for the task in it
+async fn find_upload(
+    path_params: &[String],
+    parts: &Parts,
+) -> Result<(String, Upload), Response<Body>> {
+    let [_, assignment_id, task_id, upload_id] = path_params else {
+        return Err(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap());
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Err(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap());
+    };
+
+    let Some(auth_header) = parts.headers.get(&AUTHORIZATION) else {
+        return Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized".into())
+            .unwrap());
+    };
+
+    let token = auth_header.to_str().unwrap().to_owned();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized".into())
+            .unwrap());
+    };
+
+    match upload::get(upload_id) {
+        Ok(Some(upload))
+            if upload.user_id == user_id
+                && upload.assignment_id == assignment_id
+                && upload.task_id == task_id =>
+        {
+            Ok((upload_id.clone(), upload))
+        }
+        Ok(_) => Err(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No such upload.".into())
+            .unwrap()),
+        Err(e) => {
+            tracing::error!(e);
+            Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap())
+        }
+    }
+}
+
+fn upload_status_response(upload_id: String, upload: &Upload, received: u64) -> Response<Body> {
+    let status = UploadStatus {
+        upload_id,
+        size: upload.size,
+        received,
+        max_chunk_bytes: config::get().submission_limit_mb as u64 * 1024 * 1024,
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&status).unwrap().into())
+        .unwrap()
+}
+
+pub async fn retrieve_task_score(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    if database::assignment::grading_delayed(user_id, task_id).await {
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(GRADING_DELAYED.into())
+            .unwrap();
+    }
+
+    if database::assignment::submission_in_progress(user_id, task_id).await {
+        return Response::builder()
+            .status(StatusCode::TOO_EARLY)
+            .body("Submission in progress".into())
+            .unwrap();
+    }
+
+    match database::assignment::get_task_score(user_id, task_id).await {
+        Ok(Some(res)) => {
+            let res_json = serde_json::to_string(&res).unwrap();
+            Response::builder()
+                .status(StatusCode::OK)
+                .body(res_json.into())
+                .unwrap()
+        }
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Where the student's latest submission of the task is in grading, with its place in the queue
+/// and how long it's likely to take
+pub async fn submission_status(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    let status = match current_status(user_id, task_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Not Found.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!("{e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(serde_json::to_string(&status).unwrap().into())
+        .unwrap()
+}
+
+/// Where the student's latest submission of the task is in grading. `None` => they haven't
+/// submitted it.
+async fn current_status(user_id: i32, task_id: i32) -> Result<Option<SubmissionStatus>, String> {
+    let progress = database::grading_job::submission_progress(user_id, task_id).await?;
+
+    let status = match progress {
+        Some(progress) => SubmissionStatus {
+            status: progress.status,
+            queue_position: (progress.status == GradingStatus::Queued)
+                .then_some(progress.queue_position),
+            estimated_wait_secs: container::estimated_wait(&progress).await,
+        },
+        None if database::assignment::grading_delayed(user_id, task_id).await => {
+            SubmissionStatus {
+                status: GradingStatus::Delayed,
+                queue_position: None,
+                estimated_wait_secs: None,
+            }
+        }
+        // Recorded, but not queued yet
+        None if database::assignment::submission_in_progress(user_id, task_id).await => {
+            SubmissionStatus {
+                status: GradingStatus::Queued,
+                queue_position: None,
+                estimated_wait_secs: None,
+            }
+        }
+        None if database::assignment::submission_graded(user_id, task_id).await => {
+            SubmissionStatus {
+                status: GradingStatus::Graded,
+                queue_position: None,
+                estimated_wait_secs: None,
+            }
+        }
+        None => return Ok(None),
+    };
+
+    Ok(Some(status))
+}
+
+/// Streams the grading of the student's latest submission of the task as server-sent events,
+/// starting with where it is now and ending once it's graded
+pub async fn grading_progress(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    // Listening starts first, so nothing that happens while the status is looked up is missed
+    let mut events = container::subscribe(user_id, task_id);
+
+    let (status, visibility) = match (
+        current_status(user_id, task_id).await,
+        database::assignment::task_result_visibility(task_id).await,
+    ) {
+        (Ok(Some(status)), Ok(visibility)) => (status, visibility),
+        (Ok(None), _) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Not Found.".into())
+                .unwrap();
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("{e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
+        }
+    };
+
+    let (mut writer, reader) = tokio::io::duplex(1024);
+    tokio::spawn(async move {
+        let graded = status.status == GradingStatus::Graded;
+        let sent = send_event(&mut writer, &GradingEvent::Status(status)).await;
+        if sent.is_err() || graded {
+            return;
+        }
+
+        loop {
+            let event = match tokio::time::timeout(KEEP_ALIVE, events.recv()).await {
+                Ok(Ok(event)) => event,
+                // What's still to come tells the student where grading is just as well
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return,
+                // A comment, so proxies don't close a quiet connection
+                Err(_) => match writer.write_all(b": keep-alive\n\n").await {
+                    Ok(()) => continue,
+                    Err(_) => return,
+                },
+            };
+
+            // Students who may only see their score don't see how each test went either
+            if matches!(event, GradingEvent::Test(_)) && visibility == ResultVisibility::ScoreOnly {
+                continue;
+            }
+
+            // The client went away
+            if send_event(&mut writer, &event).await.is_err() || event.is_final() {
+                return;
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+async fn send_event(writer: &mut DuplexStream, event: &GradingEvent) -> std::io::Result<()> {
+    let message = format!("data: {}\n\n", serde_json::to_string(event).unwrap());
+    writer.write_all(message.as_bytes()).await
+}
+
+/// Withdraws the student's submission of the task while it's still waiting in the queue, so a fixed
+/// version can be submitted instead
+pub async fn cancel_submission(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    match database::grading_job::cancel_queued(user_id, task_id).await {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) if database::assignment::submission_in_progress(user_id, task_id).await => {
+            Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body("The submission is already being graded.".into())
+                .unwrap()
+        }
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No queued submission.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Runs a submission against the task's sample tests only. Sample runs can be repeated freely and
+/// never record a grade.
+pub async fn run_samples(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+    zip_file: axum::body::Bytes,
+) -> Response<Body> {
+    let [class_number, assignment_id, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(task_id)) = (assignment_id.parse::<i32>(), task_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    let Some(auth_header) = parts.headers.get(&AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized".into())
+            .unwrap();
+    };
+
+    let Some(lang) = parts
+        .headers
+        .get("Language")
+        .and_then(|f| f.to_str().map(|f| f.to_owned()).ok())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Language Header Missing".into())
+            .unwrap();
+    };
+
+    match database::assignment::language_allowed(assignment_id, &lang).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("This assignment does not accept submissions in that language.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let zip_file = match upload_archive(&parts, zip_file, assignment_id, task_id, &lang).await {
+        Ok(z) => z,
+        Err(response) => return response,
+    };
+
+    let token = auth_header.to_str().unwrap().to_owned();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized".into())
+            .unwrap();
+    };
+
+    match database::assignment::locked_by(user_id, task_id).await {
+        Ok(None) => (),
+        Ok(Some(prerequisite)) => {
+            return Response::builder()
+                .status(StatusCode::LOCKED)
+                .body(
+                    format!(
+                        "This task is locked until task {} is passed with at least {:.0}%.",
+                        prerequisite.task_placement + 1,
+                        prerequisite.threshold * 100.0
+                    )
+                    .into(),
+                )
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    // Sample runs aren't worth holding on to until the runtime comes back
+    if !container::runtime_available() {
+        return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(GRADING_DELAYED.into())
+            .unwrap();
+    }
+
+    if let Some(response) = queue_full().await {
+        return response;
+    }
+
+    if let Some(response) = scan_upload(
+        user_id,
+        class_number,
+        assignment_id,
+        task_id,
+        true,
+        &zip_file,
+    )
+    .await
+    {
+        return response;
+    }
+
+    match database::sample_run::start_sample_run(user_id, task_id, &lang).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return Response::builder()
+                .status(StatusCode::TOO_EARLY)
+                .body("Previous sample run still in queue. Check for results later.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    }
+
+    let container_entry = ContainerEntry::sample(zip_file, user_id, task_id, lang);
+
+    // Add to container queue
+    if let Err(e) = container::queue(container_entry).await {
+        tracing::error!(e);
+        if let Err(e) = database::sample_run::discard_sample_run(user_id, task_id).await {
+            tracing::error!(e);
+        }
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Could not add submission to queue".into())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
+}
+
+/// Returns the results of the student's latest sample run of the task
+pub async fn retrieve_sample_results(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    match database::sample_run::get_sample_results(user_id, task_id).await {
+        Ok(Some(Some(res))) => {
             let res_json = serde_json::to_string(&res).unwrap();
             Response::builder()
                 .status(StatusCode::OK)
                 .body(res_json.into())
                 .unwrap()
         }
-        Ok(None) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body("Not Found.".into())
-            .unwrap(),
+        Ok(Some(None)) => Response::builder()
+            .status(StatusCode::TOO_EARLY)
+            .body("Sample run in progress".into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Returns the full text of a test's `input`, `expected` or `found` output that was cut short in
+/// the student's graded result
+pub async fn retrieve_full_output(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id, test_index, field] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let (Ok(task_id), Ok(test_index)) = (task_id.parse::<i32>(), test_index.parse::<usize>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    if !["input", "expected", "found"].contains(&field.as_str()) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    }
+
+    match database::assignment::get_full_output(user_id, task_id, test_index, field).await {
+        Ok(Some(contents)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(contents.into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists every attempt the student made at the task, and which one counts
+pub async fn list_attempts(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    match database::submission::history(user_id, task_id).await {
+        Ok(history) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&history).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// The results of one of the student's attempts at the task
+pub async fn retrieve_attempt(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id, attempt] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let (Ok(task_id), Ok(attempt)) = (task_id.parse::<i32>(), attempt.parse::<i32>()) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    match database::submission::attempt_results(user_id, task_id, attempt).await {
+        Ok(Some(results)) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&results).unwrap().into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!("{e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Returns the archive the student submitted, as the server stored it, so they can check what it
+/// received. The latest attempt unless another is asked for.
+pub async fn download_submission(
+    Path(path_params): Path<Vec<String>>,
+    Query(query): Query<AttemptQuery>,
+    parts: Parts,
+) -> Response<Body> {
+    let Some(auth_header) = parts.headers.get(AUTHORIZATION) else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let [_, _, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid URL".into())
+            .unwrap();
+    };
+
+    let token = auth_header.to_str().unwrap().to_string();
+    let Some(user_id) = database::user::get_user_from_session(token).await else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Access Denied.".into())
+            .unwrap();
+    };
+
+    let Ok(task_id) = task_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Invalid Request.".into())
+            .unwrap();
+    };
+
+    let (attempt, key) = match database::submission::archive(user_id, task_id, query.attempt).await
+    {
+        Ok(Some(archive)) => archive,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Nothing to download.".into())
+                .unwrap();
+        }
         Err(e) => {
             tracing::error!("{e}");
-            Response::builder()
+            return Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body("Internal Error.".into())
-                .unwrap()
+                .unwrap();
+        }
+    };
+
+    let body = match storage::stream(&key).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("{e}");
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap();
         }
+    };
+
+    let extension = storage::extension(&key);
+    let content_type = match extension {
+        "zip" => "application/zip",
+        _ => "application/gzip",
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"task{task_id}-attempt{attempt}.{extension}\""),
+        )
+        .body(body)
+        .unwrap()
+}
+
+/// Accepts the class's honor pledge for the rest of the course
+pub async fn acknowledge_honor_pledge(
+    Path(class_number): Path<String>,
+    parts: Parts,
+) -> Response<Body> {
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    if let Err(e) =
+        database::honor::record_acknowledgement(user_id, class_number, None, None).await
+    {
+        tracing::error!(e);
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Internal Error.".into())
+            .unwrap();
     }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(OK_JSON.into())
+        .unwrap()
 }
 
-pub async fn get_assignment(Path(path_params): Path<Vec<String>>) -> Response<Body> {
+pub async fn get_assignment(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
     let [_, assignment_id] = &path_params[..] else {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
@@ -204,7 +1697,11 @@ pub async fn get_assignment(Path(path_params): Path<Vec<String>>) -> Response<Bo
             .unwrap();
     };
     let assignment_id = assignment_id.parse::<i32>().unwrap();
-    let ass = database::assignment::get_assignment_info(assignment_id)
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    let ass = database::assignment::get_assignment_info(assignment_id, user_id)
         .await
         .unwrap();
 
@@ -238,7 +1735,11 @@ pub async fn get_class_info(Path(path_params): Path<Vec<String>>, parts: Parts)
             .await
             .unwrap();
 
-        let class_info = ClassInfo::new(assignments, instructors);
+        let honor_pledge = database::honor::get_honor_pledge(class_number.clone(), user_id)
+            .await
+            .unwrap();
+
+        let class_info = ClassInfo::new(assignments, instructors, honor_pledge);
 
         let class_json = serde_json::to_string(&class_info).unwrap();
 
@@ -253,3 +1754,298 @@ pub async fn get_class_info(Path(path_params): Path<Vec<String>>, parts: Parts)
             .unwrap()
     }
 }
+
+/// Lists the class's assignment categories and their weights
+pub async fn list_categories(Path(class_number): Path<String>) -> Response<Body> {
+    match database::category::list_categories(class_number).await {
+        Ok(categories) => Response::builder()
+            .status(StatusCode::OK)
+            .body(serde_json::to_string(&categories).unwrap().into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Returns the student's own weighted course grade
+pub async fn course_grade(Path(class_number): Path<String>, parts: Parts) -> Response<Body> {
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match database::category::course_grades(class_number, Some(user_id)).await {
+        Ok(grades) => match grades.into_iter().next() {
+            Some(grade) => Response::builder()
+                .status(StatusCode::OK)
+                .body(serde_json::to_string(&grade).unwrap().into())
+                .unwrap(),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Not Found.".into())
+                .unwrap(),
+        },
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error.".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Lists the anonymized submissions the student has been asked to review, with the rubric
+pub async fn list_peer_reviews(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    let settings = match database::peer_review::open_reviews(assignment_id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Peer reviews are not open for this assignment.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    match database::peer_review::list_reviews(assignment_id, user_id).await {
+        Ok(reviews) => Response::builder()
+            .status(StatusCode::OK)
+            .body(
+                format!(
+                    r#"{{ "rubric": {}, "reviews": {} }}"#,
+                    serde_json::to_string(&settings.rubric).unwrap(),
+                    serde_json::to_string(&reviews).unwrap()
+                )
+                .into(),
+            )
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Downloads one task of a submission the student was asked to review
+pub async fn download_review_submission(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+) -> Response<Body> {
+    let [_, assignment_id, review_id, task_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(review_id), Ok(task_id)) = (
+        assignment_id.parse::<i32>(),
+        review_id.parse::<i32>(),
+        task_id.parse::<i32>(),
+    ) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match database::peer_review::review_submission(assignment_id, user_id, review_id, task_id)
+        .await
+    {
+        Ok(Some(zip)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/zip")
+            .body(zip.into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Nothing to download.".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Scores a peer's submission against the rubric. Can be resubmitted to change the review.
+pub async fn submit_peer_review(
+    Path(path_params): Path<Vec<String>>,
+    parts: Parts,
+    Json(client_req): Json<ClientRequest>,
+) -> Response<Body> {
+    let [_, assignment_id, review_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let (Ok(assignment_id), Ok(review_id)) =
+        (assignment_id.parse::<i32>(), review_id.parse::<i32>())
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let ClientRequest {
+        review_scores: Some(scores),
+        review_comments,
+        ..
+    } = client_req
+    else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing required field review_scores.".into())
+            .unwrap();
+    };
+
+    let settings = match database::peer_review::open_reviews(assignment_id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Peer reviews are not open for this assignment.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    if let Err(e) = settings.check_scores(&scores) {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.into())
+            .unwrap();
+    }
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    match database::peer_review::submit_review(
+        assignment_id,
+        user_id,
+        review_id,
+        scores,
+        review_comments,
+    )
+    .await
+    {
+        Ok(true) => Response::builder()
+            .status(StatusCode::OK)
+            .body(OK_JSON.into())
+            .unwrap(),
+        Ok(false) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("Not Found".into())
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
+
+/// The completed peer reviews of the student's own submission, without reviewer names
+pub async fn received_reviews(Path(path_params): Path<Vec<String>>, parts: Parts) -> Response<Body> {
+    let [_, assignment_id] = &path_params[..] else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let Ok(assignment_id) = assignment_id.parse::<i32>() else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Bad Request".into())
+            .unwrap();
+    };
+
+    let token = parts.headers.get(AUTHORIZATION).unwrap().to_str().unwrap();
+    let user_id = database::user::get_user_from_session(token).await.unwrap();
+
+    let settings = match database::peer_review::open_reviews(assignment_id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("Peer reviews are not open for this assignment.".into())
+                .unwrap();
+        }
+        Err(e) => {
+            tracing::error!(e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap();
+        }
+    };
+
+    match database::peer_review::received_reviews(assignment_id, user_id).await {
+        Ok(reviews) => Response::builder()
+            .status(StatusCode::OK)
+            .body(
+                format!(
+                    r#"{{ "rubric": {}, "reviews": {} }}"#,
+                    serde_json::to_string(&settings.rubric).unwrap(),
+                    serde_json::to_string(&reviews).unwrap()
+                )
+                .into(),
+            )
+            .unwrap(),
+        Err(e) => {
+            tracing::error!(e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Internal Error".into())
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Builds bulk submission downloads in the background
+//!
+//! Instructors request an export, which is queued here instead of being built inside the request.
+//! Only a handful of exports are built at once, and each one pulls submissions from the database
+//! one student at a time. When an archive is ready the requester is sent a notification with a
+//! time-limited download link.
+//!
+//! Admins can also export a whole class as a research dataset. Students are replaced by salted
+//! hashes of their ids; the salt is `research_salt` from the configuration if set (so pseudonyms
+//! stay the same across exports), or a fresh random salt that is thrown away after the export.
+
+use std::collections::HashSet;
+
+use sha2::{Digest, Sha256};
+use tokio::{process::Command, sync::Semaphore};
+use tracing::{error, info};
+
+use crate::{archive, config, database};
+
+/// Maximum number of exports being built at the same time
+const MAX_CONCURRENT_EXPORTS: usize = 2;
+
+/// How long a finished export may be downloaded for
+const EXPORT_LIFETIME_HOURS: i64 = 24;
+
+pub enum ExportKind {
+    /// Every student's submissions to one assignment
+    Assignment(i32),
+    /// Pseudonymized submissions, results, and timing data for the whole class
+    Research,
+}
+
+pub struct ExportEntry {
+    job_id: i32,
+    user_id: i32,
+    class_number: String,
+    kind: ExportKind,
+}
+
+impl ExportEntry {
+    pub fn new(job_id: i32, user_id: i32, class_number: impl Into<String>, assignment_id: i32) -> Self {
+        Self {
+            job_id,
+            user_id,
+            class_number: class_number.into(),
+            kind: ExportKind::Assignment(assignment_id),
+        }
+    }
+
+    pub fn research(job_id: i32, user_id: i32, class_number: impl Into<String>) -> Self {
+        Self {
+            job_id,
+            user_id,
+            class_number: class_number.into(),
+            kind: ExportKind::Research,
+        }
+    }
+}
+
+pub async fn export_queue(mut rx: tokio::sync::mpsc::Receiver<ExportEntry>) -> ! {
+    static SEMAPHORE: Semaphore = Semaphore::const_new(MAX_CONCURRENT_EXPORTS);
+
+    loop {
+        // Clean up anything that expired since the last export
+        match database::export::expire_exports().await {
+            Ok(paths) => {
+                for path in paths {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            Err(e) => error!("Could not expire old exports: {e}"),
+        }
+
+        if let Ok(perm) = SEMAPHORE.acquire().await
+            && let Some(entry) = rx.recv().await
+        {
+            tokio::spawn(async move {
+                let job_id = entry.job_id;
+                let user_id = entry.user_id;
+
+                if let Err(e) = build_export(entry).await {
+                    error!("Export {job_id} failed: {e}");
+                    let _ = database::export::fail_export_job(job_id, e).await;
+                    let _ = database::notification::add_notification(
+                        user_id,
+                        "Your submission export failed. Please try again later.",
+                        None,
+                    )
+                    .await;
+                }
+
+                drop(perm);
+            });
+        } else {
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        }
+    }
+}
+
+async fn build_export(
+    ExportEntry {
+        job_id,
+        user_id,
+        class_number,
+        kind,
+    }: ExportEntry,
+) -> Result<(), String> {
+    database::export::set_export_status(job_id, "building").await?;
+
+    let workdir = format!("/tmp/securegrade/export/{job_id}");
+    let archive_path = format!("/tmp/securegrade/export/{job_id}.zip");
+
+    let _ = std::fs::remove_dir_all(&workdir);
+    std::fs::create_dir_all(&workdir).map_err(|e| format!("{e}"))?;
+
+    let written = match kind {
+        ExportKind::Assignment(assignment_id) => {
+            write_assignment_export(&workdir, assignment_id).await
+        }
+        ExportKind::Research => write_research_export(&workdir, &class_number).await,
+    };
+
+    if let Err(e) = written {
+        let _ = std::fs::remove_dir_all(&workdir);
+        return Err(e);
+    }
+
+    let status = Command::new("zip")
+        .current_dir(&workdir)
+        .args(["-rq", &archive_path, "."])
+        .status()
+        .await
+        .map_err(|e| format!("{e}"))?;
+
+    std::fs::remove_dir_all(&workdir).map_err(|e| format!("{e}"))?;
+
+    if !status.success() {
+        return Err(format!("zip exited with {status}"));
+    }
+
+    let download_token = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    let expiration = chrono::Utc::now() + chrono::TimeDelta::hours(EXPORT_LIFETIME_HOURS);
+
+    database::export::finish_export_job(job_id, archive_path, download_token.clone(), expiration)
+        .await?;
+
+    let (message, link) = match kind {
+        ExportKind::Assignment(assignment_id) => (
+            format!(
+                "Your submission export for assignment {assignment_id} is ready. The link expires in {EXPORT_LIFETIME_HOURS} hours."
+            ),
+            format!("/instructor/{class_number}/download_export/{download_token}"),
+        ),
+        ExportKind::Research => (
+            format!(
+                "The research export for {class_number} is ready. The link expires in {EXPORT_LIFETIME_HOURS} hours."
+            ),
+            format!(
+                "/admin/download_export?class_number={class_number}&download_token={download_token}"
+            ),
+        ),
+    };
+
+    database::notification::add_notification(user_id, message, Some(link)).await?;
+
+    info!("Export {job_id} ready");
+    Ok(())
+}
+
+/// Writes `{username}/Task{task_id}.zip` (or `.tar.gz`) for every student who submitted to the
+/// assignment. See [`student_dir_name`] for usernames that aren't safe as directory names.
+async fn write_assignment_export(workdir: &str, assignment_id: i32) -> Result<(), String> {
+    let submitters = database::export::get_export_submitters(assignment_id).await?;
+    let mut used = HashSet::new();
+
+    for (student_id, username) in submitters {
+        let dir_name = student_dir_name(&username, student_id, &mut used);
+        let student_dir = format!("{workdir}/{dir_name}");
+        std::fs::create_dir_all(&student_dir).map_err(|e| format!("{e}"))?;
+
+        for (task_id, zip) in
+            database::export::get_export_submissions(student_id, assignment_id).await?
+        {
+            let extension = archive::extension(&zip);
+            std::fs::write(format!("{student_dir}/Task{task_id}.{extension}"), zip)
+                .map_err(|e| format!("{e}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The username, with anything but letters, digits, `-` and `_` replaced by `_`. The user id is
+/// appended when that changed it or another student already has the name, so no student's
+/// directory can end up outside the export or shared with another's.
+fn student_dir_name(username: &str, user_id: i32, used: &mut HashSet<String>) -> String {
+    let sanitized = username
+        .chars()
+        .map(|c| {
+            let safe = c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if safe { c } else { '_' }
+        })
+        .collect::<String>();
+
+    let mut name = match sanitized != username || sanitized.is_empty() {
+        true => format!("{sanitized}_{user_id}"),
+        false => sanitized,
+    };
+    while !used.insert(name.clone()) {
+        name = format!("{name}_{user_id}");
+    }
+    name
+}
+
+/// Writes `dataset.json` with one record per graded attempt, and the submitted code under
+/// `submissions/{pseudonym}/Assignment{assignment_id}/Task{task_id}.zip` (or `.tar.gz`)
+async fn write_research_export(workdir: &str, class_number: &str) -> Result<(), String> {
+    let salt = config::get().research_salt.unwrap_or_else(|| {
+        rand::random::<[u8; 16]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    });
+
+    let records =
+        database::export::get_research_records(class_number.into(), |id| pseudonym(&salt, id))
+            .await?;
+
+    // Submissions are fetched per student and assignment, like the assignment export
+    let mut fetched = std::collections::HashSet::new();
+    for (student_id, record) in &records {
+        if record.submission.is_none() || !fetched.insert((*student_id, record.assignment_id)) {
+            continue;
+        }
+
+        let dir = format!(
+            "{workdir}/submissions/{}/Assignment{}",
+            record.student, record.assignment_id
+        );
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{e}"))?;
+
+        for (task_id, zip) in
+            database::export::get_export_submissions(*student_id, record.assignment_id).await?
+        {
+            let extension = archive::extension(&zip);
+            std::fs::write(format!("{dir}/Task{task_id}.{extension}"), zip)
+                .map_err(|e| format!("{e}"))?;
+        }
+    }
+
+    let dataset = records.into_iter().map(|(_, r)| r).collect::<Vec<_>>();
+    let json = serde_json::to_vec_pretty(&dataset).map_err(|e| format!("{e}"))?;
+    std::fs::write(format!("{workdir}/dataset.json"), json).map_err(|e| format!("{e}"))?;
+
+    Ok(())
+}
+
+/// The first 16 hex characters of `sha256(salt:user_id)`
+fn pseudonym(salt: &str, user_id: i32) -> String {
+    Sha256::digest(format!("{salt}:{user_id}"))
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
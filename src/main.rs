@@ -1,54 +1,115 @@
-use std::env::var;
 use std::net::SocketAddr;
 use std::sync::OnceLock;
 
 use axum::Router;
+use axum::body::Body;
 use axum::extract::DefaultBodyLimit;
 use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
-use axum::http::{HeaderName, Method};
-use axum::middleware::from_fn;
-use axum::routing::{get, post, put};
+use axum::http::{HeaderName, Method, Response, StatusCode};
+use axum::middleware::{from_fn, map_response};
+use axum::routing::{delete, get, post, put};
 use axum_server::tls_rustls::RustlsConfig;
 use tower_http::cors::{AllowOrigin, CorsLayer};
-use tracing::{Level, info};
-use tracing_subscriber::FmtSubscriber;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::reload;
 
-use crate::container::ContainerEntry;
+use crate::export::ExportEntry;
 use crate::model::supplementary_material::SupplementaryMaterial;
 
+mod archive;
+mod cli;
+mod config;
 mod container;
+mod csv;
 mod database;
+mod email;
 mod endpoints;
+mod export;
+mod markdown;
 mod model;
+mod runner;
+mod scan;
 mod security;
+mod similarity;
+mod storage;
+mod test_import;
+mod upload;
 
 /// Basic nondescript OK request body, in case the client is looking for a JSON response.
 const OK_JSON: &str = r#"{ "message": "OK" }"#;
 
-/// Static, global mpsc channel Sender. Sends ContainerEntries to the container processing queue.
-static TX: OnceLock<tokio::sync::mpsc::Sender<ContainerEntry>> = OnceLock::new();
+/// Static, global mpsc channel Sender. Sends ExportEntries to the bulk-download queue.
+static EXPORT_TX: OnceLock<tokio::sync::mpsc::Sender<ExportEntry>> = OnceLock::new();
+
+/// Caps the size of the request bodies `router` accepts, answering anything larger with a 413 that
+/// says what the limit is
+fn limit_body(router: Router, limit_mb: usize, what: &'static str) -> Router {
+    router
+        .layer(map_response(move |response: Response<Body>| async move {
+            if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+                return response;
+            }
+            Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(format!("{what} can be at most {limit_mb} MB.").into())
+                .unwrap()
+        }))
+        .layer(DefaultBodyLimit::max(limit_mb * 1024 * 1024))
+}
 
 #[tokio::main]
 async fn main() {
     // Begin logging
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    // The level filter sits behind a reload layer so it can be changed with the configuration
+    let (level_filter, log_handle) = reload::Layer::new(LevelFilter::INFO);
+    tracing_subscriber::registry()
+        .with(level_filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    config::set_log_handle(log_handle);
+
+    // Load the configuration file, aborting start-up if it is invalid
+    if let Err(e) = config::reload() {
+        tracing::error!("{}", e);
+        return;
+    }
+
+    // Recovery subcommands run against the database and exit without starting the server
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.get(1).map(String::as_str) == Some("admin") {
+        std::process::exit(cli::run_admin(&args[2..]).await);
+    }
+
+    // Remote runners grade another server's submissions, and serve nothing themselves
+    if args.get(1).map(String::as_str) == Some("runner") {
+        std::process::exit(runner::run_runner(&args[2..]).await);
+    }
 
     // Create the CORS layer, which essentially sets a guideline that requests must follow
-    // Allow GET, POST, PUT, and OPTIONS methods
-    // Allow Auth, content-type, and "language" headers
-    // Allow requests from any origin
+    // Allow GET, POST, PUT, DELETE, and OPTIONS methods
+    // Allow Auth, content-type, "language", and "honor-pledge" headers
+    // Allow requests from the configured origins (any origin if none are configured)
     // Expose internal headers content-type, admin, instructor, and student (of which are used to let the frontend know what to display)
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::OPTIONS])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
         .allow_headers([
             AUTHORIZATION,
             CONTENT_TYPE,
             HeaderName::from_lowercase(b"language").unwrap(),
+            HeaderName::from_lowercase(b"honor-pledge").unwrap(),
         ])
-        .allow_origin(AllowOrigin::any())
+        .allow_origin(AllowOrigin::predicate(|origin, _| {
+            let origins = config::get().cors_origins;
+            origins.is_empty() || origins.iter().any(|o| o.as_bytes() == origin.as_bytes())
+        }))
         .expose_headers([
             CONTENT_TYPE,
             HeaderName::from_lowercase(b"admin").unwrap(),
@@ -64,7 +125,30 @@ async fn main() {
 
     // Add admin layer
     let admin_routes: Router = Router::new()
-        .route("/create_class", post(endpoints::admin::create_class));
+        .route("/create_class", post(endpoints::admin::create_class))
+        .route("/set_class_isolation", put(endpoints::admin::set_class_isolation))
+        .route("/set_class_grading_quota", put(endpoints::admin::set_class_grading_quota))
+        .route("/languages", get(endpoints::admin::list_languages))
+        .route("/upload_language", put(endpoints::admin::upload_language))
+        .route("/disable_language", post(endpoints::admin::disable_language))
+        .route("/enable_language", post(endpoints::admin::enable_language))
+        .route("/delete_class", delete(endpoints::admin::delete_class))
+        .route("/delete_assignment", delete(endpoints::admin::delete_assignment))
+        .route("/delete_user", delete(endpoints::admin::delete_user))
+        .route("/set_sis_id", put(endpoints::admin::set_sis_id))
+        .route("/reload_config", post(endpoints::admin::reload_config))
+        .route("/email_templates", get(endpoints::admin::list_email_templates))
+        .route("/set_email_template", put(endpoints::admin::set_email_template))
+        .route("/reset_email_template", delete(endpoints::admin::reset_email_template))
+        .route("/pool_status", get(endpoints::admin::pool_status))
+        .route("/grading_queue", get(endpoints::admin::grading_queue_status))
+        .route("/janitor", get(endpoints::admin::janitor_status))
+        .route("/grading_metrics", get(endpoints::admin::grading_metrics))
+        .route(
+            "/request_research_export",
+            post(endpoints::admin::request_research_export),
+        )
+        .route("/download_export", get(endpoints::admin::download_export));
 
     // The instructor layer
     // All endpoints in this layer require a class_number path parameter.
@@ -83,6 +167,18 @@ async fn main() {
             "/{class_number}/{assignment_number}/retrieve_scores",
             get(endpoints::instructor::retrieve_scores),
         )
+        .route(
+            "/{class_number}/{assignment_id}/export_csv",
+            get(endpoints::instructor::export_csv),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/stats",
+            get(endpoints::instructor::assignment_stats),
+        )
+        .route(
+            "/{class_number}/export_csv",
+            get(endpoints::instructor::export_class_csv),
+        )
         .route(
             "/{class_number}/add_assignment",
             post(endpoints::instructor::add_assignment),
@@ -95,6 +191,54 @@ async fn main() {
             "/{class_number}/{assignment_id}/retrieve_full_assignment",
             get(endpoints::instructor::retrieve_full_assignment_info),
         )
+        .route(
+            "/{class_number}/{assignment_id}/export_assignment",
+            get(endpoints::instructor::export_assignment),
+        )
+        .route(
+            "/{class_number}/import_assignment",
+            post(endpoints::instructor::import_assignment),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/import_tests",
+            post(endpoints::instructor::import_tests),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/regrade",
+            post(endpoints::instructor::regrade_assignment),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/test_run",
+            post(endpoints::instructor::test_run),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/reference_solution",
+            post(endpoints::instructor::generate_expected_outputs),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/build_log/{username}",
+            get(endpoints::instructor::build_log),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/attempts/{username}",
+            get(endpoints::instructor::list_attempts),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/max_attempts/{username}",
+            put(endpoints::instructor::set_attempt_override)
+                .delete(endpoints::instructor::remove_attempt_override),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/grade_override/{username}",
+            put(endpoints::instructor::set_grade_override)
+                .delete(endpoints::instructor::remove_grade_override),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/curve",
+            get(endpoints::instructor::get_curve)
+                .put(endpoints::instructor::set_curve)
+                .delete(endpoints::instructor::remove_curve),
+        )
         .route(
             "/{class_number}/generate_join_code",
             get(endpoints::instructor::generate_join_code),
@@ -106,6 +250,64 @@ async fn main() {
         .route(
             "/{class_number}/list_all_students",
             get(endpoints::list_all_students),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/similarity",
+            post(endpoints::instructor::request_similarity_check)
+                .get(endpoints::instructor::similarity_report),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/request_export",
+            post(endpoints::instructor::request_export),
+        )
+        .route(
+            "/{class_number}/download_export/{download_token}",
+            get(endpoints::instructor::download_export),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/upload_attachment",
+            post(endpoints::instructor::upload_attachment),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/attachments/{attachment_id}",
+            delete(endpoints::instructor::remove_attachment),
+        )
+        .route(
+            "/{class_number}/set_honor_pledge",
+            put(endpoints::instructor::set_honor_pledge),
+        )
+        .route(
+            "/{class_number}/honor_acknowledgements",
+            get(endpoints::instructor::list_honor_acknowledgements),
+        )
+        .route(
+            "/{class_number}/grading_metrics",
+            get(endpoints::instructor::grading_metrics),
+        )
+        .route(
+            "/{class_number}/malware_incidents",
+            get(endpoints::instructor::list_malware_incidents),
+        )
+        .route(
+            "/{class_number}/add_category",
+            post(endpoints::instructor::add_category),
+        )
+        .route(
+            "/{class_number}/categories/{category_id}",
+            put(endpoints::instructor::update_category)
+                .delete(endpoints::instructor::remove_category),
+        )
+        .route(
+            "/{class_number}/course_grades",
+            get(endpoints::instructor::course_grades),
+        )
+        .route(
+            "/{class_number}/gradebook",
+            get(endpoints::instructor::gradebook),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{username}/breakdown",
+            get(endpoints::instructor::student_breakdown),
         );
 
     // The student layer
@@ -116,14 +318,98 @@ async fn main() {
             "/{class_number}/{assignment_id}/{task_id}/download_material",
             get(endpoints::student::download_material),
         )
+        .route(
+            "/{class_number}/{assignment_id}/attachments/{attachment_id}",
+            get(endpoints::student::download_attachment),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/download_template",
+            get(endpoints::student::download_template),
+        )
         .route(
             "/{class_number}/{assignment_id}/{task_id}/submit",
             post(endpoints::student::handle_submission),
         )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/uploads",
+            post(endpoints::student::start_upload),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/uploads/{upload_id}",
+            get(endpoints::student::upload_status).put(endpoints::student::upload_chunk),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/uploads/{upload_id}/finish",
+            post(endpoints::student::finish_upload),
+        )
         .route(
             "/{class_number}/{assignment_id}/{task_id}/retrieve_score",
             get(endpoints::student::retrieve_task_score),
         )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/status",
+            get(endpoints::student::submission_status),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/progress",
+            get(endpoints::student::grading_progress),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/cancel_submission",
+            delete(endpoints::student::cancel_submission),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/run_samples",
+            post(endpoints::student::run_samples),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/sample_results",
+            get(endpoints::student::retrieve_sample_results),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/full_output/{test_index}/{field}",
+            get(endpoints::student::retrieve_full_output),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/download_submission",
+            get(endpoints::student::download_submission),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/attempts",
+            get(endpoints::student::list_attempts),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/{task_id}/attempts/{attempt}",
+            get(endpoints::student::retrieve_attempt),
+        )
+        .route(
+            "/{class_number}/acknowledge_honor_pledge",
+            put(endpoints::student::acknowledge_honor_pledge),
+        )
+        .route(
+            "/{class_number}/categories",
+            get(endpoints::student::list_categories),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/peer_reviews",
+            get(endpoints::student::list_peer_reviews),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/peer_reviews/{review_id}",
+            put(endpoints::student::submit_peer_review),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/peer_reviews/{review_id}/{task_id}",
+            get(endpoints::student::download_review_submission),
+        )
+        .route(
+            "/{class_number}/{assignment_id}/received_reviews",
+            get(endpoints::student::received_reviews),
+        )
+        .route(
+            "/{class_number}/course_grade",
+            get(endpoints::student::course_grade),
+        )
         .route(
             "/{class_number}/{assignment_id}",
             get(endpoints::student::get_assignment),
@@ -135,6 +421,11 @@ async fn main() {
     let general_routes: Router = Router::new()
         .route("/join_class", put(endpoints::join_class))
         .route("/get_classes", get(endpoints::get_classes))
+        .route("/get_notifications", get(endpoints::get_notifications))
+        .route(
+            "/set_preferred_language",
+            put(endpoints::set_preferred_language),
+        )
         .route("/list_all_students", get(endpoints::list_all_students))
         .route(
             "/get_supported_languages",
@@ -147,6 +438,28 @@ async fn main() {
         .route("/login", post(endpoints::login))
         .route("/signup", post(endpoints::signup));
 
+    // The remote runner layer
+    // These endpoints are only accessible with the runner token
+    let runner_routes: Router = Router::new()
+        .route("/claim", post(endpoints::runner::claim_job))
+        .route("/heartbeat", post(endpoints::runner::heartbeat))
+        .route("/report/{job_id}", post(endpoints::runner::report_job))
+        .route_layer(from_fn(security::handle_runner_auth));
+
+    // Each router gets its own limit on request bodies. Remote runners are trusted with reports
+    // as large as their results.
+    let limits = config::get();
+    let admin_routes = limit_body(admin_routes, limits.request_limit_mb, "Requests");
+    let instructor_routes = limit_body(
+        instructor_routes,
+        limits.material_limit_mb,
+        "Assignments and their materials",
+    );
+    let student_routes = limit_body(student_routes, limits.submission_limit_mb, "Submissions");
+    let general_routes = limit_body(general_routes, limits.request_limit_mb, "Requests");
+    let public_routes = limit_body(public_routes, limits.request_limit_mb, "Requests");
+    let runner_routes = runner_routes.layer(DefaultBodyLimit::disable());
+
     // Define the app, merging the routers
     let app = Router::new()
         .nest("/admin", admin_routes)
@@ -158,8 +471,8 @@ async fn main() {
         .merge(general_routes)
         .layer(from_fn(security::handle_basic_auth))
         .merge(public_routes)
-        .layer(cors)
-        .layer(DefaultBodyLimit::max(usize::MAX));
+        .nest("/runner", runner_routes)
+        .layer(cors);
 
 
     // Load the certificate for HTTPS
@@ -176,20 +489,82 @@ async fn main() {
 
     info!("Database initialized");
 
-    // Initialize an mpsc channel so submissions can be processed
-    let (tx, rx) = tokio::sync::mpsc::channel::<ContainerEntry>(i32::MAX as usize);
+    // Move what older versions stored in the database to blob storage, before anything reads it
+    match database::blob::move_to_storage().await {
+        Ok(0) => (),
+        Ok(n) => info!("Moved {n} files from the database to blob storage"),
+        Err(e) => {
+            tracing::error!("Could not move files to blob storage: {e}");
+            return;
+        }
+    }
+
+    // Checks don't survive a restart, so let instructors ask for them again
+    match database::similarity::fail_interrupted().await {
+        Ok(0) => (),
+        Ok(n) => info!("Marked {n} interrupted similarity checks as failed"),
+        Err(e) => tracing::error!("Could not mark interrupted similarity checks: {e}"),
+    }
 
-    let n_threads = var("NTHREADS").ok().and_then(|f| f.parse::<usize>().ok());
+    // Start the workers that grade the queue, picking up whatever the last run left unfinished
+    container::start_queue().await;
+
+    // Reload the configuration whenever a SIGHUP is received
+    tokio::spawn(config::reload_on_sighup());
+
+    // Watch the container runtime, so submissions wait for it instead of being lost
+    tokio::spawn(container::runtime_monitor());
+
+    // Queue the submissions of remote runners that stop responding again
+    tokio::spawn(container::runner_monitor());
+
+    // Build each language's toolchain once, instead of with every submission
+    tokio::spawn(container::build_base_images());
+
+    // Clean up containers, images and directories grading leaves behind
+    tokio::spawn(container::janitor());
+
+    // Keep containers started for languages with a warm pool, so sample runs skip the build
+    tokio::spawn(container::warm_pools());
+
+    // Bulk downloads get their own small, bounded queue so they cannot stampede the database
+    let (export_tx, export_rx) = tokio::sync::mpsc::channel::<ExportEntry>(32);
 
-    // Spawn the persistent container-processing queue thread
     tokio::spawn(async move {
-        container::container_queue(rx, n_threads).await;
+        export::export_queue(export_rx).await;
     });
 
-    // Make the sender portion of the channel global, so it can be accessed across all threads
-    TX.set(tx).unwrap();
+    EXPORT_TX.set(export_tx).unwrap();
+
+    // Stop taking requests on SIGTERM or Ctrl-C, giving those in flight a moment to finish
+    let handle = axum_server::Handle::new();
+    tokio::spawn(shutdown_on_signal(handle.clone()));
 
     // Serve the application on port 9090
     let server = axum_server::bind_rustls("0.0.0.0:9090".parse::<SocketAddr>().unwrap(), config);
-    server.serve(app.into_make_service()).await.unwrap();
+    server
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+
+    // Whatever the workers don't finish is graded after the next start-up
+    container::shutdown_queue().await;
+}
+
+async fn shutdown_on_signal(handle: axum_server::Handle) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+        tracing::error!("Could not listen for SIGTERM");
+        return;
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+
+    info!("Shutting down");
+    handle.graceful_shutdown(Some(std::time::Duration::from_secs(10)));
 }
@@ -0,0 +1,21 @@
+//! Renders instructor-authored Markdown (assignment and task descriptions) into sanitized HTML
+//!
+//! The raw Markdown is what gets stored; rendering happens when it is served, so improvements to
+//! the sanitizer apply to existing descriptions as well.
+
+use pulldown_cmark::{Options, Parser, html};
+
+/// Renders Markdown into HTML that is safe to insert into the page as-is.
+///
+/// Scripts, event handlers, and other unsafe markup are stripped by the sanitizer.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+
+    ammonia::clean(&unsafe_html)
+}
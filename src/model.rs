@@ -1,7 +1,38 @@
+pub mod assignment_archive;
 pub mod assignment_grade;
+pub mod assignment_stats;
+pub mod build_log;
+pub mod attachment;
+pub mod breakdown;
+pub mod category;
 pub mod class_info;
 pub mod class_item;
+pub mod comparison;
+pub mod curve;
+pub mod deletion_summary;
+pub mod email_template;
+pub mod gradebook;
+pub mod grading_event;
+pub mod grading_metrics;
+pub mod grading_pool_stats;
+pub mod honor;
+pub mod interactive;
+pub mod janitor_stats;
+pub mod language_info;
+pub mod malware_incident;
+pub mod notification;
+pub mod peer_review;
+pub mod pool_stats;
 pub mod request;
+pub mod research_record;
+pub mod similarity_report;
+pub mod submission_history;
+pub mod submission_object;
+pub mod submission_receipt;
 pub mod submission_response;
+pub mod submission_status;
+pub mod test_method;
+pub mod upload_status;
 pub mod user_info;
+pub mod validation;
 pub mod supplementary_material;
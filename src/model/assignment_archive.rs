@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::request::{AssignmentSettings, Task};
+
+/// Current version of [`AssignmentArchive`]. Archives with a newer version are rejected on import.
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+/// A self-contained copy of an assignment that can be imported into any class, on any deployment.
+/// Ids are left out, since they only mean something in the database the archive came from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssignmentArchive {
+    pub format_version: u32,
+    pub assignment_name: String,
+    pub assignment_description: Option<String>,
+    pub deadline: String,
+    pub settings: AssignmentSettings,
+    pub tasks: Vec<Task>,
+    #[serde(default)]
+    pub attachments: Vec<ArchivedAttachment>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedAttachment {
+    pub filename: String,
+    pub data_base64: String,
+}
@@ -5,4 +5,15 @@ pub struct AssignmentGrade {
     pub name: String,
     pub username: String,
     pub score: f32,
+    /// `score` before the assignment's curve. `None` => no curve was applied.
+    pub uncurved_score: Option<f32>,
+    /// Languages the student's graded submissions were written in
+    pub languages: Vec<String>,
+    /// A task was graded with an older version of its language's container than the current one,
+    /// so its score may not be comparable with newer attempts
+    pub toolchain_outdated: bool,
+    /// Average score from peer reviews, already blended into `score`
+    pub peer_score: Option<f32>,
+    /// An instructor set `score` in place of the one the student's submissions earned
+    pub overridden: bool,
 }
@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+/// How the class did on an assignment. Scores are autograded, late penalties included but peer
+/// reviews not.
+#[derive(Debug, Serialize)]
+pub struct AssignmentStats {
+    pub students: i64,
+    /// Students with a graded submission, who the score statistics cover
+    pub submitted: i64,
+    /// `None` => nobody has been graded yet
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub std_dev: Option<f64>,
+    /// Ten buckets of a tenth each, the last one including full marks
+    pub histogram: Vec<HistogramBucket>,
+    /// In the order the tasks appear in the assignment
+    pub tasks: Vec<TaskStats>,
+    /// Days without submissions are left out
+    pub submissions_by_day: Vec<DailySubmissions>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistogramBucket {
+    pub from: f32,
+    pub to: f32,
+    pub students: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskStats {
+    pub task_id: i32,
+    /// Students with a graded submission to the task
+    pub attempted: i64,
+    /// Students whose submission earned full marks
+    pub passed: i64,
+    /// `None` => nobody has attempted it
+    pub pass_rate: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailySubmissions {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub submissions: i64,
+    pub students: i64,
+}
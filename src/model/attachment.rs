@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttachmentInfo {
+    pub attachment_id: i32,
+    pub filename: String,
+    /// Size in bytes
+    pub size: i32,
+}
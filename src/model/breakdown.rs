@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+use crate::model::submission_response::SubmissionResponse;
+
+/// Everything about one student's work on an assignment, for instructors looking into it
+#[derive(Debug, Serialize)]
+pub struct StudentBreakdown {
+    pub name: String,
+    pub username: String,
+    /// In the order the assignment lists them
+    pub tasks: Vec<TaskBreakdown>,
+    /// What the student's peer reviewers wrote, oldest first
+    pub peer_comments: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskBreakdown {
+    pub task_id: i32,
+    /// Points of the tests that count
+    pub points: f32,
+    /// Fraction of the points earned, before any late penalty. `None` => not graded.
+    pub grade: Option<f32>,
+    pub late: bool,
+    /// What the grade is multiplied by for lateness. `None` => nothing was submitted.
+    pub late_multiplier: Option<f32>,
+    /// Submissions made to the task
+    pub attempts: i64,
+    /// The attempt the grade comes from
+    pub counted_attempt: Option<i32>,
+    pub submitted_at: Option<String>,
+    pub graded_at: Option<String>,
+    /// The graded attempt's results, nothing hidden: every test's status, input and output, the
+    /// hints shown for failures, compiler output and style findings
+    pub results: Option<SubmissionResponse>,
+}
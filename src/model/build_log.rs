@@ -0,0 +1,11 @@
+use serde::Serialize;
+
+/// What the build of a student's submission printed when it failed
+#[derive(Debug, Serialize)]
+pub struct BuildLog {
+    pub job_id: i32,
+    /// Whether the submission was a sample run
+    pub sample: bool,
+    pub submitted_at: String,
+    pub log: String,
+}
@@ -0,0 +1,30 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Category {
+    pub category_id: i32,
+    pub name: String,
+    pub weight: f32,
+    /// How many of each student's lowest assignment scores in the category don't count
+    pub drop_lowest: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryScore {
+    pub category_id: i32,
+    pub name: String,
+    pub weight: f32,
+    /// Assignments left out of the score as the student's lowest
+    pub dropped: usize,
+    /// Average score across the category's assignments that count so far. `None` => none do.
+    pub score: Option<f32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CourseGrade {
+    pub name: String,
+    pub username: String,
+    /// Weighted total across the categories that have a score
+    pub score: Option<f32>,
+    pub categories: Vec<CategoryScore>,
+}
@@ -1,16 +1,24 @@
 use serde::{Deserialize, Serialize};
 
+use crate::model::honor::HonorPledge;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClassInfo {
     assignments: Vec<AssignmentInfo>,
     instructors: Vec<InstructorInfo>,
+    honor_pledge: Option<HonorPledge>,
 }
 
 impl ClassInfo {
-    pub fn new(assignments: Vec<AssignmentInfo>, instructors: Vec<InstructorInfo>) -> Self {
+    pub fn new(
+        assignments: Vec<AssignmentInfo>,
+        instructors: Vec<InstructorInfo>,
+        honor_pledge: Option<HonorPledge>,
+    ) -> Self {
         Self {
             assignments,
             instructors,
+            honor_pledge,
         }
     }
 }
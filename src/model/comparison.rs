@@ -0,0 +1,289 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A decimal or scientific-notation number
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?").unwrap());
+
+/// How a test's expected output is compared with what the submission printed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComparisonMode {
+    /// Equal after trimming leading and trailing whitespace
+    #[default]
+    Trimmed,
+    /// Byte-for-byte equal
+    Exact,
+    /// Equal once every run of whitespace is collapsed into a single space
+    Whitespace,
+    /// Equal after trimming, ignoring case
+    CaseInsensitive,
+    /// The same lines in any order, each trimmed
+    SortedLines,
+    /// The expected output is a regular expression that must match the whole trimmed output
+    Regex,
+    /// Numbers must be within the test's tolerance of each other; the text around them must be
+    /// equal once whitespace is collapsed
+    Numeric,
+}
+
+/// How far apart two numbers may be under [`ComparisonMode::Numeric`]. They match if they are
+/// within either tolerance.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tolerance {
+    pub absolute: f64,
+    /// Fraction of the larger of the two numbers
+    pub relative: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            absolute: 1e-6,
+            relative: 1e-6,
+        }
+    }
+}
+
+impl Tolerance {
+    /// An overflowing number like `1e999` parses as infinity, which only matches itself
+    fn close(&self, a: f64, b: f64) -> bool {
+        if a.is_infinite() || b.is_infinite() {
+            return a == b;
+        }
+        let diff = (a - b).abs();
+        diff <= self.absolute || diff <= self.relative * a.abs().max(b.abs())
+    }
+}
+
+impl ComparisonMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComparisonMode::Trimmed => "trimmed",
+            ComparisonMode::Exact => "exact",
+            ComparisonMode::Whitespace => "whitespace",
+            ComparisonMode::CaseInsensitive => "case_insensitive",
+            ComparisonMode::SortedLines => "sorted_lines",
+            ComparisonMode::Regex => "regex",
+            ComparisonMode::Numeric => "numeric",
+        }
+    }
+
+    /// Compiles the expected output of a `Regex` test, anchored to the whole output
+    pub fn compile(expected: &str) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{})$", expected.trim()))
+    }
+
+    /// Compares raw output. Output that isn't valid UTF-8 can only be compared byte for byte,
+    /// ignoring surrounding whitespace unless the mode is `Exact`.
+    pub fn matches_bytes(&self, expected: &[u8], found: &[u8], tolerance: &Tolerance) -> bool {
+        match (std::str::from_utf8(expected), std::str::from_utf8(found)) {
+            (Ok(expected), Ok(found)) => self.matches(expected, found, tolerance),
+            _ if *self == ComparisonMode::Exact => expected == found,
+            _ => expected.trim_ascii() == found.trim_ascii(),
+        }
+    }
+
+    pub fn matches(&self, expected: &str, found: &str, tolerance: &Tolerance) -> bool {
+        match self {
+            ComparisonMode::Trimmed => expected.trim() == found.trim(),
+            ComparisonMode::Exact => expected == found,
+            ComparisonMode::Whitespace => expected.split_whitespace().eq(found.split_whitespace()),
+            ComparisonMode::CaseInsensitive => {
+                expected.trim().to_lowercase() == found.trim().to_lowercase()
+            }
+            ComparisonMode::SortedLines => sorted_lines(expected) == sorted_lines(found),
+            ComparisonMode::Regex => {
+                Self::compile(expected).is_ok_and(|re| re.is_match(found.trim()))
+            }
+            ComparisonMode::Numeric => {
+                let numbers = |s: &str| {
+                    NUMBER
+                        .find_iter(s)
+                        .map(|m| m.as_str().parse::<f64>())
+                        .collect::<Result<Vec<f64>, _>>()
+                };
+                let (Ok(expected_numbers), Ok(found_numbers)) = (numbers(expected), numbers(found))
+                else {
+                    return false;
+                };
+
+                let text = |s: &str| {
+                    NUMBER
+                        .replace_all(s, " # ")
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+
+                text(expected) == text(found)
+                    && expected_numbers.len() == found_numbers.len()
+                    && expected_numbers
+                        .iter()
+                        .zip(&found_numbers)
+                        .all(|(a, b)| tolerance.close(*a, *b))
+            }
+        }
+    }
+}
+
+/// Above this many line pairs, similarity is estimated from shared lines instead of a full diff
+const MAX_DIFF_CELLS: usize = 10_000_000;
+
+/// How much of the expected output the submission got right, from 0.0 to 1.0: twice the number
+/// of lines the two outputs have in common (in order) over their total number of lines
+pub fn line_similarity(expected: &str, found: &str) -> f32 {
+    let expected = expected
+        .trim()
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<&str>>();
+    let found = found.trim().lines().map(str::trim).collect::<Vec<&str>>();
+
+    let total = expected.len() + found.len();
+    if total == 0 {
+        return 1.0;
+    }
+
+    let common = if expected.len() * found.len() <= MAX_DIFF_CELLS {
+        longest_common_subsequence(&expected, &found)
+    } else {
+        let mut remaining = sorted_lines_of(&expected);
+        found
+            .iter()
+            .filter(|line| match remaining.binary_search(line) {
+                Ok(i) => {
+                    remaining.remove(i);
+                    true
+                }
+                Err(_) => false,
+            })
+            .count()
+    };
+
+    (2 * common) as f32 / total as f32
+}
+
+fn longest_common_subsequence(a: &[&str], b: &[&str]) -> usize {
+    let mut previous = vec![0; b.len() + 1];
+    let mut current = vec![0; b.len() + 1];
+
+    for line in a {
+        for (j, other) in b.iter().enumerate() {
+            current[j + 1] = if line == other {
+                previous[j] + 1
+            } else {
+                current[j].max(previous[j + 1])
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+fn sorted_lines_of<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let mut lines = lines.to_vec();
+    lines.sort_unstable();
+    lines
+}
+
+fn sorted_lines(s: &str) -> Vec<&str> {
+    let mut lines = s.trim().lines().map(str::trim).collect::<Vec<&str>>();
+    lines.sort_unstable();
+    lines
+}
+
+impl<T> From<T> for ComparisonMode
+where
+    T: AsRef<str>,
+{
+    fn from(value: T) -> Self {
+        match value.as_ref() {
+            "exact" => ComparisonMode::Exact,
+            "whitespace" => ComparisonMode::Whitespace,
+            "case_insensitive" => ComparisonMode::CaseInsensitive,
+            "sorted_lines" => ComparisonMode::SortedLines,
+            "regex" => ComparisonMode::Regex,
+            "numeric" => ComparisonMode::Numeric,
+            _ => ComparisonMode::Trimmed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric(expected: &str, found: &str, absolute: f64, relative: f64) -> bool {
+        ComparisonMode::Numeric.matches(expected, found, &Tolerance { absolute, relative })
+    }
+
+    #[test]
+    fn absolute_tolerance() {
+        assert!(numeric("x = 1.0", "x = 1.05", 0.1, 0.0));
+        assert!(numeric("x = 1.0", "x = 0.95", 0.1, 0.0));
+        assert!(!numeric("x = 1.0", "x = 1.05", 0.01, 0.0));
+        assert!(!numeric("1000", "1000.5", 0.1, 0.0));
+    }
+
+    #[test]
+    fn relative_tolerance() {
+        assert!(numeric("1000", "1010", 0.0, 0.01));
+        assert!(numeric("-1000", "-990", 0.0, 0.01));
+        assert!(!numeric("1", "1.02", 0.0, 0.01));
+        assert!(!numeric("1000", "-1000", 0.0, 0.01));
+    }
+
+    #[test]
+    fn either_tolerance_is_enough() {
+        assert!(numeric("0", "0.05", 0.1, 0.01));
+        assert!(numeric("1000", "1005", 0.1, 0.01));
+        assert!(!numeric("10", "10.5", 0.1, 0.01));
+    }
+
+    #[test]
+    fn nan_and_infinity() {
+        // `nan` and `inf` aren't numbers to the comparator, so they're compared as text
+        assert!(numeric("nan", "nan", 1.0, 1.0));
+        assert!(!numeric("nan", "0", 1.0, 1.0));
+        assert!(!numeric("inf", "1e999", 1.0, 1.0));
+
+        assert!(numeric("1e999", "1e999", 0.0, 0.0));
+        assert!(numeric("-1e999", "-1e999", 0.0, 0.0));
+        assert!(!numeric("1e999", "-1e999", 1.0, 1.0));
+        assert!(!numeric("1e308", "1e999", 1.0, 1.0));
+    }
+
+    #[test]
+    fn token_count_mismatch() {
+        assert!(numeric("1 2 3", "1.0\n2.0\n3.0", 0.0, 0.0));
+        assert!(!numeric("1 2", "1 2 3", 1.0, 1.0));
+        assert!(!numeric("1 2 3", "1 2", 1.0, 1.0));
+        assert!(!numeric("sum: 3", "sum 3", 1.0, 1.0));
+        assert!(!numeric("sum: 3", "sum: 3 done", 1.0, 1.0));
+    }
+
+    #[test]
+    fn empty_outputs_are_identical() {
+        assert_eq!(line_similarity("", ""), 1.0);
+        assert_eq!(line_similarity(" \n\n ", "\n"), 1.0);
+    }
+
+    #[test]
+    fn nothing_printed_gets_no_credit() {
+        assert_eq!(line_similarity("a\nb", ""), 0.0);
+        assert_eq!(line_similarity("", "a\nb"), 0.0);
+    }
+
+    #[test]
+    fn partial_credit_counts_lines_in_order() {
+        assert_eq!(line_similarity("a\nb\nc", "a\nb\nc"), 1.0);
+        assert_eq!(line_similarity("a\nb\nc", "a\n  c  "), 0.8);
+        assert_eq!(line_similarity("a\nb", "b\na"), 0.5);
+        assert_eq!(line_similarity("a\nb", "c\nd"), 0.0);
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// An adjustment to every student's score on an assignment. The scores stored stay as they were
+/// earned; the curve is applied whenever they're read, so it can be changed or taken off.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Curve {
+    #[serde(flatten)]
+    pub method: CurveMethod,
+    /// No score is curved above 1. Scores already above it stay as they are.
+    #[serde(default)]
+    pub cap: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum CurveMethod {
+    /// Adds the same fraction of the points to every score
+    Flat { add: f32 },
+    /// Scales every score by the same factor, so that `from` becomes `to`
+    Linear { from: f32, to: f32 },
+}
+
+impl Curve {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.method {
+            CurveMethod::Flat { add } => {
+                if !add.is_finite() {
+                    return Err("add must be a number.".into());
+                }
+            }
+            CurveMethod::Linear { from, to } => {
+                if !(from.is_finite() && from > 0.0) {
+                    return Err("from must be greater than 0.".into());
+                }
+                if !(to.is_finite() && to >= 0.0) {
+                    return Err("to cannot be negative.".into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Curves a score. It never goes below 0.
+    pub fn apply(&self, score: f32) -> f32 {
+        let curved = match self.method {
+            CurveMethod::Flat { add } => score + add,
+            CurveMethod::Linear { from, to } => score * to / from,
+        }
+        .max(0.0);
+
+        match self.cap {
+            true => curved.min(score.max(1.0)),
+            false => curved,
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+
+/// What a delete removed, or would remove when `dry_run` is set
+#[derive(Debug, Default, Serialize)]
+pub struct DeletionSummary {
+    pub dry_run: bool,
+    pub classes: i64,
+    pub assignments: i64,
+    pub tasks: i64,
+    pub users: i64,
+    pub enrollments: i64,
+    pub submissions: i64,
+    pub grades: i64,
+}
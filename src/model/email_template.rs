@@ -0,0 +1,12 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct EmailTemplate {
+    pub template_name: String,
+    pub subject: String,
+    pub body: String,
+    /// `false` => the built-in default is in use
+    pub customized: bool,
+    /// Placeholders available to this template, used as `{{name}}`
+    pub variables: Vec<String>,
+}
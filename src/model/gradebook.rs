@@ -0,0 +1,44 @@
+use serde::Serialize;
+
+/// Every student's score on every assignment of a class
+#[derive(Debug, Serialize)]
+pub struct Gradebook {
+    /// The columns, in order of deadline
+    pub assignments: Vec<GradebookAssignment>,
+    /// The rows, by last name
+    pub students: Vec<GradebookRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GradebookAssignment {
+    pub assignment_id: i32,
+    pub name: String,
+    pub deadline: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GradebookRow {
+    pub name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub username: String,
+    /// The student's id in the campus student information system, if an admin has set one
+    pub sis_id: Option<String>,
+    /// One per assignment, in the same order as [`Gradebook::assignments`]
+    pub cells: Vec<GradebookCell>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GradebookCell {
+    pub assignment_id: i32,
+    /// Late penalties, peer reviews and curves included. `None` => nothing was submitted.
+    pub score: Option<f32>,
+    /// The assignment's curve is applied to the score
+    pub curved: bool,
+    /// The score is one an instructor set, in place of the one the submissions earned
+    pub overridden: bool,
+    /// A task counts a late submission
+    pub late: bool,
+    /// Submissions to the assignment's tasks, counted together
+    pub attempts: i64,
+}
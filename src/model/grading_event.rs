@@ -0,0 +1,51 @@
+use serde::Serialize;
+
+use crate::model::submission_status::SubmissionStatus;
+
+/// What happened to a submission in grading, as streamed to the student while they wait
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GradingEvent {
+    /// Where the submission was when the student started listening
+    Status(SubmissionStatus),
+    /// Waiting for a worker, for the first time or again
+    Queued,
+    /// Claimed by a worker, which is building it
+    Building,
+    /// Built, and its tests are running
+    Running,
+    /// A test has finished
+    Test(TestProgress),
+    /// Grading failed for reasons outside the submission, and will be tried again later
+    Retrying,
+    /// Waiting for the container runtime to come back
+    Delayed,
+    Graded {
+        score: f32,
+    },
+    /// It couldn't be graded
+    Failed {
+        error: String,
+    },
+}
+
+impl GradingEvent {
+    /// Nothing follows it
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            GradingEvent::Graded { .. } | GradingEvent::Failed { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestProgress {
+    /// Position of the test in the task, from 0
+    pub index: usize,
+    pub test_name: String,
+    pub status: String,
+    pub points: f32,
+    /// Fraction of the points a partially correct output earned
+    pub credit: Option<f32>,
+}
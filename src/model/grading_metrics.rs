@@ -0,0 +1,51 @@
+use serde::Serialize;
+
+/// How long grading took over the last `days`, by language, assignment and task
+#[derive(Debug, Serialize)]
+pub struct GradingMetrics {
+    pub days: i32,
+    pub by_language: Vec<LanguageMetrics>,
+    pub by_assignment: Vec<AssignmentMetrics>,
+    pub by_task: Vec<TaskMetrics>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LanguageMetrics {
+    pub lang: String,
+    #[serde(flatten)]
+    pub timings: TimingStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssignmentMetrics {
+    pub assignment_id: i32,
+    pub assignment_name: String,
+    #[serde(flatten)]
+    pub timings: TimingStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskMetrics {
+    pub assignment_id: i32,
+    pub task_id: i32,
+    #[serde(flatten)]
+    pub timings: TimingStats,
+}
+
+/// Seconds spent at each step of grading, over the jobs finished in the period
+#[derive(Debug, Serialize)]
+pub struct TimingStats {
+    pub jobs: i64,
+    /// From submitting until a worker or runner took the job
+    pub queue_wait: Percentiles,
+    pub build: Percentiles,
+    /// Running the tests. Submissions that didn't compile don't count.
+    pub run: Percentiles,
+}
+
+/// `None` => nothing to measure, e.g. because every job was graded before times were recorded
+#[derive(Debug, Serialize)]
+pub struct Percentiles {
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+}
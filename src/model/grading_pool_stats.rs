@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// A snapshot of the grading queue and its workers
+#[derive(Debug, Serialize)]
+pub struct GradingPoolStats {
+    /// How many workers are configured. Retiring workers finish their submission first.
+    pub target_workers: usize,
+    /// Submissions waiting for a worker
+    pub queued: usize,
+    pub queue_capacity: usize,
+    /// `false` once the server has started shutting down
+    pub accepting: bool,
+    pub workers: Vec<WorkerStats>,
+    pub runners: Vec<RunnerStats>,
+    /// Warm containers ready for sample runs, by language
+    pub warm_containers: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerStats {
+    pub id: usize,
+    /// Submissions graded since start-up
+    pub completed: u64,
+    /// Submissions that couldn't be graded
+    pub failed: u64,
+    /// Time spent grading since start-up
+    pub busy_secs: f64,
+    /// The submission being graded, if any
+    pub current: Option<CurrentJob>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurrentJob {
+    pub job_id: i32,
+    pub user_id: i32,
+    pub task_id: i32,
+    pub sample: bool,
+    pub running_secs: f64,
+}
+
+/// A remote runner, as last heard from
+#[derive(Debug, Serialize)]
+pub struct RunnerStats {
+    pub runner_id: String,
+    pub last_seen_secs: f64,
+    /// Ids of the jobs it's grading
+    pub jobs: Vec<i32>,
+    pub completed: u64,
+    pub failed: u64,
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// How often students must accept a class's honor pledge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HonorPledgeMode {
+    /// No pledge is required
+    None,
+    /// Accepted once for the whole course
+    Once,
+    /// Accepted with every submission
+    PerSubmission,
+}
+
+impl HonorPledgeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HonorPledgeMode::None => "none",
+            HonorPledgeMode::Once => "once",
+            HonorPledgeMode::PerSubmission => "per_submission",
+        }
+    }
+}
+
+impl<T> From<T> for HonorPledgeMode
+where
+    T: AsRef<str>,
+{
+    fn from(value: T) -> Self {
+        match value.as_ref() {
+            "once" => HonorPledgeMode::Once,
+            "per_submission" => HonorPledgeMode::PerSubmission,
+            _ => HonorPledgeMode::None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HonorPledge {
+    pub text: String,
+    pub mode: HonorPledgeMode,
+    /// Whether the user has accepted the pledge for the course
+    pub acknowledged: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HonorAcknowledgement {
+    pub username: String,
+    pub assignment_id: Option<i32>,
+    pub task_id: Option<i32>,
+    pub acknowledged_at: String,
+}
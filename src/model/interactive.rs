@@ -0,0 +1,68 @@
+//! Scripts for interactive tests, which alternate between sending the program input and waiting
+//! for its replies. An interactive test's input is its script, one step per line:
+//!
+//! ```text
+//! # Lines starting with # and blank lines are ignored
+//! < Guess a number
+//! > 50
+//! < (?i)too (high|low)
+//! ~ 10
+//! > 25
+//! < Correct!
+//! ```
+//!
+//! `> text` sends a line to the program. `< pattern` waits until the program's unread output
+//! matches the regular expression, and `~ seconds` changes how long each later step may wait.
+
+use std::time::Duration;
+
+use regex::Regex;
+
+/// How long a step may wait for the program until the script says otherwise
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Step {
+    /// A line to write to the program's stdin
+    Send(String),
+    /// A pattern the program's output has to match
+    Expect(Regex),
+    /// How long each following step may take
+    Timeout(Duration),
+}
+
+/// Reads a script, reporting the first malformed line
+pub fn parse(script: &str) -> Result<Vec<Step>, String> {
+    let mut steps = vec![];
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (kind, rest) = line.split_at_checked(1).unwrap_or((line, ""));
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+        let step = match kind {
+            ">" => Step::Send(rest.to_string()),
+            "<" => Regex::new(rest)
+                .map(Step::Expect)
+                .map_err(|e| format!("Line {}: invalid pattern: {e}", number + 1))?,
+            "~" => match rest.trim().parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => {
+                    Step::Timeout(Duration::from_secs_f64(secs))
+                }
+                _ => return Err(format!("Line {}: invalid timeout", number + 1)),
+            },
+            _ => {
+                return Err(format!("Line {}: steps start with >, < or ~", number + 1));
+            }
+        };
+        steps.push(step);
+    }
+
+    if steps.is_empty() {
+        return Err("The script has no steps".into());
+    }
+
+    Ok(steps)
+}
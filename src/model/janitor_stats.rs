@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+/// What the janitor has cleaned up since start-up
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct JanitorStats {
+    pub passes: u64,
+    /// Time since the last pass. `None` => there hasn't been one yet.
+    pub last_pass_secs: Option<f64>,
+    /// Grading containers left behind after they exited
+    pub containers_removed: u64,
+    /// Dangling and old submission images. Docker counts their layers as well.
+    pub images_removed: u64,
+    /// Working and scratch directories left behind
+    pub directories_removed: u64,
+    /// Archives and materials in blob storage nothing refers to anymore
+    pub objects_removed: u64,
+    /// Disk space freed. Podman doesn't say how much pruning frees, so with Podman only the
+    /// directories count.
+    pub reclaimed_bytes: u64,
+    /// Disk space freed by the last pass
+    pub last_reclaimed_bytes: u64,
+}
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+/// A language submissions can be written in, as described by its manifest
+#[derive(Debug, Serialize)]
+pub struct LanguageInfo {
+    /// What submissions name the language by
+    pub lang: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub compile: Option<String>,
+    pub run: Option<String>,
+    /// Where the editor's source goes. `None` => the language can't be submitted from the editor.
+    pub source_file: Option<String>,
+    /// What runs get when the task doesn't set its own limits
+    pub default_limits: DefaultLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DefaultLimits {
+    pub cpus: f32,
+    pub pids_limit: i32,
+    /// In megabytes. `None` => no limit.
+    pub memory_limit_mb: Option<i32>,
+    /// In megabytes
+    pub disk_limit_mb: i32,
+}
+
+/// A language as listed for admins
+#[derive(Debug, Serialize)]
+pub struct AdminLanguageInfo {
+    #[serde(flatten)]
+    pub info: LanguageInfo,
+    pub disabled: bool,
+    #[serde(flatten)]
+    pub usage: LanguageUsage,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LanguageUsage {
+    /// Graded or queued submissions made in the language
+    pub submissions: i64,
+    /// Students who have submitted in it
+    pub students: i64,
+    /// Assignments that name it among the only languages they accept
+    pub restricted_assignments: i64,
+}
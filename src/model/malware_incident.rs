@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// A submission the malware scanner flagged and turned away
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MalwareIncident {
+    pub id: i32,
+    pub username: String,
+    pub assignment_id: i32,
+    pub task_id: i32,
+    /// Whether it was uploaded for a sample run
+    pub sample: bool,
+    /// What the scanner found
+    pub signature: String,
+    /// Of the upload, which isn't kept
+    pub sha256: String,
+    pub size_bytes: i64,
+    pub detected_at: String,
+}
@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub message: String,
+    pub link: Option<String>,
+    pub created: String,
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// Turns an assignment into a peer-reviewed one. After the deadline, every student who submitted
+/// is assigned other students' submissions to score against the rubric.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerReviewSettings {
+    /// How many submissions each student reviews
+    pub reviews_per_student: i32,
+    pub rubric: Vec<RubricCriterion>,
+    /// Fraction of the assignment grade that comes from peer scores, from 0.0 to 1.0
+    pub weight: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RubricCriterion {
+    pub criterion: String,
+    pub max_points: f32,
+}
+
+impl PeerReviewSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.reviews_per_student < 1 {
+            return Err("reviews_per_student must be at least 1.".into());
+        }
+        if self.rubric.is_empty() {
+            return Err("The peer review rubric has no criteria.".into());
+        }
+        if self.rubric.iter().any(|c| c.max_points <= 0.0) {
+            return Err("Every rubric criterion must be worth more than 0 points.".into());
+        }
+        if !(0.0..=1.0).contains(&self.weight) {
+            return Err("Peer review weight must be between 0 and 1.".into());
+        }
+        Ok(())
+    }
+
+    /// Checks a reviewer's scores fit the rubric
+    pub fn check_scores(&self, scores: &[f32]) -> Result<(), String> {
+        if scores.len() != self.rubric.len() {
+            return Err(format!("Expected {} scores.", self.rubric.len()));
+        }
+        if scores
+            .iter()
+            .zip(&self.rubric)
+            .any(|(s, c)| !(0.0..=c.max_points).contains(s))
+        {
+            return Err("A score is outside its criterion's range.".into());
+        }
+        Ok(())
+    }
+
+    /// A review's scores as a fraction of the rubric's total points
+    pub fn fraction(&self, scores: &[f32]) -> f32 {
+        let max = self.rubric.iter().map(|c| c.max_points).sum::<f32>();
+        scores.iter().sum::<f32>() / max
+    }
+
+    /// Combines the autograded score with the average peer score
+    pub fn blend(&self, autograded: f32, peer_score: f32) -> f32 {
+        autograded * (1.0 - self.weight) + peer_score * self.weight
+    }
+}
+
+/// A submission the student has been asked to review. The author is never revealed.
+#[derive(Debug, Serialize)]
+pub struct PeerReview {
+    pub review_id: i32,
+    pub scores: Option<Vec<f32>>,
+    pub comments: Option<String>,
+    pub completed: bool,
+}
+
+/// A review of the student's own submission. The reviewer is never revealed.
+#[derive(Debug, Serialize)]
+pub struct ReceivedReview {
+    pub scores: Vec<f32>,
+    pub comments: Option<String>,
+}
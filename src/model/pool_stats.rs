@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+/// A snapshot of the database connection pool
+#[derive(Debug, Serialize)]
+pub struct PoolStats {
+    /// Connections currently open
+    pub size: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub max_connections: u32,
+    /// Every connection is open and busy, so new requests have to wait
+    pub saturated: bool,
+}
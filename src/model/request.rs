@@ -1,23 +1,275 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::model::{
+    comparison::{ComparisonMode, Tolerance},
+    peer_review::PeerReviewSettings,
+    submission_response::ResultVisibility,
+    test_method::TestMethod,
+};
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Test {
+    /// Present when editing an existing test, so it can be updated in place
+    pub test_id: Option<i32>,
     pub test_name: Option<String>,
     pub is_public: bool,
     pub input: Option<String>,
     pub output: Option<String>,
     pub input_file_base64: Option<String>,
     pub output_file_base64: Option<String>,
+    /// Index into the task's `variant_descriptions`. `None` => run for every variant.
+    pub variant: Option<i32>,
+    #[serde(default)]
+    pub comparison: ComparisonMode,
+    /// Only used by `ComparisonMode::Numeric`. `None` => the default tolerance.
+    #[serde(default)]
+    pub tolerance: Option<Tolerance>,
+    /// Weight of the test within its task
+    #[serde(default = "default_points")]
+    pub points: f32,
+    /// Award part of the points to outputs that are partly right, by how many lines match
+    #[serde(default)]
+    pub partial_credit: bool,
+    /// Shown to students when the test fails, without revealing its input or output
+    #[serde(default)]
+    pub hint: Option<String>,
+    /// Files placed in the program's working directory while the test runs
+    #[serde(default)]
+    pub fixtures: Vec<Fixture>,
+    /// Environment variables set for the program while the test runs
+    #[serde(default)]
+    pub env: Vec<EnvVar>,
+    /// Overrides the task's timeout for this test, in seconds
+    #[serde(default)]
+    pub timeout: Option<i32>,
+    /// Overrides the task's memory limit for this test
+    #[serde(default)]
+    pub memory_limit_mb: Option<i32>,
+    /// Name of a file the program must write in its working directory. Its contents are
+    /// compared with the expected output instead of what the program printed.
+    #[serde(default)]
+    pub artifact: Option<String>,
+    /// Only run when a student asks for a sample run, which never affects their grade
+    #[serde(default)]
+    pub sample: bool,
+    /// The input is a script of lines to send and replies to expect, instead of all of stdin at
+    /// once. The expected output is unused.
+    #[serde(default)]
+    pub interactive: bool,
+}
+
+fn default_points() -> f32 {
+    1.0
+}
+
+/// Splits raw test data into `(text, base64)`: text if it is valid UTF-8, otherwise base64 for the
+/// matching `*_file_base64` field
+pub fn text_or_base64(bytes: Vec<u8>) -> (Option<String>, Option<String>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (Some(text), None),
+        Err(e) => (
+            None,
+            Some(base64::prelude::BASE64_STANDARD.encode(e.into_bytes())),
+        ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// A plain file name, without any directories
+    pub filename: String,
+    pub data_base64: String,
+}
+
+impl Fixture {
+    pub fn has_valid_name(&self) -> bool {
+        is_plain_filename(&self.filename)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+    /// Hidden from students wherever the test's results show it
+    #[serde(default)]
+    pub secret: bool,
+}
+
+impl EnvVar {
+    /// Letters, digits and underscores, not starting with a digit
+    pub fn has_valid_name(&self) -> bool {
+        let mut chars = self.name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+}
+
+/// Whether the name can't escape the working directory it is placed in, and is safe to pass to
+/// `docker run --mount` and `docker cp`
+pub fn is_plain_filename(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0', ',', ':'])
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Task {
+    /// Present when editing an existing task, so its grades survive the update
+    pub task_id: Option<i32>,
     pub task_description: String,
     pub allow_editor: bool,
     pub material_base64: Option<String>,
     pub material_filename: Option<String>,
+    /// Starter code handed to students, and used to pre-fill the editor when `allow_editor` is set
+    pub template_base64: Option<String>,
+    pub template_filename: Option<String>,
     pub timeout: Option<i32>,
-    pub tests: Vec<Test>
+    /// Memory available to the program while each test runs, in megabytes. `None` => the
+    /// server's default.
+    #[serde(default)]
+    pub memory_limit_mb: Option<i32>,
+    /// CPUs each run may use, e.g. 0.5. `None` => the server's default.
+    #[serde(default)]
+    pub cpus: Option<f32>,
+    /// Processes and threads each run may have at once. `None` => the server's default.
+    #[serde(default)]
+    pub pids_limit: Option<i32>,
+    /// Size of the writable `/tmp` each run gets, in megabytes. `None` => the server's default.
+    #[serde(default)]
+    pub disk_limit_mb: Option<i32>,
+    pub tests: Vec<Test>,
+    /// One entry per variant of the task. Each student is assigned one variant and only sees its
+    /// description and is graded against its tests. Empty => the task has no variants.
+    #[serde(default)]
+    pub variant_descriptions: Vec<String>,
+    /// Keeps the task locked until an earlier task in the assignment is passed
+    #[serde(default)]
+    pub prerequisite: Option<Prerequisite>,
+    /// Run the tests in the order they are listed
+    #[serde(default)]
+    pub ordered_tests: bool,
+    /// Skip the remaining tests once one fails. Implies `ordered_tests`.
+    #[serde(default)]
+    pub stop_on_failure: bool,
+    /// `stdio`, `http:<port>` for submissions that are web services, or `junit` to grade from a
+    /// unit-test framework's report
+    #[serde(default)]
+    pub test_method: TestMethod,
+    /// Shell command that runs the instructor's unit tests and writes `junit.xml` to the working
+    /// directory, e.g. `pytest --junitxml=junit.xml`. Required by `junit` tasks.
+    #[serde(default)]
+    pub test_command: Option<String>,
+    /// Run each test again under a memory checker (valgrind) and report the errors it finds. Only
+    /// languages whose container supports it are checked.
+    #[serde(default)]
+    pub memory_check: bool,
+    /// Fraction of the task's points (0 to 1) deducted when memory errors are found. `None` =>
+    /// errors are only reported as warnings.
+    #[serde(default)]
+    pub memory_error_penalty: Option<f32>,
+    /// Fraction of the grade (0 to 1) that comes from the language's linter. `None` => no style
+    /// check. Languages without a linter are graded on their tests alone.
+    #[serde(default)]
+    pub lint_weight: Option<f32>,
+    /// Let the submission use the network while it is tested. Off by default, so student code
+    /// can't reach external services. Without it, web service tasks can only be reached by the
+    /// grader.
+    #[serde(default)]
+    pub network_access: bool,
+    /// Overrides the assignment's `max_attempts` for this task
+    #[serde(default)]
+    pub max_attempts: Option<i32>,
+    /// Minutes a student has to wait between submissions. `None` => no wait.
+    #[serde(default)]
+    pub cooldown_minutes: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prerequisite {
+    /// Zero-based position of the earlier task within the assignment
+    pub task_placement: i32,
+    /// Fraction of the earlier task's tests that must pass, from 0.0 to 1.0
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+}
+
+fn default_threshold() -> f32 {
+    1.0
+}
+
+/// Per-assignment settings, sent alongside the assignment's content when creating or updating it
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssignmentSettings {
+    /// `None` => any supported language
+    pub allowed_languages: Option<Vec<String>>,
+    pub result_visibility: ResultVisibility,
+    /// Minutes after the deadline during which submissions are still on time
+    pub grace_period_minutes: i32,
+    /// Penalties for late submissions. Empty => the server's default late multiplier applies.
+    pub late_tiers: Vec<LateTier>,
+    /// Must be one of the class's categories. `None` => not counted towards the course grade.
+    pub category_id: Option<i32>,
+    /// `None` => the assignment is only autograded
+    pub peer_review: Option<PeerReviewSettings>,
+    /// Submissions each student may make to each task. Tasks can set their own. `None` =>
+    /// unlimited.
+    pub max_attempts: Option<i32>,
+    /// When submissions stop being accepted at all, late or not, in the same format as the
+    /// deadline. `None` => late submissions are accepted whenever they come.
+    pub accept_until: Option<String>,
+}
+
+impl AssignmentSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts.is_some_and(|n| n < 1) {
+            return Err("max_attempts must be at least 1.".into());
+        }
+        self.accept_until()?;
+        match &self.peer_review {
+            Some(peer_review) => peer_review.validate(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn accept_until(&self) -> Result<Option<DateTime<Utc>>, String> {
+        self.accept_until
+            .as_deref()
+            .map(|t| t.parse::<DateTime<Utc>>())
+            .transpose()
+            .map_err(|e| format!("Could not parse accept_until: {e}"))
+    }
+}
+
+/// A late submission window, e.g. `-10%` for submissions within 24 hours of the deadline.
+/// Submissions after the last tier receive no credit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LateTier {
+    pub hours_after_deadline: i32,
+    /// Fraction of the score deducted, from 0.0 to 1.0
+    pub penalty: f32,
+}
+
+/// Returns the multiplier applied to a submission's score given the assignment's late tiers,
+/// or `None` if the assignment has none
+pub fn tier_multiplier(tiers: &[LateTier], hours_late: f64) -> Option<f32> {
+    if tiers.is_empty() {
+        return None;
+    }
+
+    let mut tiers = tiers.to_vec();
+    tiers.sort_by_key(|t| t.hours_after_deadline);
+
+    Some(
+        tiers
+            .iter()
+            .find(|t| hours_late < t.hours_after_deadline as f64)
+            .map(|t| 1.0 - t.penalty.clamp(0.0, 1.0))
+            .unwrap_or(0.0),
+    )
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -45,6 +297,12 @@ pub struct ClientRequest {
     pub assignment_description: Option<String>,
     pub deadline: Option<String>,
     pub tasks: Option<Vec<Task>>,
+    #[serde(flatten)]
+    pub settings: AssignmentSettings,
+
+    // Assignment Attachment
+    pub attachment_base64: Option<String>,
+    pub attachment_filename: Option<String>,
 
     // Submission
     pub assignment_id: Option<i32>,
@@ -53,6 +311,38 @@ pub struct ClientRequest {
 
     // Join Class
     pub join_code: Option<String>,
+
+    // Honor Pledge
+    pub honor_pledge: Option<String>,
+    pub honor_pledge_mode: Option<String>,
+
+    // Assignment Category
+    pub category_name: Option<String>,
+    pub category_weight: Option<f32>,
+    /// How many of the category's lowest assignment scores don't count. `None` => 0.
+    pub category_drop_lowest: Option<i32>,
+
+    // Peer Review
+    pub review_scores: Option<Vec<f32>>,
+    pub review_comments: Option<String>,
+
+    // Email Template
+    pub email_template_name: Option<String>,
+    pub email_subject: Option<String>,
+    pub email_body: Option<String>,
+
+    // Isolation
+    pub isolation: Option<String>,
+
+    // Grading Quota
+    pub grading_quota: Option<i32>,
+
+    // Student Information System
+    pub sis_id: Option<String>,
+
+    // Language Definition
+    pub dockerfile: Option<String>,
+    pub language_manifest: Option<String>,
 }
 
 impl ClientRequest {
@@ -100,3 +390,175 @@ impl ClientRequest {
         }
     }
 }
+
+/// Query parameters accepted by the admin delete endpoints
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DeleteQuery {
+    pub class_number: Option<String>,
+    pub assignment_id: Option<i32>,
+    pub user_name: Option<String>,
+    /// Report what would be removed without deleting anything
+    pub dry_run: bool,
+}
+
+/// Query parameters accepted by the admin research export endpoints
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportQuery {
+    pub class_number: Option<String>,
+    pub download_token: Option<String>,
+}
+
+/// Query parameters accepted by the student submission download endpoint
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AttemptQuery {
+    /// `None` => the latest
+    pub attempt: Option<i32>,
+}
+
+/// Starts a resumable upload of a submission
+#[derive(Debug, Deserialize)]
+pub struct UploadRequest {
+    /// Of the whole archive, in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the whole archive, checked once it has all arrived
+    pub sha256: String,
+}
+
+/// Query parameters accepted by the upload chunk endpoint
+#[derive(Debug, Deserialize)]
+pub struct ChunkQuery {
+    /// Where the chunk goes in the archive, in bytes
+    pub offset: u64,
+}
+
+/// The limit an instructor sets on one student's submissions to a task
+#[derive(Debug, Deserialize)]
+pub struct AttemptOverride {
+    /// `None` => unlimited
+    pub max_attempts: Option<i32>,
+}
+
+/// The score an instructor sets for one student on an assignment
+#[derive(Debug, Deserialize)]
+pub struct GradeOverride {
+    /// Fraction of the assignment's points. Above 1 => extra credit.
+    pub score: f32,
+    /// Why, for the record
+    pub reason: Option<String>,
+}
+
+/// Query parameters accepted by the grading metrics endpoints. Only admins choose a class.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsQuery {
+    pub class_number: Option<String>,
+    pub days: Option<i32>,
+}
+
+impl MetricsQuery {
+    /// How many days back the metrics go, 30 unless another number is asked for
+    pub fn days(&self) -> i32 {
+        self.days.unwrap_or(30)
+    }
+}
+
+/// Query parameters accepted by the similarity report endpoint
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SimilarityQuery {
+    pub min_similarity: Option<f32>,
+}
+
+impl SimilarityQuery {
+    /// The least alike a pair can be to be listed, 0.5 unless another share is asked for
+    pub fn min_similarity(&self) -> f32 {
+        self.min_similarity.unwrap_or(0.5)
+    }
+}
+
+/// Query parameters accepted by the grade CSV endpoints
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CsvQuery {
+    pub format: CsvFormat,
+}
+
+/// Which gradebook a grade CSV is laid out for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvFormat {
+    /// A column per assignment, students by username
+    #[default]
+    Plain,
+    /// Canvas's Gradebook import, students by SIS User ID or SIS Login ID
+    Canvas,
+    /// Moodle's grade import, students by ID number or username
+    Moodle,
+}
+
+/// Query parameters accepted by the admin email template endpoints
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EmailTemplateQuery {
+    pub template_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiers(tiers: &[(i32, f32)]) -> Vec<LateTier> {
+        tiers
+            .iter()
+            .map(|&(hours_after_deadline, penalty)| LateTier {
+                hours_after_deadline,
+                penalty,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_tiers_has_no_multiplier() {
+        assert_eq!(tier_multiplier(&[], 5.0), None);
+    }
+
+    #[test]
+    fn picks_the_first_tier_not_yet_passed() {
+        let tiers = tiers(&[(24, 0.1), (48, 0.25)]);
+
+        assert_eq!(tier_multiplier(&tiers, 0.5), Some(0.9));
+        assert_eq!(tier_multiplier(&tiers, 30.0), Some(0.75));
+    }
+
+    #[test]
+    fn sorts_tiers_first() {
+        let tiers = tiers(&[(48, 0.25), (24, 0.1)]);
+
+        assert_eq!(tier_multiplier(&tiers, 0.5), Some(0.9));
+        assert_eq!(tier_multiplier(&tiers, 30.0), Some(0.75));
+    }
+
+    #[test]
+    fn a_boundary_belongs_to_the_next_tier() {
+        let tiers = tiers(&[(24, 0.1), (48, 0.25)]);
+
+        assert_eq!(tier_multiplier(&tiers, 24.0), Some(0.75));
+        assert_eq!(tier_multiplier(&tiers, 48.0), Some(0.0));
+    }
+
+    #[test]
+    fn past_the_last_tier_gets_no_credit() {
+        assert_eq!(tier_multiplier(&tiers(&[(24, 0.1)]), 100.0), Some(0.0));
+    }
+
+    #[test]
+    fn clamps_penalties() {
+        let tiers = tiers(&[(24, -0.5), (48, 1.5)]);
+
+        assert_eq!(tier_multiplier(&tiers, 1.0), Some(1.0));
+        assert_eq!(tier_multiplier(&tiers, 30.0), Some(0.0));
+    }
+}
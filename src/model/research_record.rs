@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+use crate::model::submission_response::SubmissionResponse;
+
+/// One graded task attempt in a research export. Students are identified only by a pseudonym.
+#[derive(Debug, Serialize)]
+pub struct ResearchRecord {
+    /// Salted hash of the student's id, stable within an export
+    pub student: String,
+    pub assignment_id: i32,
+    pub task_id: i32,
+    pub language: Option<String>,
+    pub toolchain_version: Option<String>,
+    pub deadline: String,
+    pub submitted_at: Option<String>,
+    pub graded_at: Option<String>,
+    /// Negative when the attempt was submitted after the deadline
+    pub seconds_before_deadline: Option<i64>,
+    pub was_late: bool,
+    pub late_multiplier: Option<f32>,
+    pub grade: Option<f32>,
+    /// Test statuses only; inputs and outputs are left out
+    pub results: Option<SubmissionResponse>,
+    /// Path of the submitted code inside the archive
+    pub submission: Option<String>,
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+/// The latest similarity check of an assignment, with the pairs of students whose submissions to a
+/// task look alike
+#[derive(Debug, Serialize)]
+pub struct SimilarityReport {
+    pub report_id: i32,
+    /// `queued`, `running`, `done` or `failed`
+    pub status: String,
+    pub requested_at: String,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+    /// Most similar first
+    pub pairs: Vec<SimilarPair>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarPair {
+    pub task_id: i32,
+    pub student_a: String,
+    pub student_b: String,
+    /// Share of the smaller submission's fingerprints found in the other, from 0 to 1
+    pub similarity: f32,
+    pub matches: Vec<MatchedRegion>,
+}
+
+/// Lines of one student's file that match lines of the other's
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchedRegion {
+    pub file_a: String,
+    pub start_line_a: u32,
+    pub end_line_a: u32,
+    pub file_b: String,
+    pub start_line_b: u32,
+    pub end_line_b: u32,
+}
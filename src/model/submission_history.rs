@@ -0,0 +1,30 @@
+use serde::Serialize;
+
+/// Every submission a student made of a task, oldest first
+#[derive(Debug, Serialize)]
+pub struct SubmissionHistory {
+    /// The attempt the task's grade comes from, which is the latest. `None` => there is none,
+    /// e.g. because the latest was cancelled.
+    pub counted_attempt: Option<i32>,
+    /// `None` => unlimited
+    pub max_attempts: Option<i32>,
+    /// `None` => unlimited
+    pub remaining_attempts: Option<i32>,
+    pub attempts: Vec<Attempt>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Attempt {
+    /// Numbered from 1
+    pub attempt: i32,
+    pub lang: Option<String>,
+    pub submitted_at: Option<String>,
+    pub was_late: Option<bool>,
+    /// Of the archive, as in the receipt handed out when it was submitted
+    pub sha256: Option<String>,
+    /// `None` => not graded yet
+    pub grade: Option<f32>,
+    /// Why it couldn't be graded, if it couldn't
+    pub error: Option<String>,
+    pub graded_at: Option<String>,
+}
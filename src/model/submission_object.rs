@@ -0,0 +1,95 @@
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+
+use crate::model::request::is_plain_filename;
+
+/// A submission sent as JSON instead of an archive, e.g. from the browser editor. It has either a
+/// single `source` or a tree of `files`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SubmissionObject {
+    /// The text of a single file, put wherever the language expects its source. Only accepted
+    /// by tasks that allow the editor.
+    pub source: Option<String>,
+    pub files: Vec<FileObject>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileObject {
+    /// Directory the file is in, relative to the top of the submission, e.g. `src/util`. Empty =>
+    /// the top.
+    #[serde(default)]
+    pub parent_path: String,
+    pub name: String,
+    /// The file's text
+    pub data: String,
+}
+
+impl FileObject {
+    /// Where the file goes in the submission. `None` => it would land outside it.
+    pub fn path(&self) -> Option<String> {
+        let mut components: Vec<&str> = self
+            .parent_path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .collect();
+        components.push(&self.name);
+
+        components
+            .iter()
+            .all(|c| is_plain_filename(c))
+            .then(|| components.join("/"))
+    }
+}
+
+impl SubmissionObject {
+    /// The submission's files as paths and contents, ready to be packed. The error is meant for
+    /// whoever sent it.
+    pub fn into_files(self, source_file: Option<String>) -> Result<Vec<(String, Vec<u8>)>, String> {
+        if let Some(source) = self.source {
+            if !self.files.is_empty() {
+                return Err("Send either source or files, not both.".into());
+            }
+            let Some(source_file) = source_file else {
+                return Err("That language can't be submitted from the editor.".into());
+            };
+            return Ok(vec![(source_file, source.into_bytes())]);
+        }
+
+        if self.files.is_empty() {
+            return Err("The submission has no files.".into());
+        }
+
+        let mut paths = BTreeSet::new();
+        let files = self
+            .files
+            .into_iter()
+            .map(|file| {
+                let Some(path) = file.path() else {
+                    return Err(format!(
+                        "{}/{} is not a valid path inside the submission.",
+                        file.parent_path, file.name
+                    ));
+                };
+                if !paths.insert(path.clone()) {
+                    return Err(format!("{path} is in the submission more than once."));
+                }
+                Ok((path, file.data.into_bytes()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // A file can't also be a directory
+        if let Some(path) = paths.iter().find(|path| {
+            let dir = format!("{path}/");
+            paths
+                .range(dir.clone()..)
+                .next()
+                .is_some_and(|next| next.starts_with(&dir))
+        }) {
+            return Err(format!("{path} is both a file and a directory."));
+        }
+
+        Ok(files)
+    }
+}
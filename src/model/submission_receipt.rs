@@ -0,0 +1,23 @@
+use serde::Serialize;
+
+/// Proof of what a student submitted and when, returned when the submission is accepted and kept
+/// with the attempt
+#[derive(Debug, Clone, Serialize)]
+pub struct SubmissionReceipt {
+    pub attempt: i32,
+    /// When the server received the submission
+    pub submitted_at: String,
+    /// Hex-encoded SHA-256 of the archive as it was stored
+    pub sha256: String,
+    pub was_late: bool,
+}
+
+/// Body of the response to an accepted submission
+#[derive(Debug, Serialize)]
+pub struct SubmissionAccepted {
+    pub message: &'static str,
+    pub receipt: SubmissionReceipt,
+    /// Attempts left after this one. Left out when they're unlimited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_attempts: Option<i32>,
+}
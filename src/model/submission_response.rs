@@ -1,16 +1,152 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::{container, model::grading_event::TestProgress};
+
+/// How much of a graded submission students are allowed to see, set per assignment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultVisibility {
+    /// Only the overall score
+    ScoreOnly,
+    /// The status of each test, without any input or output
+    PassFail,
+    /// Statuses, plus input/expected/found for public tests
+    #[default]
+    PublicDiffs,
+    /// Statuses, plus input/expected/found for every test
+    Everything,
+}
+
+impl ResultVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultVisibility::ScoreOnly => "score_only",
+            ResultVisibility::PassFail => "pass_fail",
+            ResultVisibility::PublicDiffs => "public_diffs",
+            ResultVisibility::Everything => "everything",
+        }
+    }
+}
+
+impl<T> From<T> for ResultVisibility
+where
+    T: AsRef<str>,
+{
+    fn from(value: T) -> Self {
+        match value.as_ref() {
+            "score_only" => ResultVisibility::ScoreOnly,
+            "pass_fail" => ResultVisibility::PassFail,
+            "everything" => ResultVisibility::Everything,
+            _ => ResultVisibility::PublicDiffs,
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Test {
     test_name: String,
     status: String,
+    /// Results stored before visibility levels existed only carried IO for public tests
+    #[serde(default = "default_public")]
+    public: bool,
+    /// Results stored before point values existed weighted every test equally
+    #[serde(default = "default_points")]
+    points: f32,
+    /// Fraction of the points awarded to a partially correct output
+    #[serde(default)]
+    credit: Option<f32>,
+    /// The instructor's hint, only kept when the test wasn't passed
+    #[serde(default)]
+    hint: Option<String>,
+    /// What the memory checker reported when the test was run under it
+    #[serde(default)]
+    memory_errors: Option<String>,
     input_output: Option<InputOutput>,
 }
 
+impl Test {
+    /// Removes the test's IO, including any memory checker report that might quote it
+    fn hide_io(&mut self) {
+        self.input_output = None;
+        if self.memory_errors.is_some() {
+            self.memory_errors = Some("Memory errors were found.".into());
+        }
+    }
+}
+
+fn default_public() -> bool {
+    true
+}
+
+fn default_points() -> f32 {
+    1.0
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SubmissionResponse {
     tests: Vec<Test>,
     passes: usize,
+    #[serde(default)]
+    total_tests: usize,
+    #[serde(default)]
+    points_earned: f32,
+    #[serde(default)]
+    points_possible: f32,
+    /// The language the attempt was graded in
+    #[serde(default)]
+    language: Option<String>,
+    /// Fingerprint of the language's container when the attempt was graded
+    #[serde(default)]
+    toolchain_version: Option<String>,
+    /// The language's container has changed since this attempt was graded
+    #[serde(default)]
+    toolchain_outdated: bool,
+    /// What the compiler printed, when the submission failed to compile
+    #[serde(default)]
+    compiler_output: Option<String>,
+    /// Points deducted because the memory checker found errors
+    #[serde(default)]
+    memory_penalty: f32,
+    /// The style check, when the task's grade includes one
+    #[serde(default)]
+    lint: Option<LintReport>,
+    /// Why the submission couldn't be graded, when it couldn't
+    #[serde(default)]
+    error: Option<String>,
+    /// Everything the build printed, when the submission didn't compile. Only instructors see it,
+    /// so it's taken out before the results are stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    build_log: Option<String>,
+    /// How long building and running took. Kept for grading metrics, so it's taken out before the
+    /// results are stored too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    times: Option<GradingTimes>,
+}
+
+/// How long a submission took to grade
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GradingTimes {
+    pub build_secs: f64,
+    /// `None` => nothing ran, because the submission didn't compile
+    pub run_secs: Option<f64>,
+}
+
+/// Fraction of the lint credit lost for each finding
+const LINT_PENALTY_PER_FINDING: f32 = 0.1;
+
+/// The most findings kept in a result
+const MAX_LINT_FINDINGS: usize = 100;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LintReport {
+    /// Fraction of the task's grade that comes from the style check
+    weight: f32,
+    /// Fraction of that part earned (0.0 to 1.0)
+    credit: f32,
+    total_findings: usize,
+    findings: Vec<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -18,126 +154,387 @@ pub struct InputOutput {
     input: String,
     expected: String,
     found: String,
+    /// The environment the test ran with, secret values hidden
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+    /// Fields cut short for being too long. The full text can be fetched separately.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    truncated: Vec<String>,
+}
+
+/// A test's input, expected or found output in full, kept aside because it was too long to
+/// include in the result
+pub struct FullOutput {
+    pub test_index: i32,
+    /// `input`, `expected` or `found`
+    pub field: &'static str,
+    pub contents: String,
+}
+
+/// Cuts `text` down to `max_lines` lines and `max_bytes` bytes (0 => no limit), ending it with a
+/// note of how much was left out. `None` if it already fits.
+fn truncate_text(text: &str, max_lines: usize, max_bytes: usize) -> Option<String> {
+    let total_lines = text.lines().count();
+    let too_many_lines = max_lines > 0 && total_lines > max_lines;
+    let too_many_bytes = max_bytes > 0 && text.len() > max_bytes;
+    if !too_many_lines && !too_many_bytes {
+        return None;
+    }
+
+    let mut end = text.len();
+    if too_many_lines && let Some((newline, _)) = text.match_indices('\n').nth(max_lines - 1) {
+        end = newline;
+    }
+    if too_many_bytes {
+        end = end.min(max_bytes);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+    }
+
+    let kept = &text[..end];
+    let missing_lines = total_lines - kept.lines().count();
+    let note = match missing_lines {
+        0 => format!("... {} more bytes", text.len() - end),
+        1 => "\n... 1 more line".to_string(),
+        n => format!("\n... {n} more lines"),
+    };
+
+    Some(format!("{kept}{note}"))
+}
+
+/// Shown in place of secret environment variables
+pub const HIDDEN: &str = "[hidden]";
+
+/// What a result records about the test it came from
+pub struct TestMeta {
+    pub test_name: Option<String>,
+    pub public: bool,
+    pub points: f32,
+    pub hint: Option<String>,
+    /// Environment variables as shown to students, secret values already hidden
+    pub env: BTreeMap<String, String>,
+    /// Secret values to hide wherever they appear in the test's IO
+    pub secrets: Vec<String>,
 }
 
 impl SubmissionResponse {
-    pub fn pass(&mut self, test_name: Option<impl Into<String>>, was_late: bool) {
+    /// Records a test result. Input and output are kept for every test; what students actually
+    /// see is decided later by [`SubmissionResponse::redact`].
+    fn push(
+        &mut self,
+        meta: TestMeta,
+        status: impl Into<String>,
+        input: impl Into<String>,
+        expected: impl Into<String>,
+        found: impl Into<String>,
+    ) {
+        let mask = |text: String| {
+            meta.secrets
+                .iter()
+                .filter(|secret| !secret.is_empty())
+                .fold(text, |text, secret| text.replace(secret.as_str(), HIDDEN))
+        };
+        let input_output = InputOutput {
+            input: mask(input.into()),
+            expected: mask(expected.into()),
+            found: mask(found.into()),
+            env: meta.env,
+            truncated: vec![],
+        };
+
         self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: if was_late { "LATE".into() } else { "PASS".into() },
-            input_output: None,
+            test_name: meta.test_name.unwrap_or("".into()),
+            status: status.into(),
+            public: meta.public,
+            points: meta.points,
+            credit: None,
+            hint: meta.hint,
+            memory_errors: None,
+            input_output: Some(input_output),
         });
-        self.passes += 1;
+        self.total_tests = self.tests.len();
+        self.points_possible += meta.points;
     }
 
-    pub fn pub_pass(
+    pub fn pass(
         &mut self,
-        test_name: Option<impl Into<String>>,
+        mut meta: TestMeta,
         was_late: bool,
         input: impl Into<String>,
         expected: impl Into<String>,
         found: impl Into<String>,
     ) {
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: if was_late { "LATE".into() } else { "PASS".into() },
-            input_output: Some(InputOutput {
-                input: input.into(),
-                expected: expected.into(),
-                found: found.into(),
-            }),
-        });
+        let status = if was_late { "LATE" } else { "PASS" };
+        self.points_earned += meta.points;
+        meta.hint = None;
+        self.push(meta, status, input, expected, found);
         self.passes += 1;
     }
 
-    pub fn fail(&mut self, test_name: Option<impl Into<String>>) {
-        // self.tests.push((test_name.into(), TestStatus::Fail));
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "FAIL".into(),
-            input_output: None,
-        })
+    pub fn fail(
+        &mut self,
+        meta: TestMeta,
+        input: impl Into<String>,
+        expected: impl Into<String>,
+        found: impl Into<String>,
+    ) {
+        self.push(meta, "FAIL", input, expected, found);
     }
 
-    pub fn pub_fail(
+    /// A failed test that still earns `credit` (0.0 to 1.0) of its points
+    pub fn partial(
         &mut self,
-        test_name: Option<impl Into<String>>,
+        meta: TestMeta,
+        credit: f32,
         input: impl Into<String>,
         expected: impl Into<String>,
         found: impl Into<String>,
     ) {
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "FAIL".into(),
-            input_output: Some(InputOutput {
-                input: input.into(),
-                expected: expected.into(),
-                found: found.into(),
-            }),
-        });
+        self.points_earned += meta.points * credit;
+        self.push(meta, "PARTIAL", input, expected, found);
+        if let Some(test) = self.tests.last_mut() {
+            test.credit = Some(credit);
+        }
     }
 
-    pub fn time_out(&mut self, test_name: Option<impl Into<String>>) {
-        // self.tests.push((test_name.into(), TestStatus::TimeOut));
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "TIMED OUT".into(),
-            input_output: None,
-        })
+    pub fn time_out(
+        &mut self,
+        meta: TestMeta,
+        input: impl Into<String>,
+        expected: impl Into<String>,
+    ) {
+        self.push(meta, "TIMED OUT", input, expected, "");
     }
 
-    pub fn pub_time_out(
+    /// The program was killed for going over the test's memory limit
+    pub fn out_of_memory(
         &mut self,
-        test_name: Option<impl Into<String>>,
+        meta: TestMeta,
         input: impl Into<String>,
         expected: impl Into<String>,
     ) {
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "TIMED OUT".into(),
-            input_output: Some(InputOutput {
-                input: input.into(),
-                expected: expected.into(),
-                found: "".into(),
-            }),
-        });
+        self.push(meta, "OUT OF MEMORY", input, expected, "");
     }
 
-    pub fn err(&mut self, test_name: Option<impl Into<String>>) {
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "ERR".into(),
-            input_output: None,
-        })
+    /// The program was stopped for printing more than `output_limit_bytes`
+    pub fn output_limit_exceeded(
+        &mut self,
+        meta: TestMeta,
+        input: impl Into<String>,
+        expected: impl Into<String>,
+    ) {
+        self.push(meta, "OUTPUT LIMIT EXCEEDED", input, expected, "");
     }
 
-    pub fn pub_err(
+    pub fn err(
         &mut self,
-        test_name: Option<impl Into<String>>,
+        meta: TestMeta,
         input: impl Into<String>,
         expected: impl Into<String>,
         found: impl Into<String>,
     ) {
-        self.tests.push(Test {
-            // test_name: test_name.and_then(|f| Some(f.into())).unwrap_or("".into()),
-            test_name: test_name.map(|f| f.into()).unwrap_or("".into()),
-            status: "ERR".into(),
-            input_output: Some(InputOutput {
-                input: input.into(),
-                expected: expected.into(),
-                found: found.into(),
-            }),
+        self.push(meta, "ERR", input, expected, found);
+    }
+
+    /// A test that never ran because an earlier test failed
+    pub fn skip(&mut self, meta: TestMeta, input: impl Into<String>, expected: impl Into<String>) {
+        self.push(meta, "SKIPPED", input, expected, "");
+    }
+
+    /// A test that never ran because the submission didn't compile
+    pub fn compile_error(
+        &mut self,
+        meta: TestMeta,
+        input: impl Into<String>,
+        expected: impl Into<String>,
+    ) {
+        self.push(meta, "COMPILE ERROR", input, expected, "");
+    }
+
+    /// Shortens every input, expected and found output longer than the limits, returning the
+    /// full text of each one that was cut
+    pub fn truncate_io(&mut self, max_lines: usize, max_bytes: usize) -> Vec<FullOutput> {
+        let mut full_outputs = vec![];
+
+        for (index, test) in self.tests.iter_mut().enumerate() {
+            let Some(io) = &mut test.input_output else {
+                continue;
+            };
+
+            let mut truncated = vec![];
+            for (field, text) in [
+                ("input", &mut io.input),
+                ("expected", &mut io.expected),
+                ("found", &mut io.found),
+            ] {
+                if let Some(short) = truncate_text(text, max_lines, max_bytes) {
+                    full_outputs.push(FullOutput {
+                        test_index: index as i32,
+                        field,
+                        contents: std::mem::replace(text, short),
+                    });
+                    truncated.push(field.to_string());
+                }
+            }
+            io.truncated = truncated;
+        }
+
+        full_outputs
+    }
+
+    /// The status of the `index`th test, without its IO. `None` => it hasn't finished yet.
+    pub fn test_progress(&self, index: usize) -> Option<TestProgress> {
+        self.tests.get(index).map(|test| TestProgress {
+            index,
+            test_name: test.test_name.clone(),
+            status: test.status.clone(),
+            points: test.points,
+            credit: test.credit,
+        })
+    }
+
+    /// Whether the student may see the IO of the `index`th test under the given visibility level
+    pub fn io_visible(&self, index: usize, visibility: ResultVisibility) -> bool {
+        self.tests.get(index).is_some_and(|test| {
+            test.input_output.is_some()
+                && match visibility {
+                    ResultVisibility::Everything => true,
+                    ResultVisibility::PublicDiffs => test.public,
+                    ResultVisibility::PassFail | ResultVisibility::ScoreOnly => false,
+                }
+        })
+    }
+
+    pub fn with_compiler_output(mut self, compiler_output: String) -> Self {
+        self.compiler_output = Some(compiler_output);
+        self
+    }
+
+    pub fn with_error(mut self, error: Option<String>) -> Self {
+        self.error = error;
+        self
+    }
+
+    pub fn with_build_log(mut self, log: String) -> Self {
+        self.build_log = Some(log);
+        self
+    }
+
+    /// Removes the build log, to be kept apart from what students see
+    pub fn take_build_log(&mut self) -> Option<String> {
+        self.build_log.take()
+    }
+
+    pub fn with_build_secs(mut self, build_secs: f64) -> Self {
+        self.times = Some(GradingTimes {
+            build_secs,
+            run_secs: None,
+        });
+        self
+    }
+
+    /// Records that grading took `total_secs` in all, the build included. Submissions that
+    /// didn't compile never ran, so they get no run time.
+    pub fn finish_timing(&mut self, total_secs: f64) {
+        if let Some(times) = &mut self.times
+            && self.compiler_output.is_none()
+        {
+            times.run_secs = Some((total_secs - times.build_secs).max(0.0));
+        }
+    }
+
+    /// Removes how long grading took, to be stored apart from the results
+    pub fn take_times(&mut self) -> Option<GradingTimes> {
+        self.times.take()
+    }
+
+    /// Attaches the memory checker's report to the result of the `index`th test
+    pub fn memory_errors(&mut self, index: usize, report: String) {
+        if let Some(test) = self.tests.get_mut(index) {
+            test.memory_errors = Some(report);
+        }
+    }
+
+    pub fn has_memory_errors(&self) -> bool {
+        self.tests.iter().any(|t| t.memory_errors.is_some())
+    }
+
+    /// Deducts `penalty` (0.0 to 1.0) of the points available, without going below zero
+    pub fn deduct_memory_penalty(&mut self, penalty: f32) {
+        let deducted = (self.points_possible * penalty).min(self.points_earned);
+        self.points_earned -= deducted;
+        self.memory_penalty += deducted;
+    }
+
+    /// Makes `weight` (0.0 to 1.0) of the grade come from the linter's findings. Each finding costs
+    /// a tenth of that part.
+    pub fn with_lint(mut self, weight: f32, mut findings: Vec<String>) -> Self {
+        let total_findings = findings.len();
+        findings.truncate(MAX_LINT_FINDINGS);
+
+        self.lint = Some(LintReport {
+            weight,
+            credit: (1.0 - total_findings as f32 * LINT_PENALTY_PER_FINDING).max(0.0),
+            total_findings,
+            findings,
         });
+        self
     }
 
+    /// Points earned as a fraction of the points available, combined with the style check's
+    /// credit when there is one
     pub fn score(&self) -> f32 {
-        self.passes as f32 / self.tests.len() as f32
+        let tests = if self.points_possible > 0.0 {
+            self.points_earned / self.points_possible
+        } else {
+            0.0
+        };
+
+        match &self.lint {
+            Some(lint) => tests * (1.0 - lint.weight) + lint.credit * lint.weight,
+            None => tests,
+        }
+    }
+
+    /// Attaches the language and toolchain the attempt was graded with
+    pub fn with_toolchain(
+        mut self,
+        language: Option<String>,
+        toolchain_version: Option<String>,
+    ) -> Self {
+        self.toolchain_outdated = match (&language, &toolchain_version) {
+            (Some(lang), Some(version)) => {
+                container::toolchain_version(lang).is_some_and(|current| &current != version)
+            }
+            _ => false,
+        };
+        self.language = language;
+        self.toolchain_version = toolchain_version;
+
+        self
+    }
+
+    /// Strips everything the student isn't allowed to see under the given visibility level
+    pub fn redact(mut self, visibility: ResultVisibility) -> Self {
+        self.total_tests = self.tests.len();
+
+        match visibility {
+            ResultVisibility::Everything => (),
+            ResultVisibility::PublicDiffs => self
+                .tests
+                .iter_mut()
+                .filter(|t| !t.public)
+                .for_each(Test::hide_io),
+            ResultVisibility::PassFail => self.tests.iter_mut().for_each(Test::hide_io),
+            ResultVisibility::ScoreOnly => {
+                self.tests.clear();
+                if let Some(lint) = &mut self.lint {
+                    lint.findings.clear();
+                }
+            }
+        }
+
+        self
     }
 }
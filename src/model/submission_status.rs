@@ -0,0 +1,40 @@
+use serde::Serialize;
+
+/// Where a submission is in grading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradingStatus {
+    /// Waiting for a worker
+    Queued,
+    /// The submission is being built
+    Building,
+    /// The tests are running
+    Running,
+    /// Waiting for the container runtime to come back
+    Delayed,
+    Graded,
+}
+
+impl<T> From<T> for GradingStatus
+where
+    T: AsRef<str>,
+{
+    /// From a `grading_jobs.status` of a job that hasn't finished
+    fn from(value: T) -> Self {
+        match value.as_ref() {
+            "building" => GradingStatus::Building,
+            "running" => GradingStatus::Running,
+            _ => GradingStatus::Queued,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubmissionStatus {
+    pub status: GradingStatus,
+    /// Queued submissions submitted before it, plus one. The queue is shared fairly between
+    /// students, so others may still go first. Only while queued.
+    pub queue_position: Option<i64>,
+    /// Seconds until the results are likely in, judging by how long recent submissions took
+    pub estimated_wait_secs: Option<u64>,
+}
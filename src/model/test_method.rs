@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// How a task's tests are delivered to the submission. Stored in `tasks.test_method` as `stdio`,
+/// `http:<port>` or `junit`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum TestMethod {
+    /// Each test's input is piped to a fresh run of the program, and its output read from stdout
+    #[default]
+    Stdio,
+    /// The program is started once as a server listening on the port, and each test's input is
+    /// sent to it as an HTTP request
+    Http(u16),
+    /// The task's test command runs the instructor's unit tests against the submission, and each
+    /// test is graded from the JUnit XML report it writes
+    Junit,
+}
+
+impl<T> From<T> for TestMethod
+where
+    T: AsRef<str>,
+{
+    fn from(value: T) -> Self {
+        match value.as_ref().split_once(':') {
+            Some(("http", port)) => port.parse().map_or(TestMethod::Stdio, TestMethod::Http),
+            _ if value.as_ref() == "junit" => TestMethod::Junit,
+            _ => TestMethod::Stdio,
+        }
+    }
+}
+
+impl From<TestMethod> for String {
+    fn from(value: TestMethod) -> Self {
+        match value {
+            TestMethod::Stdio => "stdio".into(),
+            TestMethod::Http(port) => format!("http:{port}"),
+            TestMethod::Junit => "junit".into(),
+        }
+    }
+}
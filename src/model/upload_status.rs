@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+/// How far along a resumable upload is
+#[derive(Debug, Serialize)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    /// In bytes
+    pub size: u64,
+    /// Bytes received so far. The next chunk starts here.
+    pub received: u64,
+    /// Largest chunk the server accepts, in bytes
+    pub max_chunk_bytes: u64,
+}
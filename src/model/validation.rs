@@ -0,0 +1,418 @@
+use std::collections::HashSet;
+
+use base64::Engine;
+use serde::Serialize;
+
+use crate::model::{
+    comparison::ComparisonMode,
+    interactive,
+    request::{Task, is_plain_filename},
+    test_method::TestMethod,
+};
+
+/// The smallest memory limit docker accepts
+const MIN_MEMORY_LIMIT_MB: i32 = 6;
+
+/// A problem found in an assignment's tasks. Indexes are zero-based positions in the request.
+#[derive(Debug, Serialize)]
+pub struct ValidationIssue {
+    pub task: usize,
+    pub test: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AssignmentValidation {
+    /// The assignment is rejected if any of these are present
+    pub errors: Vec<ValidationIssue>,
+    /// Saved anyway, but likely a mistake
+    pub warnings: Vec<ValidationIssue>,
+}
+
+impl AssignmentValidation {
+    /// Checks every task for missing or malformed tests before anything is written
+    pub fn check(tasks: &[Task]) -> Self {
+        let mut validation = Self::default();
+
+        for (task_index, task) in tasks.iter().enumerate() {
+            if task.tests.is_empty() {
+                validation.error(task_index, None, "Task has no tests.");
+                continue;
+            }
+
+            if task.tests.iter().all(|t| t.sample) {
+                validation.error(
+                    task_index,
+                    None,
+                    "Task only has sample tests, so submissions can't be graded.",
+                );
+            }
+
+            if let Some(prerequisite) = &task.prerequisite {
+                if prerequisite.task_placement < 0
+                    || prerequisite.task_placement as usize >= task_index
+                {
+                    validation.error(
+                        task_index,
+                        None,
+                        "Prerequisite must be an earlier task in the assignment.",
+                    );
+                }
+
+                if !(0.0..=1.0).contains(&prerequisite.threshold) {
+                    validation.error(
+                        task_index,
+                        None,
+                        "Prerequisite threshold must be between 0 and 1.",
+                    );
+                }
+            }
+
+            if task.memory_limit_mb.is_some_and(|mb| mb < MIN_MEMORY_LIMIT_MB) {
+                validation.error(
+                    task_index,
+                    None,
+                    format!("Memory limit must be at least {MIN_MEMORY_LIMIT_MB} MB."),
+                );
+            }
+
+            if task.timeout.is_some_and(|secs| secs < 1) {
+                validation.error(task_index, None, "Timeout must be at least 1 second.");
+            }
+
+            if task.cpus.is_some_and(|cpus| cpus <= 0.0 || cpus.is_nan()) {
+                validation.error(task_index, None, "CPU limit must be greater than 0.");
+            }
+
+            if task.pids_limit.is_some_and(|pids| pids < 1) {
+                validation.error(task_index, None, "Process limit must be at least 1.");
+            }
+
+            if task.disk_limit_mb.is_some_and(|mb| mb < 1) {
+                validation.error(task_index, None, "Disk limit must be at least 1 MB.");
+            }
+
+            if task.max_attempts.is_some_and(|n| n < 1) {
+                validation.error(task_index, None, "Maximum attempts must be at least 1.");
+            }
+
+            if task.cooldown_minutes.is_some_and(|m| m < 1) {
+                validation.error(task_index, None, "Cooldown must be at least 1 minute.");
+            }
+
+            if task
+                .lint_weight
+                .is_some_and(|w| !(w > 0.0 && w <= 1.0))
+            {
+                validation.error(
+                    task_index,
+                    None,
+                    "Lint weight must be greater than 0 and at most 1.",
+                );
+            }
+
+            let has_command = task
+                .test_command
+                .as_ref()
+                .is_some_and(|c| !c.trim().is_empty());
+
+            if task.test_method == TestMethod::Junit && !has_command {
+                validation.error(task_index, None, "Task needs a command to run its unit tests.");
+            } else if task.test_method != TestMethod::Junit && has_command {
+                validation.warn(task_index, None, "Test command is only used by junit tasks.");
+            }
+
+            if task.memory_check && task.test_method != TestMethod::Stdio {
+                validation.error(
+                    task_index,
+                    None,
+                    "Memory checking is only supported by stdio tasks.",
+                );
+            }
+
+            if let Some(penalty) = task.memory_error_penalty {
+                if !(0.0..=1.0).contains(&penalty) {
+                    validation.error(
+                        task_index,
+                        None,
+                        "Memory error penalty must be between 0 and 1.",
+                    );
+                } else if !task.memory_check {
+                    validation.warn(
+                        task_index,
+                        None,
+                        "Memory error penalty has no effect unless memory checking is enabled.",
+                    );
+                }
+            }
+
+            let n_variants = task.variant_descriptions.len() as i32;
+            for variant in 0..n_variants {
+                if !task
+                    .tests
+                    .iter()
+                    .any(|t| t.variant.is_none_or(|v| v == variant))
+                {
+                    validation.error(
+                        task_index,
+                        None,
+                        format!("Variant {variant} has no tests."),
+                    );
+                }
+            }
+
+            if !task.tests.iter().any(|t| t.is_public) {
+                validation.warn(
+                    task_index,
+                    None,
+                    "Every test is hidden, so students have no public examples.",
+                );
+            }
+
+            if task
+                .tests
+                .iter()
+                .filter(|t| !t.sample)
+                .all(|t| t.points == 0.0)
+            {
+                validation.warn(
+                    task_index,
+                    None,
+                    "Every test is worth 0 points, so the task can't be scored.",
+                );
+            }
+
+            let mut names = HashSet::new();
+
+            for (test_index, test) in task.tests.iter().enumerate() {
+                if test.variant.is_some_and(|v| v < 0 || v >= n_variants) {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Test belongs to a variant the task doesn't have.",
+                    );
+                }
+
+                if task.test_method == TestMethod::Junit {
+                    // Graded from the report, so only the name matters
+                    if test.test_name.as_ref().is_none_or(|n| n.trim().is_empty()) {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            "Test needs the name of the unit test it is graded by.",
+                        );
+                    }
+                } else {
+                    if test.input.is_none() && test.input_file_base64.is_none() {
+                        validation.error(task_index, Some(test_index), "Test is missing its input.");
+                    }
+
+                    if test.output.is_none()
+                        && test.output_file_base64.is_none()
+                        && !test.interactive
+                    {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            "Test is missing its expected output.",
+                        );
+                    }
+                }
+
+                if test.interactive {
+                    if task.test_method != TestMethod::Stdio {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            "Interactive tests are only supported by stdio tasks.",
+                        );
+                    }
+
+                    let script = match &test.input_file_base64 {
+                        Some(file) => base64::prelude::BASE64_STANDARD
+                            .decode(file)
+                            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                            .ok(),
+                        None => test.input.clone(),
+                    };
+
+                    if let Some(Err(e)) = script.as_deref().map(interactive::parse) {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Invalid interactive script: {e}"),
+                        );
+                    }
+                }
+
+                let fixture_files = test.fixtures.iter().map(|f| &f.data_base64);
+                for file in [&test.input_file_base64, &test.output_file_base64]
+                    .into_iter()
+                    .flatten()
+                    .chain(fixture_files)
+                {
+                    if base64::prelude::BASE64_STANDARD.decode(file).is_err() {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            "Test file is not valid base64.",
+                        );
+                    }
+                }
+
+                let mut fixture_names = HashSet::new();
+                for fixture in &test.fixtures {
+                    if !fixture.has_valid_name() {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Invalid fixture file name \"{}\".", fixture.filename),
+                        );
+                    } else if !fixture_names.insert(&fixture.filename) {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Duplicate fixture file \"{}\".", fixture.filename),
+                        );
+                    }
+                }
+
+                if let Some(artifact) = &test.artifact {
+                    if !is_plain_filename(artifact) {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Invalid artifact file name \"{artifact}\"."),
+                        );
+                    }
+
+                    if task.test_method != TestMethod::Stdio {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            "Artifacts are only supported by stdio tasks.",
+                        );
+                    }
+                }
+
+                let mut env_names = HashSet::new();
+                for var in &test.env {
+                    if !var.has_valid_name() {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Invalid environment variable name \"{}\".", var.name),
+                        );
+                    } else if !env_names.insert(&var.name) {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Duplicate environment variable \"{}\".", var.name),
+                        );
+                    }
+
+                    if var.value.contains('\0') {
+                        validation.error(
+                            task_index,
+                            Some(test_index),
+                            format!("Environment variable \"{}\" contains a null byte.", var.name),
+                        );
+                    }
+                }
+
+                if !test.env.is_empty() && task.test_method != TestMethod::Stdio {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Environment variables are only supported by stdio tasks.",
+                    );
+                }
+
+                if !test.fixtures.is_empty() && matches!(task.test_method, TestMethod::Http(_)) {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Fixtures are not supported by HTTP tasks.",
+                    );
+                }
+
+                if test.comparison == ComparisonMode::Regex
+                    && let Some(Err(e)) = test.output.as_deref().map(ComparisonMode::compile)
+                {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        format!("Expected output is not a valid regular expression: {e}"),
+                    );
+                }
+
+                if test.memory_limit_mb.is_some_and(|mb| mb < MIN_MEMORY_LIMIT_MB) {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        format!("Memory limit must be at least {MIN_MEMORY_LIMIT_MB} MB."),
+                    );
+                }
+
+                if test.timeout.is_some_and(|secs| secs < 1) {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Timeout must be at least 1 second.",
+                    );
+                }
+
+                if test.points < 0.0 || test.points.is_nan() {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Points must not be negative.",
+                    );
+                }
+
+                if test
+                    .tolerance
+                    .is_some_and(|t| !(t.absolute >= 0.0 && t.relative >= 0.0))
+                {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        "Tolerances must not be negative.",
+                    );
+                }
+
+                if let Some(name) = test.test_name.as_ref().filter(|n| !n.trim().is_empty())
+                    && !names.insert(name.trim())
+                {
+                    validation.error(
+                        task_index,
+                        Some(test_index),
+                        format!("Duplicate test name \"{}\".", name.trim()),
+                    );
+                }
+            }
+        }
+
+        validation
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, task: usize, test: Option<usize>, message: impl Into<String>) {
+        self.errors.push(ValidationIssue {
+            task,
+            test,
+            message: message.into(),
+        });
+    }
+
+    fn warn(&mut self, task: usize, test: Option<usize>, message: impl Into<String>) {
+        self.warnings.push(ValidationIssue {
+            task,
+            test,
+            message: message.into(),
+        });
+    }
+}
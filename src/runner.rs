@@ -0,0 +1,192 @@
+//! The remote runner agent: grades submissions from another server's queue on this machine
+//!
+//! Started with `grader runner <server url>`, with `RUNNER_TOKEN` set to the server's
+//! `runner_token`. The runner is identified by `RUNNER_ID`, or the hostname if that isn't set. It
+//! uses its own configuration file for the container runtime and `grading_threads`, and needs no
+//! database. See [`crate::container`]'s `remote` module for the server's side.
+
+use std::time::Duration;
+
+use axum::http::{
+    StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config,
+    container::{self, RunnerJob, RunnerReport, RunnerRequest},
+};
+
+const USAGE: &str = "Usage:
+    RUNNER_TOKEN=<token> [RUNNER_ID=<id>] grader runner <server url>";
+
+/// How often the server is told this runner is up
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait before trying again when the server or the container runtime is unavailable
+const RETRY_DELAY: Duration = Duration::from_secs(15);
+
+/// Longer than the server holds a claim open, so an idle claim isn't cut short
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+struct Server {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    runner_id: String,
+}
+
+impl Server {
+    async fn post(&self, path: &str, body: String) -> Result<reqwest::Response, String> {
+        self.client
+            .post(format!("{}/runner/{path}", self.url))
+            .header(AUTHORIZATION, &self.token)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("{e}"))
+    }
+
+    /// The JSON body of a request, with a job's results if it reports them
+    fn request(&self, report: Option<RunnerReport>) -> String {
+        let request = RunnerRequest {
+            runner_id: self.runner_id.clone(),
+            report,
+        };
+        serde_json::to_string(&request).unwrap()
+    }
+
+    /// The next job, or `None` if the server had none to give
+    async fn claim(&self) -> Result<Option<RunnerJob>, String> {
+        let response = self.post("claim", self.request(None)).await?;
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(None),
+            StatusCode::OK => {
+                let body = response.text().await.map_err(|e| format!("{e}"))?;
+                serde_json::from_str(&body)
+                    .map(Some)
+                    .map_err(|e| format!("{e}"))
+            }
+            status => Err(format!("Claim failed with {status}")),
+        }
+    }
+
+    /// Sends a job's results, as made by [`Server::request`]
+    async fn report(&self, job_id: i32, body: &str) -> Result<(), String> {
+        let response = self
+            .post(&format!("report/{job_id}"), body.to_string())
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            StatusCode::CONFLICT => {
+                warn!("Job {job_id} was given to another runner; results discarded");
+                Ok(())
+            }
+            status => Err(format!("Report failed with {status}")),
+        }
+    }
+}
+
+/// Runs the `runner` subcommand until SIGTERM or Ctrl-C. Returns the process exit code.
+pub async fn run_runner(args: &[String]) -> i32 {
+    let (Some(url), Ok(token)) = (args.first(), std::env::var("RUNNER_TOKEN")) else {
+        eprintln!("{USAGE}");
+        return 2;
+    };
+
+    let runner_id = std::env::var("RUNNER_ID")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| "runner".into());
+
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not start the HTTP client: {e}");
+            return 1;
+        }
+    };
+
+    let server = Server {
+        client,
+        url: url.trim_end_matches('/').to_string(),
+        token,
+        runner_id,
+    };
+
+    // Build each language's toolchain once, instead of with every submission
+    tokio::spawn(container::build_base_images());
+    tokio::spawn(container::janitor());
+    tokio::spawn(container::warm_pools());
+
+    tokio::spawn(heartbeat(server.clone()));
+    for _ in 0..config::get().grading_threads.max(1) {
+        tokio::spawn(claim_loop(server.clone()));
+    }
+
+    info!(
+        "Runner {} grading for {} with {} workers",
+        server.runner_id,
+        server.url,
+        config::get().grading_threads.max(1)
+    );
+
+    use tokio::signal::unix::{SignalKind, signal};
+    let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+        error!("Could not listen for SIGTERM");
+        return 1;
+    };
+    tokio::select! {
+        _ = terminate.recv() => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+
+    // Jobs in progress are put back in the queue by the server once the heartbeats stop
+    info!("Runner stopping");
+    0
+}
+
+async fn heartbeat(server: Server) -> ! {
+    loop {
+        if let Err(e) = server.post("heartbeat", server.request(None)).await {
+            warn!("Could not reach {}: {e}", server.url);
+        }
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+/// Claims and grades jobs one at a time
+async fn claim_loop(server: Server) -> ! {
+    loop {
+        // Jobs aren't taken while they couldn't be graded here
+        if !container::check_runtime().await {
+            error!("Container runtime is unavailable; not claiming jobs");
+            tokio::time::sleep(RETRY_DELAY).await;
+            continue;
+        }
+
+        let job = match server.claim().await {
+            Ok(Some(job)) => job,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Could not claim a job from {}: {e}", server.url);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        let job_id = job.job_id;
+        let body = server.request(Some(container::grade_job(job).await));
+
+        // The results only exist here, so they're kept until the server takes them
+        while let Err(e) = server.report(job_id, &body).await {
+            error!("Could not report job {job_id}: {e}");
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
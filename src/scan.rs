@@ -0,0 +1,82 @@
+//! Scans uploaded submissions for malware with ClamAV
+//!
+//! Scanning is off unless `clamd_address` is set. Then every submission is streamed to clamd with
+//! its `INSTREAM` command before it's recorded or queued, so nothing reaches the graders unscanned.
+//! While clamd can't be reached, submissions are turned away and the student is asked to try again.
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+
+use crate::config;
+
+/// How much of the upload is sent to clamd at a time
+const CHUNK: usize = 64 * 1024;
+
+pub enum Verdict {
+    Clean,
+    /// Holds the name of what was found, e.g. `Eicar-Test-Signature`
+    Infected(String),
+}
+
+/// Scans `upload` with clamd. `Verdict::Clean` without scanning if no scanner is configured.
+pub async fn scan(upload: &[u8]) -> Result<Verdict, String> {
+    let config = config::get();
+    let Some(address) = config.clamd_address else {
+        return Ok(Verdict::Clean);
+    };
+
+    let reply = tokio::time::timeout(Duration::from_secs(config.clamd_timeout_secs), async {
+        if address.starts_with('/') {
+            instream(UnixStream::connect(&address).await?, upload).await
+        } else {
+            instream(TcpStream::connect(&address).await?, upload).await
+        }
+    })
+    .await
+    .map_err(|_| {
+        format!(
+            "clamd at {address} did not answer within {}s",
+            config.clamd_timeout_secs
+        )
+    })?
+    .map_err(|e| format!("Could not scan with clamd at {address}: {e}"))?;
+
+    // clamd answers `stream: OK`, `stream: <name> FOUND` or `<reason> ERROR`
+    match reply.strip_prefix("stream: ") {
+        Some("OK") => Ok(Verdict::Clean),
+        Some(found) if found.ends_with(" FOUND") => Ok(Verdict::Infected(
+            found.trim_end_matches(" FOUND").to_string(),
+        )),
+        _ => Err(format!(
+            "clamd at {address} could not scan the upload: {reply}"
+        )),
+    }
+}
+
+/// Sends `upload` to clamd in length-prefixed chunks, ending with an empty one, and returns its
+/// reply
+async fn instream(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    upload: &[u8],
+) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    for chunk in upload.chunks(CHUNK) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&[0; 4]).await?;
+
+    let mut reply = vec![];
+    stream.read_to_end(&mut reply).await?;
+
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string())
+}
@@ -8,6 +8,9 @@ use axum::{
     response::Response,
 };
 
+use sha2::{Digest, Sha256};
+
+use crate::config;
 use crate::database::auth::{
     session_exists_and_valid, session_is_admin, session_is_instructor, session_is_student,
 };
@@ -259,3 +262,27 @@ pub async fn handle_admin_auth(request: axum::http::Request<Body>, next: Next) -
         }
     }
 }
+
+/// Admits remote runners that present the configured `runner_token`
+pub async fn handle_runner_auth(request: axum::http::Request<Body>, next: Next) -> Response<Body> {
+    let Some(runner_token) = config::get().runner_token else {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Remote runners are not enabled.".into())
+            .unwrap();
+    };
+
+    // Digests are compared, so how long the comparison takes says nothing about the token
+    let presented = request
+        .headers()
+        .get(&AUTHORIZATION)
+        .map(|token| Sha256::digest(token.as_bytes()));
+    if presented != Some(Sha256::digest(runner_token.as_bytes())) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Not Authorized.".into())
+            .unwrap();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,623 @@
+//! Finds students whose submissions look alike
+//!
+//! Instructors start a check of an assignment, which runs in the background. The latest
+//! submission of every student to each task is unpacked, and its source files are broken into
+//! tokens. Comments are dropped and names, numbers and strings all become the same placeholder,
+//! so renaming variables or rewording comments doesn't hide a copy. Every run of [`K`] tokens is
+//! hashed, and winnowing keeps the smallest hash of every [`WINDOW`] runs in a row as the
+//! submission's fingerprints.
+//!
+//! Two submissions are as similar as the share of the smaller one's fingerprints that are found in
+//! the other. Fingerprints of the task's template don't count, and neither do ones most of the
+//! class shares. Pairs at least [`MIN_SIMILARITY`] alike are kept for the report, along with the
+//! lines where they match.
+
+use std::{
+    collections::{HashMap, HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use tokio::sync::Semaphore;
+use tracing::{error, warn};
+
+use crate::{
+    archive::{self, ExtractError, Format, Limits},
+    database,
+    model::similarity_report::MatchedRegion,
+    storage,
+};
+
+/// Tokens hashed together into one fingerprint
+const K: usize = 5;
+
+/// Hashes in a row that winnowing keeps one of
+const WINDOW: usize = 4;
+
+/// Pairs less alike than this aren't kept
+const MIN_SIMILARITY: f32 = 0.3;
+
+/// Submissions with fewer fingerprints than this are too short to tell anything from
+const MIN_FINGERPRINTS: usize = 8;
+
+/// Fingerprints shared by more than this share of the class, and at least [`COMMON_MIN`] students,
+/// are taken as common to the task rather than copied
+const COMMON_SHARE: f32 = 0.5;
+const COMMON_MIN: usize = 5;
+
+/// Matching regions kept per pair
+const MAX_REGIONS: usize = 100;
+
+/// Largest source file that is read, in bytes
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+
+/// Files with these extensions are compared. Everything else in a submission is ignored.
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "py", "rs", "java", "kt", "scala", "go", "js", "ts",
+    "cs", "rb", "swift", "hs", "ml", "php", "lua",
+];
+
+/// Kept as they are, since they give code its shape. Every other name is a placeholder.
+const KEYWORDS: &[&str] = &[
+    "and",
+    "as",
+    "async",
+    "await",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "def",
+    "default",
+    "del",
+    "do",
+    "double",
+    "elif",
+    "else",
+    "enum",
+    "except",
+    "false",
+    "finally",
+    "fn",
+    "float",
+    "for",
+    "from",
+    "if",
+    "impl",
+    "import",
+    "in",
+    "int",
+    "interface",
+    "is",
+    "lambda",
+    "let",
+    "long",
+    "loop",
+    "match",
+    "mut",
+    "new",
+    "not",
+    "or",
+    "pass",
+    "private",
+    "pub",
+    "public",
+    "raise",
+    "return",
+    "self",
+    "static",
+    "struct",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "type",
+    "unsigned",
+    "use",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+const NAME: &str = "id";
+const NUMBER: &str = "0";
+const STRING: &str = "\"\"";
+
+/// Where submissions are unpacked while they're compared
+const WORKDIR: &str = "/tmp/securegrade/similarity";
+
+/// Checks run one at a time, as each reads every submission to the assignment
+static RUNNING: Semaphore = Semaphore::const_new(1);
+
+/// Where a fingerprint was found: the file, and the lines its tokens span
+#[derive(Debug, Clone, Copy)]
+struct Place {
+    file: usize,
+    start_line: u32,
+    end_line: u32,
+}
+
+/// One student's submission to a task, fingerprinted
+struct Document {
+    user_id: i32,
+    files: Vec<String>,
+    /// The first place each fingerprint is found
+    fingerprints: HashMap<u64, Place>,
+}
+
+/// Two students whose submissions to a task look alike
+pub struct Pair {
+    pub user_a: i32,
+    pub user_b: i32,
+    pub similarity: f32,
+    pub matches: Vec<MatchedRegion>,
+}
+
+/// Runs the check in the background, then lets whoever asked for it know it's ready
+pub fn start(report_id: i32, assignment_id: i32, user_id: i32) {
+    tokio::spawn(async move {
+        let _permit = RUNNING.acquire().await;
+
+        let workdir = format!("{WORKDIR}/{report_id}");
+        let checked = check(report_id, assignment_id, &workdir).await;
+        let _ = std::fs::remove_dir_all(&workdir);
+
+        let message = match checked {
+            Ok(()) => "Your similarity report is ready.",
+            Err(e) => {
+                error!("Similarity check {report_id} failed: {e}");
+                let _ = database::similarity::fail_report(report_id, e).await;
+                "Your similarity check failed. Please try again later."
+            }
+        };
+        let _ = database::notification::add_notification(user_id, message, None).await;
+    });
+}
+
+async fn check(report_id: i32, assignment_id: i32, workdir: &str) -> Result<(), String> {
+    database::similarity::set_status(report_id, "running").await?;
+
+    let mut tasks: HashMap<i32, Vec<(i32, String)>> = HashMap::new();
+    for (task_id, user_id, key) in database::similarity::latest_submissions(assignment_id).await? {
+        tasks.entry(task_id).or_default().push((user_id, key));
+    }
+    let templates = database::similarity::templates(assignment_id).await?;
+
+    for (task_id, submissions) in tasks {
+        let template = match templates.get(&task_id) {
+            Some((template, filename)) => {
+                let dir = format!("{workdir}/{task_id}/template");
+                let files =
+                    read_template(template, filename, Path::new(&dir)).unwrap_or_else(|e| {
+                        warn!("Could not unpack the template of task {task_id}: {e}");
+                        vec![]
+                    });
+                fingerprint(0, files).fingerprints.into_keys().collect()
+            }
+            None => HashSet::new(),
+        };
+
+        let mut documents = vec![];
+        for (user_id, key) in submissions {
+            let archive = storage::get(&key).await?;
+            let dir = format!("{workdir}/{task_id}/{user_id}");
+            let files = tokio::task::spawn_blocking(move || {
+                let files = read_sources(&archive, Path::new(&dir));
+                let _ = std::fs::remove_dir_all(&dir);
+                files
+            })
+            .await
+            .map_err(|e| format!("{e}"))?;
+
+            // A submission that can't be unpacked has nothing to compare
+            match files {
+                Ok(files) => documents.push(fingerprint(user_id, files)),
+                Err(e) => {
+                    warn!("Could not unpack user {user_id}'s submission to task {task_id}: {e}")
+                }
+            }
+        }
+
+        let pairs = tokio::task::spawn_blocking(move || compare(&documents, &template))
+            .await
+            .map_err(|e| format!("{e}"))?;
+        database::similarity::add_pairs(report_id, task_id, pairs).await?;
+    }
+
+    database::similarity::finish_report(report_id).await
+}
+
+/// Unpacks the archive into `dir` and reads its source files, as (path, contents) sorted by path
+fn read_sources(archive: &[u8], dir: &Path) -> Result<Vec<(String, String)>, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{e}"))?;
+    archive::extract(archive, dir, &Limits::from_config()).map_err(|e| match e {
+        ExtractError::Rejected(reason) => reason,
+        ExtractError::Io(e) => format!("{e}"),
+    })?;
+
+    let mut files = vec![];
+    collect_sources(dir, dir, &mut files);
+    files.sort();
+    Ok(files)
+}
+
+/// The template's source files. Templates are either an archive or a single file.
+fn read_template(
+    template: &[u8],
+    filename: &str,
+    dir: &Path,
+) -> Result<Vec<(String, String)>, String> {
+    if Format::of(template).is_some() {
+        return read_sources(template, dir);
+    }
+
+    Ok(String::from_utf8(template.to_vec())
+        .map(|text| vec![(filename.to_string(), text)])
+        .unwrap_or_default())
+}
+
+fn collect_sources(root: &Path, dir: &Path, files: &mut Vec<(String, String)>) {
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_sources(root, &path, files);
+            continue;
+        }
+
+        let source = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| SOURCE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+        if !source || metadata.len() > MAX_FILE_BYTES {
+            continue;
+        }
+
+        if let (Ok(relative), Ok(text)) = (path.strip_prefix(root), std::fs::read_to_string(&path))
+        {
+            files.push((relative.to_string_lossy().into_owned(), text));
+        }
+    }
+}
+
+/// Breaks source code into tokens, each with the line it's on
+fn tokenize(source: &str) -> Vec<(&str, u32)> {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    let skip_line = |i: &mut usize| {
+        while *i < bytes.len() && bytes[*i] != b'\n' {
+            *i += 1;
+        }
+    };
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'\n' => {
+                line += 1;
+                i += 1;
+            }
+            _ if c.is_ascii_whitespace() => i += 1,
+            // Comments, and preprocessor lines, which are mostly includes
+            b'#' => skip_line(&mut i),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => skip_line(&mut i),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    if bytes[i] == b'\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 2).min(bytes.len());
+            }
+            b'"' | b'\'' | b'`' => {
+                // Strings end on the line they start on. A lone quote, like a Rust lifetime's, is
+                // punctuation.
+                let mut end = i + 1;
+                while end < bytes.len() && bytes[end] != c && bytes[end] != b'\n' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                if end < bytes.len() && bytes[end] == c {
+                    tokens.push((STRING, line));
+                    i = end + 1;
+                } else {
+                    tokens.push((&source[i..i + 1], line));
+                    i += 1;
+                }
+            }
+            _ if c.is_ascii_digit() => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.' || bytes[i] == b'_')
+                {
+                    i += 1;
+                }
+                tokens.push((NUMBER, line));
+            }
+            _ if c == b'_' || c.is_ascii_alphabetic() || c >= 0x80 => {
+                let start = i;
+                while i < bytes.len()
+                    && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric() || bytes[i] >= 0x80)
+                {
+                    i += 1;
+                }
+                let word = &source[start..i];
+                tokens.push((if KEYWORDS.contains(&word) { word } else { NAME }, line));
+            }
+            _ => {
+                tokens.push((&source[i..i + 1], line));
+                i += 1;
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Indexes of the hashes winnowing keeps: the smallest of every `WINDOW` in a row, the rightmost
+/// one on ties, each kept once
+fn winnow(hashes: &[u64]) -> Vec<usize> {
+    let mut kept = vec![];
+    for start in 0..=hashes.len().saturating_sub(WINDOW) {
+        let window = &hashes[start..(start + WINDOW).min(hashes.len())];
+        let Some((offset, _)) = window.iter().enumerate().rev().min_by_key(|(_, h)| **h) else {
+            continue;
+        };
+        if kept.last() != Some(&(start + offset)) {
+            kept.push(start + offset);
+        }
+    }
+    kept
+}
+
+fn fingerprint(user_id: i32, files: Vec<(String, String)>) -> Document {
+    let mut fingerprints = HashMap::new();
+
+    for (file, (_, text)) in files.iter().enumerate() {
+        let tokens = tokenize(text);
+        let hashes = tokens
+            .windows(K)
+            .map(|gram| {
+                let mut hasher = DefaultHasher::new();
+                gram.iter().for_each(|(token, _)| token.hash(&mut hasher));
+                hasher.finish()
+            })
+            .collect::<Vec<_>>();
+
+        for index in winnow(&hashes) {
+            fingerprints.entry(hashes[index]).or_insert(Place {
+                file,
+                start_line: tokens[index].1,
+                end_line: tokens[index + K - 1].1,
+            });
+        }
+    }
+
+    Document {
+        user_id,
+        files: files.into_iter().map(|(path, _)| path).collect(),
+        fingerprints,
+    }
+}
+
+/// The pairs of documents at least [`MIN_SIMILARITY`] alike, most similar first
+fn compare(documents: &[Document], template: &HashSet<u64>) -> Vec<Pair> {
+    let mut holders: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, document) in documents.iter().enumerate() {
+        for hash in document.fingerprints.keys() {
+            if !template.contains(hash) {
+                holders.entry(*hash).or_default().push(index);
+            }
+        }
+    }
+
+    let common = (documents.len() as f32 * COMMON_SHARE).max(COMMON_MIN as f32);
+    holders.retain(|_, holders| holders.len() as f32 <= common);
+
+    // What each document has left to compare once template and common code are taken out
+    let mut counted = vec![0usize; documents.len()];
+    let mut shared: HashMap<(usize, usize), Vec<u64>> = HashMap::new();
+    for (hash, holders) in &holders {
+        for (i, &a) in holders.iter().enumerate() {
+            counted[a] += 1;
+            for &b in &holders[i + 1..] {
+                shared.entry((a.min(b), a.max(b))).or_default().push(*hash);
+            }
+        }
+    }
+
+    let mut pairs = shared
+        .into_iter()
+        .filter_map(|((a, b), hashes)| {
+            let smaller = counted[a].min(counted[b]);
+            if smaller < MIN_FINGERPRINTS {
+                return None;
+            }
+            let similarity = hashes.len() as f32 / smaller as f32;
+            (similarity >= MIN_SIMILARITY).then(|| Pair {
+                user_a: documents[a].user_id,
+                user_b: documents[b].user_id,
+                similarity,
+                matches: regions(&documents[a], &documents[b], &hashes),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    pairs
+}
+
+/// Where the shared fingerprints are found in each document, with neighbouring lines merged
+fn regions(a: &Document, b: &Document, hashes: &[u64]) -> Vec<MatchedRegion> {
+    let mut places = hashes
+        .iter()
+        .map(|hash| (a.fingerprints[hash], b.fingerprints[hash]))
+        .collect::<Vec<_>>();
+    places.sort_by_key(|(pa, pb)| (pa.file, pa.start_line, pb.file, pb.start_line));
+
+    let mut merged: Vec<(Place, Place)> = vec![];
+    for (pa, pb) in places {
+        if let Some((last_a, last_b)) = merged.last_mut()
+            && last_a.file == pa.file
+            && last_b.file == pb.file
+            && pa.start_line <= last_a.end_line + 1
+            && pb.start_line <= last_b.end_line + 1
+            && pb.end_line + 1 >= last_b.start_line
+        {
+            last_a.end_line = last_a.end_line.max(pa.end_line);
+            last_b.start_line = last_b.start_line.min(pb.start_line);
+            last_b.end_line = last_b.end_line.max(pb.end_line);
+        } else {
+            merged.push((pa, pb));
+        }
+    }
+
+    merged
+        .into_iter()
+        .take(MAX_REGIONS)
+        .map(|(pa, pb)| MatchedRegion {
+            file_a: a.files[pa.file].clone(),
+            start_line_a: pa.start_line,
+            end_line_a: pa.end_line,
+            file_b: b.files[pb.file].clone(),
+            start_line_b: pb.start_line,
+            end_line_b: pb.end_line,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = r#"
+int count_words(const char *text) {
+    int words = 0;
+    int inside = 0;
+    for (int i = 0; text[i] != '\0'; i++) {
+        if (text[i] == ' ' || text[i] == '\n') {
+            inside = 0;
+        } else if (!inside) {
+            inside = 1;
+            words++;
+        }
+    }
+    return words;
+}
+
+double average(const int *values, int n) {
+    long total = 0;
+    for (int i = 0; i < n; i++) {
+        total += values[i];
+    }
+    return n > 0 ? (double) total / n : 0.0;
+}
+"#;
+
+    fn tokens(source: &str) -> Vec<&str> {
+        tokenize(source)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    fn document(user_id: i32, source: &str) -> Document {
+        fingerprint(user_id, vec![("main.c".into(), source.into())])
+    }
+
+    #[test]
+    fn strings_become_one_token() {
+        assert_eq!(
+            tokens(r#"x = "a // b \" c"; y = 'z';"#),
+            [NAME, "=", STRING, ";", NAME, "=", STRING, ";"]
+        );
+    }
+
+    #[test]
+    fn lone_quotes_are_punctuation() {
+        assert_eq!(
+            tokenize("struct S<'a> {\n    x: &'a str,\n}"),
+            [
+                ("struct", 1),
+                (NAME, 1),
+                ("<", 1),
+                ("'", 1),
+                (NAME, 1),
+                (">", 1),
+                ("{", 1),
+                (NAME, 2),
+                (":", 2),
+                ("&", 2),
+                ("'", 2),
+                (NAME, 2),
+                (NAME, 2),
+                (",", 2),
+                ("}", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_are_dropped_and_lines_counted() {
+        let source = "#include <stdio.h>\n// one\n/* two\nthree */ return x; // four";
+
+        assert_eq!(tokenize(source), [("return", 4), (NAME, 4), (";", 4)]);
+    }
+
+    #[test]
+    fn non_ascii_names_are_names() {
+        assert_eq!(
+            tokens("café = \"ü\"; 名前 += 1;"),
+            [NAME, "=", STRING, ";", NAME, "+", "=", NUMBER, ";"]
+        );
+    }
+
+    #[test]
+    fn winnowing_keeps_the_rightmost_minimum() {
+        assert_eq!(winnow(&[5, 1, 3, 1, 4]), [3]);
+        assert_eq!(winnow(&[1, 2, 3, 4, 5, 6]), [0, 1, 2]);
+        assert_eq!(winnow(&[3, 2]), [1]);
+        assert!(winnow(&[]).is_empty());
+    }
+
+    #[test]
+    fn renamed_identifiers_still_match() {
+        let renamed = ORIGINAL
+            .replace("count_words", "tally")
+            .replace("words", "n_words")
+            .replace("inside", "in_word")
+            .replace("total", "sum")
+            .replace("values", "xs");
+
+        let pairs = compare(
+            &[document(1, ORIGINAL), document(2, &renamed)],
+            &HashSet::new(),
+        );
+
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].user_a, pairs[0].user_b), (1, 2));
+        assert!(pairs[0].similarity >= MIN_SIMILARITY);
+        assert!(!pairs[0].matches.is_empty());
+        assert!(pairs[0].matches.iter().all(|m| m.file_a == "main.c"));
+    }
+
+    #[test]
+    fn template_fingerprints_are_excluded() {
+        let documents = [document(1, ORIGINAL), document(2, ORIGINAL)];
+        let template = documents[0].fingerprints.keys().copied().collect();
+
+        assert!(!compare(&documents, &HashSet::new()).is_empty());
+        assert!(compare(&documents, &template).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+//! Reads a task's tests out of an uploaded zip (or tar.gz) of input/output files
+//!
+//! Every `NAME.in` in the zip is paired with `NAME.out`; directories are ignored, so
+//! `tests/test01.in` and `test01.in` are the same test. Tests are created in name order. An
+//! optional `manifest.toml` sets per-test details, keyed by `NAME`:
+//!
+//! ```toml
+//! [tests.test01]
+//! name = "Empty input"
+//! public = true
+//! timeout = 5
+//! memory_limit_mb = 256
+//! artifact = "report.txt"
+//! sample = false
+//! interactive = false
+//! points = 2.5
+//! partial_credit = true
+//! hint = "Check how you handle empty input."
+//! variant = 0
+//! comparison = "numeric"
+//! tolerance = { absolute = 0.001, relative = 0.0001 }
+//! ```
+//!
+//! Tests missing from the manifest are hidden, named `NAME`, worth 1 point, and use the default
+//! timeout.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{
+    archive::{self, ExtractError},
+    model::{
+        comparison::{ComparisonMode, Tolerance},
+        request::{Test, text_or_base64},
+    },
+};
+
+/// The contents of `NAME.in` and `NAME.out`
+type IoPair = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Manifest {
+    tests: HashMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ManifestEntry {
+    name: Option<String>,
+    public: bool,
+    /// Seconds
+    timeout: Option<i32>,
+    memory_limit_mb: Option<i32>,
+    variant: Option<i32>,
+    comparison: ComparisonMode,
+    tolerance: Option<Tolerance>,
+    points: Option<f32>,
+    partial_credit: bool,
+    hint: Option<String>,
+    artifact: Option<String>,
+    sample: bool,
+    interactive: bool,
+}
+
+/// Unpacks the zip into `workdir`, which must already exist, and returns its tests
+pub fn read_test_zip(zip: &[u8], workdir: &str) -> Result<Vec<Test>, String> {
+    let files_dir = format!("{workdir}/files");
+    std::fs::create_dir_all(&files_dir).map_err(|e| format!("{e}"))?;
+
+    match archive::extract(zip, Path::new(&files_dir), &archive::Limits::from_config()) {
+        Ok(()) => (),
+        Err(ExtractError::Rejected(reason)) => return Err(reason),
+        Err(ExtractError::Io(e)) => return Err(format!("{e}")),
+    }
+
+    let mut files = HashMap::new();
+    collect_files(Path::new(&files_dir), &mut files)?;
+
+    let manifest = match files.remove("manifest.toml") {
+        Some(contents) => {
+            let contents = String::from_utf8(contents)
+                .map_err(|_| "manifest.toml is not valid UTF-8.".to_string())?;
+            toml::from_str::<Manifest>(&contents)
+                .map_err(|e| format!("Invalid manifest.toml: {e}"))?
+        }
+        None => Manifest::default(),
+    };
+
+    // stem => (input, output), ordered by stem
+    let mut pairs: BTreeMap<String, IoPair> = BTreeMap::new();
+    for (file_name, contents) in files {
+        if let Some(stem) = file_name.strip_suffix(".in") {
+            pairs.entry(stem.into()).or_default().0 = Some(contents);
+        } else if let Some(stem) = file_name.strip_suffix(".out") {
+            pairs.entry(stem.into()).or_default().1 = Some(contents);
+        }
+    }
+
+    let mut tests = vec![];
+    for (stem, pair) in pairs {
+        let (Some(input), Some(output)) = pair else {
+            return Err(format!("{stem} needs both {stem}.in and {stem}.out."));
+        };
+
+        let (input, input_file_base64) = text_or_base64(input);
+        let (output, output_file_base64) = text_or_base64(output);
+        let entry = manifest.tests.get(&stem);
+
+        tests.push(Test {
+            test_id: None,
+            test_name: Some(entry.and_then(|e| e.name.clone()).unwrap_or(stem.clone())),
+            is_public: entry.is_some_and(|e| e.public),
+            input,
+            output,
+            input_file_base64,
+            output_file_base64,
+            variant: entry.and_then(|e| e.variant),
+            comparison: entry.map(|e| e.comparison).unwrap_or_default(),
+            tolerance: entry.and_then(|e| e.tolerance),
+            points: entry.and_then(|e| e.points).unwrap_or(1.0),
+            partial_credit: entry.is_some_and(|e| e.partial_credit),
+            hint: entry.and_then(|e| e.hint.clone()),
+            fixtures: vec![],
+            env: vec![],
+            timeout: entry.and_then(|e| e.timeout),
+            memory_limit_mb: entry.and_then(|e| e.memory_limit_mb),
+            artifact: entry.and_then(|e| e.artifact.clone()),
+            sample: entry.is_some_and(|e| e.sample),
+            interactive: entry.is_some_and(|e| e.interactive),
+        });
+    }
+
+    if tests.is_empty() {
+        return Err("The zip does not contain any .in/.out pairs.".into());
+    }
+
+    Ok(tests)
+}
+
+/// Reads every file under `dir`, keyed by file name
+fn collect_files(dir: &Path, files: &mut HashMap<String, Vec<u8>>) -> Result<(), String> {
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("{e}"))? {
+        let path = entry.map_err(|e| format!("{e}"))?.path();
+
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            let contents = std::fs::read(&path).map_err(|e| format!("{e}"))?;
+            if files.insert(name.into(), contents).is_some() {
+                return Err(format!("{name} appears more than once in the zip."));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! Resumable uploads, for submissions too large to send reliably in one request
+//!
+//! A student starts an upload with the size and SHA-256 of their archive, then sends it in chunks,
+//! each at the offset it belongs at. Chunks are written straight to disk, so after a dropped
+//! connection the student asks how much arrived and carries on from there. Finishing the upload
+//! checks its size and hash before the archive is submitted like any other, and counts as
+//! submitting it at the time its last chunk arrived, as long as it's finished within
+//! [`FINISH_WINDOW`] of that. Later than that, it counts as submitted when it's finished, so
+//! uploads can't be left complete before a deadline and finished one at a time after it.
+//!
+//! Every upload is a directory under [`ROOT`] with its description and the bytes received so far.
+//! The janitor removes uploads that were never finished.
+
+use std::{
+    fs::{File, OpenOptions, create_dir_all, remove_dir_all},
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config;
+
+/// Where uploads are kept until they're finished
+pub const ROOT: &str = "/tmp/securegrade/uploads";
+
+const DESCRIPTION_FILE: &str = "upload.json";
+const DATA_FILE: &str = "data";
+/// When the last chunk arrived, once they all have
+const COMPLETED_FILE: &str = "completed";
+
+/// How long after its last chunk an upload can be finished and still count as submitted then
+pub const FINISH_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// What an upload is for, as given when it was started
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Upload {
+    pub user_id: i32,
+    pub assignment_id: i32,
+    pub task_id: i32,
+    pub lang: String,
+    /// In bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the whole archive
+    pub sha256: String,
+}
+
+pub enum UploadError {
+    /// The request doesn't fit the upload. The reason is meant for whoever sent it.
+    Rejected(String),
+    Io(std::io::Error),
+}
+
+/// Starts an upload, returning its id
+pub fn start(upload: &Upload) -> Result<String, UploadError> {
+    let max_mb = config::get().upload_max_mb;
+    if upload.size == 0 {
+        return Err(UploadError::Rejected("The upload is empty.".into()));
+    }
+    if upload.size > max_mb * 1024 * 1024 {
+        return Err(UploadError::Rejected(format!(
+            "Uploads can be at most {max_mb} MB."
+        )));
+    }
+    if upload.sha256.len() != 64 || !upload.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(UploadError::Rejected(
+            "sha256 must be 64 hexadecimal digits.".into(),
+        ));
+    }
+
+    let id = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    let dir = dir(&id);
+
+    create_dir_all(&dir).map_err(UploadError::Io)?;
+    std::fs::write(
+        dir.join(DESCRIPTION_FILE),
+        serde_json::to_vec(upload).unwrap(),
+    )
+    .map_err(UploadError::Io)?;
+    File::create(dir.join(DATA_FILE)).map_err(UploadError::Io)?;
+
+    Ok(id)
+}
+
+/// The upload with this id. `None` => there is none, or it was finished or cleaned up.
+pub fn get(id: &str) -> Result<Option<Upload>, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    match std::fs::read(dir(id).join(DESCRIPTION_FILE)) {
+        Ok(description) => serde_json::from_slice(&description)
+            .map(Some)
+            .map_err(|e| format!("{e}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Bytes of the upload received so far
+pub fn received(id: &str) -> Result<u64, String> {
+    std::fs::metadata(dir(id).join(DATA_FILE))
+        .map(|m| m.len())
+        .map_err(|e| format!("{e}"))
+}
+
+/// Writes a chunk at `offset`, returning how many bytes have been received. Chunks may be sent
+/// again, but can't leave a gap.
+pub fn write_chunk(
+    id: &str,
+    upload: &Upload,
+    offset: u64,
+    chunk: &[u8],
+) -> Result<u64, UploadError> {
+    let received = received(id).map_err(UploadError::Rejected)?;
+    if offset > received {
+        return Err(UploadError::Rejected(format!(
+            "Only {received} bytes have been received, so the next chunk starts there."
+        )));
+    }
+    let end = offset + chunk.len() as u64;
+    if end > upload.size {
+        return Err(UploadError::Rejected(format!(
+            "The chunk runs past the {} bytes the upload was started with.",
+            upload.size
+        )));
+    }
+
+    let data = OpenOptions::new()
+        .write(true)
+        .open(dir(id).join(DATA_FILE))
+        .map_err(UploadError::Io)?;
+    data.write_all_at(chunk, offset).map_err(UploadError::Io)?;
+
+    let received = received.max(end);
+    if received == upload.size {
+        std::fs::write(dir(id).join(COMPLETED_FILE), Utc::now().to_rfc3339())
+            .map_err(UploadError::Io)?;
+    }
+
+    Ok(received)
+}
+
+/// The whole archive and when it counts as submitted, once it's all there and matches its hash
+pub fn assemble(id: &str, upload: &Upload) -> Result<(Vec<u8>, DateTime<Utc>), UploadError> {
+    let archive = std::fs::read(dir(id).join(DATA_FILE)).map_err(UploadError::Io)?;
+
+    if (archive.len() as u64) < upload.size {
+        return Err(UploadError::Rejected(format!(
+            "Only {} of {} bytes have been received.",
+            archive.len(),
+            upload.size
+        )));
+    }
+
+    let sha256: String = Sha256::digest(&archive)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    if !sha256.eq_ignore_ascii_case(&upload.sha256) {
+        return Err(UploadError::Rejected(
+            "The upload doesn't match its SHA-256. Start it again.".into(),
+        ));
+    }
+
+    let completed_at = std::fs::read_to_string(dir(id).join(COMPLETED_FILE))
+        .ok()
+        .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+        .map(|t| t.with_timezone(&Utc));
+    let now = Utc::now();
+    let submitted_at = match completed_at {
+        Some(t) if now - t <= chrono::Duration::from_std(FINISH_WINDOW).unwrap() => t,
+        _ => now,
+    };
+
+    Ok((archive, submitted_at))
+}
+
+/// Discards an upload
+pub fn remove(id: &str) -> Result<(), String> {
+    remove_dir_all(dir(id)).map_err(|e| format!("{e}"))
+}
+
+fn dir(id: &str) -> PathBuf {
+    PathBuf::from(ROOT).join(id)
+}